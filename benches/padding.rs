@@ -0,0 +1,146 @@
+//! 比较开启与关闭填充时 Session 的吞吐量，以及 CMD_WASTE 带来的字节放大倍数。
+//!
+//! 两端通过内存 duplex 连接，只统计客户端写出的字节，不涉及 TLS 与网络。
+//! 运行 `cargo bench --bench padding`；设置 `ANYTLS_BENCH_SCHEME=<文件>` 可额外测试自定义填充方案。
+
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::session::{Session, SessionConfig};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::sync::mpsc;
+
+/// 单条长连接传输的数据量
+const BULK_BYTES: usize = 16 * 1024 * 1024;
+/// 短连接：每个 Session 只传一个请求大小的数据，填充占比最高
+const SHORT_BYTES: usize = 512;
+const SHORT_SESSIONS: usize = 500;
+
+/// 统计写出字节数的传输层
+struct Counting<T> {
+    inner: T,
+    written: Arc<AtomicU64>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Counting<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Counting<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            self.written.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// 新建一对 Session，经一个 Stream 上传 `payload` 字节，返回耗时与客户端写出的总字节数
+async fn transfer(padding: &Arc<PaddingFactory>, payload: usize) -> (Duration, u64) {
+    let (client_end, server_end) = tokio::io::duplex(256 * 1024);
+    let written = Arc::new(AtomicU64::new(0));
+    let client_end = Counting {
+        inner: client_end,
+        written: Arc::clone(&written),
+    };
+    let (stream_tx, mut incoming) = mpsc::unbounded_channel();
+    let server = Arc::new(Session::new_server(
+        Box::new(server_end),
+        Some(Arc::new(move |stream| {
+            let _ = stream_tx.send(stream);
+        })),
+        None,
+        Arc::clone(padding),
+        SessionConfig::default(),
+    ));
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        Arc::clone(padding),
+        SessionConfig::default(),
+    ));
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+
+    let started = Instant::now();
+    let mut stream = client.open_stream().await.unwrap();
+    let sink = tokio::spawn(async move {
+        let remote = incoming.recv().await.unwrap();
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut received = 0;
+        while received < payload {
+            let n = remote.read(&mut buf).await.unwrap();
+            assert!(n > 0, "stream closed early");
+            received += n;
+        }
+    });
+    let data = vec![0x5au8; payload];
+    stream.write_all(&data).await.unwrap();
+    stream.flush().await.unwrap();
+    sink.await.unwrap();
+    let elapsed = started.elapsed();
+
+    let _ = client.close().await;
+    let _ = server.close().await;
+    (elapsed, written.load(Ordering::Relaxed))
+}
+
+fn report(scheme: &str, workload: &str, payload: u64, elapsed: Duration, wire: u64) {
+    let mib_per_sec = payload as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64();
+    println!(
+        "{:<10} {:<6} {:>10.1} MiB/s {:>8.3}x",
+        scheme,
+        workload,
+        mib_per_sec,
+        wire as f64 / payload as f64
+    );
+}
+
+async fn bench(scheme: &str, padding: Arc<PaddingFactory>) {
+    let (elapsed, wire) = transfer(&padding, BULK_BYTES).await;
+    report(scheme, "bulk", BULK_BYTES as u64, elapsed, wire);
+
+    let (mut elapsed, mut wire) = (Duration::ZERO, 0);
+    for _ in 0..SHORT_SESSIONS {
+        let (e, w) = transfer(&padding, SHORT_BYTES).await;
+        elapsed += e;
+        wire += w;
+    }
+    report(scheme, "short", (SHORT_BYTES * SHORT_SESSIONS) as u64, elapsed, wire);
+}
+
+#[tokio::main]
+async fn main() {
+    println!("{:<10} {:<6} {:>16} {:>9}", "scheme", "load", "throughput", "wire");
+    let none = PaddingFactory::new(b"stop=0").expect("valid scheme");
+    bench("none", Arc::new(none)).await;
+    bench("default", Arc::new(PaddingFactory::default())).await;
+
+    if let Ok(path) = std::env::var("ANYTLS_BENCH_SCHEME") {
+        let raw = std::fs::read(&path).expect("failed to read ANYTLS_BENCH_SCHEME");
+        let custom = PaddingFactory::new(&raw).expect("invalid padding scheme");
+        bench("custom", Arc::new(custom)).await;
+    }
+}
@@ -0,0 +1,112 @@
+//! 统计客户端 Session 读循环处理控制帧时的堆分配次数。
+//!
+//! 对端连续发送 CMD_UPDATE_PADDING_SCHEME 或 CMD_WASTE 帧，最后用心跳确认全部处理完毕，
+//! 计数器统计期间整个进程的分配次数（含对端读取心跳应答，占比很小）。另外比较从切片与
+//! 从 `Bytes` 构建 `PaddingFactory` 的分配次数。运行 `cargo bench --bench recv_alloc`。
+
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::session::{
+    Frame, FrameCodec, Session, SessionConfig, CMD_HEART_REQUEST, CMD_HEART_RESPONSE,
+    CMD_UPDATE_PADDING_SCHEME, CMD_WASTE,
+};
+use bytes::{Bytes, BytesMut};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_util::codec::Decoder;
+
+const FRAMES: usize = 10_000;
+const WASTE_LEN: usize = 1000;
+
+/// 统计分配次数的全局分配器
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocations() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// 让客户端 Session 读取 `FRAMES` 个 `cmd` 帧，返回平均每帧的分配次数
+async fn recv_frames(cmd: u8, payload: Bytes) -> f64 {
+    let (client_end, peer) = tokio::io::duplex(256 * 1024);
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    client.run().await.unwrap();
+
+    let (mut peer_r, mut peer_w) = tokio::io::split(peer);
+    let (pong_tx, mut pong_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut wire = BytesMut::with_capacity(64 * 1024);
+        while peer_r.read_buf(&mut wire).await.unwrap_or(0) > 0 {
+            while let Some(frame) = FrameCodec.decode(&mut wire).unwrap() {
+                if frame.cmd == CMD_HEART_RESPONSE {
+                    let _ = pong_tx.send(());
+                }
+            }
+        }
+    });
+
+    // 先编码好全部帧，不计入统计
+    let mut wire = BytesMut::new();
+    for _ in 0..FRAMES {
+        Frame::with_data(cmd, 0, payload.clone()).encode_into(&mut wire);
+    }
+    Frame::new(CMD_HEART_REQUEST, 1).encode_into(&mut wire);
+
+    let before = allocations();
+    peer_w.write_all(&wire).await.unwrap();
+    pong_rx.recv().await.unwrap();
+    let count = allocations() - before;
+
+    let _ = client.close().await;
+    count as f64 / FRAMES as f64
+}
+
+fn factory_allocations(build: impl Fn() -> Option<PaddingFactory>) -> f64 {
+    let before = allocations();
+    for _ in 0..FRAMES {
+        build().unwrap();
+    }
+    (allocations() - before) as f64 / FRAMES as f64
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let scheme = PaddingFactory::default().raw_scheme;
+    println!("{:<24} {:>12}", "workload", "allocs/op");
+
+    let update = recv_frames(CMD_UPDATE_PADDING_SCHEME, scheme.clone()).await;
+    println!("{:<24} {:>12.2}", "recv padding update", update);
+    let waste = recv_frames(CMD_WASTE, Bytes::from(vec![0u8; WASTE_LEN])).await;
+    println!("{:<24} {:>12.2}", "recv waste", waste);
+
+    let from_slice = factory_allocations(|| PaddingFactory::new(&scheme));
+    println!("{:<24} {:>12.2}", "factory from slice", from_slice);
+    let from_bytes = factory_allocations(|| PaddingFactory::from_bytes(scheme.clone()));
+    println!("{:<24} {:>12.2}", "factory from bytes", from_bytes);
+}
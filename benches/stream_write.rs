@@ -0,0 +1,91 @@
+//! 比较 `AsyncWrite` 与 `Stream::write_bytes` 批量写入时的吞吐量。
+//!
+//! `AsyncWrite` 每次写入都把应用缓冲区复制进新的帧，`write_bytes` 直接切片共享调用方的 `Bytes`。
+//! 两端通过内存 duplex 连接且不填充，只比较写入路径本身。运行 `cargo bench --bench stream_write`。
+
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::session::{Session, SessionConfig, Stream};
+use bytes::Bytes;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+/// 每轮传输的数据量
+const BULK_BYTES: usize = 64 * 1024 * 1024;
+/// 应用每次交给 Stream 的数据块大小
+const CHUNK_BYTES: usize = 256 * 1024;
+const ROUNDS: usize = 3;
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Copy,
+    ZeroCopy,
+}
+
+async fn write(stream: &mut Stream, chunk: &Bytes, mode: Mode) {
+    match mode {
+        Mode::Copy => stream.write_all(chunk).await.unwrap(),
+        Mode::ZeroCopy => stream.write_bytes(chunk.clone()).await.unwrap(),
+    }
+}
+
+/// 新建一对 Session，经一个 Stream 上传 `BULK_BYTES` 字节，返回耗时
+async fn transfer(mode: Mode) -> Duration {
+    let (client_end, server_end) = tokio::io::duplex(1024 * 1024);
+    let padding = Arc::new(PaddingFactory::new(b"stop=0").expect("valid scheme"));
+    let (stream_tx, mut incoming) = mpsc::unbounded_channel();
+    let server = Arc::new(Session::new_server(
+        Box::new(server_end),
+        Some(Arc::new(move |stream| {
+            let _ = stream_tx.send(stream);
+        })),
+        None,
+        Arc::clone(&padding),
+        SessionConfig::default(),
+    ));
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        padding,
+        SessionConfig::default(),
+    ));
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+
+    let chunk = Bytes::from(vec![0x5au8; CHUNK_BYTES]);
+    let started = Instant::now();
+    let mut stream = client.open_stream().await.unwrap();
+    let sink = tokio::spawn(async move {
+        let remote: Stream = incoming.recv().await.unwrap();
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut received = 0;
+        while received < BULK_BYTES {
+            let n = remote.read(&mut buf).await.unwrap();
+            assert!(n > 0, "stream closed early");
+            received += n;
+        }
+    });
+    for _ in 0..BULK_BYTES / CHUNK_BYTES {
+        write(&mut stream, &chunk, mode).await;
+    }
+    stream.flush().await.unwrap();
+    sink.await.unwrap();
+    let elapsed = started.elapsed();
+
+    let _ = client.close().await;
+    let _ = server.close().await;
+    elapsed
+}
+
+#[tokio::main]
+async fn main() {
+    println!("{:<10} {:>16}", "path", "throughput");
+    for (name, mode) in [("copy", Mode::Copy), ("zero-copy", Mode::ZeroCopy)] {
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..ROUNDS {
+            elapsed += transfer(mode).await;
+        }
+        let mib = (BULK_BYTES * ROUNDS) as f64 / (1024.0 * 1024.0);
+        println!("{:<10} {:>10.1} MiB/s", name, mib / elapsed.as_secs_f64());
+    }
+}
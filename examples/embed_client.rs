@@ -0,0 +1,54 @@
+//! 在自己的程序中嵌入 anytls 客户端：经隧道向目标发送一个 HTTP 请求并打印响应。
+//!
+//! 运行 `cargo run --example embed_client -- <server> <password> <host:port> [path]`。
+//! `Client` 内部维护 Session 池，可以在多个任务间共享，反复调用 `connect` 复用同一条 TLS 连接。
+
+use anytls_rs::proxy::padding::DefaultPaddingFactory;
+use anytls_rs::proxy::session::Client;
+use anytls_rs::proxy::transport::{self, AuthMode};
+use anytls_rs::util::tls::TlsClientOptions;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        eprintln!("usage: {} <server> <password> <host:port> [path]", args[0]);
+        std::process::exit(2);
+    }
+    let (server, password, target) = (&args[1], &args[2], &args[3]);
+    let path = args.get(4).map_or("/", String::as_str);
+
+    // 与 anytls-client 的默认配置相同：TLS、不校验服务端证书、旧式认证
+    let tls_config = transport::create_tls_config(&TlsClientOptions::default())?;
+    let padding = DefaultPaddingFactory::load();
+    let dial_out = transport::create_dial_out_func(
+        server.clone(),
+        tls_config,
+        None,
+        transport::password_sha256(password),
+        padding.clone(),
+        AuthMode::Legacy,
+    );
+    let client = Client::builder(dial_out, padding)
+        .idle_timeout(Duration::from_secs(30))
+        .connect_timeout(Some(Duration::from_secs(10)))
+        .build();
+
+    // Stream 实现了 AsyncRead/AsyncWrite，可以交给任何基于 tokio 的协议库
+    let mut stream = client.connect(target).await?;
+    let host = target.rsplit_once(':').map_or(target.as_str(), |(host, _)| host);
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    print!("{}", String::from_utf8_lossy(&response));
+
+    client.close().await
+}
@@ -0,0 +1,12 @@
+#![no_main]
+
+use anytls_rs::proxy::protocol::frame::{Frame, HEADER_OVERHEAD_SIZE};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(frame) = Frame::from_bytes(data) {
+        // 解析成功的帧重新编码后应与输入前缀一致
+        let encoded = frame.to_bytes();
+        assert_eq!(&encoded[..], &data[..HEADER_OVERHEAD_SIZE + frame.data.len()]);
+    }
+});
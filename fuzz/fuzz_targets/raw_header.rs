@@ -0,0 +1,8 @@
+#![no_main]
+
+use anytls_rs::proxy::protocol::frame::RawHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = RawHeader::from_bytes(data);
+});
@@ -0,0 +1,19 @@
+#![no_main]
+
+use anytls_rs::proxy::addr_codec::{build_socks_addr, AddressType, SocksAddr};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((addr, consumed)) = SocksAddr::from_socks_bytes(data) {
+        assert!(consumed <= data.len());
+        // 重新编码后再解析应得到同一个地址
+        let encoded = build_socks_addr(&addr).unwrap();
+        let (reparsed, reconsumed) = SocksAddr::from_socks_bytes(&encoded).unwrap();
+        assert_eq!(reparsed, addr);
+        assert_eq!(reconsumed, encoded.len());
+        // IPv4 映射的 IPv6 地址按 IPv4 解析，编码成 ATYP 0x01；其余地址与消耗掉的输入一致
+        if !(data[0] == 0x04 && addr.atyp == AddressType::Ipv4) {
+            assert_eq!(&encoded[..], &data[..consumed]);
+        }
+    }
+});
@@ -0,0 +1,34 @@
+/// 隧道不可用时直连目标的策略，只对 `--fallback-allow` 列出的目标生效
+#[derive(Clone, Default)]
+pub struct DirectFallback {
+    enabled: bool,
+    allow: Vec<String>,
+}
+
+impl DirectFallback {
+    pub fn new(enabled: bool, allow: Vec<String>) -> Self {
+        let allow = allow.into_iter().map(|p| p.to_ascii_lowercase()).collect();
+        Self { enabled, allow }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 规则：`*` 匹配所有目标；`.example.com` 或 `*.example.com` 匹配该域及其子域；其余精确匹配
+    pub fn allows(&self, host: &str) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let host = host.to_ascii_lowercase();
+        self.allow.iter().any(|pattern| {
+            if pattern == "*" {
+                return true;
+            }
+            match pattern.strip_prefix('*').unwrap_or(pattern).strip_prefix('.') {
+                Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+                None => host == *pattern,
+            }
+        })
+    }
+}
@@ -1,11 +1,16 @@
+mod fallback;
+mod probe;
 mod runtime;
-mod socks5;
+use anytls_rs::config::{self, ClientConfig};
 use anytls_rs::proxy::padding::DefaultPaddingFactory;
-use anytls_rs::proxy::session::Client;
-use anytls_rs::proxy::transport;
+use anytls_rs::proxy::session::{Client, DEFAULT_RECV_WINDOW, MAX_PAYLOAD_SIZE};
+use anytls_rs::proxy::transport::{self, AuthMode};
+use anytls_rs::util::accept::AcceptBackoff;
+use anytls_rs::util::runtime::{spawn_guarded, RuntimeOptions};
+use anytls_rs::util::tls::{CipherPreference, TlsClientOptions};
 use anytls_rs::PROGRAM_VERSION_NAME;
-use clap::Parser;
-use log::{error, info};
+use clap::{CommandFactory, Parser};
+use log::{error, info, warn};
 use std::time::Duration;
 use tokio::net::TcpListener;
 
@@ -19,23 +24,140 @@ struct Args {
     #[arg(short = 's', long, default_value = "127.0.0.1:8443", help = "Server address")]
     server: String,
 
-    #[arg(long, help = "SNI")]
+    #[arg(long, help = "TLS SNI, a,b rotates per dial (default: --server host, empty: no SNI)")]
     sni: Option<String>,
 
     #[arg(short = 'p', long, help = "Password")]
     password: String,
+
+    #[arg(long, default_value = "legacy", help = "Auth mode: legacy|hmac|hmac-or-legacy")]
+    auth_mode: AuthMode,
+
+    #[arg(long, default_value_t = 30, help = "Close idle sessions after N seconds")]
+    idle_timeout_secs: u64,
+
+    #[arg(long, default_value_t = 1, help = "Keep at least N idle sessions")]
+    min_idle_sessions: usize,
+
+    #[arg(long, default_value_t = 0, help = "Max idle sessions (0 = unlimited)")]
+    max_idle_sessions: usize,
+
+    #[arg(long, default_value_t = DEFAULT_RECV_WINDOW, help = "Per-stream receive window (bytes)")]
+    recv_window: usize,
+
+    #[arg(long, default_value_t = MAX_PAYLOAD_SIZE, help = "Max payload per data frame (bytes)")]
+    max_payload: usize,
+
+    #[arg(long, default_value_t = 0, help = "Fail a session whose write stalls N ms (0 = off)")]
+    write_timeout_ms: u64,
+
+    #[arg(long, default_value_t = 0, help = "Restart padding after N ms without writes (0 = off)")]
+    padding_idle_reset_ms: u64,
+
+    #[arg(long, default_value_t = 0, help = "Max pushed padding bytes per packet (0 = off)")]
+    max_padding_bytes: usize,
+
+    #[arg(long, help = "Ask the server to give up connecting to a target after N ms")]
+    connect_timeout_ms: Option<u64>,
+
+    #[arg(long, help = "Run sessions over plain TCP without TLS (insecure, loopback only)")]
+    no_tls: bool,
+
+    #[arg(long, help = "Report this client's OS and architecture to the server")]
+    report_platform: bool,
+
+    #[arg(long, default_value = "auto", help = "Cipher suite preference: aes|chacha|auto")]
+    cipher_preference: CipherPreference,
+
+    #[arg(long, help = "Client certificate chain (PEM) for mutual TLS")]
+    client_cert: Option<String>,
+
+    #[arg(long, help = "Client private key (PEM) for mutual TLS")]
+    client_key: Option<String>,
+
+    #[arg(long, help = "Append TLS session keys to this file (debugging only, exposes traffic)")]
+    keylog_file: Option<String>,
+
+    #[arg(long, help = "Max TLS record size in bytes, including header (32-16389)")]
+    tls_fragment_size: Option<usize>,
+
+    #[arg(long, conflicts_with = "no_tls", help = "Verify the server cert against OS roots")]
+    trust_system_roots: bool,
+
+    #[arg(long, help = "Check reachability, TLS and auth against the server, then exit")]
+    probe: bool,
+
+    #[arg(long, help = "With --probe, also send HEAD to this host:port via the tunnel")]
+    probe_target: Option<String>,
+
+    #[arg(long, help = "Connect directly to the target when the tunnel is unavailable")]
+    direct_fallback: bool,
+
+    #[arg(long, help = "Target allowed to bypass the tunnel: host, .domain suffix or *")]
+    fallback_allow: Vec<String>,
+
+    #[arg(long, help = "Tokio worker threads (default: number of CPUs)")]
+    worker_threads: Option<usize>,
+
+    #[arg(long, help = "Run everything on a single-threaded runtime")]
+    current_thread: bool,
+
+    #[arg(long, help = "Load options from a TOML file; command-line flags take precedence")]
+    config: Option<String>,
+}
+
+/// 解析命令行；给出 `--config` 时先合并配置文件中的选项
+fn parse_args() -> std::io::Result<Args> {
+    let cli: Vec<String> = std::env::args().collect();
+    let Some(path) = config::config_path(&cli) else {
+        return Ok(Args::parse_from(cli));
+    };
+    let file: ClientConfig = config::load(&path)?;
+    let argv = config::merge_args(&Args::command(), config::to_args(&file)?, cli);
+    Ok(Args::parse_from(argv))
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let args = Args::parse();
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let runtime_options = RuntimeOptions {
+        worker_threads: args.worker_threads,
+        current_thread: args.current_thread,
+    };
+    let runtime = match runtime_options.build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "[Client] Runtime: {}, {} worker thread(s)",
+        if args.current_thread { "current-thread" } else { "multi-thread" },
+        runtime_options.effective_worker_threads()
+    );
+    runtime.block_on(run(args))
+}
 
+async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
     if args.password.is_empty() {
         error!("Please set password");
         std::process::exit(1);
     }
+    if args.max_idle_sessions != 0 && args.min_idle_sessions > args.max_idle_sessions {
+        error!(
+            "--min-idle-sessions ({}) must not exceed --max-idle-sessions ({})",
+            args.min_idle_sessions, args.max_idle_sessions
+        );
+        std::process::exit(1);
+    }
 
     let password_sha256 = transport::password_sha256(&args.password);
 
@@ -43,47 +165,111 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("[Client] SOCKS5 {} => {}", args.listen, args.server);
     info!("[Client] Session padding enabled: true");
 
-    let listener = TcpListener::bind(&args.listen).await?;
-
-    let tls_config = transport::create_tls_config();
+    if let Some(path) = &args.keylog_file {
+        warn!(
+            "[Client] Writing TLS session keys to {}; anyone with it can decrypt the tunnel",
+            path
+        );
+    }
+    let tls_config = transport::create_tls_config(&TlsClientOptions {
+        cipher: args.cipher_preference,
+        client_cert: args.client_cert,
+        client_key: args.client_key,
+        keylog_file: args.keylog_file,
+        fragment_size: args.tls_fragment_size,
+        trust_system_roots: args.trust_system_roots,
+    })?;
     let padding = DefaultPaddingFactory::load();
 
+    if args.probe {
+        if args.no_tls {
+            transport::require_loopback(&args.server).await?;
+        }
+        let probe = probe::Probe {
+            server: args.server,
+            sni: args.sni,
+            tls_config: (!args.no_tls).then_some(tls_config),
+            password_sha256,
+            auth_mode: args.auth_mode,
+            padding,
+            target: args.probe_target,
+        };
+        std::process::exit(if probe.run().await { 0 } else { 1 });
+    }
+
+    let listener = TcpListener::bind(&args.listen).await?;
+
     // 创建客户端
-    let dial_out = transport::create_dial_out_func(
-        args.server.clone(),
-        tls_config,
-        args.sni,
-        password_sha256,
-        padding.clone(),
-    );
-    let client = Client::new(
-        dial_out,
-        padding,
-        Duration::from_secs(30), // 空闲超时
-        1,                       // 最小空闲连接数
-    );
+    let dial_out = if args.no_tls {
+        transport::require_loopback(&args.server).await?;
+        warn!("[Client] TLS disabled: sessions to {} are sent in plaintext", args.server);
+        transport::create_plain_dial_out_func(
+            args.server.clone(),
+            password_sha256,
+            padding.clone(),
+            args.auth_mode,
+        )
+    } else {
+        transport::create_dial_out_func(
+            args.server.clone(),
+            tls_config,
+            args.sni,
+            password_sha256,
+            padding.clone(),
+            args.auth_mode,
+        )
+    };
+    let client = Client::builder(dial_out, padding)
+        .idle_timeout(Duration::from_secs(args.idle_timeout_secs))
+        .min_idle_sessions(args.min_idle_sessions)
+        .max_idle_sessions(args.max_idle_sessions)
+        .recv_window(args.recv_window)
+        .max_payload(args.max_payload)
+        .write_timeout(Duration::from_millis(args.write_timeout_ms))
+        .report_platform(args.report_platform)
+        .padding_idle_reset(Duration::from_millis(args.padding_idle_reset_ms))
+        .max_padding_bytes(args.max_padding_bytes)
+        .connect_timeout(args.connect_timeout_ms.map(Duration::from_millis))
+        .build();
+
+    let fallback = fallback::DirectFallback::new(args.direct_fallback, args.fallback_allow);
+    if fallback.enabled() {
+        warn!("[Client] Direct fallback enabled: allowed targets bypass the tunnel when it is down");
+    }
 
     info!("[Client] Listening on {}", args.listen);
 
     // 监听 SOCKS5 连接
+    let mut backoff = AcceptBackoff::new();
     loop {
         match listener.accept().await {
             Ok((client_conn, addr)) => {
+                backoff.reset();
                 info!("[Client] New connection from {}", addr);
 
                 // 为每个连接创建新的任务
                 let client_clone = client.clone();
-                tokio::spawn(async move {
+                let fallback = fallback.clone();
+                let context = format!("[Client] Connection from {}", addr);
+                spawn_guarded(context, async move {
                     if let Err(e) =
-                        runtime::handle_client_connection(client_conn, client_clone).await
+                        runtime::handle_client_connection(client_conn, client_clone, fallback)
+                            .await
                     {
                         error!("[Client] Connection error: {}", e);
                     }
                 });
             }
-            Err(e) => {
-                error!("[Client] Accept error: {}", e);
-            }
+            Err(e) => match backoff.on_error(&e) {
+                Some(delay) => {
+                    error!("[Client] Accept error: {}", e);
+                    tokio::time::sleep(delay).await;
+                }
+                None => {
+                    error!("[Client] Listener failed: {}", e);
+                    return Err(e.into());
+                }
+            },
         }
     }
 }
@@ -0,0 +1,145 @@
+//! `--probe`：逐阶段检查到服务端的连通性，不监听 SOCKS5。
+//!
+//! 依次执行 TCP 连接、TLS 握手、认证、Session 设置交换（以心跳往返确认服务端已接受认证），
+//! 可选地再经隧道访问一个目标并发送 HEAD 请求，每个阶段输出耗时；任一阶段失败即停止。
+
+use anytls_rs::proxy::addr_codec::{build_socks_addr, SocksAddr};
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::session::{Session, SessionConfig};
+use anytls_rs::proxy::transport::{self, AuthMode};
+use anytls_rs::util::r#type::AsyncReadWrite;
+use rustls::ClientConfig;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// 每个阶段的超时
+const PHASE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Probe {
+    pub server: String,
+    pub sni: Option<String>,
+    /// `None` 表示以明文探测（`--no-tls`）
+    pub tls_config: Option<Arc<ClientConfig>>,
+    pub password_sha256: [u8; 32],
+    pub auth_mode: AuthMode,
+    pub padding: Arc<PaddingFactory>,
+    /// 经隧道访问的目标 `host:port`
+    pub target: Option<String>,
+}
+
+impl Probe {
+    /// 执行探测并输出报告，全部阶段通过时返回 `true`
+    pub async fn run(self) -> bool {
+        println!("probing {}", self.server);
+        let result = self.run_phases().await;
+        match &result {
+            Ok(()) => println!("PASS"),
+            Err(e) => println!("FAIL: {}", e),
+        }
+        result.is_ok()
+    }
+
+    async fn run_phases(&self) -> io::Result<()> {
+        let tcp = phase("tcp", TcpStream::connect(&self.server)).await?;
+        let mut conn: Box<dyn AsyncReadWrite> = match &self.tls_config {
+            Some(config) => {
+                // 轮换多个 SNI 时只用第一个检查
+                let names = self.sni.as_deref().map(transport::split_sni_list);
+                let first = names.as_ref().and_then(|names| names.first());
+                let sni = first.map(String::as_str).or(self.sni.as_deref());
+                let server_name = transport::tls_server_name(&self.server, sni)?;
+                let connector = TlsConnector::from(transport::tls_config_for_sni(
+                    Arc::clone(config),
+                    sni,
+                ));
+                Box::new(phase("tls", connector.connect(server_name, tcp)).await?)
+            }
+            None => {
+                println!("{:<10}skipped (plaintext)", "tls");
+                Box::new(tcp)
+            }
+        };
+        let padding = Arc::clone(&self.padding);
+        phase(
+            "auth",
+            transport::authenticate(&mut conn, self.password_sha256, padding, self.auth_mode),
+        )
+        .await?;
+
+        // 认证失败时服务端不会应答心跳，而是关闭连接或转给回落站点
+        let session = Arc::new(Session::new_client(
+            conn,
+            Arc::clone(&self.padding),
+            SessionConfig::default(),
+        ));
+        let settings = async {
+            session.run().await?;
+            session.heartbeat_probe(PHASE_TIMEOUT).await.map_err(|e| {
+                io::Error::new(e.kind(), format!("{} (wrong password?)", e))
+            })
+        };
+        let result = phase("session", settings).await;
+        if result.is_ok() {
+            if let Some(target) = &self.target {
+                let status = phase("stream", head_request(&session, target)).await;
+                if let Ok(status) = &status {
+                    println!("{:<10}{}", "", status);
+                }
+                let _ = session.close().await;
+                return status.map(|_| ());
+            }
+        }
+        let _ = session.close().await;
+        result.map(|_| ())
+    }
+}
+
+/// 执行一个阶段并输出耗时或错误
+async fn phase<T>(name: &str, fut: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    let started = Instant::now();
+    let result = match tokio::time::timeout(PHASE_TIMEOUT, fut).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", name))),
+    };
+    let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+    match &result {
+        Ok(_) => println!("{:<10}ok    {:>8.1} ms", name, elapsed),
+        Err(e) => println!("{:<10}FAIL  {:>8.1} ms  {}", name, elapsed, e),
+    }
+    result
+}
+
+/// 经隧道向 `target` 发送 HEAD 请求，返回响应的状态行
+async fn head_request(session: &Arc<Session>, target: &str) -> io::Result<String> {
+    let addr = SocksAddr::parse_target(target)?;
+
+    let mut stream = session.open_stream().await?;
+    stream.write_all(&build_socks_addr(&addr)?).await?;
+    let request =
+        format!("HEAD / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", addr.host);
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 512];
+    while !response.contains(&b'\n') {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+    let status = String::from_utf8_lossy(&response);
+    match status.lines().next().filter(|line| line.starts_with("HTTP/")) {
+        Some(line) => Ok(line.to_string()),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "target closed without an HTTP response",
+        )),
+    }
+}
@@ -1,7 +1,11 @@
-use crate::socks5;
+use crate::fallback::DirectFallback;
+use anytls_rs::proxy::addr_codec::{
+    build_socks_addr, build_target_header, AddressType, SocksAddr, ATYP_UNIX,
+};
 use anytls_rs::proxy::session::Client;
+use anytls_rs::proxy::socks::{self, Socks5Handshake, SocksRequest};
 use anytls_rs::proxy::uot;
-use log::{error, info};
+use log::{error, info, warn};
 use tokio::io::copy_bidirectional;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
@@ -10,72 +14,19 @@ use tokio::net::UdpSocket;
 const UOT_DEST_HOST: &str = uot::MAGIC_ADDRESS;
 const UOT_DEST_PORT: u16 = 443;
 
-fn parse_socks5_udp_packet(
-    pkt: &[u8],
-) -> Result<(anytls_rs::proxy::addr_codec::SocksAddr, usize), Box<dyn std::error::Error>> {
-    if pkt.len() < 4 {
-        return Err("short udp packet".into());
-    }
-    if pkt[2] != 0 {
+/// 解析 SOCKS5 UDP 请求头 `RSV(2) + FRAG(1) + ATYP + ADDR + PORT`，返回目标地址与负载的偏移。
+/// 地址交给 [`SocksAddr::from_socks_bytes`] 按切片解析，截断的包返回错误而不会越界
+fn parse_socks5_udp_packet(pkt: &[u8]) -> Result<(SocksAddr, usize), Box<dyn std::error::Error>> {
+    let (header, rest) = pkt.split_first_chunk::<3>().ok_or("short udp packet")?;
+    if header[2] != 0 {
         return Err("fragmented udp is not supported".into());
     }
-    let atyp = pkt[3];
-    let mut idx = 4usize;
-    let addr = match atyp {
-        0x01 => {
-            if pkt.len() < idx + 4 + 2 {
-                return Err("short ipv4 udp header".into());
-            }
-            let ip = std::net::Ipv4Addr::new(pkt[idx], pkt[idx + 1], pkt[idx + 2], pkt[idx + 3]);
-            idx += 4;
-            let port = u16::from_be_bytes([pkt[idx], pkt[idx + 1]]);
-            idx += 2;
-            anytls_rs::proxy::addr_codec::SocksAddr {
-                atyp: anytls_rs::proxy::addr_codec::AddressType::Ipv4,
-                host: ip.to_string(),
-                port,
-            }
-        }
-        0x04 => {
-            if pkt.len() < idx + 16 + 2 {
-                return Err("short ipv6 udp header".into());
-            }
-            let mut ipb = [0u8; 16];
-            ipb.copy_from_slice(&pkt[idx..idx + 16]);
-            idx += 16;
-            let port = u16::from_be_bytes([pkt[idx], pkt[idx + 1]]);
-            idx += 2;
-            anytls_rs::proxy::addr_codec::SocksAddr {
-                atyp: anytls_rs::proxy::addr_codec::AddressType::Ipv6,
-                host: std::net::Ipv6Addr::from(ipb).to_string(),
-                port,
-            }
-        }
-        0x03 => {
-            if pkt.len() < idx + 1 {
-                return Err("short domain len".into());
-            }
-            let len = pkt[idx] as usize;
-            idx += 1;
-            if pkt.len() < idx + len + 2 {
-                return Err("short domain udp header".into());
-            }
-            let host = String::from_utf8(pkt[idx..idx + len].to_vec())?;
-            idx += len;
-            let port = u16::from_be_bytes([pkt[idx], pkt[idx + 1]]);
-            idx += 2;
-            anytls_rs::proxy::addr_codec::SocksAddr {
-                atyp: anytls_rs::proxy::addr_codec::AddressType::Domain,
-                host,
-                port,
-            }
-        }
-        _ => return Err("unsupported udp atyp".into()),
-    };
-    if pkt.len() < idx {
-        return Err("short udp header".into());
+    // Unix socket 地址只用于 AnyTLS 的目标地址，不属于 SOCKS5
+    if rest.first() == Some(&ATYP_UNIX) {
+        return Err("unsupported udp atyp".into());
     }
-    Ok((addr, idx))
+    let (addr, consumed) = SocksAddr::from_socks_bytes(rest)?;
+    Ok((addr, header.len() + consumed))
 }
 
 fn build_socks5_udp_packet(
@@ -101,7 +52,9 @@ fn build_socks5_udp_packet(
                 out.extend_from_slice(&[0u8; 16]);
             }
         }
-        anytls_rs::proxy::addr_codec::AddressType::Domain => {
+        // UoT 的回包地址不会是 Unix socket
+        anytls_rs::proxy::addr_codec::AddressType::Domain
+        | anytls_rs::proxy::addr_codec::AddressType::Unix => {
             out.push(0x03);
             let b = addr.host.as_bytes();
             let l = b.len().min(255);
@@ -120,20 +73,23 @@ async fn handle_udp_associate(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let udp_socket = UdpSocket::bind("0.0.0.0:0").await?;
     let bind_port = udp_socket.local_addr()?.port();
-    socks5::write_udp_associate_reply(&mut client_conn, bind_port).await?;
+    let bound = SocksAddr {
+        atyp: AddressType::Ipv4,
+        host: "0.0.0.0".to_string(),
+        port: bind_port,
+    };
+    socks::write_reply(&mut client_conn, socks::REP_SUCCEEDED, Some(&bound)).await?;
     info!(
         "[Client] UDP associate established on 0.0.0.0:{}",
         bind_port
     );
 
     let mut stream = client.create_stream().await?;
-    let uot_req = socks5::SocksRequest {
-        command: 0x01,
-        atyp: anytls_rs::proxy::addr_codec::AddressType::Domain,
+    let target_socks_addr = build_socks_addr(&SocksAddr {
+        atyp: AddressType::Domain,
         host: UOT_DEST_HOST.to_string(),
         port: UOT_DEST_PORT,
-    };
-    let target_socks_addr = socks5::build_socks_addr(&uot_req)?;
+    })?;
     stream.write_all(&target_socks_addr).await?;
     stream.flush().await?;
     let uot_request = uot::Request {
@@ -216,36 +172,48 @@ async fn handle_udp_associate(
 pub async fn handle_client_connection(
     mut client_conn: TcpStream,
     client: Client,
+    fallback: DirectFallback,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    socks5::accept_no_auth(&mut client_conn).await?;
-    let req = socks5::read_request(&mut client_conn).await?;
-    if req.command == 0x03 {
+    let req = Socks5Handshake::new().negotiate(&mut client_conn).await?;
+    if req.command == socks::CMD_UDP_ASSOCIATE {
         return handle_udp_associate(client_conn, client).await;
     }
-    if req.command != 0x01 {
-        socks5::write_command_not_supported_reply(&mut client_conn).await?;
+    if req.command != socks::CMD_CONNECT {
+        socks::write_reply(&mut client_conn, socks::REP_COMMAND_NOT_SUPPORTED, None).await?;
         return Ok(());
     }
 
-    info!("[Client] Connecting to {}:{}", req.host, req.port);
+    info!("[Client] Connecting to {}:{}", req.addr.host, req.addr.port);
 
     log::debug!("[Client] Creating AnyTLS stream");
-    let mut anytls_stream = client.create_stream().await?;
+    let mut anytls_stream = match client.create_stream().await {
+        Ok(stream) => stream,
+        Err(e) if fallback.allows(&req.addr.host) => {
+            warn!(
+                "[Client] Tunnel unavailable ({}), bypassing tunnel: direct connect to {}:{}",
+                e, req.addr.host, req.addr.port
+            );
+            return relay_direct(client_conn, &req).await;
+        }
+        Err(e) => return Err(e.into()),
+    };
     log::info!("[Client] AnyTLS stream created successfully");
 
-    let target_socks_addr = socks5::build_socks_addr(&req)?;
+    let target_socks_addr = build_target_header(&req.addr, client.connect_timeout())?;
     anytls_stream.write_all(&target_socks_addr).await?;
     anytls_stream.flush().await?;
     log::debug!(
         "[Client] Sent SocksAddr to server: {}:{} ({} bytes)",
-        req.host,
-        req.port,
+        req.addr.host,
+        req.addr.port,
         target_socks_addr.len()
     );
 
-    socks5::write_success_reply(&mut client_conn).await?;
+    socks::write_reply(&mut client_conn, socks::REP_SUCCEEDED, None).await?;
     log::debug!("[Client] Sent SOCKS5 connection success response");
 
+    // 任一方向读到 EOF 后 copy_bidirectional 会 shutdown 另一端的写方向，
+    // 本地应用关闭时 AnyTLS Stream 随之发出 FIN，服务端再关闭到目标的写方向
     match copy_bidirectional(&mut client_conn, &mut anytls_stream).await {
         Ok((c2t, t2c)) => {
             info!(
@@ -260,3 +228,17 @@ pub async fn handle_client_connection(
 
     Ok(())
 }
+
+async fn relay_direct(
+    mut client_conn: TcpStream,
+    req: &SocksRequest,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut target = TcpStream::connect((req.addr.host.as_str(), req.addr.port)).await?;
+    socks::write_reply(&mut client_conn, socks::REP_SUCCEEDED, None).await?;
+    let (c2t, t2c) = copy_bidirectional(&mut client_conn, &mut target).await?;
+    info!(
+        "[Client] Direct copy completed: client->target={} bytes, target->client={} bytes",
+        c2t, t2c
+    );
+    Ok(())
+}
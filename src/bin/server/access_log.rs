@@ -0,0 +1,97 @@
+//! 访问日志：每个结束的 Stream 追加一行 CSV，供离线分析。
+//!
+//! 转发任务只把记录放进有界队列，由单独的任务缓冲写入文件；队列满时丢弃记录并计数，
+//! 不让磁盘变慢拖住数据转发。文件只追加，轮转交给 logrotate 等外部工具（copytruncate）。
+
+use anytls_rs::proxy::session::StreamInfo;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+
+/// 写入任务落后时最多排队的记录数
+const QUEUE_CAPACITY: usize = 4096;
+
+/// 每行的列，与写入顺序一致
+const HEADER: &str = "timestamp,target,bytes_up,bytes_down,duration_ms,close_reason";
+
+#[derive(Clone)]
+pub(crate) struct AccessLog {
+    tx: mpsc::Sender<String>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AccessLog {
+    /// 以追加方式打开 `path` 并启动写入任务，新文件先写入表头
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("failed to open access log {}: {}", path.display(), e))
+        })?;
+        let is_new = file.metadata()?.len() == 0;
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let file = tokio::fs::File::from_std(file);
+        tokio::spawn(async move {
+            if let Err(e) = write_loop(file, rx, is_new).await {
+                log::error!("[Server] Access log write failed, logging stopped: {}", e);
+            }
+        });
+        Ok(Self { tx, dropped: Arc::new(AtomicU64::new(0)) })
+    }
+
+    /// 记录一个结束的 Stream。上行为本端从 Stream 读出的字节数（含目标地址头），下行为写入的字节数
+    pub(crate) fn record(&self, info: &StreamInfo, close_reason: &str) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let line = format!(
+            "{}.{:03},{},{},{},{},{}\n",
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            csv_field(info.target.as_deref().unwrap_or("")),
+            info.bytes_received,
+            info.bytes_sent,
+            info.age.as_millis(),
+            csv_field(close_reason),
+        );
+        if self.tx.try_send(line).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                log::warn!("[Server] Access log is falling behind, {} records dropped", dropped);
+            }
+        }
+    }
+}
+
+/// 取出队列中已有的记录一并写入，队列暂时为空时才 flush
+async fn write_loop(
+    file: tokio::fs::File,
+    mut rx: mpsc::Receiver<String>,
+    is_new: bool,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(file);
+    if is_new {
+        out.write_all(format!("{}\n", HEADER).as_bytes()).await?;
+        out.flush().await?;
+    }
+    let mut lines = Vec::new();
+    while rx.recv_many(&mut lines, 256).await > 0 {
+        for line in lines.drain(..) {
+            out.write_all(line.as_bytes()).await?;
+        }
+        if rx.is_empty() {
+            out.flush().await?;
+        }
+    }
+    out.flush().await
+}
+
+/// 含逗号、引号或换行的字段加引号，内部的引号写两次
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -1,26 +1,77 @@
+use anytls_rs::proxy::transport::{auth_response, AUTH_NONCE_LEN};
+use anytls_rs::util::redact::Redacted;
+use rand::RngCore;
 use sha2::{Digest, Sha256};
 use std::io;
-use tokio::io::AsyncReadExt;
-use tokio_rustls::server::TlsStream;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-pub(crate) fn password_sha256(password: &str) -> [u8; 32] {
-    Sha256::digest(password.as_bytes()).into()
+const AUTH_HEAD_LEN: usize = 32 + 2;
+
+/// 密码哈希只以 [`Redacted`] 形式保存，避免出现在日志中
+pub(crate) fn password_sha256(password: &str) -> Redacted<[u8; 32]> {
+    Redacted::new(Sha256::digest(password.as_bytes()).into())
+}
+
+/// hmac 模式：向客户端发送随机 nonce，返回期望收到的认证响应。
+/// 每个连接的 nonce 不同，重放截获的认证头会被拒绝
+pub(crate) async fn send_challenge<S>(
+    stream: &mut S,
+    expected_password: &Redacted<[u8; 32]>,
+) -> io::Result<Redacted<[u8; 32]>>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut nonce = [0u8; AUTH_NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    stream.write_all(&nonce).await?;
+    stream.flush().await?;
+    Ok(Redacted::new(auth_response(expected_password.expose(), &nonce)))
 }
 
-pub(crate) async fn authenticate(
-    tls_stream: &mut TlsStream<tokio::net::TcpStream>,
-    expected_password: [u8; 32],
-) -> io::Result<bool> {
-    // Auth: sha256(password) + padding_len + padding0
-    let mut auth_head = [0u8; 34];
-    tls_stream.read_exact(&mut auth_head).await?;
-    if auth_head[..32] != expected_password {
-        return Ok(false);
+/// 认证结果；失败时带回已读到的字节，便于转交给回落站点
+pub(crate) enum AuthOutcome {
+    Authenticated,
+    Rejected(Vec<u8>),
+}
+
+/// 读取认证头。超时或提前 EOF 都视为认证失败，而不是连接错误
+pub(crate) async fn authenticate<S>(
+    stream: &mut S,
+    expected_password: &Redacted<[u8; 32]>,
+    timeout: Duration,
+) -> io::Result<AuthOutcome>
+where
+    S: AsyncRead + Unpin,
+{
+    // Auth: sha256(password) + padding_len + padding0，hmac 模式下哈希换成认证响应
+    let mut auth_head = Vec::with_capacity(AUTH_HEAD_LEN);
+    let read_head = async {
+        while auth_head.len() < AUTH_HEAD_LEN {
+            let mut chunk = [0u8; AUTH_HEAD_LEN];
+            let want = AUTH_HEAD_LEN - auth_head.len();
+            let n = stream.read(&mut chunk[..want]).await?;
+            if n == 0 {
+                break;
+            }
+            auth_head.extend_from_slice(&chunk[..n]);
+        }
+        io::Result::Ok(())
+    };
+    match tokio::time::timeout(timeout, read_head).await {
+        Ok(result) => result?,
+        Err(_) => return Ok(AuthOutcome::Rejected(auth_head)),
+    }
+    if auth_head.len() < AUTH_HEAD_LEN || auth_head[..32] != expected_password.expose()[..] {
+        return Ok(AuthOutcome::Rejected(auth_head));
     }
+
     let padding_len = u16::from_be_bytes([auth_head[32], auth_head[33]]) as usize;
     if padding_len > 0 {
         let mut discard = vec![0u8; padding_len];
-        tls_stream.read_exact(&mut discard).await?;
+        tokio::time::timeout(timeout, stream.read_exact(&mut discard))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "authentication timeout"))??;
     }
-    Ok(true)
+    Ok(AuthOutcome::Authenticated)
 }
@@ -0,0 +1,28 @@
+//! 认证失败的连接转交给真实站点，对探测方表现为普通 HTTPS 服务。
+
+use anytls_rs::proxy::relay::copy_bidirectional_with_idle_timeout;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 连接回落站点，先回放认证阶段已读到的 `consumed`，再双向转发
+pub(crate) async fn serve<S>(
+    mut stream: S,
+    consumed: Vec<u8>,
+    site: &str,
+    idle_timeout: Duration,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut upstream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(site))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "fallback connect timeout"))??;
+    upstream.set_nodelay(true)?;
+    upstream.write_all(&consumed).await?;
+    copy_bidirectional_with_idle_timeout(&mut stream, &mut upstream, idle_timeout).await?;
+    Ok(())
+}
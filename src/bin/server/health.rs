@@ -0,0 +1,144 @@
+//! `--health-listen`：供负载均衡器探测的 HTTP 端点，以及滚动发布用的 lame duck 模式。
+//!
+//! `/healthz` 在进程存活时总是返回 200；`/readyz` 在正常服务时返回 200，进入 lame duck 后
+//! 返回 503。收到 SIGUSR1 进入 lame duck：关闭监听端口，不再接受新连接，已建立的 Session
+//! 继续服务，全部结束或收到 SIGTERM 后进程退出，负载均衡器据此把流量摘走。
+
+use anytls_rs::util::accept::AcceptBackoff;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+/// 读取请求头的最长时间
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// 请求头的长度上限，只需要第一行
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// 服务端的就绪状态，在各任务间共享
+#[derive(Clone)]
+pub(crate) struct Readiness {
+    lame_duck: Arc<watch::Sender<bool>>,
+}
+
+impl Readiness {
+    pub(crate) fn new() -> Self {
+        Self { lame_duck: Arc::new(watch::Sender::new(false)) }
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        !*self.lame_duck.borrow()
+    }
+
+    /// 进入 lame duck，重复调用无效
+    pub(crate) fn enter_lame_duck(&self) {
+        if !self.lame_duck.send_replace(true) {
+            log::warn!("[Server] Entering lame duck mode: not ready, refusing new connections");
+        }
+    }
+
+    /// 进入 lame duck 时完成
+    pub(crate) async fn lame_duck_entered(&self) {
+        let mut rx = self.lame_duck.subscribe();
+        // 发送端与 Readiness 一起存活，不会返回错误
+        let _ = rx.wait_for(|lame_duck| *lame_duck).await;
+    }
+}
+
+/// 收到 SIGUSR1 时进入 lame duck
+#[cfg(unix)]
+pub(crate) fn spawn_lame_duck_on_sigusr1(readiness: Readiness) -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut usr1 = signal(SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            readiness.enter_lame_duck();
+        }
+    });
+    Ok(())
+}
+
+/// lame duck 期间等待已建立的 Session 全部关闭，先收到 SIGTERM 时不再等待。
+/// 返回是否等到了排空
+pub(crate) async fn wait_for_drain(drained: impl Future<Output = ()>) -> io::Result<bool> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = drained => Ok(true),
+            _ = term.recv() => Ok(false),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        drained.await;
+        Ok(true)
+    }
+}
+
+/// 在 `addr` 上提供 `/healthz` 与 `/readyz`
+pub(crate) async fn serve(addr: &str, readiness: Readiness) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("[Server] Health endpoint on {}", listener.local_addr()?);
+    tokio::spawn(async move {
+        let mut backoff = AcceptBackoff::new();
+        loop {
+            let (conn, _) = match backoff.accept(|| listener.accept()).await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    log::error!("[Server] Health endpoint failed: {}", e);
+                    return;
+                }
+            };
+            let readiness = readiness.clone();
+            tokio::spawn(async move {
+                if let Err(e) = respond(conn, &readiness).await {
+                    log::debug!("[Server] Health request error: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn respond(mut conn: TcpStream, readiness: &Readiness) -> io::Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request_head(&mut conn))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "health request timed out"))??;
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path {
+        "/healthz" => ("200 OK", "ok\n"),
+        "/readyz" if readiness.is_ready() => ("200 OK", "ready\n"),
+        "/readyz" => ("503 Service Unavailable", "lame duck\n"),
+        _ => ("404 Not Found", "not found\n"),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    conn.write_all(response.as_bytes()).await?;
+    conn.shutdown().await
+}
+
+/// 读到空行或长度上限为止，返回请求头文本
+async fn read_request_head(conn: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_SIZE {
+        let n = conn.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&chunk[..n]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
@@ -0,0 +1,48 @@
+//! 创建监听 socket：自行绑定，或接管 init 系统（systemd、launchd）已绑定的 fd。
+//!
+//! 由 init 系统持有监听 socket 时，重启服务不会拒绝新连接，也不需要以 root 运行来绑定特权端口。
+
+use std::io;
+use tokio::net::TcpListener;
+
+/// systemd 传入的第一个 fd
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// 依次尝试 `--listen-fd`、systemd 的 `LISTEN_FDS`，都没有时绑定 `addr`
+pub(crate) async fn open(addr: &str, listen_fd: Option<i32>) -> io::Result<TcpListener> {
+    #[cfg(unix)]
+    if let Some(fd) = listen_fd.or_else(systemd_listen_fd) {
+        log::info!("[Server] Using inherited listener fd {}", fd);
+        return from_fd(fd);
+    }
+    #[cfg(not(unix))]
+    let _ = listen_fd;
+    TcpListener::bind(addr).await
+}
+
+/// `LISTEN_PID` 指向本进程时返回 systemd 传入的第一个 fd
+#[cfg(unix)]
+fn systemd_listen_fd() -> Option<i32> {
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: u32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    (pid == std::process::id() && fds >= 1).then_some(SD_LISTEN_FDS_START)
+}
+
+#[cfg(unix)]
+fn from_fd(fd: i32) -> io::Result<TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    if fd < 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid listener fd {}", fd),
+        ));
+    }
+    // SAFETY: fd 由父进程交给本进程独占使用，此后只由返回的 listener 持有并关闭
+    let std_listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    std_listener.set_nonblocking(true)?;
+    // 不是监听中的 TCP socket 时这里返回错误，而不是等到 accept 才失败
+    std_listener.local_addr()?;
+    TcpListener::from_std(std_listener)
+}
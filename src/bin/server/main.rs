@@ -1,17 +1,44 @@
+mod access_log;
 mod auth;
+mod fallback;
+mod health;
+mod listen;
+mod privilege;
 mod registry;
 mod stream_handler;
 
-use anytls_rs::proxy::padding::DefaultPaddingFactory;
-use anytls_rs::proxy::session::{Session, Stream};
+use access_log::AccessLog;
+use anytls_rs::config::{self, ServerConfig};
+use anytls_rs::proxy::addr_codec::normalize_addr;
+use anytls_rs::proxy::http_route::HttpRoutes;
+use anytls_rs::proxy::outbound::breaker::{BreakerConfig, CircuitBreaker};
+use anytls_rs::proxy::outbound::socket::{PortRange, SocketOptions};
+use anytls_rs::proxy::padding::{DefaultPaddingFactory, PaddingFactory, PaddingToken};
+use anytls_rs::proxy::proxy_protocol;
+use anytls_rs::proxy::relay::AdaptiveSizes;
+use anytls_rs::proxy::transport::{self, AuthMode};
+use anytls_rs::proxy::session::{
+    Client, Session, SessionConfig, DEFAULT_RECV_WINDOW, MAX_PAYLOAD_SIZE,
+};
+use anytls_rs::util::accept::AcceptBackoff;
+use anytls_rs::util::buffer_pool::BufferPool;
 use anytls_rs::util::mkcert;
+use anytls_rs::util::redact::Redacted;
+use anytls_rs::util::runtime::{self, RuntimeOptions};
+use anytls_rs::util::r#type::AsyncReadWrite;
+use anytls_rs::util::tls::{CipherPreference, TlsClientOptions, TlsServerOptions};
 use anytls_rs::PROGRAM_VERSION_NAME;
-use clap::Parser;
-use log::{debug, error, info};
+use clap::{CommandFactory, Parser};
+use auth::AuthOutcome;
+use log::{debug, error, info, warn};
 use registry::SessionRegistry;
+use stream_handler::StreamOptions;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
-use tokio_rustls::TlsAcceptor;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
 
 #[derive(Parser)]
 #[command(name = "anytls-server")]
@@ -28,88 +55,591 @@ struct Args {
 
     #[arg(long, default_value_t = 1, help = "Keep at least N idle sessions")]
     min_idle_session: usize,
+
+    #[arg(long, default_value_t = 5000, help = "Authentication read timeout in milliseconds")]
+    auth_timeout_ms: u64,
+
+    #[arg(long, default_value = "legacy", help = "Auth mode: legacy|hmac (hmac speaks first)")]
+    auth_mode: AuthMode,
+
+    #[arg(long, default_value_t = 10000, help = "TLS handshake timeout in milliseconds")]
+    tls_handshake_timeout_ms: u64,
+
+    #[arg(long, default_value_t = 5000, help = "Target address read timeout in milliseconds")]
+    target_timeout_ms: u64,
+
+    #[arg(long, default_value_t = 10000, help = "Default target connect timeout in ms")]
+    connect_timeout_ms: u64,
+
+    #[arg(long, default_value_t = 60000, help = "Max client-requested connect timeout in ms")]
+    max_connect_timeout_ms: u64,
+
+    #[arg(long, help = "Expect a PROXY protocol v1/v2 header on every accepted connection")]
+    proxy_protocol: bool,
+
+    #[arg(long, default_value_t = 256, help = "Per-session stream queue (needs --stream-workers)")]
+    accept_backlog: usize,
+
+    #[arg(long, default_value_t = DEFAULT_RECV_WINDOW, help = "Per-stream receive window (bytes)")]
+    recv_window: usize,
+
+    #[arg(long, default_value_t = MAX_PAYLOAD_SIZE, help = "Max payload per data frame (bytes)")]
+    max_payload: usize,
+
+    #[arg(long, default_value_t = 0, help = "Fail a session whose write stalls N ms (0 = off)")]
+    write_timeout_ms: u64,
+
+    #[arg(long, default_value_t = 0, help = "Max unread bytes per session (0 = unlimited)")]
+    max_session_buffer: usize,
+
+    #[arg(long, default_value_t = 0, help = "Max new streams per second per session (0 = off)")]
+    max_syn_rate: u32,
+
+    #[arg(long, default_value_t = 0, help = "Max streams relayed at once (0 = unlimited)")]
+    stream_workers: usize,
+
+    #[arg(long, help = "Load the padding scheme from a file")]
+    padding_scheme: Option<String>,
+
+    #[arg(long, help = "Compress pushed padding schemes for clients that support it")]
+    compress_padding: bool,
+
+    #[arg(long, default_value = "auto", help = "Cipher suite preference: aes|chacha|auto")]
+    cipher_preference: CipherPreference,
+
+    #[arg(long, help = "Require client certificates signed by this CA (PEM)")]
+    client_ca: Option<String>,
+
+    #[arg(long, help = "Append TLS session keys to this file (debugging only, exposes traffic)")]
+    keylog_file: Option<String>,
+
+    #[arg(long, help = "Max TLS record size in bytes, including header (32-16389)")]
+    tls_fragment_size: Option<usize>,
+
+    #[arg(long, help = "Drop TLS connections whose SNI is not this name")]
+    require_sni: Option<String>,
+
+    #[arg(long, help = "Route HTTP/1.x streams by Host header, e.g. example.com=127.0.0.1:8080")]
+    http_route: Vec<String>,
+
+    #[arg(long, help = "Let clients connect to this Unix socket path (repeatable, default: none)")]
+    allow_unix_socket: Vec<String>,
+
+    #[arg(long, default_value_t = 0, help = "Close relays idle for N seconds (0 = off)")]
+    outbound_idle_timeout: u64,
+
+    #[arg(long, default_value_t = 1024, help = "Pooled relay buffers (0 = no pool)")]
+    buffer_pool_size: usize,
+
+    #[arg(long, default_value_t = 16, help = "Size of each pooled relay buffer in KiB")]
+    buffer_size_kb: usize,
+
+    #[arg(long, default_value_t = 4, help = "Initial size of adaptive relay buffers in KiB")]
+    adaptive_buffer_min_kb: usize,
+
+    #[arg(long, default_value_t = 0, help = "Grow relay buffers up to N KiB (0 = fixed buffers)")]
+    adaptive_buffer_max_kb: usize,
+
+    #[arg(long, default_value_t = 0, help = "Reject a target after N connect failures (0 = off)")]
+    breaker_failures: u32,
+
+    #[arg(long, default_value_t = 10, help = "Window in seconds for counting connect failures")]
+    breaker_window_secs: u64,
+
+    #[arg(long, default_value_t = 30, help = "Seconds to reject a failing target before retrying")]
+    breaker_cooldown_secs: u64,
+
+    #[arg(long, help = "SO_SNDBUF in bytes for connections to targets (default: OS)")]
+    so_sndbuf: Option<u32>,
+
+    #[arg(long, help = "SO_RCVBUF in bytes for connections to targets (default: OS)")]
+    so_rcvbuf: Option<u32>,
+
+    #[arg(long, help = "Bind connections to targets to a source port in low-high")]
+    outbound_port_range: Option<PortRange>,
+
+    #[arg(long, help = "Serve /healthz and /readyz on this address; SIGUSR1 enters lame duck")]
+    health_listen: Option<String>,
+
+    #[arg(long, help = "Relay connections that fail authentication to this host:port")]
+    fallback_site: Option<String>,
+
+    #[arg(long, help = "Relay all streams through this upstream AnyTLS server (host:port)")]
+    upstream: Option<String>,
+
+    #[arg(long, help = "Password for --upstream (default: --password)")]
+    upstream_password: Option<String>,
+
+    #[arg(long, help = "TLS SNI for --upstream (default: host of --upstream, empty: no SNI)")]
+    upstream_sni: Option<String>,
+
+    #[arg(long, default_value = "legacy", help = "Auth mode for --upstream (default: legacy)")]
+    upstream_auth_mode: AuthMode,
+
+    #[arg(long, help = "Connect to --upstream over plain TCP (insecure, loopback only)")]
+    upstream_no_tls: bool,
+
+    #[arg(long, conflicts_with = "upstream_no_tls", help = "Verify --upstream cert via OS roots")]
+    upstream_trust_system_roots: bool,
+
+    #[cfg(unix)]
+    #[arg(long, help = "Accept on this already-bound listener fd instead of binding --listen")]
+    listen_fd: Option<i32>,
+
+    #[arg(long, default_value_t = 0, help = "Rotate the self-signed cert every N hours (0 = off)")]
+    cert_rotate_hours: u64,
+
+    #[arg(long, help = "Run sessions over plain TCP without TLS (insecure, loopback only)")]
+    no_tls: bool,
+
+    #[arg(long, help = "Write the server's process id to this file")]
+    pidfile: Option<String>,
+
+    #[arg(long, help = "Append one CSV line per finished stream to this file")]
+    access_log: Option<String>,
+
+    #[cfg(unix)]
+    #[arg(long, help = "Switch to this user after binding the listener")]
+    user: Option<String>,
+
+    #[cfg(unix)]
+    #[arg(long, help = "Switch to this group after binding (default: the user's group)")]
+    group: Option<String>,
+
+    #[arg(long, help = "Tokio worker threads (default: number of CPUs)")]
+    worker_threads: Option<usize>,
+
+    #[arg(long, help = "Run everything on a single-threaded runtime")]
+    current_thread: bool,
+
+    #[arg(long, help = "Load options from a TOML file; command-line flags take precedence")]
+    config: Option<String>,
+}
+
+/// 所有连接共享的服务端配置
+#[derive(Clone)]
+struct ServerContext {
+    /// `None` 时以明文运行（`--no-tls`）
+    tls: Option<Arc<mkcert::RotatingServerConfig>>,
+    expected_password: Redacted<[u8; 32]>,
+    auth_mode: AuthMode,
+    auth_timeout: Duration,
+    tls_handshake_timeout: Duration,
+    proxy_protocol: bool,
+    session_config: SessionConfig,
+    padding: Arc<PaddingFactory>,
+    stream_options: Arc<StreamOptions>,
+    /// 同时处理的 Stream 上限，`None` 时不限制
+    stream_workers: Option<Arc<Semaphore>>,
+    fallback_site: Option<Arc<str>>,
+    registry: SessionRegistry,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// 解析命令行；给出 `--config` 时先合并配置文件中的选项
+fn parse_args() -> std::io::Result<Args> {
+    let cli: Vec<String> = std::env::args().collect();
+    let Some(path) = config::config_path(&cli) else {
+        return Ok(Args::parse_from(cli));
+    };
+    let file: ServerConfig = config::load(&path)?;
+    let argv = config::merge_args(&Args::command(), config::to_args(&file)?, cli);
+    Ok(Args::parse_from(argv))
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let args = Args::parse();
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let runtime_options = RuntimeOptions {
+        worker_threads: args.worker_threads,
+        current_thread: args.current_thread,
+    };
+    let runtime = match runtime_options.build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "[Server] Runtime: {}, {} worker thread(s)",
+        if args.current_thread { "current-thread" } else { "multi-thread" },
+        runtime_options.effective_worker_threads()
+    );
+    runtime.block_on(run(args))
+}
+
+async fn run(args: Args) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     if args.password.is_empty() {
         error!("Please set password");
         std::process::exit(1);
     }
 
+    if args.auth_mode == AuthMode::HmacOrLegacy {
+        return Err("--auth-mode hmac-or-legacy is client-only, use hmac on the server".into());
+    }
+    // hmac 模式在 TLS 握手后立即发送 nonce，探测者先看到 nonce，回落站点无法伪装
+    if args.auth_mode != AuthMode::Legacy && args.fallback_site.is_some() {
+        return Err("--fallback-site cannot be combined with --auth-mode hmac: the nonce the \
+                    server sends first gives it away to probes"
+            .into());
+    }
     let expected_password = auth::password_sha256(&args.password);
 
     info!("[Server] {}", PROGRAM_VERSION_NAME);
     info!("[Server] Listening TCP {}", args.listen);
 
-    let listener = TcpListener::bind(&args.listen).await?;
-    let tls_config = Arc::new(mkcert::generate_key_pair("localhost")?);
-    let tls_acceptor = TlsAcceptor::from(tls_config);
-    let padding = DefaultPaddingFactory::load();
+    if let Some(path) = &args.padding_scheme {
+        load_padding_scheme(path).await?;
+    }
+
+    let http_routes = HttpRoutes::parse(&args.http_route)?;
+    if !http_routes.is_empty() {
+        info!("[Server] HTTP Host routing enabled ({} routes)", args.http_route.len());
+    }
+    if !args.allow_unix_socket.is_empty() {
+        info!("[Server] Unix socket targets allowed: {}", args.allow_unix_socket.join(", "));
+    }
+    let upstream = build_upstream(&args).await?;
+    if upstream.is_some() && !http_routes.is_empty() {
+        warn!("[Server] --http-route is ignored: all streams go to --upstream");
+    }
+
+    #[cfg(unix)]
+    let listen_fd = args.listen_fd;
+    #[cfg(not(unix))]
+    let listen_fd = None;
+    let listener = listen::open(&args.listen, listen_fd).await?;
+    if args.no_tls {
+        let local = listener.local_addr()?;
+        if !local.ip().is_loopback() {
+            let msg = format!("--no-tls is loopback-only, refusing to listen on {}", local);
+            return Err(msg.into());
+        }
+        warn!("[Server] TLS disabled: sessions on {} are accepted in plaintext", local);
+    }
+    if let Some(path) = &args.keylog_file {
+        warn!(
+            "[Server] Writing TLS session keys to {}; anyone with it can decrypt the tunnel",
+            path
+        );
+    }
+    let tls_options = TlsServerOptions {
+        cipher: args.cipher_preference,
+        client_ca: args.client_ca,
+        keylog_file: args.keylog_file,
+        fragment_size: args.tls_fragment_size,
+        require_sni: args.require_sni,
+    };
+    if tls_options.client_ca.is_some() {
+        info!("[Server] TLS client certificate required");
+    }
+    // 要求特定 SNI 时自签名证书也使用该名称
+    let cert_name = tls_options.require_sni.clone().unwrap_or_else(|| "localhost".to_string());
+    if tls_options.require_sni.is_some() {
+        info!("[Server] Only accepting TLS connections with SNI {}", cert_name);
+    }
+    let tls_config = mkcert::RotatingServerConfig::new(&cert_name, tls_options)?;
+    if args.cert_rotate_hours > 0 && !args.no_tls {
+        info!("[Server] Rotating the TLS certificate every {} hours", args.cert_rotate_hours);
+        tls_config.spawn_rotation(Duration::from_secs(args.cert_rotate_hours * 3600));
+    }
     let registry = SessionRegistry::new();
     let session_seq = Arc::new(std::sync::atomic::AtomicU64::new(1));
 
+    let adaptive_buffer = (args.adaptive_buffer_max_kb > 0).then_some(AdaptiveSizes {
+        min: args.adaptive_buffer_min_kb * 1024,
+        max: args.adaptive_buffer_max_kb * 1024,
+    });
+    if let Some(sizes) = &adaptive_buffer {
+        sizes.validate()?;
+        info!(
+            "[Server] Adaptive relay buffers: {}-{} KiB, buffer pool disabled",
+            args.adaptive_buffer_min_kb, args.adaptive_buffer_max_kb
+        );
+    }
+    let buffer_pool = (args.buffer_pool_size > 0 && adaptive_buffer.is_none())
+        .then(|| BufferPool::new(args.buffer_pool_size, args.buffer_size_kb * 1024));
+    if let Some(pool) = &buffer_pool {
+        spawn_buffer_pool_report(Arc::clone(pool));
+    }
+
+    let socket_options = SocketOptions {
+        send_buffer_size: args.so_sndbuf,
+        recv_buffer_size: args.so_rcvbuf,
+        source_ports: args.outbound_port_range,
+    };
+    socket_options.validate()?;
+    if args.so_sndbuf.is_some() || args.so_rcvbuf.is_some() {
+        // 内核可能截断或加倍请求的大小，记录实际生效的值
+        let (sndbuf, rcvbuf) = socket_options.effective()?;
+        info!("[Server] Outbound socket buffers: SO_SNDBUF={} SO_RCVBUF={}", sndbuf, rcvbuf);
+    }
+    if let Some(range) = socket_options.source_ports {
+        info!("[Server] Outbound source ports: {}", range);
+    }
+
+    // 在降权之前打开，文件可以位于只有 root 可写的目录
+    let access_log = args.access_log.as_ref().map(AccessLog::open).transpose()?;
+
     registry.spawn_idle_cleanup(args.idle_session_timeout * 1000, args.min_idle_session);
 
+    let ctx = ServerContext {
+        tls: (!args.no_tls).then_some(tls_config),
+        expected_password,
+        auth_mode: args.auth_mode,
+        auth_timeout: Duration::from_millis(args.auth_timeout_ms),
+        tls_handshake_timeout: Duration::from_millis(args.tls_handshake_timeout_ms),
+        proxy_protocol: args.proxy_protocol,
+        session_config: SessionConfig {
+            accept_backlog: Some(args.accept_backlog),
+            recv_window: args.recv_window,
+            max_payload: args.max_payload,
+            write_timeout: Duration::from_millis(args.write_timeout_ms),
+            max_buffered: args.max_session_buffer,
+            max_syn_rate: args.max_syn_rate,
+            compress_padding: args.compress_padding,
+            ..SessionConfig::default()
+        },
+        padding: DefaultPaddingFactory::load(),
+        stream_workers: (args.stream_workers > 0)
+            .then(|| Arc::new(Semaphore::new(args.stream_workers))),
+        stream_options: Arc::new(StreamOptions {
+            http_routes,
+            outbound_idle_timeout: Duration::from_secs(args.outbound_idle_timeout),
+            buffer_pool,
+            adaptive_buffer,
+            breaker: (args.breaker_failures > 0).then(|| {
+                CircuitBreaker::new(BreakerConfig {
+                    failure_threshold: args.breaker_failures,
+                    window: Duration::from_secs(args.breaker_window_secs),
+                    cooldown: Duration::from_secs(args.breaker_cooldown_secs),
+                })
+            }),
+            socket: socket_options,
+            target_timeout: Duration::from_millis(args.target_timeout_ms),
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            max_connect_timeout: Duration::from_millis(args.max_connect_timeout_ms),
+            access_log,
+            allowed_unix_sockets: args.allow_unix_socket.iter().map(PathBuf::from).collect(),
+            upstream,
+        }),
+        fallback_site: args.fallback_site.map(Arc::from),
+        registry,
+    };
+    if ctx.proxy_protocol {
+        info!("[Server] PROXY protocol header required");
+    }
+    if let Some(site) = &ctx.fallback_site {
+        info!("[Server] Unauthenticated connections fall back to {}", site);
+    }
+
+    // 监听端口与证书都已就绪，之后不再需要特权
+    if let Some(path) = &args.pidfile {
+        privilege::write_pidfile(path)?;
+    }
+    #[cfg(unix)]
+    privilege::drop_privileges(args.user.as_deref(), args.group.as_deref())?;
+
+    let readiness = health::Readiness::new();
+    #[cfg(unix)]
+    health::spawn_lame_duck_on_sigusr1(readiness.clone())?;
+    if let Some(addr) = &args.health_listen {
+        health::serve(addr, readiness.clone()).await?;
+    }
+
+    let mut backoff = AcceptBackoff::new();
     loop {
-        let (stream, peer) = listener.accept().await?;
-        let tls_acceptor = tls_acceptor.clone();
-        let padding = padding.clone();
-        let registry = registry.clone();
-        let expected = expected_password;
+        let accepted = tokio::select! {
+            accepted = backoff.accept(|| listener.accept()) => accepted,
+            _ = readiness.lame_duck_entered() => break,
+        };
+        let (stream, peer) = match accepted {
+            // 双栈监听时 IPv4 客户端显示为 ::ffff:a.b.c.d，统一成 IPv4
+            Ok((stream, peer)) => (stream, normalize_addr(peer)),
+            Err(e) => {
+                error!("[Server] Listener failed: {}", e);
+                return Err(e.into());
+            }
+        };
+        let ctx = ctx.clone();
         let session_id = session_seq.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(
-                stream,
-                tls_acceptor,
-                expected,
-                padding,
-                registry,
-                session_id,
-            )
-            .await
-            {
+        let context = format!("[Server] Connection {} (session {})", peer, session_id);
+        runtime::spawn_guarded(context, async move {
+            if let Err(e) = handle_connection(stream, peer, ctx, session_id).await {
                 debug!("[Server] Connection {} error: {}", peer, e);
             }
         });
     }
+
+    // lame duck：关闭监听端口，已建立的 Session 在各自的任务中继续服务，
+    // 全部关闭或收到 SIGTERM 后退出。此时仍在认证的连接随进程一起结束
+    drop(listener);
+    let open = ctx.registry.snapshot().await.len();
+    info!("[Server] Lame duck: waiting for {} session(s) to close", open);
+    if health::wait_for_drain(ctx.registry.drained()).await? {
+        info!("[Server] Lame duck: all sessions closed, exiting");
+    } else {
+        info!("[Server] Lame duck: SIGTERM received, exiting");
+    }
+    Ok(())
+}
+
+/// `--upstream` 对应的客户端：本进程既接受 Session，又作为客户端把 Stream 转发给上游
+async fn build_upstream(args: &Args) -> std::io::Result<Option<Client>> {
+    let Some(server) = args.upstream.clone() else {
+        return Ok(None);
+    };
+    let password = args.upstream_password.as_deref().unwrap_or(&args.password);
+    let password_sha256 = transport::password_sha256(password);
+    let padding = DefaultPaddingFactory::load();
+    let dial_out = if args.upstream_no_tls {
+        transport::require_loopback(&server).await?;
+        warn!("[Server] Upstream TLS disabled: sessions to {} are sent in plaintext", server);
+        transport::create_plain_dial_out_func(
+            server.clone(),
+            password_sha256,
+            padding.clone(),
+            args.upstream_auth_mode,
+        )
+    } else {
+        // 默认与 anytls-client 相同，接受任何证书；中转与出口之间的链路需要校验时打开
+        let tls_options = TlsClientOptions {
+            trust_system_roots: args.upstream_trust_system_roots,
+            ..TlsClientOptions::default()
+        };
+        transport::create_dial_out_func(
+            server.clone(),
+            transport::create_tls_config(&tls_options)?,
+            args.upstream_sni.clone(),
+            password_sha256,
+            padding.clone(),
+            args.upstream_auth_mode,
+        )
+    };
+    info!("[Server] Relaying all streams through upstream {}", server);
+    Ok(Some(Client::builder(dial_out, padding).build()))
+}
+
+/// 定期输出缓冲区池命中率
+fn spawn_buffer_pool_report(pool: Arc<BufferPool>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let stats = pool.stats();
+            info!(
+                "[Server] Buffer pool: hits={} misses={} idle={}",
+                stats.hits, stats.misses, stats.idle
+            );
+        }
+    });
+}
+
+async fn load_padding_scheme(path: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let raw = std::fs::read(path)?;
+    if !DefaultPaddingFactory::update(&raw).await {
+        return Err(format!("invalid padding scheme in {}", path).into());
+    }
+    let padding = DefaultPaddingFactory::load();
+    info!("[Server] Padding scheme loaded from {}:\n{}", path, padding);
+    for (pkt, tokens) in padding.describe() {
+        for token in tokens {
+            if let PaddingToken::Range(min, max) = token {
+                if min > max {
+                    warn!("[Server] Padding packet {} has inverted range {}", pkt, token);
+                }
+            }
+        }
+    }
+    Ok(())
 }
 
 async fn handle_connection(
-    stream: TcpStream,
-    acceptor: TlsAcceptor,
-    expected_password: [u8; 32],
-    padding: Arc<anytls_rs::proxy::padding::PaddingFactory>,
-    registry: SessionRegistry,
+    mut stream: TcpStream,
+    mut peer: SocketAddr,
+    ctx: ServerContext,
     session_id: u64,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut tls_stream = acceptor.accept(stream).await?;
-    if !auth::authenticate(&mut tls_stream, expected_password).await? {
+    if ctx.proxy_protocol {
+        let header = tokio::time::timeout(
+            ctx.auth_timeout,
+            proxy_protocol::read_proxy_header(&mut stream),
+        )
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "PROXY header timeout"))??;
+        if let Some(source) = header {
+            debug!("[Server] PROXY header: {} => {}", peer, source);
+            peer = normalize_addr(source);
+        }
+    }
+
+    let mut conn: Box<dyn AsyncReadWrite> = match &ctx.tls {
+        // 握手完成之前不分配任何认证相关的资源
+        Some(tls) => Box::new(
+            tokio::time::timeout(ctx.tls_handshake_timeout, tls.accept(stream))
+                .await
+                .map_err(|_| {
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, "TLS handshake timeout")
+                })??,
+        ),
+        None => Box::new(stream),
+    };
+    let expected = match ctx.auth_mode {
+        AuthMode::Legacy => ctx.expected_password,
+        AuthMode::Hmac | AuthMode::HmacOrLegacy => {
+            auth::send_challenge(&mut conn, &ctx.expected_password).await?
+        }
+    };
+    let outcome = auth::authenticate(&mut conn, &expected, ctx.auth_timeout).await?;
+    if let AuthOutcome::Rejected(consumed) = outcome {
+        debug!("[Server] Authentication failed from {}", peer);
+        if let Some(site) = &ctx.fallback_site {
+            let idle = ctx.stream_options.outbound_idle_timeout;
+            fallback::serve(conn, consumed, site, idle).await?;
+        }
         return Ok(());
     }
 
-    info!("[Server] Authentication successful");
+    info!("[Server] Authentication successful from {}", peer);
+
+    let on_close = ctx.registry.make_on_close(session_id);
 
-    let on_new_stream: Arc<dyn Fn(Stream) + Send + Sync> = Arc::new(|stream| {
-        tokio::spawn(async move {
-            if let Err(e) = stream_handler::handle_stream(stream).await {
+    let session = Arc::new(
+        Session::new_server(conn, None, Some(on_close), ctx.padding, ctx.session_config),
+    );
+    let mut incoming = session
+        .incoming()
+        .expect("incoming receiver taken once per session");
+    ctx.registry.insert(session_id, Arc::clone(&session)).await;
+    session.run().await?;
+    // 不持有 Session，关闭后队列发送端随之释放，下面的循环才会结束
+    drop(session);
+
+    while let Some(stream) = incoming.recv().await {
+        // 没有空闲名额时停止取出 Stream，队列满后新的 SYN 被拒绝
+        let permit = match &ctx.stream_workers {
+            Some(workers) => match Arc::clone(workers).acquire_owned().await {
+                Ok(permit) => Some(permit),
+                Err(_) => break,
+            },
+            None => None,
+        };
+        let options = Arc::clone(&ctx.stream_options);
+        let context = format!("[Server] Stream {} of {}", stream.id(), peer);
+        runtime::spawn_guarded(context, async move {
+            let _permit = permit;
+            if let Err(e) = stream_handler::handle_stream(stream, options).await {
                 debug!("[Server] Stream handler error: {}", e);
             }
         });
-    });
-
-    let on_close = registry.make_on_close(session_id);
-
-    let session = Arc::new(Session::new_server(
-        Box::new(tls_stream),
-        Some(on_new_stream),
-        Some(on_close),
-        padding,
-    ));
-    registry.insert(session_id, Arc::clone(&session)).await;
-    session.run().await?;
+    }
     Ok(())
 }
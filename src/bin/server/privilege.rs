@@ -0,0 +1,61 @@
+//! 部署相关：写 pidfile，以及绑定端口后切换到非特权用户。
+//!
+//! 以 root 启动以便绑定 443 等特权端口，监听 socket 建立后再 setgid/setuid；
+//! pidfile 在切换前写入，这样可以放在只有 root 可写的 /run 下。
+
+use std::io;
+
+/// 写入当前进程号，已存在时覆盖
+pub(crate) fn write_pidfile(path: &str) -> io::Result<()> {
+    std::fs::write(path, format!("{}\n", std::process::id()))
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to write pidfile {}: {}", path, e)))
+}
+
+/// 切换到 `user`/`group`。只给出用户时使用其主组；先切换组，之后才放弃 root
+#[cfg(unix)]
+pub(crate) fn drop_privileges(user: Option<&str>, group: Option<&str>) -> io::Result<()> {
+    use nix::unistd::{setgid, setgroups, setuid, Gid, Group, User};
+
+    if user.is_none() && group.is_none() {
+        return Ok(());
+    }
+    let user = match user {
+        Some(name) => Some(
+            User::from_name(name)
+                .map_err(|e| privilege_error(format!("failed to look up user {}: {}", name, e)))?
+                .ok_or_else(|| privilege_error(format!("unknown user {}", name)))?,
+        ),
+        None => None,
+    };
+    let gid = match group {
+        Some(name) => {
+            Group::from_name(name)
+                .map_err(|e| privilege_error(format!("failed to look up group {}: {}", name, e)))?
+                .ok_or_else(|| privilege_error(format!("unknown group {}", name)))?
+                .gid
+        }
+        None => user.as_ref().map(|u| u.gid).expect("user or group is set"),
+    };
+
+    // 只有 root 能清空附加组；非 root 调用时保持原样，由 setgid 报告权限不足
+    if nix::unistd::geteuid().is_root() {
+        setgroups(&[gid])
+            .map_err(|e| privilege_error(format!("failed to drop supplementary groups: {}", e)))?;
+    }
+    setgid(gid).map_err(|e| privilege_error(format!("failed to setgid({}): {}", gid, e)))?;
+    if let Some(user) = user {
+        setuid(user.uid)
+            .map_err(|e| privilege_error(format!("failed to setuid({}): {}", user.uid, e)))?;
+    }
+    log::info!(
+        "[Server] Running as uid {} gid {}",
+        nix::unistd::getuid(),
+        Gid::current()
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+fn privilege_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, msg)
+}
@@ -2,25 +2,41 @@ use anytls_rs::proxy::session::Session;
 use log::info;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use tokio::time::{interval, Duration};
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub(crate) struct SessionRegistry {
     inner: Arc<Mutex<HashMap<u64, Arc<Session>>>>,
+    /// 登记中的 Session 数，供 lame duck 等待排空
+    count: Arc<watch::Sender<usize>>,
 }
 
 impl SessionRegistry {
     pub(crate) fn new() -> Self {
-        Self::default()
+        Self {
+            inner: Arc::default(),
+            count: Arc::new(watch::Sender::new(0)),
+        }
     }
 
     pub(crate) async fn insert(&self, id: u64, session: Arc<Session>) {
-        self.inner.lock().await.insert(id, session);
+        let mut map = self.inner.lock().await;
+        map.insert(id, session);
+        self.count.send_replace(map.len());
     }
 
     pub(crate) async fn remove(&self, id: u64) {
-        self.inner.lock().await.remove(&id);
+        let mut map = self.inner.lock().await;
+        map.remove(&id);
+        self.count.send_replace(map.len());
+    }
+
+    /// 所有登记的 Session 都关闭后完成
+    pub(crate) async fn drained(&self) {
+        let mut rx = self.count.subscribe();
+        // 发送端与 registry 一起存活，不会返回错误
+        let _ = rx.wait_for(|count| *count == 0).await;
     }
 
     pub(crate) async fn snapshot(&self) -> Vec<(u64, Arc<Session>)> {
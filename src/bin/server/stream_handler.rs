@@ -1,39 +1,85 @@
-use anytls_rs::proxy::addr_codec::read_socks_addr;
-use anytls_rs::proxy::session::Stream;
+use crate::access_log::AccessLog;
+use anytls_rs::proxy::addr_codec::{read_target_header, AddressType, SocksAddr, StreamType};
+use anytls_rs::proxy::http_route::HttpRoutes;
+use anytls_rs::proxy::outbound::breaker::CircuitBreaker;
+use anytls_rs::proxy::outbound::socket::SocketOptions;
+use anytls_rs::proxy::relay::{
+    copy_bidirectional_adaptive, copy_bidirectional_pooled, copy_bidirectional_with_idle_timeout,
+    AdaptiveSizes,
+};
+use anytls_rs::proxy::session::{Client, Stream};
 use anytls_rs::proxy::uot;
-use tokio::io::copy_bidirectional;
+use anytls_rs::util::buffer_pool::BufferPool;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
 use tokio::net::UdpSocket;
 
 const UOT_DEST_HOST_SUFFIX: &str = "udp-over-tcp.arpa";
+/// 等待请求头的单次读取超时，超时后按 SOCKS 目标处理
+const HTTP_ROUTE_PEEK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// 转发 Stream 时使用的服务端配置
+pub(crate) struct StreamOptions {
+    pub(crate) http_routes: HttpRoutes,
+    /// 目标连接两个方向都没有数据的最长时间，0 表示不限制
+    pub(crate) outbound_idle_timeout: Duration,
+    /// 所有连接共享的转发缓冲区，`None` 时使用 tokio 自带的缓冲
+    pub(crate) buffer_pool: Option<Arc<BufferPool>>,
+    /// 按流量自动调整大小的转发缓冲区，设置时不使用缓冲区池
+    pub(crate) adaptive_buffer: Option<AdaptiveSizes>,
+    /// 按目标熔断持续失败的上游，`None` 时不启用
+    pub(crate) breaker: Option<CircuitBreaker>,
+    /// 连接目标时设置的 socket 缓冲区大小
+    pub(crate) socket: SocketOptions,
+    /// 新建 Stream 后等待目标地址的最长时间，超时关闭 Stream
+    pub(crate) target_timeout: Duration,
+    /// 客户端未指定时连接目标的超时
+    pub(crate) connect_timeout: Duration,
+    /// 客户端指定的连接超时上限
+    pub(crate) max_connect_timeout: Duration,
+    /// 每个结束的 Stream 追加一行记录，`None` 时不记录
+    pub(crate) access_log: Option<AccessLog>,
+    /// 允许连接的 Unix socket 路径，为空时拒绝所有 `ATYP = 0x7F` 目标
+    pub(crate) allowed_unix_sockets: Vec<PathBuf>,
+    /// 设置时所有 Stream 经这个上游 AnyTLS 服务端转发，不在本机连接目标
+    pub(crate) upstream: Option<Client>,
+}
 
 async fn handle_uot_stream(
-    mut stream: Stream,
+    stream: &mut Stream,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let request = uot::read_request(&mut stream).await?;
+    let request = uot::read_request(stream).await?;
     log::debug!(
         "[Server][UOT] request is_connect={}, destination={}:{}",
         request.is_connect,
         request.destination.host,
         request.destination.port
     );
-    let is_connect = request.is_connect;
-    let fixed_destination = request.destination;
+    relay_datagrams(stream, request.is_connect.then_some(request.destination)).await
+}
+
+/// 在 Stream 与 UDP socket 之间转发数据报，每个数据报编码为 `LEN(2) + DATA`。
+/// `fixed_destination` 为空时每个数据报前还带有 UOT 格式的目标地址，响应同样带上来源地址
+async fn relay_datagrams(
+    stream: &mut Stream,
+    fixed_destination: Option<SocksAddr>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let is_connect = fixed_destination.is_some();
     let udp_socket = UdpSocket::bind("0.0.0.0:0").await?;
     let mut buf = vec![0u8; 65535];
 
     loop {
-        let destination = if is_connect {
-            fixed_destination.clone()
-        } else {
-            match uot::read_uot_addr_port(&mut stream).await {
+        let destination = match &fixed_destination {
+            Some(destination) => destination.clone(),
+            None => match uot::read_uot_addr_port(stream).await {
                 Ok(v) => v,
                 Err(e) => {
                     log::debug!("[Server][UOT] read addr failed: {}", e);
                     break;
                 }
-            }
+            },
         };
 
         let len = match stream.read_u16().await {
@@ -66,7 +112,7 @@ async fn handle_uot_stream(
         if let Ok(Ok((n, src))) = recv_res {
             log::debug!("[Server][UOT] udp response {} bytes from {}", n, src);
             if !is_connect {
-                let src_addr = match src {
+                let src_addr = match anytls_rs::proxy::addr_codec::normalize_addr(src) {
                     std::net::SocketAddr::V4(v4) => anytls_rs::proxy::addr_codec::SocksAddr {
                         atyp: anytls_rs::proxy::addr_codec::AddressType::Ipv4,
                         host: v4.ip().to_string(),
@@ -78,7 +124,7 @@ async fn handle_uot_stream(
                         port: v6.port(),
                     },
                 };
-                uot::write_uot_addr_port(&mut stream, &src_addr).await?;
+                uot::write_uot_addr_port(stream, &src_addr).await?;
             }
             stream.write_u16(n as u16).await?;
             stream.write_all(&buf[..n]).await?;
@@ -90,27 +136,179 @@ async fn handle_uot_stream(
     Ok(())
 }
 
+/// 转发到服务端本机的 Unix socket，不经过 HTTP 路由与熔断。
+/// 只允许 `--allow-unix-socket` 列出的路径，`/var/run/docker.sock` 之类的 socket 等同于 root 权限
+#[cfg(unix)]
+async fn handle_unix_stream(
+    stream: &mut Stream,
+    path: &str,
+    options: &StreamOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !options.allowed_unix_sockets.iter().any(|allowed| allowed == std::path::Path::new(path)) {
+        let msg = format!("unix socket {} is not in --allow-unix-socket", path);
+        return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, msg).into());
+    }
+    let mut target_conn = tokio::net::UnixStream::connect(path).await?;
+    relay(stream, &mut target_conn, options).await?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn handle_unix_stream(
+    _stream: &mut Stream,
+    path: &str,
+    _options: &StreamOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    Err(format!("unix socket target {} is not supported on this platform", path).into())
+}
+
+/// 在 Stream 与目标连接之间双向转发，直到任一方向结束
+async fn relay<T>(
+    stream: &mut Stream,
+    target_conn: &mut T,
+    options: &StreamOptions,
+) -> std::io::Result<()>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let idle_timeout = options.outbound_idle_timeout;
+    let relayed = match (options.adaptive_buffer, &options.buffer_pool) {
+        (Some(sizes), _) => {
+            copy_bidirectional_adaptive(stream, target_conn, idle_timeout, sizes).await
+        }
+        (None, Some(pool)) => {
+            copy_bidirectional_pooled(stream, target_conn, idle_timeout, pool).await
+        }
+        (None, None) => {
+            copy_bidirectional_with_idle_timeout(stream, target_conn, idle_timeout).await
+        }
+    };
+    let (up, down) = relayed?;
+    log::debug!(
+        "[Server] relay completed: stream->target={} bytes, target->stream={} bytes",
+        up, down
+    );
+    Ok(())
+}
+
 pub(crate) async fn handle_stream(
     mut stream: Stream,
+    options: Arc<StreamOptions>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let target = read_socks_addr(&mut stream).await?.to_host_port();
-    log::info!("[Server] Proxy to {}", target);
+    // 超时返回后 Stream 被丢弃，向对端发送 FIN
+    let header = tokio::time::timeout(options.target_timeout, read_target_header(&mut stream))
+        .await
+        .map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::TimedOut, "target address read timed out")
+        })??;
+    let connect_timeout = header.connect_timeout.map_or(options.connect_timeout, |requested| {
+        requested.min(options.max_connect_timeout)
+    });
+    let addr = header.addr;
+    let target = addr.to_host_port();
+    log::info!("[Server] Proxy to {} ({:?})", target, header.stream_type);
+    stream.set_target(target.as_str());
 
-    if target.contains(UOT_DEST_HOST_SUFFIX) {
-        return handle_uot_stream(stream).await;
+    let result = if let Some(upstream) = &options.upstream {
+        proxy_upstream(&mut stream, upstream, &addr, header.stream_type, connect_timeout, &options)
+            .await
+    } else if header.stream_type == StreamType::Udp {
+        if addr.atyp == AddressType::Unix {
+            Err(format!("UDP is not supported for unix socket target {}", addr.host).into())
+        } else {
+            relay_datagrams(&mut stream, Some(addr)).await
+        }
+    } else if addr.atyp == AddressType::Unix {
+        handle_unix_stream(&mut stream, &addr.host, &options).await
+    } else if target.contains(UOT_DEST_HOST_SUFFIX) {
+        handle_uot_stream(&mut stream).await
+    } else {
+        proxy_tcp(&mut stream, &target, connect_timeout, &options).await
+    };
+    if let Some(access_log) = &options.access_log {
+        let reason = match &result {
+            Ok(()) => "done".to_string(),
+            Err(e) => e.to_string(),
+        };
+        access_log.record(&stream.info(), &reason);
     }
+    result
+}
 
-    let mut target_conn = TcpStream::connect(&target).await?;
-    match copy_bidirectional(&mut stream, &mut target_conn).await {
-        Ok((up, down)) => {
-            log::debug!(
-                "[Server] relay completed: stream->target={} bytes, target->stream={} bytes",
-                up, down
-            );
+/// 在上游服务端上新建同类型的 Stream 并转发，目标地址与连接超时原样交给上游。
+/// UOT 请求与 Unix socket 目标也由上游处理
+async fn proxy_upstream(
+    stream: &mut Stream,
+    upstream: &Client,
+    addr: &SocksAddr,
+    stream_type: StreamType,
+    connect_timeout: Duration,
+    options: &StreamOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let target = addr.to_host_port();
+    let connect = async {
+        match stream_type {
+            StreamType::Tcp => upstream.connect_with_timeout(&target, Some(connect_timeout)).await,
+            StreamType::Udp => upstream.connect_udp(&target).await,
         }
-        Err(e) => {
-            log::debug!("[Server] relay error: {}", e);
+    };
+    let mut upstream_stream = tokio::time::timeout(connect_timeout, connect)
+        .await
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("upstream stream to {} timed out after {:?}", target, connect_timeout),
+            ))
+        })?;
+    relay(stream, &mut upstream_stream, options).await?;
+    Ok(())
+}
+
+/// 连接 TCP 目标（或匹配的 HTTP 路由后端）并转发
+async fn proxy_tcp(
+    stream: &mut Stream,
+    target: &str,
+    connect_timeout: Duration,
+    options: &StreamOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let routes = &options.http_routes;
+    let (prefix, backend) = if routes.is_empty() {
+        (Vec::new(), None)
+    } else {
+        routes.route(stream, HTTP_ROUTE_PEEK_TIMEOUT).await?
+    };
+    let dial = match &backend {
+        Some(backend) => {
+            log::info!("[Server] HTTP route {} -> {}", target, backend);
+            backend.as_str()
         }
+        None => target,
+    };
+
+    if let Some(breaker) = &options.breaker {
+        if !breaker.allow(dial) {
+            let msg = format!("upstream {} circuit open", dial);
+            return Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, msg).into());
+        }
+    }
+    let connected = tokio::time::timeout(connect_timeout, options.socket.connect(dial))
+        .await
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("connect to {} timed out after {:?}", dial, connect_timeout),
+            ))
+        });
+    if let Some(breaker) = &options.breaker {
+        match &connected {
+            Ok(_) => breaker.record_success(dial),
+            Err(_) => breaker.record_failure(dial),
+        }
+    }
+    let mut target_conn = connected?;
+    if !prefix.is_empty() {
+        target_conn.write_all(&prefix).await?;
     }
+    relay(stream, &mut target_conn, options).await?;
     Ok(())
 }
@@ -0,0 +1,195 @@
+//! `--config <file>` 使用的 TOML 配置文件。
+//!
+//! 配置项与命令行参数一一对应，键名就是去掉 `--` 的长参数名，例如 `recv-window = 65536`。
+//! 加载后把文件中的选项转换成命令行参数，插在真实命令行参数之前交给 clap 解析，
+//! 因此取值校验与默认值都和命令行一致；命令行中出现过的选项忽略文件中的值。
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+/// `anytls-client` 的配置文件，省略的键使用命令行参数或其默认值
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ClientConfig {
+    pub listen: Option<String>,
+    pub server: Option<String>,
+    pub sni: Option<String>,
+    pub password: Option<String>,
+    pub auth_mode: Option<String>,
+    pub idle_timeout_secs: Option<u64>,
+    pub min_idle_sessions: Option<usize>,
+    pub max_idle_sessions: Option<usize>,
+    pub recv_window: Option<usize>,
+    pub max_payload: Option<usize>,
+    pub write_timeout_ms: Option<u64>,
+    pub padding_idle_reset_ms: Option<u64>,
+    pub max_padding_bytes: Option<usize>,
+    pub connect_timeout_ms: Option<u64>,
+    pub no_tls: Option<bool>,
+    pub report_platform: Option<bool>,
+    pub cipher_preference: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub keylog_file: Option<String>,
+    pub tls_fragment_size: Option<usize>,
+    pub trust_system_roots: Option<bool>,
+    pub probe: Option<bool>,
+    pub probe_target: Option<String>,
+    pub direct_fallback: Option<bool>,
+    pub fallback_allow: Option<Vec<String>>,
+    pub worker_threads: Option<usize>,
+    pub current_thread: Option<bool>,
+}
+
+/// `anytls-server` 的配置文件，省略的键使用命令行参数或其默认值
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ServerConfig {
+    pub listen: Option<String>,
+    pub password: Option<String>,
+    pub auth_mode: Option<String>,
+    pub tls_handshake_timeout_ms: Option<u64>,
+    pub idle_session_timeout: Option<u64>,
+    pub min_idle_session: Option<usize>,
+    pub auth_timeout_ms: Option<u64>,
+    pub target_timeout_ms: Option<u64>,
+    pub connect_timeout_ms: Option<u64>,
+    pub max_connect_timeout_ms: Option<u64>,
+    pub proxy_protocol: Option<bool>,
+    pub accept_backlog: Option<usize>,
+    pub recv_window: Option<usize>,
+    pub max_payload: Option<usize>,
+    pub write_timeout_ms: Option<u64>,
+    pub max_session_buffer: Option<usize>,
+    pub max_syn_rate: Option<u32>,
+    pub stream_workers: Option<usize>,
+    pub padding_scheme: Option<String>,
+    pub compress_padding: Option<bool>,
+    pub cipher_preference: Option<String>,
+    pub client_ca: Option<String>,
+    pub keylog_file: Option<String>,
+    pub tls_fragment_size: Option<usize>,
+    pub require_sni: Option<String>,
+    pub http_route: Option<Vec<String>>,
+    pub allow_unix_socket: Option<Vec<String>>,
+    pub outbound_idle_timeout: Option<u64>,
+    pub buffer_pool_size: Option<usize>,
+    pub buffer_size_kb: Option<usize>,
+    pub adaptive_buffer_min_kb: Option<usize>,
+    pub adaptive_buffer_max_kb: Option<usize>,
+    pub breaker_failures: Option<u32>,
+    pub breaker_window_secs: Option<u64>,
+    pub breaker_cooldown_secs: Option<u64>,
+    pub so_sndbuf: Option<u32>,
+    pub so_rcvbuf: Option<u32>,
+    pub outbound_port_range: Option<String>,
+    pub health_listen: Option<String>,
+    pub fallback_site: Option<String>,
+    pub upstream: Option<String>,
+    pub upstream_password: Option<String>,
+    pub upstream_sni: Option<String>,
+    pub upstream_auth_mode: Option<String>,
+    pub upstream_no_tls: Option<bool>,
+    pub upstream_trust_system_roots: Option<bool>,
+    #[cfg(unix)]
+    pub listen_fd: Option<i32>,
+    pub cert_rotate_hours: Option<u64>,
+    pub no_tls: Option<bool>,
+    pub pidfile: Option<String>,
+    pub access_log: Option<String>,
+    #[cfg(unix)]
+    pub user: Option<String>,
+    #[cfg(unix)]
+    pub group: Option<String>,
+    pub worker_threads: Option<usize>,
+    pub current_thread: Option<bool>,
+}
+
+/// 配置文件中的一个选项：长参数名与转换出的命令行参数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigArg {
+    pub long: String,
+    pub argv: Vec<String>,
+}
+
+/// 读取并校验配置文件，类型不符或出现未知的键时错误信息中给出该键
+pub fn load<T: DeserializeOwned>(path: impl AsRef<Path>) -> io::Result<T> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        io::Error::new(e.kind(), format!("failed to read config {}: {}", path.display(), e))
+    })?;
+    toml::from_str(&text).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid config {}: {}", path.display(), e.to_string().trim_end()),
+        )
+    })
+}
+
+/// 把配置转换成 `--key=value` 形式的命令行参数，值以 `-` 开头时也不会被当成参数名。
+/// 布尔值为 true 时只写参数名，列表每个元素各写一次
+pub fn to_args<T: Serialize>(config: &T) -> io::Result<Vec<ConfigArg>> {
+    let table = toml::Table::try_from(config).map_err(io::Error::other)?;
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let flag = |value| format!("--{}={}", key, plain_string(value));
+        let argv = match value {
+            toml::Value::Boolean(true) => vec![format!("--{}", key)],
+            toml::Value::Boolean(false) => continue,
+            toml::Value::Array(items) => items.into_iter().map(flag).collect(),
+            value => vec![flag(value)],
+        };
+        args.push(ConfigArg { long: key, argv });
+    }
+    Ok(args)
+}
+
+fn plain_string(value: toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s,
+        value => value.to_string(),
+    }
+}
+
+/// 取出命令行中 `--config` 指定的路径
+pub fn config_path(cli: &[String]) -> Option<String> {
+    let mut iter = cli.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = iter.next() {
+        if arg == "--config" {
+            return iter.next().cloned();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+/// 把文件中的选项插在命令行参数之前，命令行中已经给出的选项（含短参数）不再从文件取值
+pub fn merge_args(command: &clap::Command, file: Vec<ConfigArg>, cli: Vec<String>) -> Vec<String> {
+    let given = |long: &str| {
+        let short = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long))
+            .and_then(|arg| arg.get_short());
+        cli.iter().skip(1).take_while(|arg| *arg != "--").any(|arg| {
+            match arg.strip_prefix("--") {
+                Some(rest) => rest.split('=').next() == Some(long),
+                None => {
+                    let short_given = arg.strip_prefix('-').and_then(|rest| rest.chars().next());
+                    short.is_some() && short_given == short
+                }
+            }
+        })
+    };
+    let mut merged: Vec<String> = cli.iter().take(1).cloned().collect();
+    for arg in file {
+        if !given(&arg.long) {
+            merged.extend(arg.argv);
+        }
+    }
+    merged.extend(cli.into_iter().skip(1));
+    merged
+}
@@ -1,13 +1,77 @@
 //! RFC1928 address codec (`ATYP + ADDR + PORT`).
+//!
+//! 除 SOCKS5 的三种地址外，AnyTLS 扩展了 `ATYP = 0x7F` 表示服务端本机的 Unix socket：
+//! `0x7F + LEN(1) + PATH + PORT(2)`，端口固定为 0，只为保持与其他地址相同的布局。
+//!
+//! 客户端可以在地址前加 `0x7E + TIMEOUT_MS(2)` 指定这个 Stream 的连接超时，
+//! 服务端按自己的上限截断；不带前缀时使用服务端的默认值。
+//!
+//! 最前面还可以加 `0x7D + TYPE(1)` 指定 Stream 类型：`0x01` TCP，`0x02` UDP 数据报转发。
+//! 不带前缀时为 TCP，与旧版本的地址头相同。
+//!
+//! IPv4 映射的 IPv6 地址（`::ffff:1.2.3.4`）在解析时统一转换成 IPv4，日志与熔断等按
+//! 目标匹配的逻辑只会看到一种写法。
 
 use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
+/// Unix socket 地址的 ATYP，不属于 SOCKS5，只在 AnyTLS 的目标地址中使用
+pub const ATYP_UNIX: u8 = 0x7f;
+/// 连接超时前缀的标记，不属于 SOCKS5，只在 AnyTLS 的目标地址中使用
+pub const ATYP_CONNECT_TIMEOUT: u8 = 0x7e;
+/// Stream 类型前缀的标记，不属于 SOCKS5，只在 AnyTLS 的目标地址中使用
+pub const ATYP_STREAM_TYPE: u8 = 0x7d;
+/// 目标文本中 Unix socket 路径的前缀，如 `unix:/run/docker.sock`
+pub const UNIX_TARGET_PREFIX: &str = "unix:";
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddressType {
     Ipv4,
     Domain,
     Ipv6,
+    /// 服务端本机的 Unix socket，`host` 为路径，`port` 为 0
+    Unix,
+}
+
+/// Stream 承载的连接类型，由目标地址头的类型前缀指定
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamType {
+    /// 连接目标并转发字节流，不带前缀时的默认值
+    #[default]
+    Tcp,
+    /// 向目标转发 UDP 数据报，每个数据报在 Stream 中编码为 `LEN(2) + DATA`
+    Udp,
+}
+
+impl StreamType {
+    fn from_byte(b: u8) -> io::Result<Self> {
+        match b {
+            0x01 => Ok(Self::Tcp),
+            0x02 => Ok(Self::Udp),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported stream type {:#04x}", b),
+            )),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::Tcp => 0x01,
+            Self::Udp => 0x02,
+        }
+    }
+}
+
+/// [`read_target_header`] 读出的目标地址头
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetHeader {
+    pub stream_type: StreamType,
+    pub addr: SocksAddr,
+    /// 客户端指定的连接超时，为 0 或未指定时为 `None`
+    pub connect_timeout: Option<Duration>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -19,10 +83,124 @@ pub struct SocksAddr {
 
 impl SocksAddr {
     pub fn to_host_port(&self) -> String {
-        format!("{}:{}", self.host, self.port)
+        match self.atyp {
+            AddressType::Unix => format!("{}{}", UNIX_TARGET_PREFIX, self.host),
+            _ => format!("{}:{}", self.host, self.port),
+        }
+    }
+
+    /// 解析 `host:port`（IPv6 可加方括号）或 `unix:/path` 形式的目标
+    pub fn parse_target(target: &str) -> io::Result<Self> {
+        if let Some(path) = target.strip_prefix(UNIX_TARGET_PREFIX) {
+            if path.is_empty() || path.len() > u8::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid unix socket path"));
+            }
+            return Ok(SocksAddr { atyp: AddressType::Unix, host: path.to_string(), port: 0 });
+        }
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("target must be host:port or unix:/path, got {}", target),
+            )
+        };
+        let (host, port) = target.rsplit_once(':').ok_or_else(invalid)?;
+        let port = port.parse().map_err(|_| invalid())?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let (atyp, host) = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => (AddressType::Ipv4, host.to_string()),
+            Ok(IpAddr::V6(ip)) => ipv6_host(ip),
+            Err(_) => (AddressType::Domain, host.to_string()),
+        };
+        Ok(SocksAddr { atyp, host, port })
+    }
+
+    /// 从内存中的 `ATYP + ADDR + PORT` 解析地址，返回地址与消耗的字节数。
+    /// 输入不完整或格式错误时返回 `Err`，不会越界访问
+    pub fn from_socks_bytes(buf: &[u8]) -> io::Result<(Self, usize)> {
+        let truncated = || io::Error::new(io::ErrorKind::UnexpectedEof, "truncated socks address");
+        let (&atyp_raw, rest) = buf.split_first().ok_or_else(truncated)?;
+        let (atyp, host, rest) = match atyp_raw {
+            0x01 => {
+                let (ip, rest) = rest.split_first_chunk::<4>().ok_or_else(truncated)?;
+                (AddressType::Ipv4, std::net::Ipv4Addr::from(*ip).to_string(), rest)
+            }
+            0x03 | ATYP_UNIX => {
+                let (&len, rest) = rest.split_first().ok_or_else(truncated)?;
+                let domain = rest.get(..len as usize).ok_or_else(truncated)?;
+                let host = std::str::from_utf8(domain)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid domain utf8"))?;
+                (domain_or_unix(atyp_raw), host.to_string(), &rest[domain.len()..])
+            }
+            0x04 => {
+                let (ip, rest) = rest.split_first_chunk::<16>().ok_or_else(truncated)?;
+                let (atyp, host) = ipv6_host(Ipv6Addr::from(*ip));
+                (atyp, host, rest)
+            }
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported address type"))
+            }
+        };
+        let (port, rest) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+        let addr = SocksAddr {
+            atyp,
+            host,
+            port: u16::from_be_bytes(*port),
+        };
+        Ok((addr, buf.len() - rest.len()))
     }
 }
 
+/// IPv4 映射的 IPv6 地址转换成 IPv4，其他地址不变
+pub fn normalize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// 与 [`normalize_ip`] 相同，端口不变
+pub fn normalize_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(normalize_ip(addr.ip()), addr.port())
+}
+
+/// IPv6 地址的类型与文本，IPv4 映射地址按 IPv4 处理
+fn ipv6_host(ip: Ipv6Addr) -> (AddressType, String) {
+    match ip.to_ipv4_mapped() {
+        Some(v4) => (AddressType::Ipv4, v4.to_string()),
+        None => (AddressType::Ipv6, ip.to_string()),
+    }
+}
+
+/// 域名与 Unix socket 路径的编码相同，只有 ATYP 不同
+fn domain_or_unix(atyp_raw: u8) -> AddressType {
+    if atyp_raw == ATYP_UNIX {
+        AddressType::Unix
+    } else {
+        AddressType::Domain
+    }
+}
+
+/// 读取 Stream 类型、目标地址与客户端指定的连接超时。两个前缀都是可选的，顺序固定
+pub async fn read_target_header<S>(stream: &mut S) -> io::Result<TargetHeader>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut atyp = stream.read_u8().await?;
+    let mut stream_type = StreamType::Tcp;
+    if atyp == ATYP_STREAM_TYPE {
+        stream_type = StreamType::from_byte(stream.read_u8().await?)?;
+        atyp = stream.read_u8().await?;
+    }
+    let mut connect_timeout = None;
+    if atyp == ATYP_CONNECT_TIMEOUT {
+        let millis = stream.read_u16().await?;
+        connect_timeout = (millis > 0).then(|| Duration::from_millis(millis.into()));
+        atyp = stream.read_u8().await?;
+    }
+    let addr = read_socks_addr_with_atyp(stream, atyp).await?;
+    Ok(TargetHeader { stream_type, addr, connect_timeout })
+}
+
 pub async fn read_socks_addr<S>(stream: &mut S) -> io::Result<SocksAddr>
 where
     S: AsyncRead + Unpin,
@@ -55,19 +233,19 @@ where
             stream.read_exact(&mut ip).await?;
             Ok((AddressType::Ipv4, format!("{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3])))
         }
-        0x03 => {
+        0x03 | ATYP_UNIX => {
             let mut len = [0u8; 1];
             stream.read_exact(&mut len).await?;
             let mut domain = vec![0u8; len[0] as usize];
             stream.read_exact(&mut domain).await?;
             let host = String::from_utf8(domain)
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid domain utf8"))?;
-            Ok((AddressType::Domain, host))
+            Ok((domain_or_unix(atyp_raw), host))
         }
         0x04 => {
             let mut ip = [0u8; 16];
             stream.read_exact(&mut ip).await?;
-            Ok((AddressType::Ipv6, std::net::Ipv6Addr::from(ip).to_string()))
+            Ok(ipv6_host(Ipv6Addr::from(ip)))
         }
         _ => Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported address type")),
     }
@@ -83,8 +261,8 @@ pub fn build_socks_addr(addr: &SocksAddr) -> io::Result<Vec<u8>> {
             })?;
             out.extend_from_slice(&ip.octets());
         }
-        AddressType::Domain => {
-            out.push(0x03);
+        AddressType::Domain | AddressType::Unix => {
+            out.push(if addr.atyp == AddressType::Unix { ATYP_UNIX } else { 0x03 });
             if addr.host.len() > u8::MAX as usize {
                 return Err(io::Error::new(io::ErrorKind::InvalidInput, "domain too long"));
             }
@@ -93,7 +271,7 @@ pub fn build_socks_addr(addr: &SocksAddr) -> io::Result<Vec<u8>> {
         }
         AddressType::Ipv6 => {
             out.push(0x04);
-            let ip: std::net::Ipv6Addr = addr.host.parse().map_err(|e| {
+            let ip: Ipv6Addr = addr.host.parse().map_err(|e| {
                 io::Error::new(io::ErrorKind::InvalidInput, format!("invalid IPv6: {e}"))
             })?;
             out.extend_from_slice(&ip.octets());
@@ -102,3 +280,31 @@ pub fn build_socks_addr(addr: &SocksAddr) -> io::Result<Vec<u8>> {
     out.extend_from_slice(&addr.port.to_be_bytes());
     Ok(out)
 }
+
+/// 编码目标地址，`connect_timeout` 非空时加上超时前缀，按毫秒截断到 1..=65535
+pub fn build_target_header(
+    addr: &SocksAddr,
+    connect_timeout: Option<Duration>,
+) -> io::Result<Vec<u8>> {
+    build_typed_target_header(StreamType::Tcp, addr, connect_timeout)
+}
+
+/// 同 [`build_target_header`]，非 TCP 的 Stream 再加上类型前缀；TCP 不加，旧版本服务端也能解析
+pub fn build_typed_target_header(
+    stream_type: StreamType,
+    addr: &SocksAddr,
+    connect_timeout: Option<Duration>,
+) -> io::Result<Vec<u8>> {
+    let addr = build_socks_addr(addr)?;
+    let mut out = Vec::with_capacity(5 + addr.len());
+    if stream_type != StreamType::Tcp {
+        out.extend_from_slice(&[ATYP_STREAM_TYPE, stream_type.to_byte()]);
+    }
+    if let Some(timeout) = connect_timeout {
+        let millis = timeout.as_millis().clamp(1, u16::MAX.into()) as u16;
+        out.push(ATYP_CONNECT_TIMEOUT);
+        out.extend_from_slice(&millis.to_be_bytes());
+    }
+    out.extend_from_slice(&addr);
+    Ok(out)
+}
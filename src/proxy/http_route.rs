@@ -0,0 +1,131 @@
+//! HTTP/1.x Host-header based routing.
+//!
+//! 服务端可以按 Stream 开头 HTTP 请求的 `Host` 头把连接转发到配置的后端，
+//! 未匹配或不是 HTTP 请求时仍按 SOCKS 目标地址处理。
+
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const MAX_HEADER_LEN: usize = 8 * 1024;
+const METHODS: [&[u8]; 9] = [
+    b"GET ", b"POST ", b"PUT ", b"HEAD ", b"DELETE ", b"OPTIONS ", b"PATCH ", b"CONNECT ",
+    b"TRACE ",
+];
+
+/// 对已读取字节的判断结果
+#[derive(Debug, PartialEq, Eq)]
+pub enum HttpPeek {
+    /// 不是 HTTP/1.x 请求
+    NotHttp,
+    /// 请求头还不完整
+    Incomplete,
+    /// 请求头完整，附带 Host（已去掉端口并转为小写）
+    Complete(Option<String>),
+}
+
+/// 检查缓冲区开头是否为 HTTP 请求并提取 Host
+pub fn peek_http_host(buf: &[u8]) -> HttpPeek {
+    let is_method_prefix = METHODS.iter().any(|m| {
+        let n = buf.len().min(m.len());
+        buf[..n] == m[..n]
+    });
+    if !is_method_prefix {
+        return HttpPeek::NotHttp;
+    }
+    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return HttpPeek::Incomplete;
+    };
+
+    let Ok(head) = std::str::from_utf8(&buf[..end]) else {
+        return HttpPeek::NotHttp;
+    };
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    if !request_line.contains(" HTTP/1.") {
+        return HttpPeek::NotHttp;
+    }
+    let host = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("host")
+            .then(|| strip_port(value.trim()).to_ascii_lowercase())
+    });
+    HttpPeek::Complete(host)
+}
+
+fn strip_port(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => name,
+        _ => host,
+    }
+}
+
+/// `host=backend` 路由表
+#[derive(Debug, Clone, Default)]
+pub struct HttpRoutes {
+    routes: HashMap<String, String>,
+}
+
+impl HttpRoutes {
+    /// 解析 `--http-route` 参数，每项形如 `example.com=127.0.0.1:8080`
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let mut routes = HashMap::new();
+        for entry in entries {
+            let (host, backend) = entry
+                .split_once('=')
+                .filter(|(h, b)| !h.trim().is_empty() && !b.trim().is_empty())
+                .ok_or_else(|| format!("invalid http route '{}', expected host=backend", entry))?;
+            routes.insert(host.trim().to_ascii_lowercase(), backend.trim().to_string());
+        }
+        Ok(Self { routes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub fn backend_for(&self, host: &str) -> Option<&str> {
+        self.routes
+            .get(&strip_port(host).to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// 读取流开头直到能判断是否为 HTTP 请求，返回已读取的字节（调用方需原样转发）与匹配的后端。
+    ///
+    /// 每次读取最多等待 `read_timeout`，超时按未匹配处理，避免拖住由服务端先发数据的协议。
+    pub async fn route<R>(
+        &self,
+        stream: &mut R,
+        read_timeout: Duration,
+    ) -> io::Result<(Vec<u8>, Option<String>)>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut buf = Vec::with_capacity(1024);
+        let mut chunk = [0u8; 2048];
+        loop {
+            let n = match tokio::time::timeout(read_timeout, stream.read(&mut chunk)).await {
+                Ok(n) => n?,
+                Err(_) => return Ok((buf, None)),
+            };
+            if n == 0 {
+                return Ok((buf, None));
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            match peek_http_host(&buf) {
+                HttpPeek::NotHttp => return Ok((buf, None)),
+                HttpPeek::Incomplete if buf.len() < MAX_HEADER_LEN => {}
+                HttpPeek::Incomplete | HttpPeek::Complete(None) => return Ok((buf, None)),
+                HttpPeek::Complete(Some(host)) => {
+                    let backend = self.backend_for(&host).map(str::to_string);
+                    return Ok((buf, backend));
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,12 @@
 pub mod addr_codec;
+pub mod http_route;
+pub mod outbound;
 pub mod padding;
 pub mod pipe;
+pub mod protocol;
+pub mod proxy_protocol;
+pub mod relay;
 pub mod session;
+pub mod socks;
 pub mod transport;
 pub mod uot;
@@ -0,0 +1,130 @@
+//! 按目标的熔断器。
+//!
+//! 同一目标在 `window` 内连续失败 `failure_threshold` 次后进入打开状态，`cooldown` 内的新
+//! Stream 直接失败而不再连接；冷却结束后放行一个探测连接（半开），成功则恢复，失败则重新打开。
+//! 探测在又一个 `cooldown` 内没有结果时放行新的探测。
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 超过该数量时清理过期的失败计数
+const PRUNE_THRESHOLD: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerConfig {
+    /// 打开熔断所需的连续失败次数
+    pub failure_threshold: u32,
+    /// 连续失败的统计窗口，距第一次失败超过该时长后重新计数
+    pub window: Duration,
+    /// 打开后拒绝连接的时长
+    pub cooldown: Duration,
+}
+
+/// 某个目标当前所处的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerStatus {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum BreakerState {
+    Closed { failures: u32, since: Instant },
+    Open { until: Instant },
+    /// 已放行一个探测连接，结果返回前拒绝其他连接
+    HalfOpen { since: Instant },
+}
+
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    targets: Mutex<HashMap<String, BreakerState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            targets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 是否允许连接 `target`；冷却结束后的第一次调用作为探测放行
+    pub fn allow(&self, target: &str) -> bool {
+        let mut targets = self.lock();
+        let Some(state) = targets.get_mut(target) else {
+            return true;
+        };
+        let now = Instant::now();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if now >= until => {
+                *state = BreakerState::HalfOpen { since: now };
+                true
+            }
+            // 探测连接没有报告结果（例如任务被取消），不能一直拒绝该目标
+            BreakerState::HalfOpen { since } if now - since >= self.config.cooldown => {
+                *state = BreakerState::HalfOpen { since: now };
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    pub fn record_success(&self, target: &str) {
+        self.lock().remove(target);
+    }
+
+    pub fn record_failure(&self, target: &str) {
+        let now = Instant::now();
+        let mut targets = self.lock();
+        if targets.len() >= PRUNE_THRESHOLD {
+            let window = self.config.window;
+            targets.retain(|_, state| match state {
+                BreakerState::Closed { since, .. } => now.duration_since(*since) < window,
+                BreakerState::Open { until } => *until > now,
+                BreakerState::HalfOpen { since } => now - *since < self.config.cooldown,
+            });
+        }
+
+        let state = targets.entry(target.to_string()).or_insert(BreakerState::Closed {
+            failures: 0,
+            since: now,
+        });
+        let (failures, since) = match *state {
+            BreakerState::Closed { failures, since } if now - since < self.config.window => {
+                (failures + 1, since)
+            }
+            BreakerState::Closed { .. } => (1, now),
+            // 探测失败或冷却期间仍有连接失败，重新开始冷却
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                (self.config.failure_threshold, now)
+            }
+        };
+        *state = if failures >= self.config.failure_threshold {
+            log::warn!(
+                "[Server] Upstream {} failing, rejecting new streams for {:?}",
+                target,
+                self.config.cooldown
+            );
+            BreakerState::Open {
+                until: now + self.config.cooldown,
+            }
+        } else {
+            BreakerState::Closed { failures, since }
+        };
+    }
+
+    pub fn status(&self, target: &str) -> BreakerStatus {
+        match self.lock().get(target) {
+            None | Some(BreakerState::Closed { .. }) => BreakerStatus::Closed,
+            Some(BreakerState::Open { .. }) => BreakerStatus::Open,
+            Some(BreakerState::HalfOpen { .. }) => BreakerStatus::HalfOpen,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, BreakerState>> {
+        self.targets.lock().expect("circuit breaker lock poisoned")
+    }
+}
@@ -0,0 +1,4 @@
+//! 服务端连接目标相关的策略。
+
+pub mod breaker;
+pub mod socket;
@@ -0,0 +1,187 @@
+//! 连接目标时的 socket 选项。
+//!
+//! 带宽时延积较大的链路上，默认的 SO_SNDBUF/SO_RCVBUF 会限制单个连接的吞吐。
+//! 缓冲区大小在 connect 之前设置，这样 TCP 握手时就能按它协商窗口缩放；
+//! 内核可能按 `net.core.wmem_max`/`rmem_max` 截断或加倍，实际生效值见 [`SocketOptions::effective`]。
+//!
+//! 设置了源端口范围时，connect 之前把 socket 绑定到范围内的端口，从随机位置开始依次尝试；
+//! 范围内的端口都被占用时退回系统分配的临时端口并记录警告，而不是让连接失败。
+
+use crate::proxy::addr_codec::normalize_addr;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use tokio::net::{lookup_host, TcpSocket, TcpStream};
+
+pub const MIN_SOCKET_BUFFER_SIZE: u32 = 4 * 1024;
+pub const MAX_SOCKET_BUFFER_SIZE: u32 = 256 * 1024 * 1024;
+
+/// 源端口范围 `low-high`，两端都包含在内
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    pub low: u16,
+    pub high: u16,
+}
+
+impl PortRange {
+    fn len(&self) -> usize {
+        usize::from(self.high - self.low) + 1
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        (self.low..=self.high).contains(&port)
+    }
+
+    /// 从随机位置开始依次给出范围内的每个端口，多个连接同时绑定时不会总在同一个端口上冲突
+    fn ports_from_random_start(&self) -> impl Iterator<Item = u16> {
+        let len = self.len();
+        let start = fastrand::usize(..len);
+        let low = self.low;
+        (0..len).map(move |i| low + ((start + i) % len) as u16)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid port range '{}', expected low-high", s);
+        let (low, high) = s.split_once('-').ok_or_else(invalid)?;
+        let low: u16 = low.trim().parse().map_err(|_| invalid())?;
+        let high: u16 = high.trim().parse().map_err(|_| invalid())?;
+        if low == 0 || low > high {
+            return Err(format!("port range '{}' must satisfy 1 <= low <= high", s));
+        }
+        Ok(Self { low, high })
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.low, self.high)
+    }
+}
+
+/// 目标连接的 socket 选项，未设置的项使用系统默认值
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// SO_SNDBUF（字节）
+    pub send_buffer_size: Option<u32>,
+    /// SO_RCVBUF（字节）
+    pub recv_buffer_size: Option<u32>,
+    /// 连接使用的源端口范围，`None` 时由系统分配
+    pub source_ports: Option<PortRange>,
+}
+
+impl SocketOptions {
+    /// 检查缓冲区大小是否在 4 KiB..=256 MiB 之间，超出时返回 `InvalidInput`
+    pub fn validate(&self) -> io::Result<()> {
+        for (name, size) in [("send", self.send_buffer_size), ("recv", self.recv_buffer_size)] {
+            match size {
+                Some(n) if !(MIN_SOCKET_BUFFER_SIZE..=MAX_SOCKET_BUFFER_SIZE).contains(&n) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "socket {} buffer size {} out of range {}..={}",
+                            name, n, MIN_SOCKET_BUFFER_SIZE, MAX_SOCKET_BUFFER_SIZE
+                        ),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.send_buffer_size.is_none()
+            && self.recv_buffer_size.is_none()
+            && self.source_ports.is_none()
+    }
+
+    /// 在新建的 socket 上应用选项，返回内核实际采用的 (SO_SNDBUF, SO_RCVBUF)
+    pub fn effective(&self) -> io::Result<(u32, u32)> {
+        let socket = self.socket_for(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))?;
+        Ok((socket.send_buffer_size()?, socket.recv_buffer_size()?))
+    }
+
+    /// 连接 `target`（`host:port`），依次尝试解析出的地址，连接前应用选项
+    pub async fn connect(&self, target: &str) -> io::Result<TcpStream> {
+        if self.is_default() {
+            return TcpStream::connect(target).await;
+        }
+        let mut last_err = None;
+        for addr in lookup_host(target).await? {
+            // 映射地址按 IPv4 建 socket，绑定的 IPv4 源地址才能生效
+            let addr = normalize_addr(addr);
+            let attempt = match self.source_ports {
+                Some(range) => self.connect_from_range(addr, range).await,
+                None => match self.socket_for(&addr) {
+                    Ok(socket) => socket.connect(addr).await,
+                    Err(e) => Err(e),
+                },
+            };
+            match attempt {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} did not resolve to any address", target),
+            )
+        }))
+    }
+
+    /// 绑定范围内的源端口后连接 `addr`；端口被占用时换下一个，全部占用时改用临时端口
+    async fn connect_from_range(
+        &self,
+        addr: SocketAddr,
+        range: PortRange,
+    ) -> io::Result<TcpStream> {
+        let unspecified = match addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        for port in range.ports_from_random_start() {
+            let socket = self.socket_for(&addr)?;
+            match socket.bind(SocketAddr::new(unspecified, port)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e),
+            }
+            match socket.connect(addr).await {
+                // 同一四元组已被占用，换一个端口重试
+                Err(e) if is_port_conflict(&e) => continue,
+                result => return result,
+            }
+        }
+        log::warn!(
+            "[Server] No free source port in {} for {}, using an ephemeral port",
+            range,
+            addr
+        );
+        self.socket_for(&addr)?.connect(addr).await
+    }
+
+    fn socket_for(&self, addr: &SocketAddr) -> io::Result<TcpSocket> {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(socket)
+    }
+}
+
+fn is_port_conflict(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable)
+}
@@ -1,108 +1,48 @@
-use crate::util::string_map::{StringMap, StringMapExt};
-use rand::Rng;
-use std::sync::Arc;
+pub use crate::proxy::protocol::padding::*;
 
-pub const CHECK_MARK: i32 = -1;
+use arc_swap::ArcSwap;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::watch;
 
-static DEFAULT_PADDING_SCHEME: &str = r#"stop=8
-0=30-30
-1=100-400
-2=400-500,c,500-1000,c,500-1000,c,500-1000,c,500-1000
-3=9-9,500-1000
-4=500-1000
-5=500-1000
-6=500-1000
-7=500-1000"#;
+pub struct DefaultPaddingFactory;
 
-#[derive(Clone)]
-pub struct PaddingFactory {
-    scheme: StringMap,
-    pub raw_scheme: bytes::Bytes,
-    stop: u32,
-    md5: String,
+struct DefaultPadding {
+    current: ArcSwap<PaddingFactory>,
+    notify: watch::Sender<Arc<PaddingFactory>>,
 }
 
-impl Default for PaddingFactory {
-    fn default() -> Self {
-        Self::new(DEFAULT_PADDING_SCHEME.as_bytes()).unwrap()
-    }
-}
+static DEFAULT_PADDING: OnceLock<DefaultPadding> = OnceLock::new();
 
-impl PaddingFactory {
-    pub fn new(raw_scheme: &[u8]) -> Option<Self> {
-        let scheme = StringMap::from_bytes(raw_scheme);
-        if scheme.is_empty() {
-            return None;
+fn default_padding() -> &'static DefaultPadding {
+    DEFAULT_PADDING.get_or_init(|| {
+        let factory = Arc::new(PaddingFactory::default());
+        let (notify, _) = watch::channel(Arc::clone(&factory));
+        DefaultPadding {
+            current: ArcSwap::new(factory),
+            notify,
         }
-
-        let stop = scheme.get("stop")?.parse::<u32>().ok()?;
-        let bytes = bytes::Bytes::copy_from_slice(raw_scheme);
-        let md5 = format!("{:x}", md5::compute(&bytes));
-
-        Some(Self {
-            scheme,
-            raw_scheme: bytes,
-            stop,
-            md5,
-        })
-    }
-
-    pub fn generate_record_payload_sizes(&self, pkt: u32) -> Vec<i32> {
-        let mut pkt_sizes = Vec::new();
-
-        if let Some(s) = self.scheme.get(&pkt.to_string()) {
-            let s_ranges: Vec<&str> = s.split(',').collect();
-
-            for s_range in s_ranges {
-                if s_range == "c" {
-                    pkt_sizes.push(CHECK_MARK);
-                } else if let Some((min_str, max_str)) = s_range.split_once('-') {
-                    if let (Ok(min), Ok(max)) = (min_str.parse::<i64>(), max_str.parse::<i64>()) {
-                        let (min, max) = (min.min(max), min.max(max));
-                        if min > 0 && max > 0 {
-                            if min == max {
-                                pkt_sizes.push(min as i32);
-                            } else {
-                                let mut rng = rand::thread_rng();
-                                let size = rng.gen_range(min..=max);
-                                pkt_sizes.push(size as i32);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        pkt_sizes
-    }
-
-    pub fn md5(&self) -> &str {
-        &self.md5
-    }
-
-    pub fn stop(&self) -> u32 {
-        self.stop
-    }
-
-    /// 生成随机填充数据，使填充数据更像真实数据
-    pub fn rng_vec(&self, length: usize) -> Vec<u8> {
-        (0..length).map(|_| fastrand::u8(..)).collect()
-    }
+    })
 }
 
-pub struct DefaultPaddingFactory;
-
 impl DefaultPaddingFactory {
     pub fn load() -> Arc<PaddingFactory> {
-        Arc::new(PaddingFactory::default())
+        default_padding().current.load_full()
     }
 
+    /// 替换全局默认填充方案，并通知所有订阅者；方案无效时返回 false
     pub async fn update(raw_scheme: &[u8]) -> bool {
-        if let Some(_factory) = PaddingFactory::new(raw_scheme) {
-            // In a real implementation, this would update a global instance
-            true
-        } else {
-            false
-        }
+        let Some(factory) = PaddingFactory::new(raw_scheme) else {
+            return false;
+        };
+        let factory = Arc::new(factory);
+        let default = default_padding();
+        default.current.store(Arc::clone(&factory));
+        default.notify.send_replace(factory);
+        true
+    }
+
+    /// 订阅默认填充方案的变更
+    pub fn subscribe() -> watch::Receiver<Arc<PaddingFactory>> {
+        default_padding().notify.subscribe()
     }
 }
@@ -0,0 +1,149 @@
+//! 帧格式：cmd(1) + sid(4) + length(2) + data。
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io;
+
+// CMD 指令定义
+pub const CMD_WASTE: u8 = 0;               // Paddings
+pub const CMD_SYN: u8 = 1;                 // stream open
+pub const CMD_PSH: u8 = 2;                 // data push
+pub const CMD_FIN: u8 = 3;                 // stream close, a.k.a EOF mark
+pub const CMD_SETTINGS: u8 = 4;            // Settings (Client send to Server)
+pub const CMD_ALERT: u8 = 5;               // Alert
+pub const CMD_UPDATE_PADDING_SCHEME: u8 = 6; // update padding scheme
+// Since version 2
+pub const CMD_SYNACK: u8 = 7;              // Server reports to the client that the stream has been opened
+pub const CMD_HEART_REQUEST: u8 = 8;       // Keep alive command
+pub const CMD_HEART_RESPONSE: u8 = 9;      // Keep alive command
+pub const CMD_SERVER_SETTINGS: u8 = 10;    // Settings (Server send to client)
+// Extensions, only sent when the peer advertises them in settings
+pub const CMD_PSH_SEQ: u8 = 11;            // data push prefixed with a per-stream sequence number
+pub const CMD_STOP_SENDING: u8 = 12;       // receiver no longer reads the stream
+pub const CMD_FIN_ACK: u8 = 13;            // acknowledges a received CMD_FIN
+pub const CMD_RST: u8 = 14;                // abort a stream, both ends discard its buffered data
+
+pub const HEADER_OVERHEAD_SIZE: usize = 1 + 4 + 2; // cmd(1) + sid(4) + length(2)
+pub const MAX_PAYLOAD_SIZE: usize = u16::MAX as usize;
+pub const MIN_MAX_PAYLOAD_SIZE: usize = 256; // lower bound for a negotiated max-payload
+pub const SEQ_PREFIX_SIZE: usize = 4; // CMD_PSH_SEQ: seq(4) + data
+
+/// 原始头部结构
+#[derive(Debug, Clone, Copy)]
+pub struct RawHeader {
+    pub cmd: u8,
+    pub sid: u32,
+    pub length: u16,
+}
+
+impl RawHeader {
+    pub fn from_bytes(buf: &[u8]) -> io::Result<Self> {
+        if buf.len() < HEADER_OVERHEAD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Insufficient data for header",
+            ));
+        }
+
+        let mut cursor = std::io::Cursor::new(buf);
+        let cmd = cursor.get_u8();
+        let sid = cursor.get_u32();
+        let length = cursor.get_u16();
+
+        Ok(Self { cmd, sid, length })
+    }
+}
+
+/// Frame 结构体，定义从或要复用到单个连接中的数据包
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub cmd: u8,
+    pub sid: u32,
+    pub data: Bytes,
+}
+
+impl Frame {
+    pub fn new(cmd: u8, sid: u32) -> Self {
+        Self {
+            cmd,
+            sid,
+            data: Bytes::new(),
+        }
+    }
+
+    pub fn with_data(cmd: u8, sid: u32, data: Bytes) -> Self {
+        Self { cmd, sid, data }
+    }
+
+    /// 带序号的数据帧，`payload` 不能超过 `MAX_PAYLOAD_SIZE - SEQ_PREFIX_SIZE`
+    pub fn psh_seq(sid: u32, seq: u32, payload: &[u8]) -> Self {
+        let mut data = BytesMut::with_capacity(SEQ_PREFIX_SIZE + payload.len());
+        data.put_u32(seq);
+        data.put_slice(payload);
+        Self::with_data(CMD_PSH_SEQ, sid, data.freeze())
+    }
+
+    /// 拆出 CMD_PSH_SEQ 负载中的序号与数据
+    pub fn split_seq(mut data: Bytes) -> io::Result<(u32, Bytes)> {
+        if data.len() < SEQ_PREFIX_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "sequenced push shorter than its sequence number",
+            ));
+        }
+        let seq = data.get_u32();
+        Ok((seq, data))
+    }
+
+    /// 从字节流中解析 Frame
+    pub fn from_bytes(mut buf: &[u8]) -> io::Result<Self> {
+        if buf.len() < HEADER_OVERHEAD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Insufficient data for frame header",
+            ));
+        }
+
+        let cmd = buf.get_u8();
+        let sid = buf.get_u32();
+        let length = buf.get_u16() as usize;
+
+        if buf.len() < length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Insufficient data for frame payload",
+            ));
+        }
+
+        let data = Bytes::copy_from_slice(&buf[..length]);
+
+        Ok(Self { cmd, sid, data })
+    }
+
+    /// 将 Frame 序列化为字节流
+    pub fn to_bytes(&self) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    /// 帧头，长度字段取负载长度
+    pub fn header(&self) -> [u8; HEADER_OVERHEAD_SIZE] {
+        let mut header = [0u8; HEADER_OVERHEAD_SIZE];
+        header[0] = self.cmd;
+        header[1..5].copy_from_slice(&self.sid.to_be_bytes());
+        header[5..7].copy_from_slice(&(self.data.len() as u16).to_be_bytes());
+        header
+    }
+
+    /// 帧头加负载的总长度
+    pub fn encoded_len(&self) -> usize {
+        HEADER_OVERHEAD_SIZE + self.data.len()
+    }
+
+    /// 把编码后的帧追加到 `dst`
+    pub fn encode_into(&self, dst: &mut BytesMut) {
+        dst.reserve(self.encoded_len());
+        dst.put_slice(&self.header());
+        dst.put_slice(&self.data);
+    }
+}
@@ -0,0 +1,11 @@
+//! 与运行时无关的协议部分：帧编解码、设置的序列化和填充长度计算。
+//!
+//! 这里不依赖 tokio，session 和其他传输都在此基础上收发。
+
+pub mod frame;
+pub mod padding;
+pub mod settings;
+
+pub use frame::{Frame, RawHeader, HEADER_OVERHEAD_SIZE};
+pub use padding::{waste_lengths, PaddingFactory, PaddingToken, CHECK_MARK};
+pub use settings::{ClientSettings, ServerSettings, PROTOCOL_VERSION};
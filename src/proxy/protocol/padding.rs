@@ -0,0 +1,240 @@
+//! 填充方案的解析与每个包的填充长度计算。
+
+use crate::proxy::protocol::frame::HEADER_OVERHEAD_SIZE;
+use crate::util::string_map::{StringMap, StringMapExt};
+use bytes::Bytes;
+use rand::Rng;
+use std::{fmt, io};
+
+pub const CHECK_MARK: i32 = -1;
+
+/// 压缩的填充方案负载以此字节开头，文本方案不会以 NUL 开头
+pub const SCHEME_DEFLATE_MARK: u8 = 0;
+/// 解压后的填充方案最多这么多字节，防止压缩炸弹
+pub const MAX_SCHEME_SIZE: usize = 256 * 1024;
+
+static DEFAULT_PADDING_SCHEME: &str = r#"stop=8
+0=30-30
+1=100-400
+2=400-500,c,500-1000,c,500-1000,c,500-1000,c,500-1000
+3=9-9,500-1000
+4=500-1000
+5=500-1000
+6=500-1000
+7=500-1000"#;
+
+/// 把文本方案编码为压缩的 CMD_UPDATE_PADDING_SCHEME 负载：标记字节后接 raw DEFLATE 数据
+pub fn compress_scheme(raw_scheme: &[u8]) -> Bytes {
+    let compressed = miniz_oxide::deflate::compress_to_vec(raw_scheme, 9);
+    let mut payload = Vec::with_capacity(compressed.len() + 1);
+    payload.push(SCHEME_DEFLATE_MARK);
+    payload.extend_from_slice(&compressed);
+    Bytes::from(payload)
+}
+
+/// 还原 CMD_UPDATE_PADDING_SCHEME 的负载，未压缩的负载原样返回
+pub fn decompress_scheme(payload: Bytes) -> io::Result<Bytes> {
+    if payload.first() != Some(&SCHEME_DEFLATE_MARK) {
+        return Ok(payload);
+    }
+    miniz_oxide::inflate::decompress_to_vec_with_limit(&payload[1..], MAX_SCHEME_SIZE)
+        .map(Bytes::from)
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid compressed padding scheme: {:?}", e.status),
+            )
+        })
+}
+
+/// 填充方案中单个包的一项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaddingToken {
+    /// `c`：若已无剩余负载则停止后续填充
+    Check,
+    /// `min-max`，按书写顺序保留，`min > max` 说明方案写反了
+    Range(u32, u32),
+    /// `n-n`
+    Fixed(u32),
+}
+
+impl PaddingToken {
+    fn parse(s: &str) -> Option<Self> {
+        if s == "c" {
+            return Some(Self::Check);
+        }
+        let (min, max) = s.split_once('-')?;
+        let (min, max) = (min.parse::<u32>().ok()?, max.parse::<u32>().ok()?);
+        if min == max {
+            Some(Self::Fixed(min))
+        } else {
+            Some(Self::Range(min, max))
+        }
+    }
+}
+
+impl fmt::Display for PaddingToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Check => write!(f, "c"),
+            Self::Range(min, max) => write!(f, "{}-{}", min, max),
+            Self::Fixed(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PaddingFactory {
+    scheme: StringMap,
+    pub raw_scheme: bytes::Bytes,
+    stop: u32,
+    md5: String,
+}
+
+impl Default for PaddingFactory {
+    fn default() -> Self {
+        Self::new(DEFAULT_PADDING_SCHEME.as_bytes()).unwrap()
+    }
+}
+
+impl PaddingFactory {
+    pub fn new(raw_scheme: &[u8]) -> Option<Self> {
+        Self::from_bytes(bytes::Bytes::copy_from_slice(raw_scheme))
+    }
+
+    /// 与 [`PaddingFactory::new`] 相同，但直接持有 `raw_scheme`，不再复制一份
+    pub fn from_bytes(raw_scheme: bytes::Bytes) -> Option<Self> {
+        let scheme = StringMap::from_bytes(&raw_scheme);
+        if scheme.is_empty() {
+            return None;
+        }
+
+        let stop = scheme.get("stop")?.parse::<u32>().ok()?;
+        let md5 = format!("{:x}", md5::compute(&raw_scheme));
+
+        Some(Self {
+            scheme,
+            raw_scheme,
+            stop,
+            md5,
+        })
+    }
+
+    pub fn generate_record_payload_sizes(&self, pkt: u32) -> Vec<i32> {
+        let mut pkt_sizes = Vec::new();
+
+        if let Some(s) = self.scheme.get(&pkt.to_string()) {
+            let s_ranges: Vec<&str> = s.split(',').collect();
+
+            for s_range in s_ranges {
+                if s_range == "c" {
+                    pkt_sizes.push(CHECK_MARK);
+                } else if let Some((min_str, max_str)) = s_range.split_once('-') {
+                    if let (Ok(min), Ok(max)) = (min_str.parse::<i64>(), max_str.parse::<i64>()) {
+                        let (min, max) = (min.min(max), min.max(max));
+                        if min > 0 && max > 0 {
+                            if min == max {
+                                pkt_sizes.push(min as i32);
+                            } else {
+                                let mut rng = rand::thread_rng();
+                                let size = rng.gen_range(min..=max);
+                                pkt_sizes.push(size as i32);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        pkt_sizes
+    }
+
+    /// 解析后的填充方案，按包序号升序；无法识别的项被忽略（与生成逻辑一致）
+    pub fn describe(&self) -> Vec<(u32, Vec<PaddingToken>)> {
+        let mut packets: Vec<(u32, Vec<PaddingToken>)> = self
+            .scheme
+            .iter()
+            .filter_map(|(key, value)| {
+                let pkt = key.parse::<u32>().ok()?;
+                let tokens = value.split(',').filter_map(PaddingToken::parse).collect();
+                Some((pkt, tokens))
+            })
+            .collect();
+        packets.sort_by_key(|(pkt, _)| *pkt);
+        packets
+    }
+
+    /// 单个包最多写出的字节数：各项取上限相加，再取所有包中最大的一个
+    pub fn max_packet_bytes(&self) -> u64 {
+        self.describe()
+            .iter()
+            .map(|(_, tokens)| {
+                tokens
+                    .iter()
+                    .map(|token| match *token {
+                        PaddingToken::Check => 0,
+                        PaddingToken::Range(min, max) => u64::from(min.max(max)),
+                        PaddingToken::Fixed(n) => u64::from(n),
+                    })
+                    .sum::<u64>()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn md5(&self) -> &str {
+        &self.md5
+    }
+
+    pub fn stop(&self) -> u32 {
+        self.stop
+    }
+
+    /// 第 `pkt` 个包写出 `frame_len` 字节后需要追加的各个 WASTE 帧的负载长度
+    pub fn waste_lengths(&self, pkt: u32, frame_len: usize) -> Vec<usize> {
+        waste_lengths(&self.generate_record_payload_sizes(pkt), frame_len)
+    }
+
+    /// 生成随机填充数据，使填充数据更像真实数据
+    pub fn rng_vec(&self, length: usize) -> Vec<u8> {
+        (0..length).map(|_| fastrand::u8(..)).collect()
+    }
+}
+
+impl fmt::Display for PaddingFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stop={}", self.stop)?;
+        for (pkt, tokens) in self.describe() {
+            write!(f, "\n{}=", pkt)?;
+            for (i, token) in tokens.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}", token)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 按 `generate_record_payload_sizes` 给出的记录长度，计算已写出 `frame_len` 字节后
+/// 每条记录还需补足的 WASTE 帧负载长度；剩余空间放不下 WASTE 帧头的记录不补
+pub fn waste_lengths(sizes: &[i32], frame_len: usize) -> Vec<usize> {
+    let mut wastes = Vec::new();
+    let mut payload_remaining = frame_len;
+    for &size in sizes {
+        if size == CHECK_MARK {
+            if payload_remaining == 0 {
+                break;
+            }
+            continue;
+        }
+        let target_payload = size as usize;
+        let consumed = payload_remaining.min(target_payload);
+        payload_remaining -= consumed;
+        if target_payload > consumed + HEADER_OVERHEAD_SIZE {
+            wastes.push(target_payload - consumed - HEADER_OVERHEAD_SIZE);
+        }
+    }
+    wastes
+}
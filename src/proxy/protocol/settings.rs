@@ -0,0 +1,189 @@
+//! SETTINGS / SERVER_SETTINGS 帧负载的编解码，格式为多行 `key=value`。
+
+use crate::util::string_map::{StringMap, StringMapExt};
+use bytes::Bytes;
+use std::io;
+
+/// 当前实现的协议版本
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// 设置负载的字节数上限，正常的设置不超过几百字节
+pub const MAX_SETTINGS_SIZE: usize = 4096;
+/// 设置负载的条目（行）数上限
+pub const MAX_SETTINGS_ENTRIES: usize = 64;
+
+/// 解析前检查设置负载的大小与条目数，超过上限时返回 `InvalidData`
+pub fn check_settings_size(data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_SETTINGS_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("settings too large: {} bytes", data.len()),
+        ));
+    }
+    let entries = data.iter().filter(|&&b| b == b'\n').count() + 1;
+    if entries > MAX_SETTINGS_ENTRIES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("too many settings: {} entries", entries),
+        ));
+    }
+    Ok(())
+}
+
+/// 扩展：CMD_PSH_SEQ 带序号的数据帧
+pub const EXT_PSH_SEQ: &str = "psh-seq";
+/// 扩展：CMD_STOP_SENDING 通知对端停止向某个 Stream 发送数据
+pub const EXT_STOP_SENDING: &str = "stop-sending";
+/// 扩展：收到 CMD_FIN 后以 CMD_FIN_ACK 确认
+pub const EXT_FIN_ACK: &str = "fin-ack";
+/// 扩展：CMD_RST 立即重置单个 Stream
+pub const EXT_RST: &str = "rst";
+/// 扩展：CMD_UPDATE_PADDING_SCHEME 的负载可以是压缩的方案
+pub const EXT_PADDING_DEFLATE: &str = "padding-deflate";
+/// 本实现支持的扩展，在 `ext` 中以逗号分隔发送；对端也声明了的扩展才会使用
+pub const SUPPORTED_EXTENSIONS: &[&str] =
+    &[EXT_PSH_SEQ, EXT_STOP_SENDING, EXT_FIN_ACK, EXT_PADDING_DEFLATE, EXT_RST];
+
+fn encode_extensions(map: &mut StringMap, extensions: &[String]) {
+    if !extensions.is_empty() {
+        map.insert("ext".to_string(), extensions.join(","));
+    }
+}
+
+fn decode_extensions(map: &StringMap) -> Vec<String> {
+    map.get("ext")
+        .map(|ext| {
+            ext.split(',')
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn decode_max_payload(map: &StringMap) -> Option<usize> {
+    map.get("max-payload").and_then(|v| v.parse().ok())
+}
+
+fn supported_extensions() -> Vec<String> {
+    SUPPORTED_EXTENSIONS.iter().map(|ext| ext.to_string()).collect()
+}
+
+/// 客户端在 CMD_SETTINGS 中发送的设置，缺失或无法解析的字段为 `None`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientSettings {
+    pub version: Option<u32>,
+    pub client: Option<String>,
+    pub padding_md5: Option<String>,
+    /// 客户端平台，只在启用上报时发送
+    pub os: Option<String>,
+    pub arch: Option<String>,
+    pub extensions: Vec<String>,
+    /// 本端愿意接收的最大帧负载，缺省为 65535
+    pub max_payload: Option<usize>,
+}
+
+impl ClientSettings {
+    pub fn new(client: &str, padding_md5: &str) -> Self {
+        Self {
+            version: Some(PROTOCOL_VERSION),
+            client: Some(client.to_string()),
+            padding_md5: Some(padding_md5.to_string()),
+            os: None,
+            arch: None,
+            extensions: supported_extensions(),
+            max_payload: None,
+        }
+    }
+
+    pub fn supports(&self, extension: &str) -> bool {
+        self.extensions.iter().any(|ext| ext == extension)
+    }
+
+    /// 附带本机的操作系统与架构（`std::env::consts`）
+    pub fn with_platform(mut self) -> Self {
+        self.os = Some(std::env::consts::OS.to_string());
+        self.arch = Some(std::env::consts::ARCH.to_string());
+        self
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut map = StringMap::new();
+        if let Some(version) = self.version {
+            map.insert("v".to_string(), version.to_string());
+        }
+        if let Some(client) = &self.client {
+            map.insert("client".to_string(), client.clone());
+        }
+        if let Some(md5) = &self.padding_md5 {
+            map.insert("padding-md5".to_string(), md5.clone());
+        }
+        if let Some(os) = &self.os {
+            map.insert("os".to_string(), os.clone());
+        }
+        if let Some(arch) = &self.arch {
+            map.insert("arch".to_string(), arch.clone());
+        }
+        encode_extensions(&mut map, &self.extensions);
+        if let Some(max_payload) = self.max_payload {
+            map.insert("max-payload".to_string(), max_payload.to_string());
+        }
+        Bytes::from(map.to_bytes())
+    }
+
+    pub fn decode(data: &[u8]) -> Self {
+        let mut map = StringMap::from_bytes(data);
+        Self {
+            version: map.get("v").and_then(|v| v.parse().ok()),
+            client: map.remove("client"),
+            padding_md5: map.remove("padding-md5"),
+            os: map.remove("os"),
+            arch: map.remove("arch"),
+            extensions: decode_extensions(&map),
+            max_payload: decode_max_payload(&map),
+        }
+    }
+}
+
+/// 服务端在 CMD_SERVER_SETTINGS 中回复的设置
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerSettings {
+    pub version: Option<u32>,
+    pub extensions: Vec<String>,
+    pub max_payload: Option<usize>,
+}
+
+impl ServerSettings {
+    pub fn new() -> Self {
+        Self {
+            version: Some(PROTOCOL_VERSION),
+            extensions: supported_extensions(),
+            max_payload: None,
+        }
+    }
+
+    pub fn supports(&self, extension: &str) -> bool {
+        self.extensions.iter().any(|ext| ext == extension)
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut map = StringMap::new();
+        if let Some(version) = self.version {
+            map.insert("v".to_string(), version.to_string());
+        }
+        encode_extensions(&mut map, &self.extensions);
+        if let Some(max_payload) = self.max_payload {
+            map.insert("max-payload".to_string(), max_payload.to_string());
+        }
+        Bytes::from(map.to_bytes())
+    }
+
+    pub fn decode(data: &[u8]) -> Self {
+        let map = StringMap::from_bytes(data);
+        Self {
+            version: map.get("v").and_then(|v| v.parse().ok()),
+            extensions: decode_extensions(&map),
+            max_payload: decode_max_payload(&map),
+        }
+    }
+}
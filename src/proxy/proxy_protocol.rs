@@ -0,0 +1,106 @@
+//! HAProxy PROXY protocol v1/v2 header parsing.
+//!
+//! 负载均衡器在 TCP 连接开头（TLS 之前）写入 PROXY 头，携带真实的客户端地址。
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_MAX_LEN: usize = 107;
+
+/// 读取并解析 PROXY 头，只消费头部本身的字节。
+///
+/// 返回头部携带的源地址；`LOCAL` 命令或 `UNKNOWN`/非 IP 地址族返回 `None`。
+pub async fn read_proxy_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    // v1 最短的头 "PROXY UNKNOWN\r\n" 也有 15 字节，先读 12 字节不会越界
+    let mut head = [0u8; 12];
+    stream.read_exact(&mut head).await?;
+    if head == V2_SIGNATURE {
+        return read_v2(stream).await;
+    }
+    if !head.starts_with(b"PROXY ") {
+        return Err(invalid("missing PROXY protocol header"));
+    }
+
+    let mut line = head.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    parse_v1(&line[..line.len() - 2])
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+    let mut parts = line.split(' ');
+    let _ = parts.next(); // "PROXY"
+    match parts.next() {
+        Some("TCP4") | Some("TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("unsupported PROXY v1 protocol")),
+    }
+    let src: IpAddr = parse_field(parts.next())?;
+    let _dst: IpAddr = parse_field(parts.next())?;
+    let src_port: u16 = parse_field(parts.next())?;
+    let _dst_port: u16 = parse_field(parts.next())?;
+    if parts.next().is_some() {
+        return Err(invalid("trailing data in PROXY v1 header"));
+    }
+    Ok(Some(SocketAddr::new(src, src_port)))
+}
+
+fn parse_field<T: std::str::FromStr>(field: Option<&str>) -> io::Result<T> {
+    field
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(|| invalid("malformed PROXY v1 address"))
+}
+
+async fn read_v2<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut meta = [0u8; 4];
+    stream.read_exact(&mut meta).await?;
+    let ver_cmd = meta[0];
+    let family = meta[1] >> 4;
+    let len = u16::from_be_bytes([meta[2], meta[3]]) as usize;
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("unsupported PROXY v2 version"));
+    }
+
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await?;
+
+    match ver_cmd & 0x0F {
+        0x00 => return Ok(None), // LOCAL：健康检查等，由负载均衡器自身发起
+        0x01 => {}
+        _ => return Err(invalid("unsupported PROXY v2 command")),
+    }
+
+    match family {
+        0x1 => {
+            let addr = body.get(..12).ok_or_else(|| invalid("short PROXY v2 IPv4 block"))?;
+            let ip = Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
+            let port = u16::from_be_bytes([addr[8], addr[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        0x2 => {
+            let addr = body.get(..36).ok_or_else(|| invalid("short PROXY v2 IPv6 block"))?;
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&addr[..16]);
+            let port = u16::from_be_bytes([addr[32], addr[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
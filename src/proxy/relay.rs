@@ -0,0 +1,310 @@
+//! 带空闲超时的双向转发。
+//!
+//! 目标连接建立后可能中途停止收发，只限制 connect 时间无法回收这类连接；
+//! 这里在两个方向都没有数据流动超过指定时长时结束转发，由调用方关闭两端。
+//! 也可以传入共享的 [`BufferPool`]，转发缓冲区从池中借用而不是每条连接各自分配。
+//!
+//! 或者使用 [`AdaptiveBuffer`]：每个方向的缓冲区从 `min` 开始，持续读满时加倍直到 `max`，
+//! 安静一段时间后缩回 `min`。交互式连接一直使用小缓冲区，大流量传输每次读写更多数据。
+
+use crate::util::buffer_pool::{BufferPool, PooledBuffer};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::Instant;
+
+/// 与 `tokio::io::copy_bidirectional` 相同，但两个方向持续 `idle_timeout` 没有数据时返回 `TimedOut`。
+/// `idle_timeout` 为 0 时不限制。
+pub async fn copy_bidirectional_with_idle_timeout<A, B>(
+    a: &mut A,
+    b: &mut B,
+    idle_timeout: Duration,
+) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    relay(a, b, idle_timeout, Buffers::Default).await
+}
+
+/// 同 [`copy_bidirectional_with_idle_timeout`]，但两个方向的缓冲区从共享的 `pool` 借用
+pub async fn copy_bidirectional_pooled<A, B>(
+    a: &mut A,
+    b: &mut B,
+    idle_timeout: Duration,
+    pool: &Arc<BufferPool>,
+) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    relay(a, b, idle_timeout, Buffers::Pooled(pool)).await
+}
+
+/// 同 [`copy_bidirectional_with_idle_timeout`]，但两个方向各用一个 [`AdaptiveBuffer`]
+pub async fn copy_bidirectional_adaptive<A, B>(
+    a: &mut A,
+    b: &mut B,
+    idle_timeout: Duration,
+    sizes: AdaptiveSizes,
+) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    relay(a, b, idle_timeout, Buffers::Adaptive(sizes)).await
+}
+
+/// 转发缓冲区的来源
+#[derive(Clone, Copy)]
+enum Buffers<'a> {
+    /// tokio 自带的缓冲
+    Default,
+    Pooled(&'a Arc<BufferPool>),
+    Adaptive(AdaptiveSizes),
+}
+
+async fn relay<A, B>(
+    a: &mut A,
+    b: &mut B,
+    idle_timeout: Duration,
+    buffers: Buffers<'_>,
+) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    if idle_timeout.is_zero() {
+        return copy_both(a, b, buffers).await;
+    }
+
+    let activity = Activity::new();
+    let mut a = Tracked { inner: a, activity: &activity };
+    let mut b = Tracked { inner: b, activity: &activity };
+    tokio::select! {
+        result = copy_both(&mut a, &mut b, buffers) => result,
+        _ = activity.idle_for(idle_timeout) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "relay idle timeout",
+        )),
+    }
+}
+
+async fn copy_both<A, B>(a: &mut A, b: &mut B, buffers: Buffers<'_>) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    match buffers {
+        Buffers::Default => copy_bidirectional(a, b).await,
+        Buffers::Pooled(pool) => {
+            let (mut a_r, mut a_w) = tokio::io::split(a);
+            let (mut b_r, mut b_w) = tokio::io::split(b);
+            tokio::try_join!(
+                copy_one_way(&mut a_r, &mut b_w, pool.get()),
+                copy_one_way(&mut b_r, &mut a_w, pool.get()),
+            )
+        }
+        Buffers::Adaptive(sizes) => {
+            let (mut a_r, mut a_w) = tokio::io::split(a);
+            let (mut b_r, mut b_w) = tokio::io::split(b);
+            let (mut a_buf, mut b_buf) = (AdaptiveBuffer::new(sizes), AdaptiveBuffer::new(sizes));
+            tokio::try_join!(
+                copy_adaptive(&mut a_r, &mut b_w, &mut a_buf),
+                copy_adaptive(&mut b_r, &mut a_w, &mut b_buf),
+            )
+        }
+    }
+}
+
+/// 读到 EOF 后关闭写端，与 `copy_bidirectional` 的半关闭语义一致
+async fn copy_one_way<R, W>(r: &mut R, w: &mut W, mut buf: PooledBuffer) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut total = 0u64;
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            w.shutdown().await?;
+            return Ok(total);
+        }
+        w.write_all(&buf[..n]).await?;
+        total += n as u64;
+    }
+}
+
+/// 连续读满缓冲区这么多次后加倍
+const GROW_AFTER_FULL_READS: u32 = 4;
+/// 这么长时间没有数据时缩回最小值
+const SHRINK_AFTER_QUIET: Duration = Duration::from_secs(1);
+
+/// 自适应缓冲区的大小范围（字节）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveSizes {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl AdaptiveSizes {
+    /// 检查 `1 <= min <= max`
+    pub fn validate(&self) -> io::Result<()> {
+        if self.min == 0 || self.min > self.max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("adaptive buffer sizes must satisfy 0 < min <= max, got {:?}", self),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 单个方向的转发缓冲区：连续读满时加倍，安静时缩回最小值
+pub struct AdaptiveBuffer {
+    sizes: AdaptiveSizes,
+    buf: Vec<u8>,
+    full_reads: u32,
+}
+
+impl AdaptiveBuffer {
+    pub fn new(sizes: AdaptiveSizes) -> Self {
+        Self {
+            sizes,
+            buf: vec![0u8; sizes.min],
+            full_reads: 0,
+        }
+    }
+
+    /// 当前的缓冲区大小
+    pub fn size(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// 记录一次读取了 `n` 字节
+    fn on_read(&mut self, n: usize) {
+        if n < self.buf.len() {
+            self.full_reads = 0;
+            return;
+        }
+        self.full_reads += 1;
+        if self.full_reads >= GROW_AFTER_FULL_READS && self.buf.len() < self.sizes.max {
+            let size = (self.buf.len() * 2).min(self.sizes.max);
+            self.buf.resize(size, 0);
+            self.full_reads = 0;
+        }
+    }
+
+    /// 一段时间没有数据，释放多余的内存
+    fn on_quiet(&mut self) {
+        self.full_reads = 0;
+        if self.buf.len() > self.sizes.min {
+            self.buf.truncate(self.sizes.min);
+            self.buf.shrink_to_fit();
+        }
+    }
+}
+
+/// 用 `buf` 把 `r` 的数据转发到 `w`，读到 EOF 后关闭写端，返回转发的字节数
+pub async fn copy_adaptive<R, W>(r: &mut R, w: &mut W, buf: &mut AdaptiveBuffer) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut total = 0u64;
+    loop {
+        // read 可以安全取消，超时不会丢失数据
+        let n = match tokio::time::timeout(SHRINK_AFTER_QUIET, r.read(&mut buf.buf)).await {
+            Ok(n) => n?,
+            Err(_) => {
+                buf.on_quiet();
+                continue;
+            }
+        };
+        if n == 0 {
+            w.shutdown().await?;
+            return Ok(total);
+        }
+        w.write_all(&buf.buf[..n]).await?;
+        total += n as u64;
+        buf.on_read(n);
+    }
+}
+
+/// 最近一次收发数据的时间，以相对 `start` 的毫秒数保存
+struct Activity {
+    start: Instant,
+    last_ms: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let ms = self.start.elapsed().as_millis() as u64;
+        self.last_ms.store(ms, Ordering::Relaxed);
+    }
+
+    /// 空闲时长达到 `timeout` 时完成
+    async fn idle_for(&self, timeout: Duration) {
+        loop {
+            let last = self.start + Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+            let deadline = last + timeout;
+            if Instant::now() >= deadline {
+                return;
+            }
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+}
+
+struct Tracked<'a, T: ?Sized> {
+    inner: &'a mut T,
+    activity: &'a Activity,
+}
+
+impl<T: AsyncRead + Unpin + ?Sized> AsyncRead for Tracked<'_, T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        if matches!(result, Poll::Ready(Ok(()))) && buf.filled().len() > before {
+            self.activity.touch();
+        }
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin + ?Sized> AsyncWrite for Tracked<'_, T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if matches!(result, Poll::Ready(Ok(n)) if n > 0) {
+            self.activity.touch();
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
@@ -1,11 +1,15 @@
+use crate::proxy::addr_codec::{
+    build_target_header, build_typed_target_header, SocksAddr, StreamType,
+};
 use crate::proxy::padding::PaddingFactory;
-use crate::proxy::session::{Session, Stream};
+use crate::proxy::session::{PaddingStats, Session, SessionConfig, Stream};
 use crate::util::r#type::DialOutFunc;
 use linked_hash_map::LinkedHashMap;
 use std::collections::HashMap;
 use std::io;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use tokio::io::AsyncWriteExt;
 use tokio::time::Duration;
 
 const MAX_ACTIVE_STREAMS_PER_SESSION: u32 = 8;
@@ -40,6 +44,10 @@ impl IdlePool {
         self.entries.len()
     }
 
+    fn contains(&self, session: &Arc<Session>) -> bool {
+        self.entries.contains_key(&session_key(session))
+    }
+
     fn insert_or_refresh(&mut self, session: Arc<Session>, idle_since_ms: u64) {
         let key = session_key(&session);
         let _ = self.entries.remove(&key);
@@ -105,25 +113,135 @@ pub struct Client {
     active_sessions: Arc<Mutex<HashMap<usize, Arc<Session>>>>,
     idle_timeout: Duration,
     min_idle_sessions: usize,
+    max_idle_sessions: usize,
+    max_session_age: Duration,
+    max_session_uses: u64,
+    session_config: SessionConfig,
+    connect_timeout: Option<Duration>,
     closed: Arc<AtomicBool>,
     prewarm_running: Arc<AtomicBool>,
 }
 
-impl Client {
-    pub fn new(
-        dial_out: DialOutFunc,
-        padding: Arc<PaddingFactory>,
-        idle_timeout: Duration,
-        min_idle_sessions: usize,
-    ) -> Self {
-        let client = Self {
+/// 池中 Session 的元数据，用于指标统计
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub age: Duration,
+    pub last_used_unix_ms: u64,
+    pub streams_served: u64,
+    pub active_streams: u32,
+    /// 已收到但尚未被读取的字节数
+    pub buffered_bytes: usize,
+    /// 填充带来的额外字节，用于评估填充方案的开销
+    pub padding: PaddingStats,
+    pub idle: bool,
+}
+
+/// Client 构建器，未设置的参数使用默认值
+pub struct ClientBuilder {
+    dial_out: DialOutFunc,
+    padding: Arc<PaddingFactory>,
+    idle_timeout: Duration,
+    min_idle_sessions: usize,
+    max_idle_sessions: usize,
+    max_session_age: Duration,
+    max_session_uses: u64,
+    session_config: SessionConfig,
+    connect_timeout: Option<Duration>,
+}
+
+impl ClientBuilder {
+    /// 空闲 Session 的超时时间，默认 30 秒
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// 至少保留的空闲 Session 数，默认 1
+    pub fn min_idle_sessions(mut self, min_idle_sessions: usize) -> Self {
+        self.min_idle_sessions = min_idle_sessions;
+        self
+    }
+
+    /// 空闲池的硬上限，0 表示不限制（默认）
+    pub fn max_idle_sessions(mut self, max_idle_sessions: usize) -> Self {
+        self.max_idle_sessions = max_idle_sessions;
+        self
+    }
+
+    /// Session 存活超过该时长后不再复用，0 表示不限制（默认）
+    pub fn max_session_age(mut self, max_session_age: Duration) -> Self {
+        self.max_session_age = max_session_age;
+        self
+    }
+
+    /// Session 累计承载该数量的 Stream 后不再复用，0 表示不限制（默认）
+    pub fn max_session_uses(mut self, max_session_uses: u64) -> Self {
+        self.max_session_uses = max_session_uses;
+        self
+    }
+
+    /// 新建 Session 使用的参数，会覆盖此前设置的单项参数
+    pub fn session_config(mut self, session_config: SessionConfig) -> Self {
+        self.session_config = session_config;
+        self
+    }
+
+    /// 每个 Stream 的接收窗口（字节），见 [`Session::with_recv_window`]
+    pub fn recv_window(mut self, recv_window: usize) -> Self {
+        self.session_config.recv_window = recv_window;
+        self
+    }
+
+    /// 连接写入超时，见 [`Session::with_write_timeout`]，0 表示不限制（默认）
+    pub fn write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.session_config.write_timeout = write_timeout;
+        self
+    }
+
+    /// 单帧负载上限（字节），见 [`Session::with_max_payload`]
+    pub fn max_payload(mut self, max_payload: usize) -> Self {
+        self.session_config.max_payload = max_payload;
+        self
+    }
+
+    /// 拒绝单个包填充超过 `bytes` 字节的填充方案更新，见 [`Session::with_max_padding_bytes`]
+    pub fn max_padding_bytes(mut self, bytes: usize) -> Self {
+        self.session_config.max_padding_bytes = bytes;
+        self
+    }
+
+    /// 在 SETTINGS 中上报本机操作系统与架构，默认关闭
+    pub fn report_platform(mut self, report_platform: bool) -> Self {
+        self.session_config.report_platform = report_platform;
+        self
+    }
+
+    /// 自适应填充的空闲间隔，见 [`Session::with_padding_idle_reset`]，0 表示关闭（默认）
+    pub fn padding_idle_reset(mut self, padding_idle_reset: Duration) -> Self {
+        self.session_config.padding_idle_reset = padding_idle_reset;
+        self
+    }
+
+    /// 请服务端连接目标时使用的超时，服务端按自己的上限截断；`None` 时使用服务端默认值（默认）
+    pub fn connect_timeout(mut self, connect_timeout: Option<Duration>) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let client = Client {
             id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
-            dial_out,
-            padding,
+            dial_out: self.dial_out,
+            padding: self.padding,
             idle_sessions: Arc::new(Mutex::new(IdlePool::new())),
             active_sessions: Arc::new(Mutex::new(HashMap::new())),
-            idle_timeout,
-            min_idle_sessions,
+            idle_timeout: self.idle_timeout,
+            min_idle_sessions: self.min_idle_sessions,
+            max_idle_sessions: self.max_idle_sessions,
+            max_session_age: self.max_session_age,
+            max_session_uses: self.max_session_uses,
+            session_config: self.session_config,
+            connect_timeout: self.connect_timeout,
             closed: Arc::new(AtomicBool::new(false)),
             prewarm_running: Arc::new(AtomicBool::new(false)),
         };
@@ -137,6 +255,119 @@ impl Client {
 
         client
     }
+}
+
+impl Client {
+    pub fn builder(dial_out: DialOutFunc, padding: Arc<PaddingFactory>) -> ClientBuilder {
+        ClientBuilder {
+            dial_out,
+            padding,
+            idle_timeout: Duration::from_secs(30),
+            min_idle_sessions: 1,
+            max_idle_sessions: 0,
+            max_session_age: Duration::ZERO,
+            max_session_uses: 0,
+            session_config: SessionConfig::default(),
+            connect_timeout: None,
+        }
+    }
+
+    pub fn new(
+        dial_out: DialOutFunc,
+        padding: Arc<PaddingFactory>,
+        idle_timeout: Duration,
+        min_idle_sessions: usize,
+    ) -> Self {
+        Self::builder(dial_out, padding)
+            .idle_timeout(idle_timeout)
+            .min_idle_sessions(min_idle_sessions)
+            .build()
+    }
+
+    /// 构建时设置的连接超时，见 [`ClientBuilder::connect_timeout`]
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    pub fn idle_session_count(&self) -> usize {
+        self.idle_sessions.lock_pool().len()
+    }
+
+    /// 当前所有 Session（含空闲）的元数据
+    pub fn session_info(&self) -> Vec<SessionInfo> {
+        let sessions: Vec<Arc<Session>> =
+            self.active_sessions.lock_pool().values().cloned().collect();
+        let idle_sessions = self.idle_sessions.lock_pool();
+        sessions
+            .iter()
+            .map(|session| SessionInfo {
+                age: session.created_at().elapsed(),
+                last_used_unix_ms: session.last_active_unix_ms(),
+                streams_served: session.streams_served(),
+                active_streams: session.stream_count(),
+                buffered_bytes: session.buffered_bytes(),
+                padding: session.padding_stats(),
+                idle: idle_sessions.contains(session),
+            })
+            .collect()
+    }
+
+    /// 复用策略：未关闭，且未超过存活时长与累计使用次数上限
+    fn is_reusable(&self, session: &Session) -> bool {
+        if session.is_closed() {
+            return false;
+        }
+        if !self.max_session_age.is_zero() && session.created_at().elapsed() >= self.max_session_age
+        {
+            return false;
+        }
+        self.max_session_uses == 0 || session.streams_served() < self.max_session_uses
+    }
+
+    async fn retire_sessions(&self, sessions: Vec<Arc<Session>>) {
+        for session in sessions {
+            let padding = session.padding_stats();
+            log::debug!(
+                "Retiring session, streams_served={}, age={:?}, padding={}/{} bytes ({:.2}x)",
+                session.streams_served(),
+                session.created_at().elapsed(),
+                padding.padding_bytes,
+                padding.payload_bytes,
+                padding.amplification()
+            );
+            self.remove_active_session(&session).await;
+            let _ = session.close().await;
+        }
+    }
+
+    /// 新建 Stream 并写入目标地址，`target` 为 `host:port` 或服务端本机的 `unix:/path`
+    pub async fn connect(&self, target: &str) -> io::Result<Stream> {
+        self.connect_with_timeout(target, self.connect_timeout).await
+    }
+
+    /// 同 [`Client::connect`]，但为这个 Stream 单独指定服务端的连接超时
+    pub async fn connect_with_timeout(
+        &self,
+        target: &str,
+        connect_timeout: Option<Duration>,
+    ) -> io::Result<Stream> {
+        let header = build_target_header(&SocksAddr::parse_target(target)?, connect_timeout)?;
+        let mut stream = self.create_stream().await?;
+        stream.write_all(&header).await?;
+        stream.set_target(target);
+        Ok(stream)
+    }
+
+    /// 新建转发 UDP 数据报的 Stream，服务端把每个数据报发往 `target`（`host:port`）。
+    /// 数据报在 Stream 中编码为 `LEN(2) + DATA`，目标的响应以同样的格式返回
+    pub async fn connect_udp(&self, target: &str) -> io::Result<Stream> {
+        let addr = SocksAddr::parse_target(target)?;
+        let header = build_typed_target_header(StreamType::Udp, &addr, None)?;
+        let mut stream = self.create_stream().await?;
+        stream.write_all(&header).await?;
+        stream.set_target(target);
+        Ok(stream)
+    }
 
     pub async fn create_stream(&self) -> io::Result<Stream> {
         if self.closed.load(Ordering::Acquire) {
@@ -158,7 +389,7 @@ impl Client {
         };
         let this = self.clone();
         let session_for_hook = Arc::clone(&session);
-        let stream_id = stream.id;
+        let stream_id = stream.id();
         stream.set_on_close(Box::new(move || {
             tokio::spawn(async move {
                 session_for_hook.finish_stream(stream_id).await;
@@ -208,16 +439,28 @@ impl Client {
         Ok(rtt)
     }
 
+    /// 优先取最近归还的（最新鲜的）空闲 Session，超过复用上限的直接淘汰
     async fn get_idle_session(&self) -> Option<Arc<Session>> {
-        let mut idle_sessions = self.idle_sessions.lock_pool();
-        while let Some(entry) = idle_sessions.pop_back() {
-            if !entry.session.is_closed() {
-                let session = entry.session;
-                self.ensure_min_idle_sessions_background();
-                return Some(session);
+        let mut retired = Vec::new();
+        let found = {
+            let mut idle_sessions = self.idle_sessions.lock_pool();
+            let mut found = None;
+            while let Some(entry) = idle_sessions.pop_back() {
+                if self.is_reusable(&entry.session) {
+                    found = Some(entry.session);
+                    break;
+                }
+                if !entry.session.is_closed() {
+                    retired.push(entry.session);
+                }
             }
+            found
+        };
+        self.retire_sessions(retired).await;
+        if found.is_some() {
+            self.ensure_min_idle_sessions_background();
         }
-        None
+        found
     }
 
     async fn get_active_session(&self) -> Option<Arc<Session>> {
@@ -226,7 +469,7 @@ impl Client {
         active_sessions
             .values()
             .filter(|session| {
-                !session.is_closed() && session.stream_count() < MAX_ACTIVE_STREAMS_PER_SESSION
+                self.is_reusable(session) && session.stream_count() < MAX_ACTIVE_STREAMS_PER_SESSION
             })
             .min_by_key(|session| session.stream_count())
             .cloned()
@@ -234,7 +477,9 @@ impl Client {
 
     async fn create_session(&self) -> io::Result<Arc<Session>> {
         let conn = (self.dial_out)().await?;
-        let session = Arc::new(Session::new_client(conn, self.padding.clone()));
+        let session = Arc::new(
+            Session::new_client(conn, self.padding.clone(), self.session_config),
+        );
         session.run().await?;
         self.active_sessions
             .lock_pool()
@@ -253,19 +498,38 @@ impl Client {
         if session.stream_count() != 0 {
             return;
         }
+        if !self.is_reusable(&session) {
+            self.retire_sessions(vec![session]).await;
+            return;
+        }
 
         self.insert_idle_session(session).await;
         self.ensure_min_idle_sessions_background();
     }
 
     async fn insert_idle_session(&self, session: Arc<Session>) {
-        let mut idle_sessions = self.idle_sessions.lock_pool();
-        idle_sessions.insert_or_refresh(session, now_unix_ms());
-        let idle_pool_size = idle_sessions.len();
+        {
+            let mut idle_sessions = self.idle_sessions.lock_pool();
+            let at_cap = self.max_idle_sessions > 0
+                && idle_sessions.len() >= self.max_idle_sessions
+                && !idle_sessions.contains(&session);
+            if !at_cap {
+                idle_sessions.insert_or_refresh(session, now_unix_ms());
+                let idle_pool_size = idle_sessions.len();
+                log::debug!(
+                    "Session returned to idle pool, idle_pool_size={}",
+                    idle_pool_size
+                );
+                return;
+            }
+        }
+
         log::debug!(
-            "Session returned to idle pool, idle_pool_size={}",
-            idle_pool_size
+            "Idle pool full (max_idle_sessions={}), closing session",
+            self.max_idle_sessions
         );
+        self.remove_active_session(&session).await;
+        let _ = session.close().await;
     }
 
     async fn remove_active_session(&self, session: &Arc<Session>) {
@@ -426,6 +690,11 @@ impl Clone for Client {
             active_sessions: self.active_sessions.clone(),
             idle_timeout: self.idle_timeout,
             min_idle_sessions: self.min_idle_sessions,
+            max_idle_sessions: self.max_idle_sessions,
+            max_session_age: self.max_session_age,
+            max_session_uses: self.max_session_uses,
+            session_config: self.session_config,
+            connect_timeout: self.connect_timeout,
             closed: self.closed.clone(),
             prewarm_running: self.prewarm_running.clone(),
         }
@@ -0,0 +1,44 @@
+use crate::proxy::protocol::frame::{Frame, RawHeader, HEADER_OVERHEAD_SIZE};
+use bytes::{Buf, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// 基于 tokio-util 的 Frame 编解码器，可配合 `Framed`/`FramedRead` 在任意 AsyncRead/AsyncWrite 上使用
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameCodec;
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Frame>> {
+        if src.len() < HEADER_OVERHEAD_SIZE {
+            return Ok(None);
+        }
+        let header = RawHeader::from_bytes(&src[..HEADER_OVERHEAD_SIZE])?;
+        let frame_len = HEADER_OVERHEAD_SIZE + header.length as usize;
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(HEADER_OVERHEAD_SIZE);
+        let data = src.split_to(header.length as usize).freeze();
+        Ok(Some(Frame::with_data(header.cmd, header.sid, data)))
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> io::Result<()> {
+        if frame.data.len() > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame payload exceeds 65535 bytes",
+            ));
+        }
+        frame.encode_into(dst);
+        Ok(())
+    }
+}
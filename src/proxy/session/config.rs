@@ -0,0 +1,73 @@
+//! Session 的可调参数。
+//!
+//! 客户端与服务端共用同一组默认值。两端固有的差异由角色决定，不在这里配置：
+//! 只有客户端发送 SETTINGS、填充数据帧并等待 SYNACK，只有服务端接受对端打开的 Stream。
+
+use crate::proxy::protocol::frame::{MAX_PAYLOAD_SIZE, MIN_MAX_PAYLOAD_SIZE};
+use std::time::Duration;
+
+/// 每个 Stream 默认的接收窗口（字节）
+pub const DEFAULT_RECV_WINDOW: usize = 4 * 1024 * 1024;
+/// 接收窗口下限：至少能容纳一个最大的 PSH 帧
+pub(crate) const MIN_RECV_WINDOW: usize = u16::MAX as usize;
+/// 写循环每个优先级队列默认的容量（帧）
+pub const DEFAULT_FRAME_QUEUE_CAPACITY: usize = 1024;
+
+/// 创建 Session 时的参数，未修改的字段使用 [`SessionConfig::default`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// 每个 Stream 最多缓存的未读字节数，见 [`super::Session::with_recv_window`]
+    pub recv_window: usize,
+    /// 本端声明的单帧负载上限，取值限制在 256..=65535 之间
+    pub max_payload: usize,
+    /// 单次写入连接的超时，0 表示不限制
+    pub write_timeout: Duration,
+    /// 写循环每个优先级队列的容量（帧），队列满时写入方等待
+    pub frame_queue_capacity: usize,
+    /// 服务端：已接受 Stream 的队列容量，`None` 时交给 `on_new_stream` 回调
+    pub accept_backlog: Option<usize>,
+    /// 客户端：在 SETTINGS 中附带本机操作系统与架构
+    pub report_platform: bool,
+    /// 客户端：自适应填充的空闲间隔，0 表示关闭
+    pub padding_idle_reset: Duration,
+    /// 所有 Stream 已收到未读出的数据总量上限（字节），超过时告警并关闭 Session，0 表示不限制
+    pub max_buffered: usize,
+    /// 关闭 Stream 时等待对端确认 CMD_FIN 的最长时间，0 表示不等待；对端不支持时不等待
+    pub fin_ack_timeout: Duration,
+    /// 服务端：每秒最多接受的新 Stream 数，可突发一秒的配额，0 表示不限制
+    pub max_syn_rate: u32,
+    /// 客户端：服务端下发的填充方案中单个包最多写出的字节数，超过时告警并关闭 Session，0 表示不限制
+    pub max_padding_bytes: usize,
+    /// 服务端：客户端声明支持时，压缩下发的填充方案
+    pub compress_padding: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            recv_window: DEFAULT_RECV_WINDOW,
+            max_payload: MAX_PAYLOAD_SIZE,
+            write_timeout: Duration::ZERO,
+            frame_queue_capacity: DEFAULT_FRAME_QUEUE_CAPACITY,
+            accept_backlog: None,
+            report_platform: false,
+            padding_idle_reset: Duration::ZERO,
+            max_buffered: 0,
+            fin_ack_timeout: Duration::ZERO,
+            max_syn_rate: 0,
+            max_padding_bytes: 0,
+            compress_padding: false,
+        }
+    }
+}
+
+impl SessionConfig {
+    /// 把超出范围的取值限制到有效范围内
+    pub(crate) fn normalized(mut self) -> Self {
+        self.recv_window = self.recv_window.clamp(MIN_RECV_WINDOW, u32::MAX as usize);
+        self.max_payload = self.max_payload.clamp(MIN_MAX_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE);
+        self.frame_queue_capacity = self.frame_queue_capacity.max(1);
+        self.accept_backlog = self.accept_backlog.map(|backlog| backlog.max(1));
+        self
+    }
+}
@@ -1,57 +1,111 @@
-use crate::proxy::padding::PaddingFactory;
+use crate::proxy::padding::{compress_scheme, PaddingFactory};
+use crate::proxy::protocol::frame::{
+    Frame, CMD_FIN, CMD_HEART_REQUEST, CMD_PSH, CMD_RST, CMD_SETTINGS, CMD_SYN,
+    CMD_UPDATE_PADDING_SCHEME,
+    HEADER_OVERHEAD_SIZE, MAX_PAYLOAD_SIZE, MIN_MAX_PAYLOAD_SIZE,
+};
+use crate::proxy::protocol::settings::ClientSettings;
 use crate::proxy::session::close_reason::is_expected_close_error;
-use crate::proxy::session::frame::{
-    Frame, CMD_FIN, CMD_HEART_REQUEST, CMD_PSH, CMD_SETTINGS, CMD_SYN, HEADER_OVERHEAD_SIZE,
+use crate::proxy::session::config::{SessionConfig, MIN_RECV_WINDOW};
+use crate::proxy::session::io_loop::{
+    flush_outbound, outbound_channel, write_frame_to, Outbound, OutboundRx, OutboundTx,
+    StreamDropped,
+};
+use crate::proxy::session::state::{SessionState, STREAM_ID_STEP};
+use crate::proxy::session::stream::{
+    reset_message, FinAck, Priority, Stream, StreamHandle, StreamInfo, StreamParams,
 };
-use crate::proxy::session::io_loop::write_frame_to;
-use crate::proxy::session::state::SessionState;
-use crate::proxy::session::stream::Stream;
 use crate::util::r#type::AsyncReadWrite;
-use crate::util::string_map::{StringMap, StringMapExt};
+use arc_swap::ArcSwap;
 use bytes::Bytes;
 use std::io;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::io::{AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::sync::{mpsc, oneshot, Mutex, Notify};
-use tokio::time::Duration;
+use tokio::time::{Duration, Instant};
 
 const SYNACK_TIMEOUT: Duration = Duration::from_secs(3);
 
+/// 进程内递增的 Session 编号，只用于日志
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Session 在连接中的角色，决定它处理哪些命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// 发送 SETTINGS、打开 Stream 并按填充方案填充
+    Client,
+    /// 接受对端打开的 Stream，可以下发填充方案
+    Server,
+}
+
+impl Role {
+    pub fn is_client(self) -> bool {
+        self == Role::Client
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Client => "Client",
+            Role::Server => "Server",
+        })
+    }
+}
+
+/// [`Session::padding_stats`] 返回的填充开销，只统计按填充方案发送的前几个包
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PaddingStats {
+    /// 填充阶段写出的数据与控制帧字节数（含帧头）
+    pub payload_bytes: u64,
+    /// 填充阶段写出的 CMD_WASTE 帧字节数（含帧头）
+    pub padding_bytes: u64,
+}
+
+impl PaddingStats {
+    /// 填充阶段实际写出的字节数与负载字节数之比，未发送负载时为 1
+    pub fn amplification(&self) -> f64 {
+        if self.payload_bytes == 0 {
+            return 1.0;
+        }
+        (self.payload_bytes + self.padding_bytes) as f64 / self.payload_bytes as f64
+    }
+}
+
 /// Session 管理多个 Stream 的连接复用
 pub struct Session {
+    id: u64,
     pub(super) state: SessionState,
     pub(super) conn_r: Mutex<Option<ReadHalf<Box<dyn AsyncReadWrite>>>>,
     pub(super) conn_w: Mutex<Option<WriteHalf<Box<dyn AsyncReadWrite>>>>,
-    pub(super) is_client: bool,
-    pub(super) padding: Arc<PaddingFactory>,
+    pub(super) role: Role,
+    /// 创建时的参数，已限制在有效范围内
+    pub(super) config: SessionConfig,
+    /// 当前填充方案，可由 [`Session::set_padding`] 在运行中替换
+    pub(super) padding: ArcSwap<PaddingFactory>,
     pub(super) pkt_counter: AtomicU32,
     pub(super) send_padding: AtomicBool,
-    pub(super) frame_tx: mpsc::Sender<Frame>,
-    pub(super) frame_rx: Mutex<Option<mpsc::Receiver<Frame>>>,
+    /// 写循环最近一次写出数据帧的时间
+    pub(super) last_write: std::sync::Mutex<Option<Instant>>,
+    pub(super) frame_tx: OutboundTx,
+    pub(super) frame_rx: Mutex<Option<OutboundRx>>,
+    pub(super) dropped_tx: mpsc::UnboundedSender<StreamDropped>,
+    dropped_rx: Mutex<Option<mpsc::UnboundedReceiver<StreamDropped>>>,
     pub(super) close_notify: Arc<Notify>,
     pub(super) on_new_stream: Option<Arc<dyn Fn(Stream) + Send + Sync>>,
     pub(super) on_close: Option<Arc<dyn Fn() + Send + Sync>>,
+    pub(super) incoming_tx: Option<mpsc::Sender<Stream>>,
+    incoming_rx: std::sync::Mutex<Option<mpsc::Receiver<Stream>>>,
 }
 
 impl Session {
-    pub fn new_client(conn: Box<dyn AsyncReadWrite>, padding: Arc<PaddingFactory>) -> Self {
-        let (conn_r, conn_w) = tokio::io::split(conn);
-        let (frame_tx, frame_rx) = mpsc::channel(1024);
-        Self {
-            state: SessionState::new(),
-            conn_r: Mutex::new(Some(conn_r)),
-            conn_w: Mutex::new(Some(conn_w)),
-            is_client: true,
-            padding,
-            pkt_counter: AtomicU32::new(0),
-            send_padding: AtomicBool::new(true),
-            frame_tx,
-            frame_rx: Mutex::new(Some(frame_rx)),
-            close_notify: Arc::new(Notify::new()),
-            on_new_stream: None,
-            on_close: None,
-        }
+    pub fn new_client(
+        conn: Box<dyn AsyncReadWrite>,
+        padding: Arc<PaddingFactory>,
+        config: SessionConfig,
+    ) -> Self {
+        Self::new(conn, Role::Client, None, None, padding, config)
     }
 
     pub fn new_server(
@@ -59,30 +113,201 @@ impl Session {
         on_new_stream: Option<Arc<dyn Fn(Stream) + Send + Sync>>,
         on_close: Option<Arc<dyn Fn() + Send + Sync>>,
         padding: Arc<PaddingFactory>,
+        config: SessionConfig,
     ) -> Self {
+        Self::new(conn, Role::Server, on_new_stream, on_close, padding, config)
+    }
+
+    fn new(
+        conn: Box<dyn AsyncReadWrite>,
+        role: Role,
+        on_new_stream: Option<Arc<dyn Fn(Stream) + Send + Sync>>,
+        on_close: Option<Arc<dyn Fn() + Send + Sync>>,
+        padding: Arc<PaddingFactory>,
+        config: SessionConfig,
+    ) -> Self {
+        let config = config.normalized();
         let (conn_r, conn_w) = tokio::io::split(conn);
-        let (frame_tx, frame_rx) = mpsc::channel(1024);
+        let (frame_tx, frame_rx) = outbound_channel(config.frame_queue_capacity);
+        let (dropped_tx, dropped_rx) = mpsc::unbounded_channel();
+        let (incoming_tx, incoming_rx) = match config.accept_backlog {
+            Some(backlog) => {
+                let (tx, rx) = mpsc::channel(backlog);
+                (Some(tx), Some(rx))
+            }
+            None => (None, None),
+        };
+        // 客户端使用奇数 id，服务端使用偶数 id
+        let state = SessionState::new(if role.is_client() { 1 } else { 2 });
+        state.send_max_payload.store(config.max_payload, Ordering::Release);
         Self {
-            state: SessionState::new(),
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            state,
             conn_r: Mutex::new(Some(conn_r)),
             conn_w: Mutex::new(Some(conn_w)),
-            is_client: false,
-            padding,
+            role,
+            config,
+            padding: ArcSwap::new(padding),
             pkt_counter: AtomicU32::new(0),
-            send_padding: AtomicBool::new(false),
+            // 只有客户端填充
+            send_padding: AtomicBool::new(role.is_client()),
+            last_write: std::sync::Mutex::new(None),
             frame_tx,
             frame_rx: Mutex::new(Some(frame_rx)),
+            dropped_tx,
+            dropped_rx: Mutex::new(Some(dropped_rx)),
             close_notify: Arc::new(Notify::new()),
             on_new_stream,
             on_close,
+            incoming_tx,
+            incoming_rx: std::sync::Mutex::new(incoming_rx),
+        }
+    }
+
+    /// 当前生效的参数
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// 进程内唯一的编号，与日志中的 `Session N` 对应
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// 下一个分配的 Stream id 从 `id` 开始查找，按角色调整奇偶性，仅供测试回绕
+    #[doc(hidden)]
+    pub fn set_next_stream_id(&self, id: u32) {
+        let id = if self.role.is_client() { id | 1 } else { id & !1 };
+        self.state.next_stream_id.store(id, Ordering::Release);
+    }
+
+    /// 服务端：新建的 Stream 改为投递到容量为 `backlog` 的有界队列，通过 [`Session::incoming`] 取出。
+    /// 队列已满时直接以 SYNACK 错误拒绝新的 SYN，不再调用 `on_new_stream`。
+    pub fn with_accept_backlog(mut self, backlog: usize) -> Self {
+        let backlog = backlog.max(1);
+        let (tx, rx) = mpsc::channel(backlog);
+        self.config.accept_backlog = Some(backlog);
+        self.incoming_tx = Some(tx);
+        self.incoming_rx = std::sync::Mutex::new(Some(rx));
+        self
+    }
+
+    /// 服务端：每秒最多接受 `rate` 个新 Stream，可突发一秒的配额，超过的 SYN 以 SYNACK 错误拒绝。
+    /// 0 表示不限制（默认）
+    pub fn with_max_syn_rate(mut self, rate: u32) -> Self {
+        self.config.max_syn_rate = rate;
+        self
+    }
+
+    /// 每个 Stream 最多缓存 `bytes` 字节未读数据，超过后暂停读取连接，直到应用读走数据。
+    /// 小于一个最大帧（65535 字节）时按一个最大帧计算。
+    pub fn with_recv_window(mut self, bytes: usize) -> Self {
+        self.config.recv_window = bytes.clamp(MIN_RECV_WINDOW, u32::MAX as usize);
+        self
+    }
+
+    /// 单次写入连接超过 `timeout` 仍未完成时判定连接失效并关闭 Session，0 表示不限制（默认）。
+    /// 对端停止读取时，否则所有 Stream 的写入都会永久挂起。
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    /// 关闭 Stream 的写方向时最多等待 `timeout` 让对端确认 CMD_FIN，0 表示不等待（默认）。
+    /// 对端不支持该扩展时不等待
+    pub fn with_fin_ack_timeout(mut self, timeout: Duration) -> Self {
+        self.config.fin_ack_timeout = timeout;
+        self
+    }
+
+    /// 在设置中声明单帧负载上限（字节），发送方按双方上限中较小的一个拆分数据帧。
+    /// 取值限制在 256..=65535 之间
+    pub fn with_max_payload(mut self, bytes: usize) -> Self {
+        self.config.max_payload = bytes.clamp(MIN_MAX_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE);
+        self.state.send_max_payload.store(self.config.max_payload, Ordering::Release);
+        self
+    }
+
+    /// 客户端：连续 `gap` 没有写出数据后，下一次写出时包序号归零，重新填充 `stop` 个包，
+    /// 使每段突发流量都像一条新建的连接。0 表示关闭（默认）；服务端不填充，设置无效
+    pub fn with_padding_idle_reset(mut self, gap: Duration) -> Self {
+        self.config.padding_idle_reset = gap;
+        self
+    }
+
+    /// 客户端：服务端通过 CMD_UPDATE_PADDING_SCHEME 下发的方案中，单个包最多写出的字节数
+    /// 超过 `bytes` 时回复 CMD_ALERT 并关闭 Session，不采用该方案。0 表示不限制（默认）
+    pub fn with_max_padding_bytes(mut self, bytes: usize) -> Self {
+        self.config.max_padding_bytes = bytes;
+        self
+    }
+
+    /// 服务端：客户端声明支持时，CMD_UPDATE_PADDING_SCHEME 下发压缩的方案，默认关闭
+    pub fn with_compress_padding(mut self, compress: bool) -> Self {
+        self.config.compress_padding = compress;
+        self
+    }
+
+    /// 客户端：在 SETTINGS 中附带本机操作系统与架构，默认关闭
+    pub fn with_report_platform(mut self, report: bool) -> Self {
+        self.config.report_platform = report;
+        self
+    }
+
+    pub(super) fn stream_params(&self) -> StreamParams {
+        StreamParams {
+            frame_tx: self.frame_tx.clone(),
+            dropped_tx: self.dropped_tx.clone(),
+            recv_window: self.config.recv_window,
+            sequenced: self.state.peer_psh_seq.load(Ordering::Acquire),
+            max_payload: Arc::clone(&self.state.send_max_payload),
+            session_buffered: Arc::clone(&self.state.buffered_bytes),
+            peer_stop_sending: Arc::clone(&self.state.peer_stop_sending),
+            awaits_synack: false,
+            fin_ack: FinAck {
+                timeout: self.config.fin_ack_timeout,
+                peer_supports: Arc::clone(&self.state.peer_fin_ack),
+                waiters: Arc::clone(&self.state.fin_ack_waiters),
+            },
         }
     }
 
+    /// 对端声明了上限时，发送方改用双方上限中较小的一个
+    pub(super) fn apply_peer_max_payload(&self, peer_max_payload: Option<usize>) {
+        if let Some(peer) = peer_max_payload {
+            let limit = peer
+                .clamp(MIN_MAX_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE)
+                .min(self.config.max_payload);
+            self.state.send_max_payload.store(limit, Ordering::Release);
+        }
+    }
+
+    /// 服务端：客户端在 SETTINGS 中发送的设置，尚未收到时为 `None`
+    pub fn peer_settings(&self) -> Option<ClientSettings> {
+        self.state
+            .peer_settings
+            .lock()
+            .expect("session peer settings lock poisoned")
+            .clone()
+    }
+
+    /// 取出已接受 Stream 的接收端，只能取一次；未设置 accept backlog 时返回 `None`
+    pub fn incoming(&self) -> Option<mpsc::Receiver<Stream>> {
+        self.incoming_rx
+            .lock()
+            .expect("session incoming lock poisoned")
+            .take()
+    }
+
     /// 启动 Session。采用“后台循环 + 立即返回”的模型。
     pub async fn run(self: &Arc<Self>) -> io::Result<()> {
-        log::debug!("[Session] Starting session (client: {})", self.is_client);
-        if self.is_client {
-            self.send_client_settings().await?;
+        log::debug!("[Session] Starting session {} (role: {})", self.id, self.role);
+        if self.role.is_client() {
+            self.timed_write(self.send_client_settings()).await?;
             log::debug!("[Session] Client settings sent");
         }
 
@@ -91,20 +316,36 @@ impl Session {
                 io::Error::other("writer loop already started")
             })?;
 
+        let mut dropped_rx = self.dropped_rx.lock().await.take().ok_or_else(|| {
+            io::Error::other("writer loop already started")
+        })?;
+
         let writer_session = Arc::clone(self);
         tokio::spawn(async move {
-            writer_session.run_writer_loop(&mut writer_rx).await;
+            writer_session.run_writer_loop(&mut writer_rx, &mut dropped_rx).await;
         });
 
         let recv_session = Arc::clone(self);
         tokio::spawn(async move {
-            if let Err(e) = recv_session.recv_loop().await {
+            // Session 关闭时放弃正在进行的读取，释放读半部的锁
+            let close_notify = Arc::clone(&recv_session.close_notify);
+            let closed = close_notify.notified();
+            tokio::pin!(closed);
+            closed.as_mut().enable();
+            let result = tokio::select! {
+                result = recv_session.recv_loop() => result,
+                _ = closed => Ok(()),
+            };
+            if let Err(e) = result {
+                let id = recv_session.id();
                 if is_expected_close_error(&e) {
-                    log::debug!("Session receive loop ended: {}", e);
+                    log::debug!("[Session] Session {} receive loop ended: {}", id, e);
                 } else {
-                    log::error!("Session receive loop error: {}", e);
+                    log::error!("[Session] Session {} receive loop error: {}", id, e);
                 }
             }
+            // 连接已失效，关闭 Session 以通知所有 Stream
+            let _ = recv_session.close().await;
         });
         Ok(())
     }
@@ -115,19 +356,24 @@ impl Session {
         }
         self.touch_activity();
 
-        let stream_id = self.state.next_stream_id.fetch_add(1, Ordering::AcqRel);
-        let (data_tx, data_rx) = mpsc::channel(100);
-        let (close_tx, _close_rx) = oneshot::channel();
-        let stream = Stream::new(stream_id, data_rx, self.frame_tx.clone(), close_tx);
-
-        {
+        // 持有写锁分配 id，回绕后不会与仍在使用的 Stream 冲突
+        let (stream, stream_id, awaits_synack) = {
             let mut streams = self.state.streams.write().await;
-            streams.insert(stream_id, data_tx);
-        }
-        self.state.stream_count.fetch_add(1, Ordering::AcqRel);
+            let stream_id = self.state.allocate_stream_id(&streams);
+            let awaits_synack = self.role.is_client()
+                && stream_id >= 2
+                && self.state.peer_version.load(Ordering::Acquire) >= 2;
+            let params = StreamParams {
+                awaits_synack,
+                ..self.stream_params()
+            };
+            let (stream, handle) = Stream::new(stream_id, params);
+            streams.insert(stream_id, handle);
+            (stream, stream_id, awaits_synack)
+        };
+        self.state.stream_opened();
 
-        if self.is_client && stream_id >= 2 && self.state.peer_version.load(Ordering::Acquire) >= 2
-        {
+        if awaits_synack {
             let (tx, rx) = oneshot::channel();
             {
                 let mut waiters = self.state.synack_waiters.write().await;
@@ -162,6 +408,10 @@ impl Session {
 
         self.write_control_frame(Frame::new(CMD_SYN, stream_id)).await?;
         log::debug!("[Session] SYN frame sent for stream {}", stream_id);
+        // 对端会回复 SYNACK 时等待确认，拒绝的原因直接返回给调用方
+        if awaits_synack {
+            stream.established().await?;
+        }
         Ok(stream)
     }
 
@@ -175,21 +425,48 @@ impl Session {
         self.remove_stream(stream_id).await;
     }
 
-    pub(super) async fn remove_stream(&self, stream_id: u32) -> bool {
-        let mut streams = self.state.streams.write().await;
-        if streams.remove(&stream_id).is_some() {
-            self.state.stream_count.fetch_sub(1, Ordering::AcqRel);
-            true
+    /// 立即重置 Stream：两端丢弃其缓冲的数据并移除，本端的读写返回 `ConnectionReset`。
+    /// 对端支持时发送带 `reason` 的 CMD_RST，否则发送 CMD_FIN，对端只会读到 EOF
+    pub async fn reset_stream(&self, stream_id: u32, reason: &str) -> io::Result<()> {
+        let Some(handle) = self.take_stream(stream_id).await else {
+            return Ok(());
+        };
+        handle.mark_reset(reset_message("stream reset", reason));
+        self.write_control_frame(self.reset_frame(stream_id, reason)).await.map(|_| ())
+    }
+
+    /// 通知对端重置 Stream 的帧
+    pub(super) fn reset_frame(&self, stream_id: u32, reason: &str) -> Frame {
+        if self.state.peer_rst.load(Ordering::Acquire) {
+            Frame::with_data(CMD_RST, stream_id, Bytes::copy_from_slice(reason.as_bytes()))
         } else {
-            false
+            Frame::new(CMD_FIN, stream_id)
         }
     }
 
+    pub(super) async fn remove_stream(&self, stream_id: u32) -> bool {
+        match self.take_stream(stream_id).await {
+            Some(handle) => {
+                handle.mark_closed();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 从 Session 中取出 Stream 的句柄，由调用方标记关闭原因
+    pub(super) async fn take_stream(&self, stream_id: u32) -> Option<StreamHandle> {
+        let handle = self.state.streams.write().await.remove(&stream_id)?;
+        self.state.stream_count.fetch_sub(1, Ordering::AcqRel);
+        Some(handle)
+    }
+
     pub async fn write_data_frame(&self, stream_id: u32, data: &[u8]) -> io::Result<usize> {
         self.touch_activity();
         let frame = Frame::with_data(CMD_PSH, stream_id, Bytes::copy_from_slice(data));
         self.frame_tx
-            .send(frame)
+            .queue(Priority::Normal)
+            .send(Outbound::Frame(frame))
             .await
             .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "session writer closed"))?;
         Ok(data.len() + HEADER_OVERHEAD_SIZE)
@@ -199,19 +476,71 @@ impl Session {
         self.touch_activity();
         let n = frame.data.len();
         self.frame_tx
-            .send(frame)
+            .queue(Priority::Normal)
+            .send(Outbound::Frame(frame))
             .await
             .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "session writer closed"))?;
         Ok(n + HEADER_OVERHEAD_SIZE)
     }
 
+    /// 把一组帧作为整体排队写出，其他 Stream 或控制帧不会插在它们之间。
+    /// 返回这组帧编码后的总字节数
+    pub async fn write_frames(&self, frames: Vec<Frame>) -> io::Result<usize> {
+        if frames.is_empty() {
+            return Ok(0);
+        }
+        self.touch_activity();
+        let n = frames.iter().map(Frame::encoded_len).sum();
+        self.frame_tx
+            .queue(Priority::Normal)
+            .send(Outbound::Frames(frames))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "session writer closed"))?;
+        Ok(n)
+    }
+
+    /// 当前使用的填充方案
+    pub fn padding(&self) -> Arc<PaddingFactory> {
+        self.padding.load_full()
+    }
+
+    /// 替换运行中 Session 的填充方案，之后写出的帧按新方案填充。
+    /// 客户端的包序号未超过新方案的 `stop` 时恢复填充；服务端不填充，改为向客户端发送
+    /// CMD_UPDATE_PADDING_SCHEME，由客户端决定是否采用
+    pub async fn set_padding(&self, padding: Arc<PaddingFactory>) -> io::Result<()> {
+        let raw_scheme = padding.raw_scheme.clone();
+        let stop = padding.stop();
+        self.padding.store(padding);
+        if self.role.is_client() {
+            if self.pkt_counter.load(Ordering::Acquire) < stop {
+                self.send_padding.store(true, Ordering::Release);
+            }
+            return Ok(());
+        }
+        self.write_control_frame(self.padding_scheme_frame(raw_scheme)).await.map(|_| ())
+    }
+
+    /// 下发填充方案的帧，配置了压缩且对端支持时负载为压缩的方案
+    pub(super) fn padding_scheme_frame(&self, raw_scheme: Bytes) -> Frame {
+        let compress = self.config.compress_padding
+            && self.state.peer_padding_deflate.load(Ordering::Acquire);
+        let payload = if compress { compress_scheme(&raw_scheme) } else { raw_scheme };
+        Frame::with_data(CMD_UPDATE_PADDING_SCHEME, 0, payload)
+    }
+
+    /// 等待此前排队的所有帧写入连接并 flush
+    pub async fn flush(&self) -> io::Result<()> {
+        // 低优先级队列最后处理，轮到它时更高优先级的队列中此前入队的帧都已写出
+        flush_outbound(self.frame_tx.queue(Priority::Low).clone()).await
+    }
+
     async fn send_client_settings(&self) -> io::Result<()> {
-        let settings = StringMap::from([
-            ("v".to_string(), "2".to_string()),
-            ("client".to_string(), crate::PROGRAM_VERSION_NAME.to_string()),
-            ("padding-md5".to_string(), self.padding.md5().to_string()),
-        ]);
-        let frame = Frame::with_data(CMD_SETTINGS, 0, Bytes::from(settings.to_bytes()));
+        let mut settings = ClientSettings::new(crate::PROGRAM_VERSION_NAME, self.padding.load().md5());
+        if self.config.report_platform {
+            settings = settings.with_platform();
+        }
+        settings.max_payload = Some(self.config.max_payload);
+        let frame = Frame::with_data(CMD_SETTINGS, 0, settings.encode());
         let mut conn_guard = self.conn_w.lock().await;
         let conn = conn_guard.as_mut().ok_or_else(|| {
             io::Error::new(io::ErrorKind::BrokenPipe, "session write half closed")
@@ -232,6 +561,38 @@ impl Session {
         self.state.last_active_unix_ms()
     }
 
+    pub fn created_at(&self) -> std::time::Instant {
+        self.state.created_at
+    }
+
+    /// 自创建以来承载过的 Stream 总数
+    pub fn streams_served(&self) -> u64 {
+        self.state.streams_served.load(Ordering::Relaxed)
+    }
+
+    /// 填充阶段的负载与填充字节数，服务端不填充，总是为 0
+    pub fn padding_stats(&self) -> PaddingStats {
+        PaddingStats {
+            payload_bytes: self.state.padded_payload_bytes.load(Ordering::Relaxed),
+            padding_bytes: self.state.padding_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 所有 Stream 已收到但尚未被应用读取的字节数之和
+    pub fn buffered_bytes(&self) -> usize {
+        self.state.buffered_bytes.load(Ordering::Acquire)
+    }
+
+    /// 当前打开的 Stream 的快照，按 id 排序。只在复制统计时短暂持有读锁
+    pub async fn active_streams(&self) -> Vec<StreamInfo> {
+        let mut infos: Vec<StreamInfo> = {
+            let streams = self.state.streams.read().await;
+            streams.iter().map(|(&id, handle)| handle.info(id)).collect()
+        };
+        infos.sort_by_key(|info| info.id);
+        infos
+    }
+
     pub async fn close(&self) -> io::Result<()> {
         if self.state.closed.swap(true, Ordering::AcqRel) {
             return Ok(());
@@ -241,7 +602,7 @@ impl Session {
         {
             let mut w = self.conn_w.lock().await;
             if let Some(mut wh) = w.take() {
-                let _ = wh.shutdown().await;
+                let _ = self.timed_write(wh.shutdown()).await;
             }
         }
         {
@@ -250,12 +611,19 @@ impl Session {
         }
         {
             let mut streams = self.state.streams.write().await;
-            streams.clear();
+            for (_, handle) in streams.drain() {
+                handle.mark_closed();
+            }
         }
         {
             let mut waiters = self.state.synack_waiters.write().await;
             waiters.clear();
         }
+        self.state
+            .fin_ack_waiters
+            .lock()
+            .expect("fin ack waiters lock poisoned")
+            .clear();
         self.state.stream_count.store(0, Ordering::Release);
         if let Some(cb) = &self.on_close {
             cb();
@@ -271,7 +639,7 @@ impl Session {
         if self.is_closed() {
             return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Session closed"));
         }
-        let sid = self.state.next_stream_id.fetch_add(1, Ordering::AcqRel);
+        let sid = self.state.next_stream_id.fetch_add(STREAM_ID_STEP, Ordering::AcqRel);
         let (tx, rx) = oneshot::channel();
         {
             let mut waiters = self.state.heartbeat_waiters.write().await;
@@ -300,3 +668,35 @@ impl Session {
         }
     }
 }
+
+impl Drop for Session {
+    /// 未经 [`Session::close`] 就被释放（例如运行它的任务被中止）时记录日志，并尽力标记
+    /// 所有 Stream 已关闭，让仍持有 Stream 的一方读到 EOF 而不是永久等待
+    fn drop(&mut self) {
+        if self.state.closed.swap(true, Ordering::AcqRel) {
+            log::debug!("[Session] Session {} dropped", self.id);
+            return;
+        }
+        log::warn!(
+            "[Session] Session {} ({}) dropped without close, open streams: {}",
+            self.id,
+            self.role,
+            self.state.stream_count()
+        );
+        self.close_notify.notify_waiters();
+        // 锁被占用时放弃，持有锁的一方随后也会因 Session 已关闭而退出
+        if let Ok(mut streams) = self.state.streams.try_write() {
+            for (_, handle) in streams.drain() {
+                handle.mark_closed();
+            }
+        }
+        if let Ok(mut waiters) = self.state.synack_waiters.try_write() {
+            waiters.clear();
+        }
+        if let Ok(mut waiters) = self.state.fin_ack_waiters.try_lock() {
+            waiters.clear();
+        }
+        // 不调用 on_close：回调可能要求运行时，而这里可能已在运行时之外
+        self.state.stream_count.store(0, Ordering::Release);
+    }
+}
@@ -1,31 +1,74 @@
-use super::core::Session;
-use crate::proxy::session::frame::{
-    Frame, CMD_ALERT, CMD_FIN, CMD_HEART_REQUEST, CMD_HEART_RESPONSE, CMD_PSH, CMD_SERVER_SETTINGS,
-    CMD_SETTINGS, CMD_SYN, CMD_SYNACK, CMD_UPDATE_PADDING_SCHEME, CMD_WASTE,
+use super::core::{Role, Session};
+use crate::proxy::protocol::frame::{
+    Frame, CMD_ALERT, CMD_FIN, CMD_FIN_ACK, CMD_HEART_REQUEST, CMD_HEART_RESPONSE, CMD_PSH,
+    CMD_PSH_SEQ, CMD_RST, CMD_SERVER_SETTINGS, CMD_SETTINGS, CMD_STOP_SENDING, CMD_SYN, CMD_SYNACK,
+    CMD_UPDATE_PADDING_SCHEME, CMD_WASTE,
 };
+use crate::proxy::protocol::settings::{
+    check_settings_size, ClientSettings, ServerSettings, EXT_FIN_ACK, EXT_PADDING_DEFLATE,
+    EXT_PSH_SEQ, EXT_RST, EXT_STOP_SENDING,
+};
+use crate::proxy::session::stream::reset_message;
+use crate::proxy::padding::decompress_scheme;
 use crate::proxy::session::stream::Stream;
-use crate::util::string_map::{StringMap, StringMapExt};
 use bytes::Bytes;
 use std::io;
 use std::sync::atomic::Ordering;
-use tokio::sync::mpsc::error::TrySendError;
-use tokio::sync::{mpsc, oneshot};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 发送 CMD_ALERT 后等待其写出的最长时间
+const ALERT_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
 
 impl Session {
     pub(super) async fn handle_frame(&self, cmd: u8, sid: u32, data: Bytes) -> io::Result<()> {
         self.touch_activity();
+        match self.role {
+            Role::Client => self.handle_frame_client(cmd, sid, data).await,
+            Role::Server => self.handle_frame_server(cmd, sid, data).await,
+        }
+    }
+
+    /// 只有服务端发送的命令，其余交给 `handle_frame_shared`
+    async fn handle_frame_client(&self, cmd: u8, sid: u32, data: Bytes) -> io::Result<()> {
+        match cmd {
+            CMD_SYNACK => self.handle_synack(sid, data).await,
+            CMD_SERVER_SETTINGS => self.handle_server_settings_cmd(data).await,
+            CMD_UPDATE_PADDING_SCHEME => self.handle_padding_scheme_update_cmd(data).await,
+            _ => self.handle_frame_shared(cmd, sid, data).await,
+        }
+    }
+
+    /// 只有客户端发送的命令，其余交给 `handle_frame_shared`
+    async fn handle_frame_server(&self, cmd: u8, sid: u32, data: Bytes) -> io::Result<()> {
+        match cmd {
+            CMD_SYN => self.handle_syn(sid).await,
+            CMD_SETTINGS => self.handle_settings(data).await,
+            _ => self.handle_frame_shared(cmd, sid, data).await,
+        }
+    }
+
+    /// 两端都会收到的命令；对端角色不该发送的命令与未知命令被忽略
+    async fn handle_frame_shared(&self, cmd: u8, sid: u32, data: Bytes) -> io::Result<()> {
         match cmd {
             CMD_WASTE => Ok(()),
             CMD_PSH => self.handle_psh(sid, data).await,
-            CMD_SYN => self.handle_syn(sid).await,
-            CMD_SYNACK => self.handle_synack(sid, data).await,
+            CMD_PSH_SEQ => self.handle_psh_seq(sid, data).await,
             CMD_FIN => self.handle_fin(sid).await,
-            CMD_SETTINGS => self.handle_settings(data).await,
             CMD_ALERT => self.handle_alert(data),
-            CMD_UPDATE_PADDING_SCHEME => self.handle_padding_scheme_update_cmd(data).await,
             CMD_HEART_REQUEST => self.handle_heartbeat_request(sid).await,
             CMD_HEART_RESPONSE => self.handle_heartbeat_response(sid).await,
-            CMD_SERVER_SETTINGS => self.handle_server_settings_cmd(data).await,
+            CMD_STOP_SENDING => self.handle_stop_sending(sid).await,
+            CMD_RST => self.handle_rst(sid, data).await,
+            CMD_FIN_ACK => self.handle_fin_ack(sid),
+            CMD_SYN => {
+                log::warn!("{} received unexpected SYN for stream: {}", self.role, sid);
+                Ok(())
+            }
+            CMD_SYNACK => {
+                log::warn!("{} received unexpected SYNACK for stream: {}", self.role, sid);
+                Ok(())
+            }
             _ => Ok(()),
         }
     }
@@ -34,33 +77,79 @@ impl Session {
         if data.is_empty() {
             return Ok(());
         }
-        let stream_tx = {
+        let target = {
             let streams = self.state.streams.read().await;
-            streams.get(&sid).cloned()
+            streams
+                .get(&sid)
+                .filter(|handle| !handle.is_read_shutdown())
+                .map(|handle| (handle.data_tx.clone(), Arc::clone(&handle.window)))
+        };
+        let Some((stream_tx, window)) = target else {
+            return Ok(());
         };
 
-        if let Some(stream_tx) = stream_tx {
-            match stream_tx.try_send(data) {
-                Ok(()) => {}
-                Err(TrySendError::Full(data)) => {
-                    if stream_tx.send(data).await.is_err() {
-                        log::debug!("[Session] Stream {} closed while backpressure waiting", sid);
-                    }
-                }
-                Err(TrySendError::Closed(_)) => {
-                    log::debug!("[Session] Stream {} already closed", sid);
-                }
+        // 窗口用尽时停在这里不再读取连接，由 TCP 把背压传给对端
+        match window.acquire_many(data.len() as u32).await {
+            Ok(permit) => permit.forget(),
+            Err(_) => {
+                log::debug!("[Session] Stream {} closed while backpressure waiting", sid);
+                return Ok(());
             }
         }
+        self.account_buffered(data.len()).await?;
+        if stream_tx.send(data).is_err() {
+            log::debug!("[Session] Stream {} already closed", sid);
+        }
         Ok(())
     }
 
-    async fn handle_syn(&self, sid: u32) -> io::Result<()> {
-        if self.is_client {
-            log::warn!("Client received unexpected SYN for stream: {}", sid);
+    /// 按序号重排后交付；窗口按到达时计算，暂存在重排缓冲中的数据同样占用窗口
+    async fn handle_psh_seq(&self, sid: u32, data: Bytes) -> io::Result<()> {
+        let (seq, data) = Frame::split_seq(data)?;
+        let window = {
+            let streams = self.state.streams.read().await;
+            streams
+                .get(&sid)
+                .filter(|handle| !handle.is_read_shutdown())
+                .map(|handle| Arc::clone(&handle.window))
+        };
+        let Some(window) = window else {
+            return Ok(());
+        };
+        if !data.is_empty() {
+            match window.acquire_many(data.len() as u32).await {
+                Ok(permit) => permit.forget(),
+                Err(_) => return Ok(()),
+            }
+            self.account_buffered(data.len()).await?;
+        }
+
+        let streams = self.state.streams.read().await;
+        let Some(handle) = streams.get(&sid) else {
             return Ok(());
+        };
+        let data_len = data.len();
+        let ready = handle
+            .reorder
+            .lock()
+            .expect("stream reorder lock poisoned")
+            .push(seq, data)?;
+        match ready {
+            Some(ready) => {
+                for data in ready.into_iter().filter(|data| !data.is_empty()) {
+                    let _ = handle.data_tx.send(data);
+                }
+            }
+            // 重复的帧不交付，归还为它占用的窗口
+            None => {
+                handle.window.add_permits(data_len);
+                self.state.buffered_bytes.fetch_sub(data_len, Ordering::AcqRel);
+            }
         }
+        Ok(())
+    }
 
+    async fn handle_syn(&self, sid: u32) -> io::Result<()> {
         if self.state.streams.read().await.contains_key(&sid) {
             let err = format!("Stream {} already exists", sid);
             let _ = self
@@ -69,34 +158,61 @@ impl Session {
             return Ok(());
         }
 
-        let (data_tx, data_rx) = mpsc::channel(100);
-        let (close_tx, _close_rx) = oneshot::channel();
-        let stream = Stream::new(sid, data_rx, self.frame_tx.clone(), close_tx);
+        // 反复打开关闭 Stream 不受并发上限约束，超过速率的 SYN 直接拒绝
+        let rate = self.config.max_syn_rate;
+        if rate > 0 && !self.state.syn_bucket.lock().unwrap().try_take(rate) {
+            log::debug!("[Session] Stream open rate exceeded, rejecting stream {}", sid);
+            let _ = self
+                .write_control_frame(Frame::with_data(
+                    CMD_SYNACK,
+                    sid,
+                    Bytes::from_static(b"stream open rate exceeded"),
+                ))
+                .await;
+            return Ok(());
+        }
+
+        // 先占住队列位置，满了就拒绝，避免接受后再丢弃
+        let permit = match &self.incoming_tx {
+            Some(tx) => match tx.try_reserve() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    log::debug!("[Session] Accept backlog full, rejecting stream {}", sid);
+                    let _ = self
+                        .write_control_frame(Frame::with_data(
+                            CMD_SYNACK,
+                            sid,
+                            Bytes::from_static(b"accept backlog full"),
+                        ))
+                        .await;
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+
+        let (stream, handle) = Stream::new(sid, self.stream_params());
         {
             let mut streams = self.state.streams.write().await;
-            streams.insert(sid, data_tx);
+            streams.insert(sid, handle);
         }
-        self.state.stream_count.fetch_add(1, Ordering::AcqRel);
+        self.state.stream_opened();
 
         if let Err(e) = self.write_control_frame(Frame::new(CMD_SYNACK, sid)).await {
             log::error!("Failed to send SYNACK for stream {}: {}", sid, e);
-            let mut streams = self.state.streams.write().await;
-            streams.remove(&sid);
+            self.remove_stream(sid).await;
             return Ok(());
         }
         log::debug!("Stream {} opened successfully", sid);
-        if let Some(cb) = &self.on_new_stream {
+        if let Some(permit) = permit {
+            permit.send(stream);
+        } else if let Some(cb) = &self.on_new_stream {
             cb(stream);
         }
         Ok(())
     }
 
     async fn handle_synack(&self, sid: u32, data: Bytes) -> io::Result<()> {
-        if !self.is_client {
-            log::warn!("Server received unexpected SYNACK for stream: {}", sid);
-            return Ok(());
-        }
-
         let waiter = {
             let mut waiters = self.state.synack_waiters.write().await;
             waiters.remove(&sid)
@@ -104,6 +220,9 @@ impl Session {
 
         if !data.is_empty() {
             let msg = String::from_utf8_lossy(&data).to_string();
+            if let Some(handle) = self.state.streams.read().await.get(&sid) {
+                handle.mark_rejected(msg.clone());
+            }
             if let Some(tx) = waiter {
                 let _ = tx.send(Err(io::Error::other(format!("remote: {}", msg))));
             } else {
@@ -113,27 +232,90 @@ impl Session {
             return Ok(());
         }
 
+        if let Some(handle) = self.state.streams.read().await.get(&sid) {
+            handle.mark_established();
+        }
         if let Some(tx) = waiter {
             let _ = tx.send(Ok(()));
         }
         Ok(())
     }
 
+    /// 对端不再读取该 Stream，之后本端的写入返回错误
+    async fn handle_stop_sending(&self, sid: u32) -> io::Result<()> {
+        if let Some(handle) = self.state.streams.read().await.get(&sid) {
+            handle.mark_write_stopped();
+        }
+        Ok(())
+    }
+
+    /// 对端重置了 Stream：立即移除，本端之后的读写返回 `ConnectionReset`
+    async fn handle_rst(&self, sid: u32, data: Bytes) -> io::Result<()> {
+        if let Some(handle) = self.take_stream(sid).await {
+            let reason = String::from_utf8_lossy(&data);
+            handle.mark_reset(reset_message("stream reset by peer", &reason));
+        }
+        Ok(())
+    }
+
     async fn handle_fin(&self, sid: u32) -> io::Result<()> {
-        let mut streams = self.state.streams.write().await;
-        if streams.remove(&sid).is_some() {
-            self.state.stream_count.fetch_sub(1, Ordering::AcqRel);
+        self.remove_stream(sid).await;
+        if self.state.peer_fin_ack.load(Ordering::Acquire) {
+            self.write_control_frame(Frame::new(CMD_FIN_ACK, sid)).await?;
+        }
+        Ok(())
+    }
+
+    fn handle_fin_ack(&self, sid: u32) -> io::Result<()> {
+        let waiter = self
+            .state
+            .fin_ack_waiters
+            .lock()
+            .expect("fin ack waiters lock poisoned")
+            .remove(&sid);
+        if let Some(tx) = waiter {
+            let _ = tx.send(());
         }
         Ok(())
     }
 
     async fn handle_settings(&self, data: Bytes) -> io::Result<()> {
-        if !self.is_client && !data.is_empty() {
+        if !data.is_empty() {
+            self.check_settings(&data).await?;
             self.handle_client_settings(data).await?;
         }
         Ok(())
     }
 
+    /// 设置负载超过上限时向对端发送 CMD_ALERT，并返回错误关闭 Session
+    async fn check_settings(&self, data: &[u8]) -> io::Result<()> {
+        match check_settings_size(data) {
+            Ok(()) => Ok(()),
+            Err(e) => self.fail_with_alert(e).await,
+        }
+    }
+
+    /// 计入 `n` 字节新收到的数据；所有 Stream 的缓冲总量超过上限时发送 CMD_ALERT 并关闭 Session
+    async fn account_buffered(&self, n: usize) -> io::Result<()> {
+        // Stream 可能恰好在取得窗口后被丢弃并先行扣减，计数会短暂回绕
+        let total = self.state.buffered_bytes.fetch_add(n, Ordering::AcqRel).wrapping_add(n);
+        let limit = self.config.max_buffered;
+        if limit == 0 || total <= limit {
+            return Ok(());
+        }
+        let msg = format!("buffered data exceeds session limit ({} > {} bytes)", total, limit);
+        self.fail_with_alert(io::Error::other(msg)).await
+    }
+
+    /// 把错误原因以 CMD_ALERT 告知对端，尽量等它写出后返回该错误
+    async fn fail_with_alert(&self, e: io::Error) -> io::Result<()> {
+        let alert = Frame::with_data(CMD_ALERT, 0, Bytes::from(e.to_string()));
+        if self.write_control_frame(alert).await.is_ok() {
+            let _ = tokio::time::timeout(ALERT_FLUSH_TIMEOUT, self.flush()).await;
+        }
+        Err(e)
+    }
+
     fn handle_alert(&self, data: Bytes) -> io::Result<()> {
         if data.is_empty() {
             return Ok(());
@@ -159,50 +341,85 @@ impl Session {
     }
 
     async fn handle_server_settings_cmd(&self, data: Bytes) -> io::Result<()> {
-        if self.is_client && !data.is_empty() {
-            let settings = StringMap::from_bytes(&data);
-            if let Some(version) = settings.get("v") {
-                if let Ok(v) = version.parse::<u32>() {
-                    self.state.peer_version.store(v, Ordering::Release);
-                }
+        if !data.is_empty() {
+            self.check_settings(&data).await?;
+            let settings = ServerSettings::decode(&data);
+            if let Some(v) = settings.version {
+                self.state.peer_version.store(v, Ordering::Release);
             }
+            let psh_seq = settings.supports(EXT_PSH_SEQ);
+            self.state.peer_psh_seq.store(psh_seq, Ordering::Release);
+            let stop_sending = settings.supports(EXT_STOP_SENDING);
+            self.state.peer_stop_sending.store(stop_sending, Ordering::Release);
+            let fin_ack = settings.supports(EXT_FIN_ACK);
+            self.state.peer_fin_ack.store(fin_ack, Ordering::Release);
+            self.state.peer_rst.store(settings.supports(EXT_RST), Ordering::Release);
+            self.apply_peer_max_payload(settings.max_payload);
         }
         Ok(())
     }
 
     async fn handle_client_settings(&self, data: Bytes) -> io::Result<()> {
-        let settings = StringMap::from_bytes(&data);
-        if let Some(padding_md5) = settings.get("padding-md5") {
-            if padding_md5 != self.padding.md5() {
-                let raw_scheme = self.padding.raw_scheme.clone();
-                let frame = Frame::with_data(CMD_UPDATE_PADDING_SCHEME, 0, raw_scheme);
+        let settings = ClientSettings::decode(&data);
+        let padding_deflate = settings.supports(EXT_PADDING_DEFLATE);
+        self.state.peer_padding_deflate.store(padding_deflate, Ordering::Release);
+        if let Some(padding_md5) = &settings.padding_md5 {
+            let padding = self.padding.load();
+            if padding_md5 != padding.md5() {
+                let frame = self.padding_scheme_frame(padding.raw_scheme.clone());
                 self.write_control_frame(frame).await?;
             }
         }
-        if let Some(version) = settings.get("v") {
-            if let Ok(v) = version.parse::<u32>() {
-                self.state.peer_version.store(v, Ordering::Release);
-                if v >= 2 {
-                    let server_settings = StringMap::from([("v".to_string(), "2".to_string())]);
-                    let frame = Frame::with_data(
-                        CMD_SERVER_SETTINGS,
-                        0,
-                        Bytes::from(server_settings.to_bytes()),
-                    );
-                    self.write_control_frame(frame).await?;
-                }
+        *self
+            .state
+            .peer_settings
+            .lock()
+            .expect("session peer settings lock poisoned") = Some(settings.clone());
+        let psh_seq = settings.supports(EXT_PSH_SEQ);
+        self.state.peer_psh_seq.store(psh_seq, Ordering::Release);
+        let stop_sending = settings.supports(EXT_STOP_SENDING);
+        self.state.peer_stop_sending.store(stop_sending, Ordering::Release);
+        let fin_ack = settings.supports(EXT_FIN_ACK);
+        self.state.peer_fin_ack.store(fin_ack, Ordering::Release);
+        self.state.peer_rst.store(settings.supports(EXT_RST), Ordering::Release);
+        self.apply_peer_max_payload(settings.max_payload);
+        if let Some(v) = settings.version {
+            self.state.peer_version.store(v, Ordering::Release);
+            if v >= 2 {
+                let server_settings = ServerSettings {
+                    max_payload: Some(self.config.max_payload),
+                    ..ServerSettings::new()
+                };
+                let frame = Frame::with_data(CMD_SERVER_SETTINGS, 0, server_settings.encode());
+                self.write_control_frame(frame).await?;
             }
         }
         Ok(())
     }
 
     async fn handle_padding_scheme_update_cmd(&self, data: Bytes) -> io::Result<()> {
-        if !self.is_client || data.is_empty() {
+        if data.is_empty() {
             return Ok(());
         }
-        if crate::proxy::padding::PaddingFactory::new(&data).is_none() {
+        let data = decompress_scheme(data)?;
+        let Some(padding) = crate::proxy::padding::PaddingFactory::from_bytes(data) else {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid padding scheme"));
+        };
+        // 过大的填充会让客户端把带宽浪费在 WASTE 帧上。cmdAlert 会让对端关闭 Session，
+        // 所以只记录日志，继续使用原来的方案
+        let limit = self.config.max_padding_bytes as u64;
+        let packet_bytes = padding.max_packet_bytes();
+        if limit > 0 && packet_bytes > limit {
+            log::warn!(
+                "[Session] Session {} ignored a padding scheme over the limit \
+                 ({} > {} bytes per packet), keeping the old scheme",
+                self.id(),
+                packet_bytes,
+                limit
+            );
+            return Ok(());
         }
-        Ok(())
+        // 新方案的 stop 大于已发送的包数时恢复填充
+        self.set_padding(Arc::new(padding)).await
     }
 }
@@ -1,101 +1,67 @@
-use bytes::{Buf, BufMut, Bytes, BytesMut};
-use std::io;
+//! 帧定义位于 [`crate::proxy::protocol::frame`]，这里保留原来的导入路径，
+//! 并补充依赖 tokio 的读取方法。
 
-// CMD 指令定义
-pub const CMD_WASTE: u8 = 0;               // Paddings
-pub const CMD_SYN: u8 = 1;                 // stream open
-pub const CMD_PSH: u8 = 2;                 // data push
-pub const CMD_FIN: u8 = 3;                 // stream close, a.k.a EOF mark
-pub const CMD_SETTINGS: u8 = 4;            // Settings (Client send to Server)
-pub const CMD_ALERT: u8 = 5;               // Alert
-pub const CMD_UPDATE_PADDING_SCHEME: u8 = 6; // update padding scheme
-// Since version 2
-pub const CMD_SYNACK: u8 = 7;              // Server reports to the client that the stream has been opened
-pub const CMD_HEART_REQUEST: u8 = 8;       // Keep alive command
-pub const CMD_HEART_RESPONSE: u8 = 9;      // Keep alive command
-pub const CMD_SERVER_SETTINGS: u8 = 10;    // Settings (Server send to client)
+pub use crate::proxy::protocol::frame::*;
 
-pub const HEADER_OVERHEAD_SIZE: usize = 1 + 4 + 2; // cmd(1) + sid(4) + length(2)
+use bytes::BytesMut;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
-/// 原始头部结构
-#[derive(Debug, Clone, Copy)]
-pub struct RawHeader {
-    pub cmd: u8,
-    pub sid: u32,
-    pub length: u16,
-}
+/// 读取负载时每次分配的缓冲区大小。多个小帧共用一块内存，全部释放后整块回收复用
+const READ_CHUNK_SIZE: usize = 16 * 1024;
 
 impl RawHeader {
-    pub fn from_bytes(buf: &[u8]) -> io::Result<Self> {
-        if buf.len() < HEADER_OVERHEAD_SIZE {
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "Insufficient data for header",
-            ));
-        }
-
-        let mut cursor = std::io::Cursor::new(buf);
-        let cmd = cursor.get_u8();
-        let sid = cursor.get_u32();
-        let length = cursor.get_u16();
-
-        Ok(Self { cmd, sid, length })
-    }
-}
-
-/// Frame 结构体，定义从或要复用到单个连接中的数据包
-#[derive(Debug, Clone)]
-pub struct Frame {
-    pub cmd: u8,
-    pub sid: u32,
-    pub data: Bytes,
-}
-
-impl Frame {
-    pub fn new(cmd: u8, sid: u32) -> Self {
-        Self {
-            cmd,
-            sid,
-            data: Bytes::new(),
-        }
-    }
-
-    pub fn with_data(cmd: u8, sid: u32, data: Bytes) -> Self {
-        Self { cmd, sid, data }
+    /// 从 `reader` 读取一个完整的帧，负载放入清空后的 `payload`，返回帧头。
+    /// 反复传入同一个缓冲区时，之前取走的负载都已释放就复用原来的内存
+    pub async fn read_from<R>(reader: &mut R, payload: &mut BytesMut) -> io::Result<Self>
+    where
+        R: AsyncRead + Unpin,
+    {
+        Self::read_next(reader, payload).await?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before a frame")
+        })
     }
 
-    /// 从字节流中解析 Frame
-    pub fn from_bytes(mut buf: &[u8]) -> io::Result<Self> {
-        if buf.len() < HEADER_OVERHEAD_SIZE {
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "Insufficient data for frame header",
-            ));
+    /// 同 [`RawHeader::read_from`]，但在帧边界读到 EOF 时返回 `None`，表示对端正常关闭连接。
+    /// 帧头或负载读到一半时 EOF 仍返回 `UnexpectedEof`
+    pub async fn read_next<R>(reader: &mut R, payload: &mut BytesMut) -> io::Result<Option<Self>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut header_buf = [0u8; HEADER_OVERHEAD_SIZE];
+        let n = reader.read(&mut header_buf).await?;
+        if n == 0 {
+            return Ok(None);
         }
-
-        let cmd = buf.get_u8();
-        let sid = buf.get_u32();
-        let length = buf.get_u16() as usize;
-
-        if buf.len() < length {
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "Insufficient data for frame payload",
-            ));
+        reader.read_exact(&mut header_buf[n..]).await.map_err(truncated)?;
+        let header = Self::from_bytes(&header_buf)?;
+        let len = header.length as usize;
+        payload.clear();
+        if payload.capacity() < len {
+            payload.reserve(len.max(READ_CHUNK_SIZE));
         }
+        payload.resize(len, 0);
+        reader.read_exact(payload).await.map_err(truncated)?;
+        Ok(Some(header))
+    }
+}
 
-        let data = Bytes::copy_from_slice(&buf[..length]);
-
-        Ok(Self { cmd, sid, data })
+fn truncated(e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed in the middle of a frame")
+    } else {
+        e
     }
+}
 
-    /// 将 Frame 序列化为字节流
-    pub fn to_bytes(&self) -> BytesMut {
-        let mut buf = BytesMut::with_capacity(HEADER_OVERHEAD_SIZE + self.data.len());
-        buf.put_u8(self.cmd);
-        buf.put_u32(self.sid);
-        buf.put_u16(self.data.len() as u16);
-        buf.put_slice(&self.data);
-        buf
+impl Frame {
+    /// 从 `reader` 读取一个完整的帧；帧头或负载不完整时返回 `UnexpectedEof`
+    pub async fn read_from<R>(reader: &mut R) -> io::Result<Self>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut payload = BytesMut::new();
+        let header = RawHeader::read_from(reader, &mut payload).await?;
+        Ok(Self::with_data(header.cmd, header.sid, payload.freeze()))
     }
 }
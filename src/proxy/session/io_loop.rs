@@ -1,39 +1,268 @@
 use super::close_reason::is_expected_close_error;
 use super::core::Session;
-use crate::proxy::session::frame::{Frame, RawHeader, CMD_WASTE, HEADER_OVERHEAD_SIZE};
-use bytes::{Buf, BufMut, BytesMut};
+use super::stream::Priority;
+use crate::proxy::protocol::frame::{Frame, RawHeader, CMD_FIN, CMD_WASTE};
+use bytes::{Buf, Bytes, BytesMut};
+use std::future::{poll_fn, Future};
 use std::io;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::sync::mpsc;
+use std::task::Poll;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+/// 一次合并写入的上限
+const MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// 写循环队列中的条目
+pub(crate) enum Outbound {
+    Frame(Frame),
+    /// 连续写出的一组帧，中间不会插入其他帧
+    Frames(Vec<Frame>),
+    /// 之前入队的帧全部写出并 flush 连接后应答
+    Flush(oneshot::Sender<()>),
+}
+
+/// 写循环的发送端，每个优先级一个有界队列
+#[derive(Clone)]
+pub(crate) struct OutboundTx([mpsc::Sender<Outbound>; 3]);
+
+impl OutboundTx {
+    pub(crate) fn queue(&self, priority: Priority) -> &mpsc::Sender<Outbound> {
+        &self.0[priority as usize]
+    }
+}
+
+/// 写循环的接收端：总是先取优先级最高的非空队列，同一队列内保持入队顺序
+pub(crate) struct OutboundRx([mpsc::Receiver<Outbound>; 3]);
+
+impl OutboundRx {
+    /// 所有队列都已关闭时返回 `None`
+    pub(crate) async fn recv(&mut self) -> Option<Outbound> {
+        poll_fn(|cx| {
+            let mut open = false;
+            for rx in self.0.iter_mut().rev() {
+                match rx.poll_recv(cx) {
+                    Poll::Ready(Some(outbound)) => return Poll::Ready(Some(outbound)),
+                    Poll::Ready(None) => {}
+                    Poll::Pending => open = true,
+                }
+            }
+            if open {
+                Poll::Pending
+            } else {
+                Poll::Ready(None)
+            }
+        })
+        .await
+    }
+
+    fn try_recv(&mut self) -> Option<Outbound> {
+        self.0.iter_mut().rev().find_map(|rx| rx.try_recv().ok())
+    }
+}
+
+/// 每个优先级容量为 `capacity` 的发送队列
+pub(crate) fn outbound_channel(capacity: usize) -> (OutboundTx, OutboundRx) {
+    let (low_tx, low_rx) = mpsc::channel(capacity);
+    let (normal_tx, normal_rx) = mpsc::channel(capacity);
+    let (high_tx, high_rx) = mpsc::channel(capacity);
+    (
+        OutboundTx([low_tx, normal_tx, high_tx]),
+        OutboundRx([low_rx, normal_rx, high_rx]),
+    )
+}
+
+/// Stream 被丢弃时发给写循环的通知。走无界通道，帧队列已满时也不会丢失；
+/// 写循环据此移除 Session 中的条目，并在 Stream 尚未发出 FIN 时补发
+pub(crate) struct StreamDropped {
+    pub(crate) id: u32,
+    pub(crate) send_fin: bool,
+    /// 由 [`super::Stream::reset`] 发出时为重置原因，改为通知对端重置
+    pub(crate) reset: Option<String>,
+}
+
+/// 等待队列中已有的帧写入连接
+pub(crate) async fn flush_outbound(tx: mpsc::Sender<Outbound>) -> io::Result<()> {
+    let (ack_tx, ack_rx) = oneshot::channel();
+    tx.send(Outbound::Flush(ack_tx))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "session writer closed"))?;
+    ack_rx
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "session writer closed"))
+}
 
 impl Session {
-    pub(super) async fn run_writer_loop(self: Arc<Self>, writer_rx: &mut mpsc::Receiver<Frame>) {
+    pub(super) async fn run_writer_loop(
+        self: Arc<Self>,
+        writer_rx: &mut OutboundRx,
+        dropped_rx: &mut mpsc::UnboundedReceiver<StreamDropped>,
+    ) {
         loop {
-            tokio::select! {
+            // 先清空帧队列再处理丢弃通知，保证补发的 FIN 排在该 Stream 已入队的数据之后
+            let result = tokio::select! {
+                biased;
                 _ = self.close_notify.notified() => break,
-                maybe_frame = writer_rx.recv() => {
-                    match maybe_frame {
-                        Some(frame) => {
-                            if let Err(e) = self.write_frame(frame).await {
-                                if is_expected_close_error(&e) {
-                                    log::debug!("Session writer loop ended: {}", e);
-                                } else {
-                                    log::error!("Session writer loop error: {}", e);
-                                }
-                                break;
-                            }
-                        }
-                        None => break,
+                maybe_outbound = writer_rx.recv() => match maybe_outbound {
+                    Some(Outbound::Frame(frame)) => {
+                        self.restart_padding_after_idle();
+                        self.timed_write(self.write_batch(frame, writer_rx)).await
+                    }
+                    Some(Outbound::Frames(frames)) => {
+                        self.restart_padding_after_idle();
+                        self.timed_write(self.write_group(frames)).await
                     }
+                    Some(Outbound::Flush(ack)) => self.timed_write(self.flush_and_ack(ack)).await,
+                    None => break,
+                },
+                Some(dropped) = dropped_rx.recv() => {
+                    self.timed_write(self.finish_dropped_stream(dropped)).await
+                }
+            };
+            if let Err(e) = result {
+                if is_expected_close_error(&e) {
+                    log::debug!("Session writer loop ended: {}", e);
+                } else {
+                    log::error!("Session writer loop error: {}", e);
                 }
+                // 连接已无法写入，关闭 Session 让所有 Stream 失败而不是挂起
+                let _ = self.close().await;
+                break;
             }
         }
     }
 
+    /// 自适应填充：距上次写出超过 `padding_idle_reset` 时包序号归零并恢复填充
+    fn restart_padding_after_idle(&self) {
+        let gap = self.config.padding_idle_reset;
+        if !self.role.is_client() || gap.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut last_write = self.last_write.lock().expect("session last write lock poisoned");
+        if last_write.is_some_and(|last| now.duration_since(last) >= gap) {
+            self.pkt_counter.store(0, Ordering::Release);
+            self.send_padding.store(self.padding.load().stop() > 0, Ordering::Release);
+        }
+        *last_write = Some(now);
+    }
+
+    async fn finish_dropped_stream(&self, dropped: StreamDropped) -> io::Result<()> {
+        self.remove_stream(dropped.id).await;
+        if let Some(reason) = &dropped.reset {
+            self.write_frame(self.reset_frame(dropped.id, reason)).await?;
+        } else if dropped.send_fin {
+            self.write_frame(Frame::new(CMD_FIN, dropped.id)).await?;
+        }
+        Ok(())
+    }
+
+    /// 把队列中已就绪的帧（可能来自多个 Stream）合并成一次写入。
+    /// 仍在发送 padding 时每帧的填充依赖包序号，逐帧写出。
+    async fn write_batch(
+        &self,
+        first: Frame,
+        writer_rx: &mut OutboundRx,
+    ) -> io::Result<()> {
+        if self.send_padding.load(Ordering::Acquire) {
+            return self.write_frame(first).await.map(|_| ());
+        }
+
+        let mut frames = vec![first];
+        let mut batch_len = frames[0].encoded_len();
+        let mut flush = None;
+        while batch_len < MAX_BATCH_BYTES {
+            match writer_rx.try_recv() {
+                Some(Outbound::Frame(frame)) => {
+                    batch_len += frame.encoded_len();
+                    frames.push(frame);
+                }
+                Some(Outbound::Frames(group)) => {
+                    batch_len += group.iter().map(Frame::encoded_len).sum::<usize>();
+                    frames.extend(group);
+                }
+                Some(Outbound::Flush(ack)) => {
+                    flush = Some(ack);
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        {
+            let mut conn_guard = self.conn_w.lock().await;
+            let conn = conn_guard.as_mut().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "session write half closed")
+            })?;
+            if frames.len() == 1 {
+                write_frame_to(conn, frames.pop().expect("batch has one frame")).await?;
+            } else {
+                let mut buf = BytesMut::with_capacity(batch_len);
+                for frame in &frames {
+                    frame.encode_into(&mut buf);
+                }
+                conn.write_all(&buf).await?;
+            }
+        }
+
+        match flush {
+            Some(ack) => self.flush_and_ack(ack).await,
+            None => Ok(()),
+        }
+    }
+
+    /// 一组帧编码后一次写出；仍在发送 padding 时逐帧写出，组内只会插入 CMD_WASTE
+    async fn write_group(&self, frames: Vec<Frame>) -> io::Result<()> {
+        if self.send_padding.load(Ordering::Acquire) {
+            for frame in frames {
+                self.write_frame(frame).await?;
+            }
+            return Ok(());
+        }
+
+        let mut buf = BytesMut::with_capacity(frames.iter().map(Frame::encoded_len).sum());
+        for frame in &frames {
+            frame.encode_into(&mut buf);
+        }
+        let mut conn_guard = self.conn_w.lock().await;
+        let conn = conn_guard.as_mut().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "session write half closed")
+        })?;
+        conn.write_all(&buf).await
+    }
+
+    /// 按 `write_timeout` 限制一次连接写入
+    pub(super) async fn timed_write<T>(
+        &self,
+        write: impl Future<Output = io::Result<T>>,
+    ) -> io::Result<T> {
+        let timeout = self.config.write_timeout;
+        if timeout.is_zero() {
+            return write.await;
+        }
+        tokio::time::timeout(timeout, write)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "session write timed out"))?
+    }
+
+    async fn flush_and_ack(&self, ack: oneshot::Sender<()>) -> io::Result<()> {
+        self.flush_conn().await?;
+        let _ = ack.send(());
+        Ok(())
+    }
+
+    async fn flush_conn(&self) -> io::Result<()> {
+        let mut conn_guard = self.conn_w.lock().await;
+        let conn = conn_guard.as_mut().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "session write half closed")
+        })?;
+        conn.flush().await
+    }
+
     async fn write_frame(&self, frame: Frame) -> io::Result<usize> {
-        let frame_len = frame_len(&frame);
+        let frame_len = frame.encoded_len();
         let mut conn_guard = self.conn_w.lock().await;
         let conn = conn_guard.as_mut().ok_or_else(|| {
             io::Error::new(io::ErrorKind::BrokenPipe, "session write half closed")
@@ -52,44 +281,31 @@ impl Session {
         W: AsyncWrite + Unpin,
     {
         let pkt = self.pkt_counter.fetch_add(1, Ordering::AcqRel);
-        let data_len = frame_len(&frame);
-        if pkt >= self.padding.stop() {
+        let data_len = frame.encoded_len();
+        let padding = self.padding.load();
+        if pkt >= padding.stop() {
             self.send_padding.store(false, Ordering::Release);
             write_frame_to(conn, frame).await?;
             return Ok(());
         }
 
         write_frame_to(conn, frame).await?;
-        let pkt_sizes = self.padding.generate_record_payload_sizes(pkt);
-        let mut payload_remaining = data_len;
-        for size in pkt_sizes {
-            if size == crate::proxy::padding::CHECK_MARK {
-                if payload_remaining == 0 {
-                    break;
-                }
-                continue;
-            }
-            let target_payload = size as usize;
-            let consumed = payload_remaining.min(target_payload);
-            payload_remaining -= consumed;
-            if target_payload > consumed + HEADER_OVERHEAD_SIZE {
-                let waste_payload_len = target_payload - consumed - HEADER_OVERHEAD_SIZE;
-                let mut waste = BytesMut::with_capacity(HEADER_OVERHEAD_SIZE + waste_payload_len);
-                waste.put_u8(CMD_WASTE);
-                waste.put_u32(0);
-                waste.put_u16(waste_payload_len as u16);
-                waste.extend_from_slice(&self.padding.rng_vec(waste_payload_len));
-                conn.write_all(&waste).await?;
-            }
+        self.state.padded_payload_bytes.fetch_add(data_len as u64, Ordering::Relaxed);
+        for waste_len in padding.waste_lengths(pkt, data_len) {
+            let waste = Frame::with_data(CMD_WASTE, 0, Bytes::from(padding.rng_vec(waste_len)));
+            let waste_frame_len = waste.encoded_len() as u64;
+            write_frame_to(conn, waste).await?;
+            self.state.padding_bytes.fetch_add(waste_frame_len, Ordering::Relaxed);
         }
         Ok(())
     }
 
+    /// 对端在帧边界关闭连接或本端已关闭时返回 `Ok`，其余情况返回读取或协议错误
     pub(super) async fn recv_loop(&self) -> io::Result<()> {
-        let mut header_buf = [0u8; HEADER_OVERHEAD_SIZE];
+        let mut recv_buf = BytesMut::new();
         loop {
             if self.is_closed() {
-                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Session closed"));
+                return Ok(());
             }
 
             let (cmd, sid, data) = {
@@ -97,14 +313,17 @@ impl Session {
                 let conn = conn_guard.as_mut().ok_or_else(|| {
                     io::Error::new(io::ErrorKind::BrokenPipe, "session read half closed")
                 })?;
-                conn.read_exact(&mut header_buf).await?;
-                let header = RawHeader::from_bytes(&header_buf)?;
-                let mut data = BytesMut::with_capacity(header.length as usize);
-                if header.length > 0 {
-                    data.resize(header.length as usize, 0);
-                    conn.read_exact(&mut data).await?;
-                }
-                (header.cmd, header.sid, data.freeze())
+                let Some(header) = RawHeader::read_next(conn, &mut recv_buf).await? else {
+                    log::debug!("[Session] Session {} closed by peer", self.id());
+                    return Ok(());
+                };
+                let data = if header.cmd == CMD_WASTE {
+                    // 填充数据留在缓冲区中，读取下一帧时直接覆盖
+                    Bytes::new()
+                } else {
+                    recv_buf.split().freeze()
+                };
+                (header.cmd, header.sid, data)
             };
             self.handle_frame(cmd, sid, data).await?;
         }
@@ -115,19 +334,7 @@ pub(super) async fn write_frame_to<W>(conn: &mut W, frame: Frame) -> io::Result<
 where
     W: AsyncWrite + Unpin,
 {
-    let header = frame_header(&frame);
+    let header = frame.header();
     let mut data = Buf::chain(&header[..], frame.data);
     conn.write_all_buf(&mut data).await
 }
-
-fn frame_header(frame: &Frame) -> [u8; HEADER_OVERHEAD_SIZE] {
-    let mut header = [0u8; HEADER_OVERHEAD_SIZE];
-    header[0] = frame.cmd;
-    header[1..5].copy_from_slice(&frame.sid.to_be_bytes());
-    header[5..7].copy_from_slice(&(frame.data.len() as u16).to_be_bytes());
-    header
-}
-
-fn frame_len(frame: &Frame) -> usize {
-    HEADER_OVERHEAD_SIZE + frame.data.len()
-}
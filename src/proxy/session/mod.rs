@@ -1,5 +1,7 @@
 pub mod client;
 mod close_reason;
+pub mod codec;
+pub mod config;
 mod core;
 mod dispatcher;
 pub mod frame;
@@ -7,7 +9,9 @@ mod io_loop;
 mod state;
 pub mod stream;
 
-pub use client::Client;
-pub use core::Session;
+pub use client::{Client, ClientBuilder, SessionInfo};
+pub use codec::FrameCodec;
+pub use config::{SessionConfig, DEFAULT_RECV_WINDOW};
+pub use core::{PaddingStats, Role, Session};
 pub use frame::*;
-pub use stream::Stream;
+pub use stream::{Priority, Stream, StreamInfo};
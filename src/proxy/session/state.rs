@@ -1,32 +1,87 @@
-use bytes::Bytes;
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use super::stream::{FinAckWaiters, StreamHandle};
+use crate::proxy::protocol::frame::MAX_PAYLOAD_SIZE;
+use crate::proxy::protocol::settings::ClientSettings;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::{collections::HashMap, io};
-use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::sync::{oneshot, RwLock};
+
+/// 本端分配的 Stream id 每次递增的步长，保持奇偶性不变
+pub(super) const STREAM_ID_STEP: u32 = 2;
 
 pub(super) struct SessionState {
-    pub(super) streams: Arc<RwLock<HashMap<u32, mpsc::Sender<Bytes>>>>,
+    pub(super) streams: Arc<RwLock<HashMap<u32, StreamHandle>>>,
     pub(super) heartbeat_waiters: Arc<RwLock<HashMap<u32, oneshot::Sender<()>>>>,
     pub(super) synack_waiters: Arc<RwLock<HashMap<u32, oneshot::Sender<io::Result<()>>>>>,
     pub(super) next_stream_id: AtomicU32,
     pub(super) peer_version: AtomicU32,
+    /// 对端在设置中声明支持 CMD_PSH_SEQ
+    pub(super) peer_psh_seq: AtomicBool,
+    /// 对端在设置中声明支持 CMD_STOP_SENDING，所有 Stream 共享
+    pub(super) peer_stop_sending: Arc<AtomicBool>,
+    /// 对端在设置中声明支持 CMD_RST
+    pub(super) peer_rst: AtomicBool,
+    /// 对端在设置中声明可以接收压缩的填充方案
+    pub(super) peer_padding_deflate: AtomicBool,
+    /// 对端在设置中声明支持 CMD_FIN_ACK，所有 Stream 共享
+    pub(super) peer_fin_ack: Arc<AtomicBool>,
+    /// 等待 CMD_FIN_ACK 的 Stream，在 `poll_shutdown` 中同步注册
+    pub(super) fin_ack_waiters: FinAckWaiters,
+    /// 发送数据帧时的负载上限，所有 Stream 共享
+    pub(super) send_max_payload: Arc<AtomicUsize>,
+    /// 所有 Stream 已收到但尚未被读取的字节数之和，包括重排缓冲中的数据
+    pub(super) buffered_bytes: Arc<AtomicUsize>,
+    pub(super) peer_settings: std::sync::Mutex<Option<ClientSettings>>,
     pub(super) closed: Arc<AtomicBool>,
     pub(super) stream_count: AtomicU32,
     pub(super) last_active_unix_ms: AtomicU64,
+    pub(super) streams_served: AtomicU64,
+    /// 填充阶段写出的非 WASTE 帧字节数（含帧头）
+    pub(super) padded_payload_bytes: AtomicU64,
+    /// 填充阶段写出的 WASTE 帧字节数（含帧头）
+    pub(super) padding_bytes: AtomicU64,
+    /// 限制接受新 Stream 的速率
+    pub(super) syn_bucket: std::sync::Mutex<TokenBucket>,
+    pub(super) created_at: Instant,
 }
 
 impl SessionState {
-    pub(super) fn new() -> Self {
+    /// `first_stream_id` 为本端分配的第一个 Stream id，其奇偶性决定之后所有 id 的奇偶性
+    pub(super) fn new(first_stream_id: u32) -> Self {
         Self {
             streams: Arc::new(RwLock::new(HashMap::new())),
             heartbeat_waiters: Arc::new(RwLock::new(HashMap::new())),
             synack_waiters: Arc::new(RwLock::new(HashMap::new())),
-            next_stream_id: AtomicU32::new(1),
+            next_stream_id: AtomicU32::new(first_stream_id),
             peer_version: AtomicU32::new(0),
+            peer_psh_seq: AtomicBool::new(false),
+            peer_stop_sending: Arc::new(AtomicBool::new(false)),
+            peer_rst: AtomicBool::new(false),
+            peer_padding_deflate: AtomicBool::new(false),
+            peer_fin_ack: Arc::new(AtomicBool::new(false)),
+            fin_ack_waiters: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            send_max_payload: Arc::new(AtomicUsize::new(MAX_PAYLOAD_SIZE)),
+            buffered_bytes: Arc::new(AtomicUsize::new(0)),
+            peer_settings: std::sync::Mutex::new(None),
             closed: Arc::new(AtomicBool::new(false)),
             stream_count: AtomicU32::new(0),
             last_active_unix_ms: AtomicU64::new(now_unix_ms()),
+            streams_served: AtomicU64::new(0),
+            padded_payload_bytes: AtomicU64::new(0),
+            padding_bytes: AtomicU64::new(0),
+            syn_bucket: std::sync::Mutex::new(TokenBucket::new()),
+            created_at: Instant::now(),
+        }
+    }
+
+    /// 在持有 `streams` 写锁时分配新 Stream 的 id。计数回绕后跳过 0 与仍在使用的 id
+    pub(super) fn allocate_stream_id(&self, streams: &HashMap<u32, StreamHandle>) -> u32 {
+        loop {
+            let id = self.next_stream_id.fetch_add(STREAM_ID_STEP, Ordering::AcqRel);
+            if id != 0 && !streams.contains_key(&id) {
+                return id;
+            }
         }
     }
 
@@ -42,11 +97,43 @@ impl SessionState {
         self.last_active_unix_ms.load(Ordering::Acquire)
     }
 
+    /// 新建 Stream 时调用，同时累计活跃数与总服务数
+    pub(super) fn stream_opened(&self) {
+        self.stream_count.fetch_add(1, Ordering::AcqRel);
+        self.streams_served.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub(super) fn touch_activity(&self) {
         self.last_active_unix_ms.store(now_unix_ms(), Ordering::Release);
     }
 }
 
+/// 令牌桶：按 `rate` 每秒补充令牌，最多积攒 `rate` 个
+pub(super) struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// 初始为满桶，容量在第一次取令牌时按速率确定
+    fn new() -> Self {
+        Self { tokens: f64::INFINITY, refilled_at: Instant::now() }
+    }
+
+    /// 取一个令牌，桶空时返回 false
+    pub(super) fn try_take(&mut self, rate: u32) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.refilled_at = now;
+        self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
 fn now_unix_ms() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -1,87 +1,538 @@
-use crate::proxy::session::frame::{Frame, CMD_FIN, CMD_PSH};
-use bytes::Bytes;
-use std::future::Future;
+use super::io_loop::{flush_outbound, Outbound, OutboundTx, StreamDropped};
+use crate::proxy::protocol::frame::{Frame, CMD_FIN, CMD_PSH, CMD_STOP_SENDING, SEQ_PREFIX_SIZE};
+use bytes::{Buf, Bytes};
+use std::collections::{BTreeMap, HashMap};
+use std::future::{poll_fn, Future};
 use std::io;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::task::{Context, Poll};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-use tokio::sync::{
-    mpsc::{self, error::TrySendError},
-    oneshot,
-};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::{oneshot, Notify, Semaphore};
 
 type PendingFrameSend =
-    Pin<Box<dyn Future<Output = Result<(), mpsc::error::SendError<Frame>>> + Send>>;
+    Pin<Box<dyn Future<Output = Result<(), mpsc::error::SendError<Outbound>>> + Send>>;
+type PendingFlush = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+type PendingFinAck = Pin<Box<dyn Future<Output = ()> + Send>>;
+/// 等待 CMD_FIN_ACK 的 Stream id 与唤醒端
+pub(crate) type FinAckWaiters = Arc<Mutex<HashMap<u32, oneshot::Sender<()>>>>;
 
-/// Stream 实现 AsyncRead 和 AsyncWrite，提供读写缓冲区
-pub struct Stream {
+/// Stream 的发送优先级。Session 的写循环总是先写出优先级更高的 Stream 的帧，
+/// 交互式连接可以设为 `High`，避免排在大流量传输之后
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Stream 与 Session 共享的状态标记，关闭或收到 SYNACK 时唤醒等待者
+#[derive(Default)]
+struct CloseSignal {
+    closed: AtomicBool,
+    established: AtomicBool,
+    /// 对端在 SYNACK 中给出的拒绝原因
+    rejected: OnceLock<String>,
+    /// 本端调用了 [`Stream::shutdown_read`]，收到的数据直接丢弃
+    read_shutdown: AtomicBool,
+    /// 对端以 CMD_STOP_SENDING 表示不再读取，之后的写入返回错误
+    write_stopped: AtomicBool,
+    /// 对端以 CMD_FIN_ACK 确认收到了本端的 CMD_FIN
+    fin_acked: AtomicBool,
+    /// 被任一端重置时的错误信息，之后的读写返回 `ConnectionReset`
+    reset: OnceLock<String>,
+    notify: Notify,
+}
+
+impl CloseSignal {
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    fn establish(&self) {
+        self.established.store(true, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+
+    /// 被重置或被对端拒绝时返回其原因，否则返回 `fallback`
+    fn error(&self, kind: io::ErrorKind, fallback: &str) -> io::Error {
+        if let Some(msg) = self.reset.get() {
+            return io::Error::new(io::ErrorKind::ConnectionReset, msg.clone());
+        }
+        match self.rejected.get() {
+            Some(msg) => io::Error::other(format!("remote: {}", msg)),
+            None => io::Error::new(kind, fallback.to_string()),
+        }
+    }
+}
+
+/// 重置 Stream 时报告给读写方的错误信息
+pub(crate) fn reset_message(prefix: &str, reason: &str) -> String {
+    if reason.is_empty() {
+        prefix.to_string()
+    } else {
+        format!("{}: {}", prefix, reason)
+    }
+}
+
+/// Stream 的流量统计，与 Session 侧的 StreamHandle 共享，读取时不需要加锁
+struct StreamStats {
+    created_at: Instant,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    /// 由应用通过 [`Stream::set_target`] 记录的目标地址
+    target: OnceLock<String>,
+}
+
+impl StreamStats {
+    fn new() -> Self {
+        Self {
+            created_at: Instant::now(),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            target: OnceLock::new(),
+        }
+    }
+
+    fn snapshot(&self, id: u32) -> StreamInfo {
+        StreamInfo {
+            id,
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            age: self.created_at.elapsed(),
+            target: self.target.get().cloned(),
+        }
+    }
+}
+
+/// [`crate::proxy::session::Session::active_streams`] 返回的单个 Stream 的快照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
     pub id: u32,
+    /// 已交给 Session 发送的数据字节数
+    pub bytes_sent: u64,
+    /// 应用已读出的数据字节数
+    pub bytes_received: u64,
+    pub age: Duration,
+    pub target: Option<String>,
+}
+
+/// Session 持有的 Stream 句柄：数据发送端、接收窗口与共享的关闭标记
+pub(crate) struct StreamHandle {
+    pub(crate) data_tx: mpsc::UnboundedSender<Bytes>,
+    /// 剩余接收窗口（字节），Stream 读出数据后归还
+    pub(crate) window: Arc<Semaphore>,
+    /// CMD_PSH_SEQ 乱序到达时的重排缓冲
+    pub(crate) reorder: Mutex<ReorderBuffer>,
+    closed: Arc<CloseSignal>,
+    stats: Arc<StreamStats>,
+}
+
+impl StreamHandle {
+    /// 由 Session 标记 Stream 已关闭（收到 FIN、被移除或 Session 关闭）
+    pub(crate) fn mark_closed(&self) {
+        self.closed.close();
+        // 唤醒可能正在等待窗口的接收循环
+        self.window.close();
+    }
+
+    /// 收到成功的 SYNACK
+    pub(crate) fn mark_established(&self) {
+        self.closed.establish();
+    }
+
+    /// 对端以 SYNACK 拒绝了该 Stream，之后的读写与 `Stream::established()` 返回该原因
+    pub(crate) fn mark_rejected(&self, reason: String) {
+        let _ = self.closed.rejected.set(reason);
+        self.mark_closed();
+    }
+
+    /// Stream 被重置，未读出的数据不再交付，之后的读写返回 `message`
+    pub(crate) fn mark_reset(&self, message: String) {
+        let _ = self.closed.reset.set(message);
+        self.mark_closed();
+    }
+
+    /// 本端已关闭读方向，新到的数据不再交付
+    pub(crate) fn is_read_shutdown(&self) -> bool {
+        self.closed.read_shutdown.load(Ordering::Acquire)
+    }
+
+    /// 收到对端的 CMD_STOP_SENDING
+    pub(crate) fn mark_write_stopped(&self) {
+        self.closed.write_stopped.store(true, Ordering::Release);
+    }
+
+    /// 当前的统计快照
+    pub(crate) fn info(&self, id: u32) -> StreamInfo {
+        self.stats.snapshot(id)
+    }
+}
+
+/// 创建 Stream 时由 Session 提供的参数
+pub(crate) struct StreamParams {
+    pub(crate) frame_tx: OutboundTx,
+    pub(crate) dropped_tx: mpsc::UnboundedSender<StreamDropped>,
+    pub(crate) recv_window: usize,
+    /// 对端支持时以 CMD_PSH_SEQ 发送
+    pub(crate) sequenced: bool,
+    /// 协商后的单帧负载上限，对端设置到达后可能变小
+    pub(crate) max_payload: Arc<AtomicUsize>,
+    /// Session 内所有 Stream 共享的缓冲字节计数，读出或丢弃数据时扣减
+    pub(crate) session_buffered: Arc<AtomicUsize>,
+    /// 对端是否支持 CMD_STOP_SENDING，对端设置到达后可能改变
+    pub(crate) peer_stop_sending: Arc<AtomicBool>,
+    /// 是否等待对端的 SYNACK 确认
+    pub(crate) awaits_synack: bool,
+    pub(crate) fin_ack: FinAck,
+}
+
+/// 关闭写方向时等待 CMD_FIN_ACK 所需的 Session 状态
+#[derive(Clone)]
+pub(crate) struct FinAck {
+    /// 最长等待时间，0 表示不等待
+    pub(crate) timeout: Duration,
+    /// 对端是否支持 CMD_FIN_ACK，对端设置到达后可能改变
+    pub(crate) peer_supports: Arc<AtomicBool>,
+    pub(crate) waiters: FinAckWaiters,
+}
+
+/// 待写入的数据：应用缓冲区需要复制进帧，`Bytes` 可以直接切片共享
+#[derive(Clone, Copy)]
+enum Payload<'a> {
+    Slice(&'a [u8]),
+    Shared(&'a Bytes),
+}
+
+impl Payload<'_> {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Payload::Slice(buf) => buf,
+            Payload::Shared(data) => data,
+        }
+    }
+
+    /// 前 `n` 字节作为帧数据
+    fn take(&self, n: usize) -> Bytes {
+        match self {
+            Payload::Slice(buf) => Bytes::copy_from_slice(&buf[..n]),
+            Payload::Shared(data) => data.slice(..n),
+        }
+    }
+}
+
+/// 最多暂存的乱序帧数，超过视为对端违反协议
+const MAX_REORDER_FRAMES: usize = 1024;
+
+/// 按序号重排 CMD_PSH_SEQ 的数据，序号从 0 开始逐帧递增
+#[derive(Default)]
+pub(crate) struct ReorderBuffer {
+    next: u32,
+    pending: BTreeMap<u32, Bytes>,
+}
+
+impl ReorderBuffer {
+    /// 收下序号为 `seq` 的数据，返回此时可以按序交付的数据；
+    /// 重复或已交付过的序号返回 `Ok(None)`，由调用方丢弃
+    pub(crate) fn push(&mut self, seq: u32, data: Bytes) -> io::Result<Option<Vec<Bytes>>> {
+        // 按回绕距离判断先后，序号可以超过 u32::MAX 后从 0 继续
+        if (seq.wrapping_sub(self.next) as i32) < 0 || self.pending.contains_key(&seq) {
+            return Ok(None);
+        }
+        if seq != self.next {
+            if self.pending.len() >= MAX_REORDER_FRAMES {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "too many out-of-order frames",
+                ));
+            }
+            self.pending.insert(seq, data);
+            return Ok(Some(Vec::new()));
+        }
+
+        let mut ready = vec![data];
+        self.next = self.next.wrapping_add(1);
+        while let Some(data) = self.pending.remove(&self.next) {
+            ready.push(data);
+            self.next = self.next.wrapping_add(1);
+        }
+        Ok(Some(ready))
+    }
+}
+
+/// Stream 实现 AsyncRead 和 AsyncWrite，提供读写缓冲区。
+///
+/// 读、写状态各自由一把锁保护，因此也可以通过 `&Stream`（例如 `Arc<Stream>`）用
+/// [`Stream::read`]/[`Stream::write`] 或 [`Stream::split_ref`] 并发读写；
+/// 同一时刻应只有一个读者和一个写者。
+pub struct Stream {
+    id: u32,
 
     // 用于从 session 读取数据
-    rx: mpsc::Receiver<Bytes>,
+    reader: Mutex<ReadState>,
+    /// 关闭读方向时发送一个空块，唤醒正在等待数据的读者；不阻止通道随 Session 侧关闭
+    wake_reader: mpsc::WeakUnboundedSender<Bytes>,
+    window: Arc<Semaphore>,
+    recv_window: usize,
+    session_buffered: Arc<AtomicUsize>,
+
+    // 用于向 session 写入帧，按 WriteState::priority 选择队列
+    frame_tx: OutboundTx,
+    dropped_tx: mpsc::UnboundedSender<StreamDropped>,
+    /// 对端支持时以 CMD_PSH_SEQ 发送数据，创建时确定，整个 Stream 内不变
+    sequenced: bool,
+    max_payload: Arc<AtomicUsize>,
+    peer_stop_sending: Arc<AtomicBool>,
+    fin_ack: FinAck,
+    writer: Mutex<WriteState>,
 
-    // 用于向 session 写入帧
-    frame_tx: mpsc::Sender<Frame>,
+    // Stream 状态，与 Session 侧的 StreamHandle 共享
+    closed: Arc<CloseSignal>,
+    stats: Arc<StreamStats>,
+    on_close: Mutex<Option<Box<dyn FnOnce() + Send + 'static>>>,
+}
 
+struct ReadState {
+    rx: mpsc::UnboundedReceiver<Bytes>,
     // 部分读取的缓冲区
     read_buffer: Option<Bytes>,
     read_offset: usize,
+}
 
-    // Stream 状态
-    closed: Arc<AtomicBool>,
-
-    // 用于通知 Stream 关闭
-    close_tx: Option<oneshot::Sender<()>>,
-
-    // 异步发送状态（用于正确处理背压）
+/// 异步发送状态（用于正确处理背压）
+#[derive(Default)]
+struct WriteState {
+    fin_sent: bool,
+    priority: Priority,
+    next_seq: u32,
     pending_send: Option<PendingFrameSend>,
     pending_send_len: usize,
     pending_shutdown: Option<PendingFrameSend>,
-    on_close: Option<Box<dyn FnOnce() + Send + 'static>>,
+    pending_fin_ack: Option<PendingFinAck>,
+    pending_flush: Option<PendingFlush>,
 }
 
 impl Stream {
-    pub fn new(
-        id: u32,
-        rx: mpsc::Receiver<Bytes>,
-        frame_tx: mpsc::Sender<Frame>,
-        close_tx: oneshot::Sender<()>,
-    ) -> Self {
-        Self {
-            id,
-            rx,
+    pub(crate) fn new(id: u32, params: StreamParams) -> (Self, StreamHandle) {
+        let StreamParams {
             frame_tx,
-            read_buffer: None,
-            read_offset: 0,
-            closed: Arc::new(AtomicBool::new(false)),
-            close_tx: Some(close_tx),
-            pending_send: None,
-            pending_send_len: 0,
-            pending_shutdown: None,
-            on_close: None,
+            dropped_tx,
+            recv_window,
+            sequenced,
+            max_payload,
+            session_buffered,
+            peer_stop_sending,
+            awaits_synack,
+            fin_ack,
+        } = params;
+        let (data_tx, rx) = mpsc::unbounded_channel();
+        let window = Arc::new(Semaphore::new(recv_window));
+        let closed = Arc::new(CloseSignal::default());
+        let stats = Arc::new(StreamStats::new());
+        if !awaits_synack {
+            closed.establish();
         }
+        let handle = StreamHandle {
+            data_tx,
+            window: Arc::clone(&window),
+            reorder: Mutex::new(ReorderBuffer::default()),
+            closed: Arc::clone(&closed),
+            stats: Arc::clone(&stats),
+        };
+        let stream = Self {
+            id,
+            wake_reader: handle.data_tx.downgrade(),
+            reader: Mutex::new(ReadState {
+                rx,
+                read_buffer: None,
+                read_offset: 0,
+            }),
+            window,
+            recv_window,
+            session_buffered,
+            frame_tx,
+            dropped_tx,
+            sequenced,
+            max_payload,
+            peer_stop_sending,
+            fin_ack,
+            writer: Mutex::new(WriteState::default()),
+            closed,
+            stats,
+            on_close: Mutex::new(None),
+        };
+        (stream, handle)
+    }
+
+    /// Stream 在所属 Session 内的 id
+    pub fn id(&self) -> u32 {
+        self.id
     }
 
     pub fn set_on_close(&mut self, on_close: Box<dyn FnOnce() + Send + 'static>) {
-        self.on_close = Some(on_close);
+        *self.on_close.get_mut().expect("stream on_close lock poisoned") = Some(on_close);
     }
 
     /// 检查是否已关闭
     pub fn is_closed(&self) -> bool {
-        self.closed.load(Ordering::Acquire)
+        self.closed.is_closed()
     }
 
-    /// 标记为关闭
-    fn mark_closed(&mut self) {
-        self.closed.store(true, Ordering::Release);
-        if let Some(tx) = self.close_tx.take() {
-            let _ = tx.send(());
+    /// 对端已确认收到本端关闭写方向时发送的 CMD_FIN。
+    /// 只在设置了 [`crate::proxy::session::SessionConfig::fin_ack_timeout`] 且对端支持时可能为 true
+    pub fn close_acknowledged(&self) -> bool {
+        self.closed.fin_acked.load(Ordering::Acquire)
+    }
+
+    /// 在 Stream 关闭（收到 FIN、Session 关闭或本端关闭）时完成。
+    /// 返回的 future 不借用 Stream，可以在 split 之前取出再与读写并发等待。
+    pub fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        let signal = Arc::clone(&self.closed);
+        async move {
+            loop {
+                // 先注册再检查，避免错过检查与等待之间的通知
+                let notified = signal.notify.notified();
+                if signal.is_closed() {
+                    return;
+                }
+                notified.await;
+            }
         }
-        if let Some(on_close) = self.on_close.take() {
-            on_close();
+    }
+
+    /// 等待对端确认打开该 Stream。对端不发送 SYNACK 时（协议版本 1、服务端）立即返回；
+    /// 对端拒绝时返回其给出的原因，确认前 Stream 关闭返回 `BrokenPipe`。
+    /// `Session::open_stream` 返回前已等待过确认
+    pub async fn established(&self) -> io::Result<()> {
+        let signal = &self.closed;
+        loop {
+            let notified = signal.notify.notified();
+            if signal.established.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            if signal.is_closed() {
+                return Err(signal.error(io::ErrorKind::BrokenPipe, "stream closed before SYNACK"));
+            }
+            notified.await;
+        }
+    }
+
+    /// 当前的发送优先级
+    pub fn priority(&self) -> Priority {
+        self.lock_writer().priority
+    }
+
+    /// 修改发送优先级，之后写入的数据按新优先级排队。
+    /// 先等待已排队的数据写出，保证数据不会因换队列而乱序；调用期间不应同时写入
+    pub async fn set_priority(&self, priority: Priority) -> io::Result<()> {
+        if self.priority() == priority {
+            return Ok(());
+        }
+        poll_fn(|cx| self.poll_flush_shared(cx)).await?;
+        self.lock_writer().priority = priority;
+        Ok(())
+    }
+
+    /// 记录该 Stream 的目标地址，供 [`crate::proxy::session::Session::active_streams`] 展示；
+    /// 只有第一次设置生效
+    pub fn set_target(&self, target: impl Into<String>) {
+        let _ = self.stats.target.set(target.into());
+    }
+
+    /// 当前的流量统计，与 [`crate::proxy::session::Session::active_streams`] 中的条目相同
+    pub fn info(&self) -> StreamInfo {
+        self.stats.snapshot(self.id)
+    }
+
+    /// 已收到但尚未被读取的字节数，不超过接收窗口
+    pub fn buffered_bytes(&self) -> usize {
+        self.recv_window.saturating_sub(self.window.available_permits())
+    }
+
+    /// 通过共享引用读取，语义同 `AsyncReadExt::read`
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        poll_fn(|cx| {
+            let mut read_buf = ReadBuf::new(buf);
+            ready!(self.poll_read_shared(cx, &mut read_buf))?;
+            Poll::Ready(Ok(read_buf.filled().len()))
+        })
+        .await
+    }
+
+    /// 通过共享引用写入，语义同 `AsyncWriteExt::write`。
+    /// 与 `AsyncWrite` 一样，数据会被复制进新分配的帧
+    pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_write_shared(cx, buf)).await
+    }
+
+    /// 写入全部 `data`，按单帧负载上限切片后直接放入帧中，不复制数据。
+    /// 以 CMD_PSH_SEQ 发送的 Stream 需要在数据前加序号，仍会复制
+    pub async fn write_bytes(&self, mut data: Bytes) -> io::Result<()> {
+        while !data.is_empty() {
+            let n = poll_fn(|cx| {
+                self.poll_write_locked(&mut self.lock_writer(), cx, Payload::Shared(&data))
+            })
+            .await?;
+            data.advance(n);
         }
+        Ok(())
+    }
+
+    /// 关闭读方向：丢弃已缓冲与之后到达的数据，之后的读取返回 EOF，写方向不受影响。
+    /// 对端支持时发送 CMD_STOP_SENDING，对端之后向该 Stream 写入会失败；
+    /// 不支持时对端照常发送，数据到达后在本端丢弃
+    pub async fn shutdown_read(&self) -> io::Result<()> {
+        if self.closed.read_shutdown.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        self.discard_buffered(&mut self.lock_reader());
+        if let Some(tx) = self.wake_reader.upgrade() {
+            let _ = tx.send(Bytes::new());
+        }
+        if self.is_closed() || !self.peer_stop_sending.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let queue = self.frame_tx.queue(self.priority()).clone();
+        let frame = Frame::new(CMD_STOP_SENDING, self.id);
+        queue
+            .send(Outbound::Frame(frame))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "session is closed"))
+    }
+
+    /// 立即重置 Stream：丢弃未读出与未发送的数据，之后两端的读写都返回 `ConnectionReset`。
+    /// 对端支持时发送带 `reason` 的 CMD_RST，否则发送 CMD_FIN，对端只会读到 EOF
+    pub fn reset(&self, reason: &str) {
+        if self.is_closed() {
+            return;
+        }
+        let _ = self.closed.reset.set(reset_message("stream reset", reason));
+        // 之后丢弃 Stream 时不再补发 FIN
+        self.lock_writer().fin_sent = true;
+        self.discard_buffered(&mut self.lock_reader());
+        let _ = self.dropped_tx.send(StreamDropped {
+            id: self.id,
+            send_fin: false,
+            reset: Some(reason.to_string()),
+        });
+        self.mark_closed();
+        if let Some(tx) = self.wake_reader.upgrade() {
+            let _ = tx.send(Bytes::new());
+        }
+    }
+
+    /// 借用方式拆分为读写两半，不消耗 Stream，可用于 `Arc<Stream>`
+    pub fn split_ref(&self) -> (StreamReadRef<'_>, StreamWriteRef<'_>) {
+        (StreamReadRef { stream: self }, StreamWriteRef { stream: self })
     }
 
     /// 分割 Stream 为读写两部分
@@ -89,80 +540,171 @@ impl Stream {
     pub fn split(self) -> (tokio::io::ReadHalf<Self>, tokio::io::WriteHalf<Self>) {
         tokio::io::split(self)
     }
-}
 
-impl AsyncRead for Stream {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
+    /// 归还已读出字节占用的窗口
+    fn release_window(&self, n: usize) {
+        if n > 0 {
+            self.window.add_permits(n);
+            self.session_buffered.fetch_sub(n, Ordering::AcqRel);
+            self.stats.bytes_received.fetch_add(n as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// 丢弃尚未读出的数据并归还其占用的窗口，不计入已读字节
+    fn discard_buffered(&self, state: &mut ReadState) {
+        let mut discarded = 0;
+        if let Some(data) = state.read_buffer.take() {
+            discarded += data.len() - state.read_offset;
+            state.read_offset = 0;
+        }
+        while let Ok(data) = state.rx.try_recv() {
+            discarded += data.len();
+        }
+        if discarded > 0 {
+            self.window.add_permits(discarded);
+            self.session_buffered.fetch_sub(discarded, Ordering::AcqRel);
+        }
+    }
+
+    /// 标记为关闭，on_close 只会触发一次
+    fn mark_closed(&self) {
+        self.closed.close();
+        self.window.close();
+        let on_close = self
+            .on_close
+            .lock()
+            .expect("stream on_close lock poisoned")
+            .take();
+        if let Some(on_close) = on_close {
+            on_close();
+        }
+    }
+
+    fn lock_reader(&self) -> MutexGuard<'_, ReadState> {
+        self.reader.lock().expect("stream reader lock poisoned")
+    }
+
+    fn lock_writer(&self) -> MutexGuard<'_, WriteState> {
+        self.writer.lock().expect("stream writer lock poisoned")
+    }
+
+    fn poll_read_shared(
+        &self,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
-        if self.is_closed() {
+        let state = &mut *self.lock_reader();
+
+        // 被重置：缓冲的数据不再交付，报告错误而不是 EOF
+        if self.closed.reset.get().is_some() {
+            self.discard_buffered(state);
+            return Poll::Ready(Err(self.closed.error(io::ErrorKind::ConnectionReset, "")));
+        }
+
+        // 读方向已关闭：关闭前刚交付的数据同样丢弃
+        if self.closed.read_shutdown.load(Ordering::Acquire) {
+            self.discard_buffered(state);
             return Poll::Ready(Ok(()));
         }
 
         // 首先尝试从现有缓冲区读取
-        if let Some(data) = &self.read_buffer {
-            let remaining = data.len() - self.read_offset;
+        if let Some(data) = &state.read_buffer {
+            let remaining = data.len() - state.read_offset;
             let to_copy = remaining.min(buf.remaining());
 
-            buf.put_slice(&data[self.read_offset..self.read_offset + to_copy]);
+            buf.put_slice(&data[state.read_offset..state.read_offset + to_copy]);
 
-            let new_offset = self.read_offset + to_copy;
+            self.release_window(to_copy);
+            let new_offset = state.read_offset + to_copy;
             if new_offset >= data.len() {
-                self.read_buffer = None;
-                self.read_offset = 0;
+                state.read_buffer = None;
+                state.read_offset = 0;
             } else {
-                self.read_offset = new_offset;
+                state.read_offset = new_offset;
             }
 
             return Poll::Ready(Ok(()));
         }
 
-        // 尝试接收新数据
-        match self.rx.poll_recv(cx) {
+        // 尝试接收新数据；已关闭时只交付通道中残留的数据，不再等待
+        let polled = if self.is_closed() {
+            Poll::Ready(state.rx.try_recv().ok())
+        } else {
+            state.rx.poll_recv(cx)
+        };
+        match polled {
             Poll::Ready(Some(data)) => {
                 let data_len = data.len();
                 let to_copy = data_len.min(buf.remaining());
                 buf.put_slice(&data[..to_copy]);
+                self.release_window(to_copy);
 
                 if to_copy < data_len {
-                    self.read_buffer = Some(data);
-                    self.read_offset = to_copy;
+                    state.read_buffer = Some(data);
+                    state.read_offset = to_copy;
                 }
 
                 Poll::Ready(Ok(()))
             }
             Poll::Ready(None) => {
                 self.mark_closed();
+                // 被对端拒绝的 Stream 报告原因而不是 EOF
+                if self.closed.rejected.get().is_some() {
+                    return Poll::Ready(Err(self.closed.error(io::ErrorKind::Other, "")));
+                }
                 Poll::Ready(Ok(()))
             }
             Poll::Pending => Poll::Pending,
         }
     }
-}
 
-impl AsyncWrite for Stream {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
+    fn poll_write_shared(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_locked(&mut self.lock_writer(), cx, Payload::Slice(buf))
+    }
+
+    fn poll_write_locked(
+        &self,
+        state: &mut WriteState,
         cx: &mut Context<'_>,
-        buf: &[u8],
+        payload: Payload<'_>,
     ) -> Poll<io::Result<usize>> {
-        if self.is_closed() {
-            return Poll::Ready(Err(io::Error::new(
-                io::ErrorKind::BrokenPipe,
-                "stream is closed",
-            )));
+        if self.is_closed() || state.fin_sent {
+            let err = self.closed.error(io::ErrorKind::BrokenPipe, "stream is closed");
+            return Poll::Ready(Err(err));
         }
 
-        if self.pending_send.is_none() {
-            let frame = Frame::with_data(CMD_PSH, self.id, Bytes::copy_from_slice(buf));
-            match self.frame_tx.try_send(frame) {
-                Ok(()) => return Poll::Ready(Ok(buf.len())),
+        if state.pending_send.is_none() {
+            let buf = payload.as_slice();
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            if self.closed.write_stopped.load(Ordering::Acquire) {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "peer stopped reading the stream",
+                )));
+            }
+            // 一次只发送一帧能容纳的数据，其余由调用方再次写入
+            let max_payload = self.max_payload.load(Ordering::Acquire);
+            let (frame, n) = if self.sequenced {
+                let n = buf.len().min(max_payload - SEQ_PREFIX_SIZE);
+                let seq = state.next_seq;
+                state.next_seq = seq.wrapping_add(1);
+                (Frame::psh_seq(self.id, seq, &buf[..n]), n)
+            } else {
+                let n = buf.len().min(max_payload);
+                (Frame::with_data(CMD_PSH, self.id, payload.take(n)), n)
+            };
+            let queue = self.frame_tx.queue(state.priority);
+            match queue.try_send(Outbound::Frame(frame)) {
+                Ok(()) => {
+                    self.stats.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+                    return Poll::Ready(Ok(n));
+                }
                 Err(TrySendError::Full(frame)) => {
-                    let tx = self.frame_tx.clone();
-                    self.pending_send = Some(Box::pin(async move { tx.send(frame).await }));
-                    self.pending_send_len = buf.len();
+                    let tx = queue.clone();
+                    state.pending_send = Some(Box::pin(async move { tx.send(frame).await }));
+                    state.pending_send_len = n;
                 }
                 Err(TrySendError::Closed(_)) => {
                     return Poll::Ready(Err(io::Error::new(
@@ -173,17 +715,18 @@ impl AsyncWrite for Stream {
             }
         }
 
-        if let Some(fut) = self.pending_send.as_mut() {
+        if let Some(fut) = state.pending_send.as_mut() {
             match fut.as_mut().poll(cx) {
                 Poll::Ready(Ok(())) => {
-                    let n = self.pending_send_len;
-                    self.pending_send = None;
-                    self.pending_send_len = 0;
+                    let n = state.pending_send_len;
+                    state.pending_send = None;
+                    state.pending_send_len = 0;
+                    self.stats.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
                     Poll::Ready(Ok(n))
                 }
                 Poll::Ready(Err(_)) => {
-                    self.pending_send = None;
-                    self.pending_send_len = 0;
+                    state.pending_send = None;
+                    state.pending_send_len = 0;
                     Poll::Ready(Err(io::Error::new(
                         io::ErrorKind::BrokenPipe,
                         "session is closed",
@@ -196,62 +739,203 @@ impl AsyncWrite for Stream {
         }
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Poll::Ready(Ok(()))
+    /// 等到此前写入的数据真正写进底层连接才返回
+    fn poll_flush_shared(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let state = &mut *self.lock_writer();
+        if state.pending_send.is_some() {
+            ready!(self.poll_write_locked(state, cx, Payload::Slice(&[])))?;
+        }
+
+        if state.pending_flush.is_none() {
+            let tx = self.frame_tx.queue(state.priority).clone();
+            state.pending_flush = Some(Box::pin(flush_outbound(tx)));
+        }
+        let fut = state.pending_flush.as_mut().expect("pending flush just set");
+        match fut.as_mut().poll(cx) {
+            Poll::Ready(result) => {
+                state.pending_flush = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => Poll::Pending,
+        }
     }
 
-    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    fn poll_shutdown_shared(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         if self.is_closed() {
             return Poll::Ready(Ok(()));
         }
 
-        if self.pending_send.is_some() {
-            match self.as_mut().poll_write(cx, &[]) {
-                Poll::Ready(Ok(_)) => {}
-                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
-                Poll::Pending => return Poll::Pending,
-            }
+        let state = &mut *self.lock_writer();
+        if state.pending_send.is_some() {
+            ready!(self.poll_write_locked(state, cx, Payload::Slice(&[])))?;
         }
 
-        if self.pending_shutdown.is_none() {
+        if !state.fin_sent && state.pending_shutdown.is_none() {
+            // 先注册再发送，确认不会早于注册到达
+            state.pending_fin_ack = self.register_fin_ack();
             let frame = Frame::new(CMD_FIN, self.id);
-            match self.frame_tx.try_send(frame) {
-                Ok(()) => {
-                    self.mark_closed();
-                    return Poll::Ready(Ok(()));
-                }
+            let queue = self.frame_tx.queue(state.priority);
+            match queue.try_send(Outbound::Frame(frame)) {
+                Ok(()) => state.fin_sent = true,
                 Err(TrySendError::Full(frame)) => {
-                    let tx = self.frame_tx.clone();
-                    self.pending_shutdown = Some(Box::pin(async move { tx.send(frame).await }));
+                    let tx = queue.clone();
+                    state.pending_shutdown = Some(Box::pin(async move { tx.send(frame).await }));
                 }
                 Err(TrySendError::Closed(_)) => {
-                    self.mark_closed();
-                    return Poll::Ready(Ok(()));
+                    state.fin_sent = true;
+                    self.abandon_fin_ack(state);
                 }
             }
         }
 
-        if let Some(fut) = self.pending_shutdown.as_mut() {
-            match fut.as_mut().poll(cx) {
-                Poll::Ready(_) => {
-                    self.pending_shutdown = None;
-                    self.mark_closed();
-                    Poll::Ready(Ok(()))
-                }
-                Poll::Pending => Poll::Pending,
+        if let Some(fut) = state.pending_shutdown.as_mut() {
+            let sent = ready!(fut.as_mut().poll(cx));
+            state.pending_shutdown = None;
+            state.fin_sent = true;
+            if sent.is_err() {
+                self.abandon_fin_ack(state);
             }
-        } else {
-            Poll::Pending
         }
+
+        if let Some(fut) = state.pending_fin_ack.as_mut() {
+            ready!(fut.as_mut().poll(cx));
+            state.pending_fin_ack = None;
+        }
+        self.mark_closed();
+        Poll::Ready(Ok(()))
+    }
+
+    /// 需要等待对端确认时注册等待者，返回等待确认或超时的 future。
+    /// 超时视为对端已关闭：不支持该扩展的旧版本不会确认，确认也可能随连接一起丢失
+    fn register_fin_ack(&self) -> Option<PendingFinAck> {
+        let fin_ack = &self.fin_ack;
+        if fin_ack.timeout.is_zero() || !fin_ack.peer_supports.load(Ordering::Acquire) {
+            return None;
+        }
+        let (tx, rx) = oneshot::channel();
+        let waiters = Arc::clone(&fin_ack.waiters);
+        waiters.lock().expect("fin ack waiters lock poisoned").insert(self.id, tx);
+        let (id, timeout, closed) = (self.id, fin_ack.timeout, Arc::clone(&self.closed));
+        Some(Box::pin(async move {
+            // 先结束等待再清理，此时接收端已丢弃
+            let acked = tokio::time::timeout(timeout, rx).await;
+            match acked {
+                Ok(Ok(())) => closed.fin_acked.store(true, Ordering::Release),
+                Ok(Err(_)) => {}
+                Err(_) => remove_abandoned_fin_ack(&waiters, id),
+            }
+        }))
+    }
+
+    /// 不再等待确认时移除注册的等待者，免得过期的条目留在 Session 中
+    fn abandon_fin_ack(&self, state: &mut WriteState) {
+        if state.pending_fin_ack.take().is_some() {
+            remove_abandoned_fin_ack(&self.fin_ack.waiters, self.id);
+        }
+    }
+}
+
+/// 移除接收端已丢弃的等待者；id 复用后属于新 Stream 的条目保留
+fn remove_abandoned_fin_ack(waiters: &FinAckWaiters, id: u32) {
+    let mut waiters = waiters.lock().expect("fin ack waiters lock poisoned");
+    if waiters.get(&id).is_some_and(|tx| tx.is_closed()) {
+        waiters.remove(&id);
+    }
+}
+
+impl std::fmt::Debug for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stream").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.poll_read_shared(cx, buf)
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_shared(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush_shared(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_shutdown_shared(cx)
+    }
+}
+
+/// [`Stream::split_ref`] 返回的读半部
+pub struct StreamReadRef<'a> {
+    stream: &'a Stream,
+}
+
+/// [`Stream::split_ref`] 返回的写半部
+pub struct StreamWriteRef<'a> {
+    stream: &'a Stream,
+}
+
+impl AsyncRead for StreamReadRef<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.stream.poll_read_shared(cx, buf)
+    }
+}
+
+impl AsyncWrite for StreamWriteRef<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.stream.poll_write_shared(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.stream.poll_flush_shared(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.stream.poll_shutdown_shared(cx)
     }
 }
 
 impl Drop for Stream {
     fn drop(&mut self) {
-        if !self.is_closed() {
-            let frame = Frame::new(CMD_FIN, self.id);
-            let _ = self.frame_tx.try_send(frame);
-            self.mark_closed();
+        // 即使对端已发来 FIN，也要回一个 FIN 让对端清理它的 Stream 表；
+        // 交给写循环处理，帧队列已满时也能送达，并移除本端 Session 中的条目
+        let fin_sent = self.writer.get_mut().map(|w| w.fin_sent).unwrap_or(true);
+        // 等待确认时被丢弃，等待者不会再被取走
+        let awaiting_ack = self
+            .writer
+            .get_mut()
+            .map(|w| w.pending_fin_ack.take().is_some())
+            .unwrap_or(false);
+        if awaiting_ack {
+            remove_abandoned_fin_ack(&self.fin_ack.waiters, self.id);
         }
+        let _ = self.dropped_tx.send(StreamDropped {
+            id: self.id,
+            send_fin: !fin_sent,
+            reset: None,
+        });
+        self.mark_closed();
+        // 窗口已关闭，不会再有数据计入；未读出的数据随 Stream 一起释放
+        self.session_buffered.fetch_sub(self.buffered_bytes(), Ordering::AcqRel);
     }
 }
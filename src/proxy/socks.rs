@@ -0,0 +1,273 @@
+//! SOCKS5 server-side handshake (RFC 1928, RFC 1929 username/password).
+//!
+//! 客户端二进制用它做本地入口，嵌入方也可以直接拿来实现 SOCKS5 前端。
+
+use crate::proxy::addr_codec::{
+    build_socks_addr, read_socks_addr_with_atyp, AddressType, SocksAddr,
+};
+use std::fmt;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const SOCKS_VERSION: u8 = 0x05;
+
+pub const METHOD_NO_AUTH: u8 = 0x00;
+pub const METHOD_USER_PASS: u8 = 0x02;
+pub const METHOD_NO_ACCEPTABLE: u8 = 0xFF;
+
+pub const CMD_CONNECT: u8 = 0x01;
+pub const CMD_BIND: u8 = 0x02;
+pub const CMD_UDP_ASSOCIATE: u8 = 0x03;
+
+pub const REP_SUCCEEDED: u8 = 0x00;
+pub const REP_GENERAL_FAILURE: u8 = 0x01;
+pub const REP_CONNECTION_NOT_ALLOWED: u8 = 0x02;
+pub const REP_NETWORK_UNREACHABLE: u8 = 0x03;
+pub const REP_HOST_UNREACHABLE: u8 = 0x04;
+pub const REP_CONNECTION_REFUSED: u8 = 0x05;
+pub const REP_TTL_EXPIRED: u8 = 0x06;
+pub const REP_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+pub const REP_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+const USER_PASS_VERSION: u8 = 0x01;
+
+/// 请求校验失败的具体原因，包装在 `io::Error` 中返回，可用 [`SocksError::from_io`] 取出
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocksError {
+    /// VER 不是 0x05
+    InvalidVersion,
+    /// RSV 必须为 0x00
+    NonZeroReserved,
+    /// ATYP 不是 IPv4、域名或 IPv6
+    UnsupportedAddressType,
+    /// 域名长度为 0
+    EmptyDomain,
+    /// CONNECT 的目标端口为 0
+    ZeroPort,
+}
+
+impl SocksError {
+    pub fn from_io(err: &io::Error) -> Option<Self> {
+        err.get_ref()?.downcast_ref::<Self>().copied()
+    }
+
+    /// 出错时回给客户端的 REP
+    pub fn reply_code(self) -> u8 {
+        match self {
+            Self::UnsupportedAddressType => REP_ADDRESS_TYPE_NOT_SUPPORTED,
+            _ => REP_GENERAL_FAILURE,
+        }
+    }
+}
+
+impl fmt::Display for SocksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Self::InvalidVersion => "unsupported SOCKS version",
+            Self::NonZeroReserved => "non-zero reserved byte",
+            Self::UnsupportedAddressType => "unsupported address type",
+            Self::EmptyDomain => "empty domain name",
+            Self::ZeroPort => "destination port is zero",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for SocksError {}
+
+impl From<SocksError> for io::Error {
+    fn from(err: SocksError) -> Self {
+        let kind = match err {
+            SocksError::UnsupportedAddressType => io::ErrorKind::Unsupported,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// 客户端的 SOCKS5 请求，命令是否支持由调用方决定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocksRequest {
+    pub command: u8,
+    pub addr: SocksAddr,
+}
+
+/// SOCKS5 服务端握手，默认不需要认证
+#[derive(Debug, Clone, Default)]
+pub struct Socks5Handshake {
+    credentials: Option<(String, String)>,
+    allow_anonymous: bool,
+}
+
+impl Socks5Handshake {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 要求客户端使用用户名/密码认证
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// 配置了用户名/密码时仍接受只提供无认证方法的客户端，同时提供两者的客户端使用用户名/密码
+    pub fn with_anonymous(mut self, allow: bool) -> Self {
+        self.allow_anonymous = allow;
+        self
+    }
+
+    /// 本端支持的认证方法，按优先级从高到低
+    pub fn supported_methods(&self) -> Vec<u8> {
+        match self.credentials {
+            Some(_) if self.allow_anonymous => vec![METHOD_USER_PASS, METHOD_NO_AUTH],
+            Some(_) => vec![METHOD_USER_PASS],
+            None => vec![METHOD_NO_AUTH],
+        }
+    }
+
+    /// 完成方法协商、认证并读取请求；成功后调用方需用 [`write_reply`] 应答
+    pub async fn negotiate<S>(&self, stream: &mut S) -> io::Result<SocksRequest>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let method = self.negotiate_method(stream).await?;
+        if method == METHOD_USER_PASS {
+            self.authenticate(stream).await?;
+        }
+        match read_request(stream).await {
+            Ok(req) => Ok(req),
+            Err(e) => {
+                if let Some(err) = SocksError::from_io(&e) {
+                    let _ = write_reply(stream, err.reply_code(), None).await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// 读取问候，在客户端提供的方法中选择本端优先级最高的一个；无可用方法时回复 0xFF 并报错
+    pub async fn negotiate_method<S>(&self, stream: &mut S) -> io::Result<u8>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head).await?;
+        check_version(head[0])?;
+        let mut methods = vec![0u8; head[1] as usize];
+        stream.read_exact(&mut methods).await?;
+
+        let chosen = self.supported_methods().into_iter().find(|m| methods.contains(m));
+        let Some(chosen) = chosen else {
+            stream.write_all(&[SOCKS_VERSION, METHOD_NO_ACCEPTABLE]).await?;
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "no acceptable auth method",
+            ));
+        };
+        stream.write_all(&[SOCKS_VERSION, chosen]).await?;
+        Ok(chosen)
+    }
+
+    /// RFC 1929 用户名/密码子协商
+    pub async fn authenticate<S>(&self, stream: &mut S) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut ver = [0u8; 1];
+        stream.read_exact(&mut ver).await?;
+        if ver[0] != USER_PASS_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported auth version",
+            ));
+        }
+        let username = read_len_prefixed(stream).await?;
+        let password = read_len_prefixed(stream).await?;
+
+        let accepted = self
+            .credentials
+            .as_ref()
+            .is_some_and(|(u, p)| u.as_bytes() == username && p.as_bytes() == password);
+        stream
+            .write_all(&[USER_PASS_VERSION, if accepted { 0x00 } else { 0x01 }])
+            .await?;
+        if !accepted {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "invalid SOCKS credentials",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 读取 `VER CMD RSV ATYP DST.ADDR DST.PORT` 并严格校验，失败时返回带 [`SocksError`] 的错误。
+/// 所有长度字段都是单字节，最多读取 4 + 1 + 255 + 2 字节。
+///
+/// UDP ASSOCIATE 的目标地址按 RFC 1928 允许为全零，因此端口为 0 只对 CONNECT 报错。
+pub async fn read_request<S>(stream: &mut S) -> io::Result<SocksRequest>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    check_version(head[0])?;
+    if head[2] != 0x00 {
+        return Err(SocksError::NonZeroReserved.into());
+    }
+    if !matches!(head[3], 0x01 | 0x03 | 0x04) {
+        return Err(SocksError::UnsupportedAddressType.into());
+    }
+    let addr = read_socks_addr_with_atyp(stream, head[3]).await?;
+    if addr.atyp == AddressType::Domain && addr.host.is_empty() {
+        return Err(SocksError::EmptyDomain.into());
+    }
+    if head[1] == CMD_CONNECT && addr.port == 0 {
+        return Err(SocksError::ZeroPort.into());
+    }
+    Ok(SocksRequest {
+        command: head[1],
+        addr,
+    })
+}
+
+/// 写入 `VER REP RSV ATYP BND.ADDR BND.PORT`，未给出绑定地址时使用 0.0.0.0:0
+pub async fn write_reply<S>(
+    stream: &mut S,
+    code: u8,
+    bound_addr: Option<&SocksAddr>,
+) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let unspecified = SocksAddr {
+        atyp: AddressType::Ipv4,
+        host: "0.0.0.0".to_string(),
+        port: 0,
+    };
+    let mut reply = vec![SOCKS_VERSION, code, 0x00];
+    reply.extend_from_slice(&build_socks_addr(bound_addr.unwrap_or(&unspecified))?);
+    stream.write_all(&reply).await
+}
+
+fn check_version(version: u8) -> io::Result<()> {
+    if version != SOCKS_VERSION {
+        return Err(SocksError::InvalidVersion.into());
+    }
+    Ok(())
+}
+
+async fn read_len_prefixed<S>(stream: &mut S) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut len = [0u8; 1];
+    stream.read_exact(&mut len).await?;
+    let mut value = vec![0u8; len[0] as usize];
+    stream.read_exact(&mut value).await?;
+    Ok(value)
+}
@@ -1,35 +1,105 @@
 use crate::proxy::padding::PaddingFactory;
 use crate::util::r#type::{AsyncReadWrite, DialOutFunc};
+use crate::util::tls::{check_fragment_size, load_system_roots, KeyLogToFile, TlsClientOptions};
 use bytes::{BufMut, BytesMut};
+use hmac::{Hmac, Mac};
+use rustls::pki_types::ServerName;
 use rustls::ClientConfig;
-use sha2::Digest;
+use sha2::{Digest, Sha256};
+use std::fmt;
 use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio_rustls::TlsConnector;
 
-pub fn create_tls_config() -> Arc<ClientConfig> {
-    let mut config = ClientConfig::builder()
-        .with_root_certificates(rustls::RootCertStore::empty())
-        .with_no_client_auth();
-    config
-        .dangerous()
-        .set_certificate_verifier(Arc::new(AllowAnyCertVerifier));
+pub fn create_tls_config(options: &TlsClientOptions) -> io::Result<Arc<ClientConfig>> {
+    let roots = if options.trust_system_roots {
+        load_system_roots()?
+    } else {
+        rustls::RootCertStore::empty()
+    };
+    let builder = ClientConfig::builder_with_provider(options.cipher.crypto_provider())
+        .with_safe_default_protocol_versions()
+        .expect("ring provider supports the default protocol versions")
+        .with_root_certificates(roots);
+    let mut config = match options.load_client_auth()? {
+        Some((cert_chain, key)) => builder
+            .with_client_auth_cert(cert_chain, key)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => builder.with_no_client_auth(),
+    };
+    if !options.trust_system_roots {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(AllowAnyCertVerifier));
+    }
+    if let Some(path) = &options.keylog_file {
+        config.key_log = KeyLogToFile::open(path)?;
+    }
+    config.max_fragment_size = check_fragment_size(options.fragment_size)?;
+    Ok(Arc::new(config))
+}
+
+/// 握手使用的服务器名：`sni` 未设置或为空时取 `server_addr` 的主机部分。
+/// 主机部分是 IP 地址时 rustls 不发送 SNI 扩展
+pub fn tls_server_name(server_addr: &str, sni: Option<&str>) -> io::Result<ServerName<'static>> {
+    let name = match sni {
+        Some(sni) if !sni.is_empty() => sni,
+        _ => server_host(server_addr),
+    };
+    ServerName::try_from(name.to_string()).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("invalid SNI {:?}: {}", name, e))
+    })
+}
+
+/// `sni` 为空字符串时返回不发送 SNI 扩展的配置，否则原样返回
+pub fn tls_config_for_sni(tls_config: Arc<ClientConfig>, sni: Option<&str>) -> Arc<ClientConfig> {
+    if sni != Some("") {
+        return tls_config;
+    }
+    let mut config = (*tls_config).clone();
+    config.enable_sni = false;
     Arc::new(config)
 }
 
+/// 把逗号分隔的 `sni` 拆成多个名字，去掉空白与空项
+pub fn split_sni_list(sni: &str) -> Vec<String> {
+    sni.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect()
+}
+
+/// `host:port` 或 `[v6]:port` 的主机部分
+fn server_host(server_addr: &str) -> &str {
+    let host = match server_addr.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => server_addr,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// `sni` 为 `None` 时使用 `server_addr` 的主机名，为空字符串时不发送 SNI，见 [`tls_server_name`]。
+/// `sni` 是逗号分隔的多个名字时，每次拨号按顺序轮流使用其中一个
 pub fn create_dial_out_func(
     server_addr: String,
     tls_config: Arc<ClientConfig>,
     sni: Option<String>,
     password_sha256: [u8; 32],
     padding: Arc<PaddingFactory>,
+    auth_mode: AuthMode,
 ) -> DialOutFunc {
+    let tls_config = tls_config_for_sni(tls_config, sni.as_deref());
+    let names = sni.as_deref().map(split_sni_list).unwrap_or_default();
+    let next_name = AtomicUsize::new(0);
     Arc::new(move || {
         let server_addr = server_addr.clone();
         let tls_config = tls_config.clone();
-        let sni = sni.clone();
+        let sni = match names.len() {
+            0 => sni.clone(),
+            n => Some(names[next_name.fetch_add(1, Ordering::Relaxed) % n].clone()),
+        };
         let password_sha256 = password_sha256;
         let padding = padding.clone();
 
@@ -38,18 +108,15 @@ pub fn create_dial_out_func(
             let tcp_stream = TcpStream::connect(&server_addr).await?;
             log::debug!("[Client] TCP connection to AnyTLS server established");
 
-            let server_name = sni.unwrap_or_else(|| "localhost".to_string());
-            log::debug!("[Client] Using SNI: {}", server_name);
-            let server_name = server_name
-                .try_into()
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let server_name = tls_server_name(&server_addr, sni.as_deref())?;
+            log::debug!("[Client] Using SNI: {:?}", server_name);
 
             let tls_connector = TlsConnector::from(tls_config);
             log::debug!("[Client] Starting TLS handshake");
             let mut tls_stream = tls_connector.connect(server_name, tcp_stream).await?;
             log::debug!("[Client] TLS handshake completed");
 
-            send_authentication(&mut tls_stream, password_sha256, padding.clone()).await?;
+            authenticate(&mut tls_stream, password_sha256, padding.clone(), auth_mode).await?;
             log::debug!("[Client] Authentication completed");
 
             Ok(Box::new(tls_stream) as Box<dyn AsyncReadWrite>)
@@ -57,12 +124,158 @@ pub fn create_dial_out_func(
     })
 }
 
+/// 不经 TLS、直接在 TCP 上运行 Session 的拨号函数，只用于本机测试与性能分析。
+/// 流量完全明文，调用方应先用 [`require_loopback`] 检查服务端地址
+pub fn create_plain_dial_out_func(
+    server_addr: String,
+    password_sha256: [u8; 32],
+    padding: Arc<PaddingFactory>,
+    auth_mode: AuthMode,
+) -> DialOutFunc {
+    Arc::new(move || {
+        let server_addr = server_addr.clone();
+        let padding = padding.clone();
+
+        Box::new(Box::pin(async move {
+            let mut tcp_stream = TcpStream::connect(&server_addr).await?;
+            authenticate(&mut tcp_stream, password_sha256, padding, auth_mode).await?;
+            Ok(Box::new(tcp_stream) as Box<dyn AsyncReadWrite>)
+        }))
+    })
+}
+
+/// `addr` 解析出的所有地址都是回环地址时返回 `Ok`
+pub async fn require_loopback(addr: &str) -> io::Result<()> {
+    let mut resolved = tokio::net::lookup_host(addr).await?.peekable();
+    if resolved.peek().is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} did not resolve", addr),
+        ));
+    }
+    match resolved.find(|a| !a.ip().is_loopback()) {
+        Some(a) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a loopback address; plaintext mode is loopback-only", a),
+        )),
+        None => Ok(()),
+    }
+}
+
 pub fn password_sha256(password: &str) -> [u8; 32] {
     sha2::Sha256::digest(password.as_bytes()).into()
 }
 
-async fn send_authentication(
-    tls_stream: &mut tokio_rustls::client::TlsStream<TcpStream>,
+/// 认证方式。`legacy` 直接发送密码哈希；`hmac` 由服务端先发送随机 nonce，
+/// 客户端回复 `HMAC-SHA256(sha256(password), nonce)`，截获的认证头无法重放。
+/// `hmac-or-legacy` 只用于客户端：收不到 nonce 时退回 legacy，主动攻击者可借此降级
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuthMode {
+    #[default]
+    Legacy,
+    Hmac,
+    HmacOrLegacy,
+}
+
+impl FromStr for AuthMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "legacy" => Ok(Self::Legacy),
+            "hmac" => Ok(Self::Hmac),
+            "hmac-or-legacy" => Ok(Self::HmacOrLegacy),
+            other => Err(format!(
+                "unknown auth mode '{}', expected legacy|hmac|hmac-or-legacy",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AuthMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Legacy => write!(f, "legacy"),
+            Self::Hmac => write!(f, "hmac"),
+            Self::HmacOrLegacy => write!(f, "hmac-or-legacy"),
+        }
+    }
+}
+
+/// hmac 模式下服务端发送的 nonce 长度
+pub const AUTH_NONCE_LEN: usize = 32;
+
+/// hmac 模式下客户端等待 nonce 的时间
+pub const AUTH_NONCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// hmac 模式的认证响应：以密码哈希为密钥对 nonce 计算 HMAC-SHA256
+pub fn auth_response(password_sha256: &[u8; 32], nonce: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(password_sha256)
+        .expect("HMAC accepts keys of any length");
+    mac.update(nonce);
+    mac.finalize().into_bytes().into()
+}
+
+/// 按 `mode` 完成认证。hmac 模式先等待服务端的 nonce，[`AUTH_NONCE_TIMEOUT`] 内没有收到时
+/// 返回错误，不发送可重放的密码哈希；只有 `hmac-or-legacy` 才改为发送密码哈希
+pub async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut S,
+    password_sha256: [u8; 32],
+    padding: Arc<PaddingFactory>,
+    mode: AuthMode,
+) -> io::Result<()> {
+    let proof = match mode {
+        AuthMode::Legacy => password_sha256,
+        AuthMode::Hmac | AuthMode::HmacOrLegacy => match read_nonce(conn).await? {
+            Some(nonce) => auth_response(&password_sha256, &nonce),
+            None if mode == AuthMode::HmacOrLegacy => {
+                log::warn!("[Client] No auth nonce from server, falling back to legacy auth");
+                password_sha256
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "no auth nonce from server, refusing to send the replayable password hash \
+                     (hmac-or-legacy allows legacy servers)",
+                ));
+            }
+        },
+    };
+    send_authentication(conn, proof, padding).await
+}
+
+/// 读取服务端的 nonce；超时前一个字节都没有收到时返回 `None`
+async fn read_nonce<R>(conn: &mut R) -> io::Result<Option<[u8; AUTH_NONCE_LEN]>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut nonce = [0u8; AUTH_NONCE_LEN];
+    let mut filled = 0;
+    let read = async {
+        while filled < AUTH_NONCE_LEN {
+            let n = conn.read(&mut nonce[filled..]).await?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed while reading auth nonce",
+                ));
+            }
+            filled += n;
+        }
+        Ok(())
+    };
+    let result = tokio::time::timeout(AUTH_NONCE_TIMEOUT, read).await;
+    match result {
+        Ok(read) => read.map(|()| Some(nonce)),
+        Err(_) if filled == 0 => Ok(None),
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "incomplete auth nonce")),
+    }
+}
+
+/// 发送认证头：`sha256(password) + padding_len + padding0`，hmac 模式下哈希换成认证响应
+pub async fn send_authentication<W: AsyncWrite + Unpin>(
+    conn: &mut W,
     password_sha256: [u8; 32],
     padding: Arc<PaddingFactory>,
 ) -> io::Result<()> {
@@ -75,8 +288,8 @@ async fn send_authentication(
     auth_request.put_u16(padding_length);
     auth_request.extend_from_slice(&padding_data);
 
-    tls_stream.write_all(&auth_request).await?;
-    tls_stream.flush().await?;
+    conn.write_all(&auth_request).await?;
+    conn.flush().await?;
     log::debug!("[Client] Authentication request sent (padding: {} bytes)", padding_length);
     Ok(())
 }
@@ -59,6 +59,12 @@ where
             })?;
             stream.write_all(&ip.octets()).await?;
         }
+        AddressType::Unix => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix socket targets are not supported over UDP",
+            ));
+        }
     }
     stream.write_u16(addr.port).await
 }
@@ -135,6 +141,11 @@ where
             stream.write_u8(0x02).await?;
             stream.write_u8(addr.host.len() as u8).await?;
             stream.write_all(addr.host.as_bytes()).await?;
+        }        AddressType::Unix => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix socket targets are not supported over UDP",
+            ));
         }
     }
     stream.write_u16(addr.port).await
@@ -0,0 +1,103 @@
+//! accept 出错时的退避策略。
+//!
+//! 单个连接的错误（对端重置、握手中断）直接重试；文件描述符、内存等资源耗尽时按指数退避
+//! 等待后重试，避免空转；只有监听 socket 本身失效时才返回错误。
+
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+const MIN_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+#[cfg(unix)]
+const EBADF: i32 = 9;
+#[cfg(unix)]
+const ENFILE: i32 = 23;
+#[cfg(unix)]
+const EMFILE: i32 = 24;
+
+/// accept 错误的分类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptErrorKind {
+    /// 只影响这一个连接，立即重试
+    Connection,
+    /// 资源耗尽或未知的暂时错误，退避后重试
+    Transient,
+    /// 监听 socket 已不可用
+    Fatal,
+}
+
+pub fn classify_accept_error(err: &io::Error) -> AcceptErrorKind {
+    #[cfg(unix)]
+    match err.raw_os_error() {
+        Some(EMFILE | ENFILE) => return AcceptErrorKind::Transient,
+        Some(EBADF) => return AcceptErrorKind::Fatal,
+        _ => {}
+    }
+    match err.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut => AcceptErrorKind::Connection,
+        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported => AcceptErrorKind::Fatal,
+        _ => AcceptErrorKind::Transient,
+    }
+}
+
+/// 连续暂时错误时的退避时长，从 5ms 翻倍到 1s，成功 accept 后复位
+#[derive(Debug, Default)]
+pub struct AcceptBackoff {
+    next: Option<Duration>,
+}
+
+impl AcceptBackoff {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 返回重试前应等待的时长；错误不可恢复时返回 `None`
+    pub fn on_error(&mut self, err: &io::Error) -> Option<Duration> {
+        match classify_accept_error(err) {
+            AcceptErrorKind::Connection => Some(Duration::ZERO),
+            AcceptErrorKind::Fatal => None,
+            AcceptErrorKind::Transient => {
+                let delay = self.next.unwrap_or(MIN_BACKOFF);
+                self.next = Some((delay * 2).min(MAX_BACKOFF));
+                Some(delay)
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.next = None;
+    }
+
+    /// 反复调用 `accept` 直到成功；可恢复的错误记录日志并按需等待，不可恢复的错误返回给调用方
+    pub async fn accept<T, F, Fut>(&mut self, mut accept: F) -> io::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<T>>,
+    {
+        loop {
+            match accept().await {
+                Ok(conn) => {
+                    self.reset();
+                    return Ok(conn);
+                }
+                Err(e) => match self.on_error(&e) {
+                    None => return Err(e),
+                    Some(delay) if delay.is_zero() => {
+                        log::debug!("accept error: {}", e);
+                    }
+                    Some(delay) => {
+                        log::warn!("accept error: {}, retrying in {:?}", e, delay);
+                        tokio::time::sleep(delay).await;
+                    }
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! 转发用的定长缓冲区池，所有连接共享，避免每条连接各自分配。
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// 缓冲区池统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    /// 从池中取到现成缓冲区的次数
+    pub hits: u64,
+    /// 池为空、新分配缓冲区的次数
+    pub misses: u64,
+    /// 当前池中空闲的缓冲区数
+    pub idle: usize,
+}
+
+/// 最多保留 `capacity` 个 `buffer_size` 字节的缓冲区，按需分配
+pub struct BufferPool {
+    buffers: Mutex<Vec<Box<[u8]>>>,
+    capacity: usize,
+    buffer_size: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize, buffer_size: usize) -> Arc<Self> {
+        Arc::new(Self {
+            buffers: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+            buffer_size: buffer_size.max(1),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// 取出一个缓冲区，Drop 时自动归还
+    pub fn get(self: &Arc<Self>) -> PooledBuffer {
+        let reused = self.lock().pop();
+        let buf = match reused {
+            Some(buf) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                buf
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                vec![0u8; self.buffer_size].into_boxed_slice()
+            }
+        };
+        PooledBuffer {
+            buf: Some(buf),
+            pool: Arc::clone(self),
+        }
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            idle: self.lock().len(),
+        }
+    }
+
+    fn put(&self, buf: Box<[u8]>) {
+        let mut buffers = self.lock();
+        if buffers.len() < self.capacity {
+            buffers.push(buf);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Box<[u8]>>> {
+        self.buffers.lock().expect("buffer pool lock poisoned")
+    }
+}
+
+/// 从 [`BufferPool`] 借出的缓冲区
+pub struct PooledBuffer {
+    buf: Option<Box<[u8]>>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buf.as_deref().expect("pooled buffer already returned")
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buf.as_deref_mut().expect("pooled buffer already returned")
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.pool.put(buf);
+        }
+    }
+}
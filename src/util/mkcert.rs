@@ -1,8 +1,20 @@
+use crate::util::tls::{check_fragment_size, CipherPreference, KeyLogToFile, TlsServerOptions};
+use arc_swap::ArcSwap;
 use rcgen::generate_simple_self_signed;
+use rustls::server::WebPkiClientVerifier;
 use rustls::ServerConfig;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::{LazyConfigAcceptor, TlsAcceptor};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 pub fn generate_key_pair(
     server_name: &str,
+    options: &TlsServerOptions,
 ) -> Result<ServerConfig, Box<dyn std::error::Error + Send + Sync>> {
     let cert_key = generate_simple_self_signed(vec![server_name.to_string()])?;
     let cert_chain = vec![rustls::pki_types::CertificateDer::from(
@@ -10,9 +22,91 @@ pub fn generate_key_pair(
     )];
     let key = rustls::pki_types::PrivateKeyDer::Pkcs8(cert_key.signing_key.serialize_der().into());
 
-    let config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(cert_chain, key)?;
+    let provider = options.cipher.crypto_provider();
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+    let builder = match options.load_client_roots()? {
+        // 双向 TLS：没有有效客户端证书的连接在握手阶段即被拒绝
+        Some(roots) => builder.with_client_cert_verifier(
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?,
+        ),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(cert_chain, key)?;
+    // 指定偏好时按服务端顺序选择套件
+    config.ignore_client_order = options.cipher != CipherPreference::Auto;
+    if let Some(path) = &options.keylog_file {
+        config.key_log = KeyLogToFile::open(path)?;
+    }
+    config.max_fragment_size = check_fragment_size(options.fragment_size)?;
 
     Ok(config)
 }
+
+/// 可定期重新生成自签名证书的服务端配置。
+/// 新的握手使用最新的证书，已建立的连接及其 Session 不受影响；
+/// 固定证书指纹的客户端会在轮换后握手失败，需改用 CA 校验或自行处理轮换
+pub struct RotatingServerConfig {
+    server_name: String,
+    options: TlsServerOptions,
+    current: ArcSwap<ServerConfig>,
+}
+
+impl RotatingServerConfig {
+    pub fn new(server_name: &str, options: TlsServerOptions) -> Result<Arc<Self>, BoxError> {
+        let config = generate_key_pair(server_name, &options)?;
+        Ok(Arc::new(Self {
+            server_name: server_name.to_string(),
+            options,
+            current: ArcSwap::from_pointee(config),
+        }))
+    }
+
+    /// 使用当前证书的 acceptor，每次握手前获取
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.load_full())
+    }
+
+    /// 完成服务端握手。设置了 `require_sni` 时先读取 ClientHello，SNI 不符就返回
+    /// `PermissionDenied`，不发送任何 TLS 记录，调用方丢弃连接即可
+    pub async fn accept<IO>(&self, stream: IO) -> io::Result<TlsStream<IO>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        if self.options.require_sni.is_none() {
+            return self.acceptor().accept(stream).await;
+        }
+        let start = LazyConfigAcceptor::new(rustls::server::Acceptor::default(), stream).await?;
+        let sni = start.client_hello().server_name().map(str::to_string);
+        if !self.options.sni_allowed(sni.as_deref()) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("unexpected TLS server name {:?}", sni),
+            ));
+        }
+        start.into_stream(self.current.load_full()).await
+    }
+
+    /// 立即生成新证书并替换当前配置
+    pub fn rotate(&self) -> Result<(), BoxError> {
+        let config = generate_key_pair(&self.server_name, &self.options)?;
+        self.current.store(Arc::new(config));
+        Ok(())
+    }
+
+    /// 每隔 `interval` 轮换一次证书；生成失败时保留旧证书，下个周期重试
+    pub fn spawn_rotation(self: &Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let rotating = Arc::clone(self);
+        tokio::spawn(async move {
+            let first = tokio::time::Instant::now() + interval;
+            let mut ticker = tokio::time::interval_at(first, interval);
+            loop {
+                ticker.tick().await;
+                match rotating.rotate() {
+                    Ok(()) => log::info!("[Server] Rotated self-signed TLS certificate"),
+                    Err(e) => log::error!("[Server] Certificate rotation failed: {}", e),
+                }
+            }
+        })
+    }
+}
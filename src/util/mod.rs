@@ -1,3 +1,8 @@
+pub mod accept;
+pub mod buffer_pool;
 pub mod mkcert;
+pub mod redact;
+pub mod runtime;
 pub mod string_map;
+pub mod tls;
 pub mod r#type;
@@ -0,0 +1,39 @@
+//! 日志中隐藏敏感数据。
+//!
+//! 密码哈希等秘密包在 [`Redacted`] 中传递，`Debug`/`Display` 只输出 `***`，
+//! 即使被错误信息或调试日志格式化也不会泄露；需要原值时显式调用 [`Redacted::expose`]。
+
+use std::fmt;
+
+/// 格式化时输出 `***` 的包装类型
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Redacted<T>(T);
+
+impl<T> Redacted<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// 取得原值，仅用于比较或写入连接
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Redacted<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+impl<T> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
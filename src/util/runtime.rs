@@ -0,0 +1,103 @@
+//! 两个可执行文件共用的 tokio 运行时构建。
+//!
+//! 默认与 `#[tokio::main]` 相同：多线程运行时，工作线程数等于 CPU 核数。代理的开销主要在
+//! TLS 加解密与拷贝，线程数超过可用核数只会增加切换；用 taskset/cgroup 把进程限制在部分
+//! 核上时，应同时把工作线程数设为可用核数。单线程运行时没有跨线程唤醒与任务迁移，
+//! 连接数少时延迟更稳定，也便于排查调度相关的问题，但只能用满一个核。
+//!
+//! 每个连接的处理任务用 [`spawn_guarded`] 启动，任务 panic 时记录连接信息并计数。
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::Poll;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinHandle;
+
+/// 工作线程数上限，超过这个值基本是参数写错
+pub const MAX_WORKER_THREADS: usize = 1024;
+
+/// 运行时的线程配置
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RuntimeOptions {
+    /// 多线程运行时的工作线程数，`None` 时使用 CPU 核数
+    pub worker_threads: Option<usize>,
+    /// 使用单线程运行时，所有任务在主线程上执行
+    pub current_thread: bool,
+}
+
+impl RuntimeOptions {
+    pub fn validate(&self) -> io::Result<()> {
+        match self.worker_threads {
+            Some(_) if self.current_thread => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--worker-threads cannot be combined with --current-thread",
+            )),
+            Some(n) if n == 0 || n > MAX_WORKER_THREADS => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--worker-threads must be between 1 and {}", MAX_WORKER_THREADS),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// 实际使用的工作线程数
+    pub fn effective_worker_threads(&self) -> usize {
+        if self.current_thread {
+            return 1;
+        }
+        self.worker_threads.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        })
+    }
+
+    /// 校验参数并构建运行时
+    pub fn build(&self) -> io::Result<Runtime> {
+        self.validate()?;
+        let mut builder = if self.current_thread {
+            Builder::new_current_thread()
+        } else {
+            let mut builder = Builder::new_multi_thread();
+            builder.worker_threads(self.effective_worker_threads());
+            builder
+        };
+        builder.enable_all().build()
+    }
+}
+
+static PANICKED_TASKS: AtomicU64 = AtomicU64::new(0);
+
+/// 进程启动以来 [`spawn_guarded`] 捕获到的 panic 次数
+pub fn panicked_tasks() -> u64 {
+    PANICKED_TASKS.load(Ordering::Relaxed)
+}
+
+/// 启动任务并捕获其中的 panic：以 error 级别记录 `context` 与 panic 信息并计数，
+/// 任务视为正常结束。panic hook 仍会照常输出。
+/// 只在 `panic = "unwind"` 下有效，`abort` 时第一次 panic 就会终止整个进程
+pub fn spawn_guarded<F>(context: impl fmt::Display + Send + 'static, future: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut future = Box::pin(future);
+    tokio::spawn(async move {
+        let polled = std::future::poll_fn(|cx| {
+            match std::panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+                Ok(Poll::Ready(())) => Poll::Ready(Ok(())),
+                Ok(Poll::Pending) => Poll::Pending,
+                Err(payload) => Poll::Ready(Err(payload)),
+            }
+        });
+        if let Err(payload) = polled.await {
+            let count = PANICKED_TASKS.fetch_add(1, Ordering::Relaxed) + 1;
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("non-string panic payload");
+            log::error!("{} task panicked ({} so far): {}", context, count, message);
+        }
+    })
+}
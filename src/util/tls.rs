@@ -0,0 +1,263 @@
+//! TLS 密码套件偏好。
+//!
+//! - `aes`：AES-GCM 优先，适合有 AES-NI / ARMv8 Crypto 扩展的机器（大多数服务器）。
+//! - `chacha`：ChaCha20-Poly1305 优先，适合没有 AES 硬件加速的设备（部分路由器、旧手机）。
+//! - `auto`：保持 rustls 默认顺序。
+//!
+//! 客户端的套件顺序会出现在 ClientHello 中，是 TLS 指纹的一部分；偏离默认顺序会让指纹
+//! 更容易与普通客户端区分，除非确有性能问题，客户端建议保持 `auto`。
+//! 服务端指定偏好时会忽略客户端顺序，按自己的顺序选择。
+//!
+//! 另外提供双向 TLS 所需的证书加载：客户端 `--client-cert`/`--client-key`，服务端 `--client-ca`。
+//!
+//! `--tls-fragment-size` 限制单个 TLS 记录的大小（含 5 字节记录头，32..=16389）。填充方案
+//! 决定每次写入的应用层字节数，TLS 再按该上限把一次写入切成多个记录；上限小于方案中的包长时，
+//! 一个填充后的包会被拆成几个记录，线上看到的是拆分后的长度分布而不是方案本身。
+//! 同时使用时应让上限不小于方案中最大的包长，或只用其中一种手段塑形。
+//!
+//! 客户端默认不校验服务端证书（服务端通常使用自签名证书）。服务端使用公共 CA 签发的证书时，
+//! `--trust-system-roots` 从操作系统证书库加载根证书并按常规方式校验证书链与服务器名。
+//!
+//! `--keylog-file` 以 NSS Key Log 格式写出会话密钥，供 Wireshark 解密隧道流量排查问题；
+//! 任何拿到该文件的人都能解密全部流量，只应在调试时临时开启。
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{CipherSuite, KeyLog, RootCertStore, SupportedCipherSuite};
+use std::fmt;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write as _;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// 客户端 TLS 选项
+#[derive(Debug, Clone, Default)]
+pub struct TlsClientOptions {
+    pub cipher: CipherPreference,
+    /// 双向 TLS 的客户端证书链与私钥（PEM 文件路径），需同时设置
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// 设置后把 TLS 会话密钥追加写入该文件
+    pub keylog_file: Option<String>,
+    /// 单个 TLS 记录的最大字节数，见 [`check_fragment_size`]
+    pub fragment_size: Option<usize>,
+    /// 用系统证书库校验服务端证书，否则接受任意证书
+    pub trust_system_roots: bool,
+}
+
+impl TlsClientOptions {
+    /// 读取客户端证书；未配置时返回 `None`，只配置了其中一个时报错
+    pub fn load_client_auth(
+        &self,
+    ) -> io::Result<Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>> {
+        match (&self.client_cert, &self.client_key) {
+            (None, None) => Ok(None),
+            (Some(cert), Some(key)) => Ok(Some((load_certs(cert)?, load_private_key(key)?))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "client certificate and key must be set together",
+            )),
+        }
+    }
+}
+
+/// 服务端 TLS 选项
+#[derive(Debug, Clone, Default)]
+pub struct TlsServerOptions {
+    pub cipher: CipherPreference,
+    /// 设置后要求客户端出示由该 CA（PEM 文件）签发的证书
+    pub client_ca: Option<String>,
+    /// 设置后把 TLS 会话密钥追加写入该文件
+    pub keylog_file: Option<String>,
+    /// 单个 TLS 记录的最大字节数，见 [`check_fragment_size`]
+    pub fragment_size: Option<usize>,
+    /// 设置后只接受 SNI 与之相同（不区分大小写）的 ClientHello，其他连接不回应直接断开
+    pub require_sni: Option<String>,
+}
+
+impl TlsServerOptions {
+    /// ClientHello 中的 SNI 是否满足 `require_sni`
+    pub fn sni_allowed(&self, sni: Option<&str>) -> bool {
+        match &self.require_sni {
+            Some(expected) => sni.is_some_and(|sni| sni.eq_ignore_ascii_case(expected)),
+            None => true,
+        }
+    }
+
+    pub fn load_client_roots(&self) -> io::Result<Option<RootCertStore>> {
+        let Some(path) = &self.client_ca else {
+            return Ok(None);
+        };
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(path)? {
+            roots.add(cert).map_err(|e| invalid_pem(path, e))?;
+        }
+        Ok(Some(roots))
+    }
+}
+
+/// 从操作系统证书库加载根证书，无法解析的证书被跳过；一个可用的证书都没有时报错
+pub fn load_system_roots() -> io::Result<RootCertStore> {
+    let loaded = rustls_native_certs::load_native_certs();
+    for err in &loaded.errors {
+        log::warn!("[TLS] Failed to load system certificates: {}", err);
+    }
+    let mut roots = RootCertStore::empty();
+    let (added, ignored) = roots.add_parsable_certificates(loaded.certs);
+    if ignored > 0 {
+        log::warn!("[TLS] Ignored {} unparsable system certificates", ignored);
+    }
+    if added == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no usable certificates found in the system certificate store",
+        ));
+    }
+    log::debug!("[TLS] Loaded {} system root certificates", added);
+    Ok(roots)
+}
+
+/// rustls 接受的 TLS 记录上限（含记录头）
+pub const MIN_FRAGMENT_SIZE: usize = 32;
+pub const MAX_FRAGMENT_SIZE: usize = 16389;
+
+/// 检查 TLS 记录上限是否在 rustls 接受的范围内，超出时返回 `InvalidInput`
+pub fn check_fragment_size(size: Option<usize>) -> io::Result<Option<usize>> {
+    match size {
+        Some(n) if !(MIN_FRAGMENT_SIZE..=MAX_FRAGMENT_SIZE).contains(&n) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "TLS fragment size {} out of range {}..={}",
+                n, MIN_FRAGMENT_SIZE, MAX_FRAGMENT_SIZE
+            ),
+        )),
+        _ => Ok(size),
+    }
+}
+
+/// 把会话密钥写入指定文件的 [`KeyLog`]；`rustls::KeyLogFile` 只读取 `SSLKEYLOGFILE` 环境变量
+#[derive(Debug)]
+pub struct KeyLogToFile {
+    file: Mutex<File>,
+}
+
+impl KeyLogToFile {
+    /// 以追加方式打开文件，文件不存在时创建
+    pub fn open(path: &str) -> io::Result<Arc<Self>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+        Ok(Arc::new(Self {
+            file: Mutex::new(file),
+        }))
+    }
+}
+
+impl KeyLog for KeyLogToFile {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let mut line = String::with_capacity(256);
+        line.push_str(label);
+        line.push(' ');
+        push_hex(&mut line, client_random);
+        line.push(' ');
+        push_hex(&mut line, secret);
+        line.push('\n');
+        let mut file = self.file.lock().expect("keylog file lock poisoned");
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            log::warn!("failed to write TLS key log: {}", e);
+        }
+    }
+
+    fn will_log(&self, _label: &str) -> bool {
+        true
+    }
+}
+
+fn push_hex(out: &mut String, bytes: &[u8]) {
+    for b in bytes {
+        let _ = write!(out, "{:02x}", b);
+    }
+}
+
+/// 读取 PEM 文件中的全部证书
+pub fn load_certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid_pem(path, e))?;
+    if certs.is_empty() {
+        return Err(invalid_pem(path, "no certificate found"));
+    }
+    Ok(certs)
+}
+
+/// 读取 PEM 文件中的第一个私钥（PKCS#8、PKCS#1 或 SEC1）
+pub fn load_private_key(path: &str) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| invalid_pem(path, e))
+}
+
+fn invalid_pem(path: &str, err: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, err))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CipherPreference {
+    #[default]
+    Auto,
+    Aes,
+    Chacha,
+}
+
+impl CipherPreference {
+    /// 按偏好排序密码套件的 CryptoProvider；TLS 1.3 套件始终排在 TLS 1.2 之前
+    pub fn crypto_provider(self) -> Arc<CryptoProvider> {
+        let mut provider = ring::default_provider();
+        if self != Self::Auto {
+            let prefer_chacha = self == Self::Chacha;
+            provider.cipher_suites.sort_by_key(|suite| {
+                let is_tls12 = matches!(suite, SupportedCipherSuite::Tls12(_));
+                (is_tls12, is_chacha(suite) != prefer_chacha)
+            });
+        }
+        Arc::new(provider)
+    }
+}
+
+fn is_chacha(suite: &SupportedCipherSuite) -> bool {
+    matches!(
+        suite.suite(),
+        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256
+            | CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256
+    )
+}
+
+impl FromStr for CipherPreference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "aes" => Ok(Self::Aes),
+            "chacha" => Ok(Self::Chacha),
+            other => Err(format!(
+                "unknown cipher preference '{}', expected aes|chacha|auto",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CipherPreference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Aes => write!(f, "aes"),
+            Self::Chacha => write!(f, "chacha"),
+        }
+    }
+}
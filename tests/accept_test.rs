@@ -0,0 +1,68 @@
+use anytls_rs::util::accept::{classify_accept_error, AcceptBackoff, AcceptErrorKind};
+use std::io;
+use std::time::{Duration, Instant};
+
+fn emfile() -> io::Error {
+    io::Error::from_raw_os_error(24)
+}
+
+#[test]
+fn accept_errors_are_classified() {
+    let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+    assert_eq!(classify_accept_error(&reset), AcceptErrorKind::Connection);
+    assert_eq!(classify_accept_error(&emfile()), AcceptErrorKind::Transient);
+    let invalid = io::Error::from(io::ErrorKind::InvalidInput);
+    assert_eq!(classify_accept_error(&invalid), AcceptErrorKind::Fatal);
+}
+
+#[test]
+fn backoff_grows_caps_and_resets() {
+    let mut backoff = AcceptBackoff::new();
+    let delays: Vec<Duration> = (0..10).map(|_| backoff.on_error(&emfile()).unwrap()).collect();
+    assert_eq!(delays[0], Duration::from_millis(5));
+    assert_eq!(delays[1], Duration::from_millis(10));
+    assert!(delays.windows(2).all(|w| w[0] <= w[1]));
+    assert_eq!(*delays.last().unwrap(), Duration::from_secs(1));
+
+    let reset = io::Error::from(io::ErrorKind::ConnectionAborted);
+    assert_eq!(backoff.on_error(&reset), Some(Duration::ZERO));
+    backoff.reset();
+    assert_eq!(backoff.on_error(&emfile()), Some(Duration::from_millis(5)));
+}
+
+#[tokio::test]
+async fn accept_retries_repeated_errors_until_success() {
+    let mut backoff = AcceptBackoff::new();
+    let mut calls = 0;
+    let started = Instant::now();
+    let conn = backoff
+        .accept(|| {
+            calls += 1;
+            let n = calls;
+            async move {
+                match n {
+                    1 | 2 => Err(io::Error::from(io::ErrorKind::ConnectionReset)),
+                    3..=5 => Err(emfile()),
+                    _ => Ok(n),
+                }
+            }
+        })
+        .await
+        .unwrap();
+    assert_eq!(conn, 6);
+    // 三次资源耗尽：5 + 10 + 20ms
+    assert!(started.elapsed() >= Duration::from_millis(35));
+
+    // 成功后退避复位
+    assert_eq!(backoff.on_error(&emfile()), Some(Duration::from_millis(5)));
+}
+
+#[tokio::test]
+async fn accept_returns_fatal_errors() {
+    let mut backoff = AcceptBackoff::new();
+    let err = backoff
+        .accept(|| async { Err::<(), _>(io::Error::from(io::ErrorKind::InvalidInput)) })
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
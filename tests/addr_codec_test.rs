@@ -1,4 +1,10 @@
-use anytls_rs::proxy::addr_codec::{build_socks_addr, AddressType, SocksAddr};
+use anytls_rs::proxy::addr_codec::{
+    build_socks_addr, build_target_header, build_typed_target_header, normalize_addr,
+    read_target_header, AddressType, SocksAddr, StreamType, TargetHeader, ATYP_CONNECT_TIMEOUT,
+    ATYP_STREAM_TYPE, ATYP_UNIX,
+};
+use std::net::SocketAddr;
+use std::time::Duration;
 
 #[test]
 fn build_socks_addr_domain() {
@@ -26,3 +32,144 @@ fn build_socks_addr_ipv4() {
 
     assert_eq!(out, vec![0x01, 1, 2, 3, 4, 0x1f, 0x90]);
 }
+
+#[test]
+fn from_socks_bytes_reports_consumed_length() {
+    let mut wire = build_socks_addr(&SocksAddr {
+        atyp: AddressType::Domain,
+        host: "example.com".to_string(),
+        port: 80,
+    })
+    .unwrap();
+    let len = wire.len();
+    wire.extend_from_slice(b"trailing payload");
+
+    let (addr, consumed) = SocksAddr::from_socks_bytes(&wire).unwrap();
+    assert_eq!(consumed, len);
+    assert_eq!(addr.to_host_port(), "example.com:80");
+}
+
+#[test]
+fn from_socks_bytes_rejects_every_truncation() {
+    let wires: [(&[u8], &str); 4] = [
+        (&[0x01, 10, 0, 0, 1, 0x1f, 0x90], "10.0.0.1:8080"),
+        (&[0x03, 3, b'a', b'.', b'b', 0x1f, 0x90], "a.b:8080"),
+        (&[ATYP_UNIX, 4, b'/', b'r', b'u', b'n', 0, 0], "unix:/run"),
+        (&[0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x1f, 0x90], "::1:8080"),
+    ];
+    for (wire, expected) in wires {
+        let (addr, consumed) = SocksAddr::from_socks_bytes(wire).unwrap();
+        assert_eq!((addr.to_host_port().as_str(), consumed), (expected, wire.len()));
+        for end in 0..wire.len() {
+            let err = SocksAddr::from_socks_bytes(&wire[..end]).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof, "{:?}", &wire[..end]);
+        }
+    }
+
+    // 域名长度超出剩余数据，包括最大长度 255
+    for len in [4, 255] {
+        let wire = [0x03, len, b'a', b'.', b'b', 0x1f, 0x90];
+        let err = SocksAddr::from_socks_bytes(&wire).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
+
+#[test]
+fn unix_target_round_trips_with_extension_atyp() {
+    let addr = SocksAddr::parse_target("unix:/run/echo.sock").unwrap();
+    assert_eq!(addr.atyp, AddressType::Unix);
+    assert_eq!(addr.host, "/run/echo.sock");
+    let wire = build_socks_addr(&addr).unwrap();
+    assert_eq!(wire[0], ATYP_UNIX);
+    assert_eq!(&wire[wire.len() - 2..], &[0, 0]);
+
+    let (decoded, consumed) = SocksAddr::from_socks_bytes(&wire).unwrap();
+    assert_eq!((decoded.clone(), consumed), (addr, wire.len()));
+    assert_eq!(decoded.to_host_port(), "unix:/run/echo.sock");
+}
+
+#[test]
+fn parse_target_accepts_host_port_forms() {
+    let parsed = |target| SocksAddr::parse_target(target).unwrap();
+    assert_eq!(parsed("1.2.3.4:80").atyp, AddressType::Ipv4);
+    assert_eq!(parsed("[::1]:443").to_host_port(), "::1:443");
+    assert_eq!(parsed("example.com:8080").atyp, AddressType::Domain);
+    assert!(SocksAddr::parse_target("example.com").is_err());
+    assert!(SocksAddr::parse_target("unix:").is_err());
+}
+
+#[test]
+fn normalize_addr_unwraps_only_ipv4_mapped_addresses() {
+    let normalized = |addr: &str| normalize_addr(addr.parse::<SocketAddr>().unwrap()).to_string();
+    assert_eq!(normalized("[::ffff:1.2.3.4]:80"), "1.2.3.4:80");
+    assert_eq!(normalized("1.2.3.4:80"), "1.2.3.4:80");
+    assert_eq!(normalized("[2001:db8::1]:443"), "[2001:db8::1]:443");
+    // IPv4 兼容地址（已废弃）与回环地址保持 IPv6
+    assert_eq!(normalized("[::1.2.3.4]:80"), "[::102:304]:80");
+    assert_eq!(normalized("[::1]:80"), "[::1]:80");
+}
+
+#[tokio::test]
+async fn ipv4_mapped_targets_decode_as_ipv4() {
+    let parsed = SocksAddr::parse_target("[::ffff:10.0.0.1]:53").unwrap();
+    assert_eq!((parsed.atyp, parsed.to_host_port()), (AddressType::Ipv4, "10.0.0.1:53".into()));
+
+    let mut wire = vec![0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 10, 0, 0, 1, 0, 53];
+    let (decoded, _) = SocksAddr::from_socks_bytes(&wire).unwrap();
+    assert_eq!(decoded, parsed);
+    let read = read_target_header(&mut &wire[..]).await.unwrap();
+    assert_eq!(read.addr, parsed);
+
+    // 重新编码后按 IPv4 发送
+    wire = build_socks_addr(&decoded).unwrap();
+    assert_eq!(wire, vec![0x01, 10, 0, 0, 1, 0, 53]);
+}
+
+#[tokio::test]
+async fn target_header_carries_optional_connect_timeout() {
+    let addr = SocksAddr::parse_target("example.com:443").unwrap();
+    let plain = build_target_header(&addr, None).unwrap();
+    assert_eq!(plain, build_socks_addr(&addr).unwrap());
+    let header = read_target_header(&mut &plain[..]).await.unwrap();
+    assert_eq!((header.addr, header.connect_timeout), (addr.clone(), None));
+
+    let wire = build_target_header(&addr, Some(Duration::from_millis(1500))).unwrap();
+    assert_eq!(&wire[..3], &[ATYP_CONNECT_TIMEOUT, 0x05, 0xdc]);
+    let header = read_target_header(&mut &wire[..]).await.unwrap();
+    assert_eq!((header.addr, header.connect_timeout), (addr, Some(Duration::from_millis(1500))));
+}
+
+#[tokio::test]
+async fn target_header_carries_optional_stream_type() {
+    let addr = SocksAddr::parse_target("1.1.1.1:53").unwrap();
+    // 不带前缀时为 TCP，TCP 也不会加前缀
+    let legacy = build_socks_addr(&addr).unwrap();
+    assert_eq!(build_typed_target_header(StreamType::Tcp, &addr, None).unwrap(), legacy);
+    let header = read_target_header(&mut &legacy[..]).await.unwrap();
+    assert_eq!(header.stream_type, StreamType::Tcp);
+
+    let timeout = Some(Duration::from_millis(1500));
+    let wire = build_typed_target_header(StreamType::Udp, &addr, timeout).unwrap();
+    assert_eq!(&wire[..5], &[ATYP_STREAM_TYPE, 0x02, ATYP_CONNECT_TIMEOUT, 0x05, 0xdc]);
+    let header = read_target_header(&mut &wire[..]).await.unwrap();
+    let expected = TargetHeader { stream_type: StreamType::Udp, addr, connect_timeout: timeout };
+    assert_eq!(header, expected);
+
+    // 显式的 TCP 前缀同样可以解析
+    let explicit = [&[ATYP_STREAM_TYPE, 0x01][..], &legacy].concat();
+    let header = read_target_header(&mut &explicit[..]).await.unwrap();
+    assert_eq!(header.stream_type, StreamType::Tcp);
+
+    let unknown = [&[ATYP_STREAM_TYPE, 0x03][..], &legacy].concat();
+    let err = read_target_header(&mut &unknown[..]).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn target_header_clamps_connect_timeout_to_wire_range() {
+    let addr = SocksAddr::parse_target("127.0.0.1:80").unwrap();
+    let zero = build_target_header(&addr, Some(Duration::ZERO)).unwrap();
+    assert_eq!(&zero[..3], &[ATYP_CONNECT_TIMEOUT, 0, 1]);
+    let long = build_target_header(&addr, Some(Duration::from_secs(3600))).unwrap();
+    assert_eq!(&long[..3], &[ATYP_CONNECT_TIMEOUT, 0xff, 0xff]);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::session::{Session, SessionConfig};
+use anytls_rs::proxy::transport::{self, AuthMode, AUTH_NONCE_LEN, AUTH_NONCE_TIMEOUT};
+use common::{ServerProcess, PASSWORD};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+/// 以 `mode` 认证并建立 Session，心跳往返成功说明服务端接受了认证
+async fn session_heartbeat(server: &ServerProcess, mode: AuthMode) -> io::Result<Duration> {
+    let padding = Arc::new(PaddingFactory::default());
+    let password = transport::password_sha256(PASSWORD);
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        password,
+        Arc::clone(&padding),
+        mode,
+    );
+    let conn = dial().await?;
+    let session = Arc::new(Session::new_client(conn, padding, SessionConfig::default()));
+    session.run().await?;
+    session.heartbeat_probe(Duration::from_secs(2)).await
+}
+
+/// 发送认证头后等待服务端关闭连接；服务端未关闭时返回 `false`
+async fn server_closes_after(conn: &mut TcpStream, proof: [u8; 32]) -> bool {
+    let padding = Arc::new(PaddingFactory::default());
+    transport::send_authentication(conn, proof, padding).await.unwrap();
+    let mut buf = [0u8; 64];
+    let read = tokio::time::timeout(Duration::from_secs(2), conn.read(&mut buf)).await;
+    matches!(read, Ok(Ok(0)) | Ok(Err(_)))
+}
+
+async fn read_nonce(conn: &mut TcpStream) -> [u8; AUTH_NONCE_LEN] {
+    let mut nonce = [0u8; AUTH_NONCE_LEN];
+    conn.read_exact(&mut nonce).await.unwrap();
+    nonce
+}
+
+#[test]
+fn auth_mode_parses_and_displays() {
+    assert_eq!("legacy".parse::<AuthMode>(), Ok(AuthMode::Legacy));
+    assert_eq!("HMAC".parse::<AuthMode>(), Ok(AuthMode::Hmac));
+    assert_eq!("hmac-or-legacy".parse::<AuthMode>(), Ok(AuthMode::HmacOrLegacy));
+    assert_eq!(AuthMode::HmacOrLegacy.to_string(), "hmac-or-legacy");
+    assert!("plain".parse::<AuthMode>().is_err());
+    assert_eq!(AuthMode::Hmac.to_string(), "hmac");
+    assert_eq!(AuthMode::default(), AuthMode::Legacy);
+}
+
+#[test]
+fn auth_response_depends_on_nonce_and_password() {
+    let password = transport::password_sha256(PASSWORD);
+    let response = transport::auth_response(&password, &[1; AUTH_NONCE_LEN]);
+    assert_eq!(response, transport::auth_response(&password, &[1; AUTH_NONCE_LEN]));
+    assert_ne!(response, transport::auth_response(&password, &[2; AUTH_NONCE_LEN]));
+    let other = transport::password_sha256("other");
+    assert_ne!(response, transport::auth_response(&other, &[1; AUTH_NONCE_LEN]));
+    assert_ne!(response, password);
+}
+
+#[tokio::test]
+async fn legacy_mode_authenticates() {
+    let server = ServerProcess::spawn(&["--no-tls"]);
+    session_heartbeat(&server, AuthMode::Legacy).await.unwrap();
+}
+
+#[tokio::test]
+async fn hmac_mode_authenticates() {
+    let server = ServerProcess::spawn(&["--no-tls", "--auth-mode", "hmac"]);
+    session_heartbeat(&server, AuthMode::Hmac).await.unwrap();
+}
+
+#[tokio::test]
+async fn hmac_client_refuses_server_that_withholds_the_nonce() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    // 模拟扣下 nonce 的中间人：不发送任何数据，记录客户端发来的内容
+    let server = tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        conn.read_to_end(&mut received).await.unwrap();
+        received
+    });
+
+    let dial = transport::create_plain_dial_out_func(
+        addr,
+        transport::password_sha256(PASSWORD),
+        Arc::new(PaddingFactory::default()),
+        AuthMode::Hmac,
+    );
+    let err = dial().await.err().expect("dial without a nonce must fail");
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(server.await.unwrap().is_empty(), "client sent its password hash");
+}
+
+#[tokio::test]
+async fn hmac_or_legacy_client_falls_back_to_legacy_server() {
+    let server = ServerProcess::spawn(&["--no-tls"]);
+    let start = Instant::now();
+    session_heartbeat(&server, AuthMode::HmacOrLegacy).await.unwrap();
+    assert!(start.elapsed() >= AUTH_NONCE_TIMEOUT);
+}
+
+#[test]
+fn server_rejects_hmac_with_fallback_site() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_anytls-server"))
+        .args(["-l", &common::free_addr(), "-p", PASSWORD, "--no-tls"])
+        .args(["--auth-mode", "hmac", "--fallback-site", "127.0.0.1:80"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--fallback-site"));
+}
+
+#[test]
+fn server_rejects_client_only_auth_mode() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_anytls-server"))
+        .args(["-l", &common::free_addr(), "-p", PASSWORD, "--no-tls"])
+        .args(["--auth-mode", "hmac-or-legacy"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[tokio::test]
+async fn hmac_server_rejects_bare_password_hash() {
+    let server = ServerProcess::spawn(&["--no-tls", "--auth-mode", "hmac"]);
+    let mut conn = TcpStream::connect(&server.addr).await.unwrap();
+    read_nonce(&mut conn).await;
+    assert!(server_closes_after(&mut conn, transport::password_sha256(PASSWORD)).await);
+}
+
+#[tokio::test]
+async fn hmac_server_rejects_replayed_response() {
+    let server = ServerProcess::spawn(&["--no-tls", "--auth-mode", "hmac"]);
+    let password = transport::password_sha256(PASSWORD);
+
+    let mut first = TcpStream::connect(&server.addr).await.unwrap();
+    let nonce = read_nonce(&mut first).await;
+    let captured = transport::auth_response(&password, &nonce);
+    assert!(!server_closes_after(&mut first, captured).await);
+
+    let mut replay = TcpStream::connect(&server.addr).await.unwrap();
+    let fresh_nonce = read_nonce(&mut replay).await;
+    assert_ne!(fresh_nonce, nonce);
+    assert!(server_closes_after(&mut replay, captured).await);
+}
@@ -0,0 +1,87 @@
+use anytls_rs::proxy::outbound::breaker::{BreakerConfig, BreakerStatus, CircuitBreaker};
+use std::time::Duration;
+
+const TARGET: &str = "10.0.0.1:443";
+
+fn breaker(window: Duration, cooldown: Duration) -> CircuitBreaker {
+    CircuitBreaker::new(BreakerConfig {
+        failure_threshold: 3,
+        window,
+        cooldown,
+    })
+}
+
+#[test]
+fn breaker_cycles_through_open_half_open_and_closed() {
+    let breaker = breaker(Duration::from_secs(10), Duration::from_millis(100));
+    for _ in 0..2 {
+        assert!(breaker.allow(TARGET));
+        breaker.record_failure(TARGET);
+    }
+    assert_eq!(breaker.status(TARGET), BreakerStatus::Closed);
+    breaker.record_failure(TARGET);
+    assert_eq!(breaker.status(TARGET), BreakerStatus::Open);
+    assert!(!breaker.allow(TARGET));
+    // 其他目标不受影响
+    assert!(breaker.allow("10.0.0.2:443"));
+
+    // 冷却结束后只放行一个探测连接
+    std::thread::sleep(Duration::from_millis(120));
+    assert!(breaker.allow(TARGET));
+    assert_eq!(breaker.status(TARGET), BreakerStatus::HalfOpen);
+    assert!(!breaker.allow(TARGET));
+
+    breaker.record_success(TARGET);
+    assert_eq!(breaker.status(TARGET), BreakerStatus::Closed);
+    assert!(breaker.allow(TARGET));
+}
+
+#[test]
+fn failed_probe_reopens_the_breaker() {
+    let breaker = breaker(Duration::from_secs(10), Duration::from_millis(50));
+    for _ in 0..3 {
+        breaker.record_failure(TARGET);
+    }
+    std::thread::sleep(Duration::from_millis(70));
+    assert!(breaker.allow(TARGET));
+    breaker.record_failure(TARGET);
+    assert_eq!(breaker.status(TARGET), BreakerStatus::Open);
+    assert!(!breaker.allow(TARGET));
+}
+
+#[test]
+fn failures_outside_the_window_and_successes_reset_the_count() {
+    let breaker = breaker(Duration::from_millis(50), Duration::from_secs(10));
+    breaker.record_failure(TARGET);
+    breaker.record_failure(TARGET);
+    std::thread::sleep(Duration::from_millis(70));
+    breaker.record_failure(TARGET);
+    assert_eq!(breaker.status(TARGET), BreakerStatus::Closed);
+
+    breaker.record_failure(TARGET);
+    breaker.record_success(TARGET);
+    breaker.record_failure(TARGET);
+    breaker.record_failure(TARGET);
+    assert_eq!(breaker.status(TARGET), BreakerStatus::Closed);
+    breaker.record_failure(TARGET);
+    assert_eq!(breaker.status(TARGET), BreakerStatus::Open);
+}
+
+#[test]
+fn probe_without_a_result_is_retried_after_the_cooldown() {
+    let breaker = breaker(Duration::from_secs(10), Duration::from_millis(50));
+    for _ in 0..3 {
+        breaker.record_failure(TARGET);
+    }
+    std::thread::sleep(Duration::from_millis(70));
+    // 探测被放行后没有记录结果就被丢弃
+    assert!(breaker.allow(TARGET));
+    assert!(!breaker.allow(TARGET));
+
+    std::thread::sleep(Duration::from_millis(70));
+    assert!(breaker.allow(TARGET));
+    assert_eq!(breaker.status(TARGET), BreakerStatus::HalfOpen);
+    assert!(!breaker.allow(TARGET));
+    breaker.record_success(TARGET);
+    assert_eq!(breaker.status(TARGET), BreakerStatus::Closed);
+}
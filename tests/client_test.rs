@@ -0,0 +1,119 @@
+mod common;
+
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::session::Client;
+use common::{echo_handler, memory_dial_out, wait_for};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn idle_pool_never_exceeds_max_idle_sessions() {
+    let (dial_out, dials) = memory_dial_out(echo_handler());
+    let client = Client::builder(dial_out, Arc::new(PaddingFactory::default()))
+        .min_idle_sessions(0)
+        .max_idle_sessions(1)
+        .build();
+
+    // 每个 Session 最多承载 8 个活跃 Stream，20 个并发 Stream 会用到 3 个 Session
+    let mut streams = Vec::new();
+    for _ in 0..20 {
+        streams.push(client.create_stream().await.unwrap());
+    }
+    assert!(dials.load(Ordering::Acquire) >= 3);
+
+    drop(streams);
+    // 所有 Stream 关闭后至少有一个 Session 会回到空闲池
+    wait_for("a session to be parked", || {
+        client.idle_session_count() == 1
+    })
+    .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(client.idle_session_count(), 1);
+}
+
+fn served_counts(client: &Client) -> Vec<u64> {
+    let mut counts: Vec<u64> = client
+        .session_info()
+        .iter()
+        .map(|info| info.streams_served)
+        .collect();
+    counts.sort_unstable();
+    counts
+}
+
+#[tokio::test]
+async fn freshest_idle_session_is_reused_first() {
+    let (dial_out, dials) = memory_dial_out(echo_handler());
+    let client = Client::builder(dial_out, Arc::new(PaddingFactory::default()))
+        .min_idle_sessions(0)
+        .build();
+
+    // 前 8 个 Stream 占满第一个 Session，第 9 个落在第二个 Session
+    let mut first = Vec::new();
+    for _ in 0..8 {
+        first.push(client.create_stream().await.unwrap());
+    }
+    let second = client.create_stream().await.unwrap();
+    assert_eq!(dials.load(Ordering::Acquire), 2);
+    assert_eq!(served_counts(&client), vec![1, 8]);
+
+    drop(first);
+    wait_for("first session parked", || client.idle_session_count() == 1).await;
+    drop(second);
+    wait_for("second session parked", || client.idle_session_count() == 2).await;
+
+    let _reused = client.create_stream().await.unwrap();
+    assert_eq!(served_counts(&client), vec![2, 8]);
+    assert_eq!(dials.load(Ordering::Acquire), 2);
+}
+
+#[tokio::test]
+async fn sessions_over_use_limit_are_not_reused() {
+    let (dial_out, dials) = memory_dial_out(echo_handler());
+    let client = Client::builder(dial_out, Arc::new(PaddingFactory::default()))
+        .min_idle_sessions(0)
+        .max_session_uses(2)
+        .build();
+
+    drop(client.create_stream().await.unwrap());
+    wait_for("session parked", || client.idle_session_count() == 1).await;
+
+    // 第二次归还时已达上限，会被直接淘汰而不是放回池中
+    drop(client.create_stream().await.unwrap());
+    assert_eq!(dials.load(Ordering::Acquire), 1);
+    wait_for("used-up session retired", || client.session_info().is_empty()).await;
+    assert_eq!(client.idle_session_count(), 0);
+    let _stream = client.create_stream().await.unwrap();
+    assert_eq!(dials.load(Ordering::Acquire), 2);
+    assert_eq!(served_counts(&client), vec![1]);
+}
+
+#[tokio::test]
+async fn sessions_over_age_limit_are_not_reused() {
+    let (dial_out, dials) = memory_dial_out(echo_handler());
+    let client = Client::builder(dial_out, Arc::new(PaddingFactory::default()))
+        .min_idle_sessions(0)
+        .max_session_age(Duration::from_millis(100))
+        .build();
+
+    drop(client.create_stream().await.unwrap());
+    wait_for("session parked", || client.idle_session_count() == 1).await;
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    let _stream = client.create_stream().await.unwrap();
+    assert_eq!(dials.load(Ordering::Acquire), 2);
+    assert_eq!(client.idle_session_count(), 0);
+    assert_eq!(served_counts(&client), vec![1]);
+}
+
+#[test]
+fn client_rejects_min_idle_above_max_idle() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_anytls-client"))
+        .args(["-l", &common::free_addr(), "-p", common::PASSWORD])
+        .args(["--min-idle-sessions", "3", "--max-idle-sessions", "2"])
+        .output()
+        .expect("failed to run anytls-client");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--min-idle-sessions"));
+}
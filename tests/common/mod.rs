@@ -0,0 +1,226 @@
+#![allow(dead_code)]
+
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::session::{Session, SessionConfig, Stream};
+use anytls_rs::util::r#type::{AsyncReadWrite, DialOutFunc};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+pub const PASSWORD: &str = "password";
+
+/// 以子进程方式启动 anytls-server，Drop 时结束进程
+pub struct ServerProcess {
+    child: Child,
+    pub addr: String,
+}
+
+impl ServerProcess {
+    pub fn spawn(extra_args: &[&str]) -> Self {
+        let addr = free_addr();
+        let child = Command::new(env!("CARGO_BIN_EXE_anytls-server"))
+            .args(["-l", &addr, "-p", PASSWORD])
+            .args(extra_args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to spawn anytls-server");
+        wait_until_listening(&addr);
+        Self { child, addr }
+    }
+
+    /// 把已绑定的 `listener` 作为 fd `LISTENER_FD` 交给服务端，模拟 socket 激活
+    #[cfg(unix)]
+    pub fn spawn_with_listener(listener: &std::net::TcpListener) -> Self {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::process::CommandExt;
+
+        const LISTENER_FD: i32 = 100;
+        let addr = listener.local_addr().unwrap().to_string();
+        let fd = listener.as_raw_fd();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_anytls-server"));
+        command
+            .args(["-l", "127.0.0.1:1", "-p", PASSWORD])
+            .args(["--listen-fd", &LISTENER_FD.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        // SAFETY: dup2 是 async-signal-safe 的，新 fd 不带 CLOEXEC，exec 后仍然有效
+        unsafe {
+            command.pre_exec(move || {
+                nix::unistd::dup2(fd, LISTENER_FD)?;
+                Ok(())
+            });
+        }
+        let child = command.spawn().expect("failed to spawn anytls-server");
+        Self { child, addr }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// 进程已退出时返回退出状态
+    pub fn try_wait(&mut self) -> Option<ExitStatus> {
+        self.child.try_wait().unwrap()
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// 以子进程方式启动 anytls-client（SOCKS5 入口），Drop 时结束进程
+pub struct ClientProcess {
+    child: Child,
+    pub addr: String,
+}
+
+impl ClientProcess {
+    pub fn spawn(server: &str, extra_args: &[&str]) -> Self {
+        let addr = free_addr();
+        let child = Command::new(env!("CARGO_BIN_EXE_anytls-client"))
+            .args(["-l", &addr, "-s", server, "-p", PASSWORD])
+            .args(extra_args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to spawn anytls-client");
+        wait_until_listening(&addr);
+        Self { child, addr }
+    }
+}
+
+impl Drop for ClientProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub fn free_addr() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn wait_until_listening(addr: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if std::net::TcpStream::connect(addr).is_ok() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    panic!("server at {} did not start listening", addr);
+}
+
+pub type StreamHandler = Arc<dyn Fn(Stream) + Send + Sync>;
+
+/// 回显收到的所有数据
+pub fn echo_handler() -> StreamHandler {
+    Arc::new(|stream| {
+        tokio::spawn(async move {
+            let (mut r, mut w) = stream.split();
+            let _ = tokio::io::copy(&mut r, &mut w).await;
+        });
+    })
+}
+
+/// 内存拨号：每次拨号创建一对 duplex，对端运行一个服务端 Session。返回值同时给出拨号计数。
+pub fn memory_dial_out(on_new_stream: StreamHandler) -> (DialOutFunc, Arc<AtomicUsize>) {
+    let dials = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&dials);
+    let dial_out: DialOutFunc = Arc::new(move || {
+        let on_new_stream = Arc::clone(&on_new_stream);
+        counter.fetch_add(1, Ordering::AcqRel);
+        Box::new(Box::pin(async move {
+            let (client_end, server_end) = tokio::io::duplex(256 * 1024);
+            let server = Arc::new(Session::new_server(
+                Box::new(server_end),
+                Some(on_new_stream),
+                None,
+                Arc::new(PaddingFactory::default()),
+                SessionConfig::default(),
+            ));
+            server.run().await?;
+            Ok(Box::new(client_end) as Box<dyn AsyncReadWrite>)
+        }))
+    });
+    (dial_out, dials)
+}
+
+/// 收集 warn 及以上级别日志的全局 logger，同一测试进程内只安装一次
+pub struct CapturedLogs(Mutex<Vec<String>>);
+
+impl CapturedLogs {
+    pub fn lines(&self) -> MutexGuard<'_, Vec<String>> {
+        self.0.lock().unwrap()
+    }
+}
+
+impl log::Log for CapturedLogs {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURED_LOGS: CapturedLogs = CapturedLogs(Mutex::new(Vec::new()));
+
+/// 安装并返回全局 logger
+pub fn captured_logs() -> &'static CapturedLogs {
+    if log::set_logger(&CAPTURED_LOGS).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+    &CAPTURED_LOGS
+}
+
+/// 轮询等待条件成立，超时则 panic
+pub async fn wait_for(what: &str, mut cond: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !cond() {
+        if Instant::now() > deadline {
+            panic!("timed out waiting for {}", what);
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// 通过 duplex 直连的一对 Session，服务端新建的 Stream 从返回的通道取出
+pub async fn session_pair() -> (Arc<Session>, Arc<Session>, mpsc::UnboundedReceiver<Stream>) {
+    session_pair_with(SessionConfig::default()).await
+}
+
+/// 与 [`session_pair`] 相同，两端都使用 `config`
+pub async fn session_pair_with(
+    config: SessionConfig,
+) -> (Arc<Session>, Arc<Session>, mpsc::UnboundedReceiver<Stream>) {
+    let (client_end, server_end) = tokio::io::duplex(256 * 1024);
+    let (stream_tx, stream_rx) = mpsc::unbounded_channel();
+    let on_new_stream: StreamHandler = Arc::new(move |stream| {
+        let _ = stream_tx.send(stream);
+    });
+    let padding = Arc::new(PaddingFactory::default());
+    let server = Arc::new(Session::new_server(
+        Box::new(server_end),
+        Some(on_new_stream),
+        None,
+        Arc::clone(&padding),
+        config,
+    ));
+    let client = Arc::new(Session::new_client(Box::new(client_end), padding, config));
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+    (client, server, stream_rx)
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use anytls_rs::config::{self, ClientConfig, ConfigArg, ServerConfig};
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::session::{Session, SessionConfig};
+use anytls_rs::proxy::transport::{self, AuthMode};
+use common::{ServerProcess, PASSWORD};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn write_config(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("anytls-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn config_file_round_trips() {
+    let server = ServerConfig {
+        listen: Some("127.0.0.1:8443".to_string()),
+        password: Some("-starts-with-dash".to_string()),
+        recv_window: Some(1 << 20),
+        no_tls: Some(true),
+        http_route: Some(vec!["a.example=127.0.0.1:1".into(), "b.example=127.0.0.1:2".into()]),
+        ..ServerConfig::default()
+    };
+    let path = write_config("round-trip", &toml::to_string(&server).unwrap());
+    let loaded: ServerConfig = config::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(loaded, server);
+
+    let args = config::to_args(&loaded).unwrap();
+    let argv: Vec<&str> = args.iter().flat_map(|arg| &arg.argv).map(String::as_str).collect();
+    assert_eq!(
+        argv,
+        [
+            "--http-route=a.example=127.0.0.1:1",
+            "--http-route=b.example=127.0.0.1:2",
+            "--listen=127.0.0.1:8443",
+            "--no-tls",
+            "--password=-starts-with-dash",
+            "--recv-window=1048576",
+        ]
+    );
+}
+
+#[test]
+fn invalid_config_names_the_offending_field() {
+    let path = write_config("unknown-key", "password = \"x\"\nrecv-windw = 1\n");
+    let err = config::load::<ClientConfig>(&path).unwrap_err().to_string();
+    let _ = std::fs::remove_file(&path);
+    assert!(err.contains("recv-windw"), "{}", err);
+
+    let path = write_config("bad-type", "recv-window = \"large\"\n");
+    let err = config::load::<ClientConfig>(&path).unwrap_err().to_string();
+    let _ = std::fs::remove_file(&path);
+    assert!(err.contains("recv-window"), "{}", err);
+}
+
+#[test]
+fn command_line_flags_override_file_values() {
+    let command = clap::Command::new("test")
+        .arg(clap::Arg::new("password").short('p').long("password"))
+        .arg(clap::Arg::new("listen").short('l').long("listen"))
+        .arg(clap::Arg::new("server").long("server"));
+    let file = vec![
+        ConfigArg { long: "listen".into(), argv: vec!["--listen=file".into()] },
+        ConfigArg { long: "password".into(), argv: vec!["--password=file".into()] },
+        ConfigArg { long: "server".into(), argv: vec!["--server=file".into()] },
+    ];
+    let cli: Vec<String> = ["bin", "-pcli", "--server", "cli"].map(String::from).to_vec();
+    assert_eq!(config::config_path(&cli), None);
+    assert_eq!(
+        config::merge_args(&command, file, cli),
+        ["bin", "--listen=file", "-pcli", "--server", "cli"]
+    );
+    let cli = ["bin", "--config=a.toml"].map(String::from);
+    assert_eq!(config::config_path(&cli).as_deref(), Some("a.toml"));
+}
+
+#[tokio::test]
+async fn server_reads_options_from_config_file() {
+    // 文件中的 listen 被命令行覆盖，no-tls 从文件生效
+    let path = write_config("server", "listen = \"0.0.0.0:1\"\nno-tls = true\n");
+    let server = ServerProcess::spawn(&["--config", path.to_str().unwrap()]);
+    let padding = Arc::new(PaddingFactory::default());
+    let password = transport::password_sha256(PASSWORD);
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        password,
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let conn = dial().await.unwrap();
+    let session = Arc::new(Session::new_client(conn, padding, SessionConfig::default()));
+    session.run().await.unwrap();
+    session.heartbeat_probe(Duration::from_secs(5)).await.unwrap();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn server_rejects_invalid_config_file() {
+    let path = write_config("server-invalid", "no-such-option = 1\n");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_anytls-server"))
+        .args(["-p", PASSWORD, "--config", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no-such-option"), "{}", stderr);
+}
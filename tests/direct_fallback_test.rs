@@ -0,0 +1,70 @@
+mod common;
+
+use common::{free_addr, ClientProcess};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn spawn_echo_target() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = conn.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+    addr
+}
+
+/// 完成 SOCKS5 握手并请求连接 127.0.0.1:port，返回应答码
+async fn socks5_connect(proxy: &str, port: u16) -> (TcpStream, u8) {
+    let mut conn = TcpStream::connect(proxy).await.unwrap();
+    conn.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut method = [0u8; 2];
+    conn.read_exact(&mut method).await.unwrap();
+    assert_eq!(method, [0x05, 0x00]);
+
+    let mut req = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    conn.write_all(&req).await.unwrap();
+    let mut reply = [0u8; 10];
+    let rep = match conn.read_exact(&mut reply).await {
+        Ok(_) => reply[1],
+        Err(_) => 0xff,
+    };
+    (conn, rep)
+}
+
+#[tokio::test]
+async fn dead_server_falls_back_to_direct_relay() {
+    let target = spawn_echo_target().await;
+    let port: u16 = target.rsplit(':').next().unwrap().parse().unwrap();
+    let dead_server = free_addr();
+    let client = ClientProcess::spawn(
+        &dead_server,
+        &["--direct-fallback", "--fallback-allow", "127.0.0.1"],
+    );
+
+    let (mut conn, rep) = socks5_connect(&client.addr, port).await;
+    assert_eq!(rep, 0x00);
+    conn.write_all(b"direct").await.unwrap();
+    let mut buf = [0u8; 6];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"direct");
+}
+
+#[tokio::test]
+async fn fallback_is_refused_for_targets_outside_allow_list() {
+    let target = spawn_echo_target().await;
+    let port: u16 = target.rsplit(':').next().unwrap().parse().unwrap();
+    let dead_server = free_addr();
+    let client = ClientProcess::spawn(
+        &dead_server,
+        &["--direct-fallback", "--fallback-allow", ".example.com"],
+    );
+
+    let (_conn, rep) = socks5_connect(&client.addr, port).await;
+    assert_ne!(rep, 0x00);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use common::{ServerProcess, PASSWORD};
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// `cargo test` 会一并编译 examples，产物位于测试可执行文件上一级的 examples 目录
+fn example_path(name: &str) -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let profile_dir = exe.parent().and_then(|deps| deps.parent()).unwrap();
+    profile_dir.join("examples").join(name)
+}
+
+#[tokio::test]
+async fn embed_client_example_fetches_through_the_tunnel() {
+    let server = ServerProcess::spawn(&[]);
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = target.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut conn, _) = target.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = conn.read(&mut buf).await.unwrap();
+            assert!(n > 0, "request ended early");
+            request.extend_from_slice(&buf[..n]);
+        }
+        assert!(request.starts_with(b"GET /hello HTTP/1.1\r\n"));
+        conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhello\n")
+            .await
+            .unwrap();
+    });
+
+    let output = tokio::process::Command::new(example_path("embed_client"))
+        .args([&server.addr, PASSWORD, &target_addr, "/hello"])
+        .output()
+        .await
+        .expect("failed to run the embed_client example");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "example failed: {:?}", output);
+    assert!(stdout.starts_with("HTTP/1.1 200 OK"), "unexpected output: {}", stdout);
+    assert!(stdout.ends_with("hello\n"));
+}
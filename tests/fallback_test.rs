@@ -0,0 +1,61 @@
+mod common;
+
+use anytls_rs::proxy::transport;
+use anytls_rs::util::tls::TlsClientOptions;
+use common::ServerProcess;
+use rustls::pki_types::ServerName;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio_rustls::TlsConnector;
+
+const PAGE: &str = "HTTP/1.1 200 OK\r\nContent-Length: 13\r\nConnection: close\r\n\r\nfallback-page";
+
+/// 模拟真实站点：读完请求头后返回固定页面，并把收到的请求交给测试
+async fn spawn_site() -> (String, oneshot::Receiver<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = conn.read(&mut buf).await.unwrap();
+            assert!(n > 0, "request ended early");
+            request.extend_from_slice(&buf[..n]);
+        }
+        conn.write_all(PAGE.as_bytes()).await.unwrap();
+        conn.shutdown().await.unwrap();
+        let _ = tx.send(request);
+    });
+    (addr, rx)
+}
+
+#[tokio::test]
+async fn unauthenticated_client_gets_fallback_site() {
+    let (site, request_rx) = spawn_site().await;
+    let server = ServerProcess::spawn(&["--fallback-site", &site]);
+
+    let connector =
+        TlsConnector::from(transport::create_tls_config(&TlsClientOptions::default()).unwrap());
+    let tcp = TcpStream::connect(&server.addr).await.unwrap();
+    let mut tls = connector
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await
+        .unwrap();
+
+    // 普通 HTTPS 请求代替认证头，前 34 字节被当作错误的密码读走
+    let request = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n";
+    tls.write_all(request).await.unwrap();
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), tls.read_to_end(&mut response))
+        .await
+        .expect("fallback response timed out")
+        .unwrap();
+    assert_eq!(String::from_utf8(response).unwrap(), PAGE);
+
+    // 站点收到完整的请求，包括认证阶段读走的部分
+    assert_eq!(request_rx.await.unwrap(), request);
+}
@@ -1,5 +1,6 @@
-use anytls_rs::proxy::session::{Frame, CMD_PSH};
-use bytes::Bytes;
+use anytls_rs::proxy::session::{Frame, FrameCodec, RawHeader, CMD_FIN, CMD_PSH, CMD_WASTE};
+use bytes::{Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
 
 #[test]
 fn frame_serialization_round_trip() {
@@ -11,3 +12,76 @@ fn frame_serialization_round_trip() {
     assert_eq!(frame.sid, parsed.sid);
     assert_eq!(frame.data, parsed.data);
 }
+
+#[tokio::test]
+async fn read_from_reads_consecutive_frames() {
+    let mut wire = BytesMut::new();
+    Frame::with_data(CMD_PSH, 3, Bytes::from("first")).encode_into(&mut wire);
+    Frame::new(CMD_FIN, 3).encode_into(&mut wire);
+    Frame::with_data(CMD_WASTE, 0, Bytes::from(vec![0u8; 100])).encode_into(&mut wire);
+    let mut reader = &wire[..];
+
+    let first = Frame::read_from(&mut reader).await.unwrap();
+    assert_eq!((first.cmd, first.sid, first.data), (CMD_PSH, 3, Bytes::from("first")));
+    let fin = Frame::read_from(&mut reader).await.unwrap();
+    assert_eq!((fin.cmd, fin.sid, fin.data.len()), (CMD_FIN, 3, 0));
+
+    // 复用的缓冲区每次只保留当前帧的负载
+    let mut payload = BytesMut::from(&b"stale"[..]);
+    let header = RawHeader::read_from(&mut reader, &mut payload).await.unwrap();
+    assert_eq!((header.cmd, header.length), (CMD_WASTE, 100));
+    assert_eq!(&payload[..], &[0u8; 100][..]);
+    assert!(reader.is_empty());
+}
+
+#[tokio::test]
+async fn read_from_rejects_truncated_frames() {
+    let wire = Frame::with_data(CMD_PSH, 9, Bytes::from("payload")).to_bytes();
+    for end in 0..wire.len() {
+        let err = Frame::read_from(&mut &wire[..end]).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof, "cut at {}", end);
+    }
+}
+
+#[tokio::test]
+async fn read_next_separates_clean_close_from_truncation() {
+    let wire = Frame::with_data(CMD_PSH, 9, Bytes::from("payload")).to_bytes();
+    let mut payload = BytesMut::new();
+    let mut reader = &wire[..];
+    let header = RawHeader::read_next(&mut reader, &mut payload).await.unwrap().unwrap();
+    assert_eq!((header.cmd, header.sid), (CMD_PSH, 9));
+    // 帧边界上的 EOF 是对端正常关闭
+    assert!(RawHeader::read_next(&mut reader, &mut payload).await.unwrap().is_none());
+
+    for end in 1..wire.len() {
+        let err = RawHeader::read_next(&mut &wire[..end], &mut payload).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof, "cut at {}", end);
+    }
+}
+
+#[test]
+fn frame_codec_decodes_input_split_across_reads() {
+    let first = Frame::with_data(CMD_PSH, 7, Bytes::from("split payload"));
+    let second = Frame::new(CMD_FIN, 7);
+    let mut wire = BytesMut::new();
+    FrameCodec.encode(first.clone(), &mut wire).unwrap();
+    FrameCodec.encode(second, &mut wire).unwrap();
+
+    // 每次只到达一个字节，模拟最碎片化的读取
+    let mut codec = FrameCodec;
+    let mut buf = BytesMut::new();
+    let mut decoded = Vec::new();
+    for byte in wire {
+        buf.extend_from_slice(&[byte]);
+        while let Some(frame) = codec.decode(&mut buf).unwrap() {
+            decoded.push(frame);
+        }
+    }
+
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(decoded[0].sid, first.sid);
+    assert_eq!(decoded[0].data, first.data);
+    assert_eq!(decoded[1].cmd, CMD_FIN);
+    assert!(decoded[1].data.is_empty());
+    assert!(buf.is_empty());
+}
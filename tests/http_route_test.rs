@@ -0,0 +1,133 @@
+mod common;
+
+use anytls_rs::proxy::http_route::{peek_http_host, HttpPeek, HttpRoutes};
+use common::{ClientProcess, ServerProcess};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: App.Example.com:8080\r\nAccept: */*\r\n\r\n";
+
+fn routes(entries: &[&str]) -> HttpRoutes {
+    let entries: Vec<String> = entries.iter().map(|e| e.to_string()).collect();
+    HttpRoutes::parse(&entries).unwrap()
+}
+
+#[test]
+fn parse_rejects_malformed_entries() {
+    for bad in ["example.com", "=127.0.0.1:80", "example.com="] {
+        assert!(HttpRoutes::parse(&[bad.to_string()]).is_err(), "{}", bad);
+    }
+    let r = routes(&["Example.COM=127.0.0.1:80"]);
+    assert_eq!(r.backend_for("example.com:443"), Some("127.0.0.1:80"));
+    assert_eq!(r.backend_for("other.com"), None);
+}
+
+#[test]
+fn peek_extracts_host_without_port() {
+    assert_eq!(peek_http_host(b"GE"), HttpPeek::Incomplete);
+    assert_eq!(peek_http_host(b"GET / HTTP/1.1\r\nHost: a"), HttpPeek::Incomplete);
+    assert_eq!(peek_http_host(b"\x16\x03\x01\x02\x00"), HttpPeek::NotHttp);
+    assert_eq!(
+        peek_http_host(REQUEST),
+        HttpPeek::Complete(Some("app.example.com".to_string()))
+    );
+    assert_eq!(
+        peek_http_host(b"GET / HTTP/1.1\r\nHost: [::1]:80\r\n\r\n"),
+        HttpPeek::Complete(Some("::1".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn route_returns_backend_and_keeps_prefix() {
+    let r = routes(&["app.example.com=10.0.0.1:80"]);
+    let (mut client, mut server) = tokio::io::duplex(1024);
+    tokio::spawn(async move {
+        // 分两次写入，确认请求头跨多次读取也能识别
+        client.write_all(&REQUEST[..10]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.write_all(&REQUEST[10..]).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    });
+    let (prefix, backend) = r.route(&mut server, Duration::from_secs(1)).await.unwrap();
+    assert_eq!(prefix, REQUEST);
+    assert_eq!(backend.as_deref(), Some("10.0.0.1:80"));
+}
+
+#[tokio::test]
+async fn route_falls_back_for_non_http_and_unknown_hosts() {
+    let r = routes(&["other.example.com=10.0.0.1:80"]);
+
+    let mut reader: &[u8] = REQUEST;
+    let (prefix, backend) = r.route(&mut reader, Duration::from_secs(1)).await.unwrap();
+    assert_eq!(prefix, REQUEST);
+    assert_eq!(backend, None);
+
+    let mut reader: &[u8] = b"\x16\x03\x01 not http";
+    let (prefix, backend) = r.route(&mut reader, Duration::from_secs(1)).await.unwrap();
+    assert_eq!(prefix, b"\x16\x03\x01 not http");
+    assert_eq!(backend, None);
+
+    // 客户端不先发数据时超时返回，不丢失后续数据
+    let (_client, mut server) = tokio::io::duplex(1024);
+    let (prefix, backend) = r.route(&mut server, Duration::from_millis(50)).await.unwrap();
+    assert!(prefix.is_empty());
+    assert_eq!(backend, None);
+}
+
+/// 接受连接后回写固定标记和收到的数据
+async fn spawn_tagged_target(tag: &'static [u8]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let _ = conn.write_all(tag).await;
+                let (mut r, mut w) = conn.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+    addr
+}
+
+async fn http_via_socks(proxy: &str, target_port: u16, request: &[u8], tag_len: usize) -> Vec<u8> {
+    let mut conn = TcpStream::connect(proxy).await.unwrap();
+    conn.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut method = [0u8; 2];
+    conn.read_exact(&mut method).await.unwrap();
+    let mut req = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    req.extend_from_slice(&target_port.to_be_bytes());
+    conn.write_all(&req).await.unwrap();
+    let mut reply = [0u8; 10];
+    conn.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00);
+
+    conn.write_all(request).await.unwrap();
+    let mut buf = vec![0u8; tag_len + request.len()];
+    tokio::time::timeout(Duration::from_secs(5), conn.read_exact(&mut buf))
+        .await
+        .expect("relay timed out")
+        .unwrap();
+    buf
+}
+
+#[tokio::test]
+async fn server_routes_by_host_and_falls_back_to_socks_target() {
+    let socks_target = spawn_tagged_target(b"socks:").await;
+    let backend = spawn_tagged_target(b"route:").await;
+    let port: u16 = socks_target.rsplit(':').next().unwrap().parse().unwrap();
+
+    let route = format!("app.example.com={}", backend);
+    let server = ServerProcess::spawn(&["--http-route", &route]);
+    let client = ClientProcess::spawn(&server.addr, &[]);
+
+    let routed = http_via_socks(&client.addr, port, REQUEST, 6).await;
+    assert_eq!(&routed[..6], b"route:");
+    assert_eq!(&routed[6..], REQUEST);
+
+    let other = b"GET / HTTP/1.1\r\nHost: unknown.example.com\r\n\r\n";
+    let fallback = http_via_socks(&client.addr, port, other, 6).await;
+    assert_eq!(&fallback[..6], b"socks:");
+    assert_eq!(&fallback[6..], other);
+}
@@ -0,0 +1,89 @@
+use anytls_rs::proxy::padding::{
+    compress_scheme, decompress_scheme, DefaultPaddingFactory, PaddingFactory, PaddingToken,
+};
+use bytes::Bytes;
+
+#[tokio::test]
+async fn default_padding_update_notifies_subscribers() {
+    let mut rx = DefaultPaddingFactory::subscribe();
+    let scheme = b"stop=2\n0=10-20\n1=30-40";
+    let expected_md5 = format!("{:x}", md5::compute(scheme));
+
+    assert!(!DefaultPaddingFactory::update(b"not-a-scheme").await);
+    assert!(DefaultPaddingFactory::update(scheme).await);
+
+    rx.changed().await.unwrap();
+    assert_eq!(rx.borrow_and_update().md5(), expected_md5);
+    assert_eq!(DefaultPaddingFactory::load().md5(), expected_md5);
+}
+
+#[test]
+fn default_scheme_describes_expected_structure() {
+    use PaddingToken::{Check, Fixed, Range};
+
+    let described = PaddingFactory::default().describe();
+    let pkts: Vec<u32> = described.iter().map(|(pkt, _)| *pkt).collect();
+    assert_eq!(pkts, (0..8).collect::<Vec<_>>());
+    assert_eq!(described[0].1, vec![Fixed(30)]);
+    assert_eq!(described[1].1, vec![Range(100, 400)]);
+    assert_eq!(
+        described[2].1,
+        vec![
+            Range(400, 500),
+            Check,
+            Range(500, 1000),
+            Check,
+            Range(500, 1000),
+            Check,
+            Range(500, 1000),
+            Check,
+            Range(500, 1000)
+        ]
+    );
+    assert_eq!(described[3].1, vec![Fixed(9), Range(500, 1000)]);
+
+    let shown = PaddingFactory::default().to_string();
+    assert!(shown.starts_with("stop=8\n0=30\n1=100-400\n2=400-500,c,500-1000,c"));
+}
+
+#[test]
+fn max_packet_bytes_sums_upper_bounds() {
+    assert_eq!(PaddingFactory::default().max_packet_bytes(), 4500);
+    let factory = PaddingFactory::new(b"stop=2\n0=900-100,c,7-7\n1=50-60").unwrap();
+    assert_eq!(factory.max_packet_bytes(), 907);
+    assert_eq!(PaddingFactory::new(b"stop=0").unwrap().max_packet_bytes(), 0);
+}
+
+#[test]
+fn describe_keeps_inverted_ranges() {
+    let factory = PaddingFactory::new(b"stop=1\n0=900-100").unwrap();
+    assert_eq!(factory.describe(), vec![(0, vec![PaddingToken::Range(900, 100)])]);
+}
+
+#[test]
+fn from_bytes_keeps_the_buffer_without_copying() {
+    let raw = bytes::Bytes::from_static(b"stop=2\n0=10-20\n1=30-40");
+    let factory = PaddingFactory::from_bytes(raw.clone()).unwrap();
+    assert_eq!(factory.raw_scheme.as_ptr(), raw.as_ptr());
+    assert_eq!(factory.md5(), PaddingFactory::new(&raw).unwrap().md5());
+    assert!(PaddingFactory::from_bytes(bytes::Bytes::from_static(b"0=10-20")).is_none());
+}
+
+#[test]
+fn compressed_scheme_round_trips() {
+    let mut raw = b"stop=500".to_vec();
+    for pkt in 0..500 {
+        raw.extend_from_slice(format!("\n{}=100-400,c,500-1000,c,500-1000", pkt).as_bytes());
+    }
+    let payload = compress_scheme(&raw);
+    assert!(payload.len() * 4 < raw.len(), "{} -> {} bytes", raw.len(), payload.len());
+    let restored = decompress_scheme(payload).unwrap();
+    assert_eq!(restored, raw);
+    assert_eq!(PaddingFactory::from_bytes(restored).unwrap().stop(), 500);
+
+    // 未压缩的方案原样返回，损坏的压缩数据被拒绝
+    let plain = Bytes::from_static(b"stop=2\n0=10-20\n1=30-40");
+    assert_eq!(decompress_scheme(plain.clone()).unwrap().as_ptr(), plain.as_ptr());
+    let err = decompress_scheme(Bytes::from_static(&[0, 0xff, 0xff, 0xff])).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::{ServerProcess, PASSWORD};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::process::{Command, Output};
+
+fn probe(server: &str, password: &str, extra_args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_anytls-client"))
+        .args(["--probe", "-s", server, "-p", password])
+        .args(extra_args)
+        .output()
+        .expect("failed to run anytls-client --probe")
+}
+
+#[test]
+fn probe_reports_every_phase_and_the_target_status() {
+    let target = TcpListener::bind("127.0.0.1:0").unwrap();
+    let target_addr = target.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let (mut conn, _) = target.accept().unwrap();
+        let mut request = [0u8; 1024];
+        let n = conn.read(&mut request).unwrap();
+        assert!(request[..n].starts_with(b"HEAD / HTTP/1.1\r\n"));
+        conn.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+    });
+
+    let server = ServerProcess::spawn(&[]);
+    let output = probe(&server.addr, PASSWORD, &["--probe-target", &target_addr]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    for phase in ["tcp", "tls", "auth", "session", "stream"] {
+        assert!(stdout.lines().any(|l| l.starts_with(phase) && l.contains("ok")), "{}", stdout);
+    }
+    assert!(stdout.contains("HTTP/1.1 204 No Content"), "{}", stdout);
+    assert!(stdout.ends_with("PASS\n"), "{}", stdout);
+}
+
+#[test]
+fn probe_fails_on_wrong_password() {
+    let server = ServerProcess::spawn(&[]);
+    let output = probe(&server.addr, "not-the-password", &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{}", stdout);
+    assert!(stdout.lines().any(|l| l.starts_with("session") && l.contains("FAIL")), "{}", stdout);
+}
+
+#[test]
+fn probe_fails_when_server_is_down() {
+    let output = probe(&common::free_addr(), PASSWORD, &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(stdout.lines().any(|l| l.starts_with("tcp") && l.contains("FAIL")), "{}", stdout);
+}
@@ -0,0 +1,144 @@
+//! protocol 模块不依赖运行时，这里全部是同步测试
+
+use anytls_rs::proxy::protocol::frame::{CMD_PSH, CMD_SETTINGS, SEQ_PREFIX_SIZE};
+use anytls_rs::proxy::protocol::settings::{
+    check_settings_size, EXT_PSH_SEQ, MAX_SETTINGS_ENTRIES, MAX_SETTINGS_SIZE,
+};
+use anytls_rs::proxy::protocol::{
+    waste_lengths, ClientSettings, Frame, PaddingFactory, RawHeader, ServerSettings, CHECK_MARK,
+    HEADER_OVERHEAD_SIZE, PROTOCOL_VERSION,
+};
+use bytes::{Bytes, BytesMut};
+
+#[test]
+fn header_matches_encoded_prefix() {
+    let frame = Frame::with_data(CMD_PSH, 0x01020304, Bytes::from_static(b"abc"));
+    let encoded = frame.to_bytes();
+    assert_eq!(encoded.len(), frame.encoded_len());
+    assert_eq!(&encoded[..HEADER_OVERHEAD_SIZE], &frame.header());
+    assert_eq!(&encoded[..HEADER_OVERHEAD_SIZE], &[CMD_PSH, 1, 2, 3, 4, 0, 3]);
+
+    let header = RawHeader::from_bytes(&encoded).unwrap();
+    assert_eq!((header.cmd, header.sid, header.length), (CMD_PSH, 0x01020304, 3));
+}
+
+#[test]
+fn encode_into_appends_consecutive_frames() {
+    let first = Frame::with_data(CMD_PSH, 1, Bytes::from_static(b"one"));
+    let second = Frame::new(CMD_SETTINGS, 0);
+    let mut buf = BytesMut::new();
+    first.encode_into(&mut buf);
+    second.encode_into(&mut buf);
+    assert_eq!(buf.len(), first.encoded_len() + second.encoded_len());
+
+    let decoded = Frame::from_bytes(&buf[first.encoded_len()..]).unwrap();
+    assert_eq!((decoded.cmd, decoded.sid), (CMD_SETTINGS, 0));
+    assert!(decoded.data.is_empty());
+}
+
+#[test]
+fn truncated_frame_is_rejected() {
+    let frame = Frame::with_data(CMD_PSH, 9, Bytes::from_static(b"payload"));
+    let encoded = frame.to_bytes();
+    assert!(Frame::from_bytes(&encoded[..encoded.len() - 1]).is_err());
+    assert!(RawHeader::from_bytes(&encoded[..HEADER_OVERHEAD_SIZE - 1]).is_err());
+}
+
+#[test]
+fn client_settings_round_trip() {
+    let settings = ClientSettings::new("anytls-rs/test", "0123abcd");
+    let decoded = ClientSettings::decode(&settings.encode());
+    assert_eq!(decoded, settings);
+    assert_eq!(decoded.version, Some(PROTOCOL_VERSION));
+}
+
+#[test]
+fn settings_tolerate_missing_and_malformed_fields() {
+    let decoded = ClientSettings::decode(b"v=abc\nunknown=1\npadding-md5=ff");
+    assert_eq!(decoded.version, None);
+    assert_eq!(decoded.client, None);
+    assert_eq!(decoded.padding_md5.as_deref(), Some("ff"));
+
+    assert_eq!(ServerSettings::decode(b"").version, None);
+    assert_eq!(ServerSettings::decode(&ServerSettings::new().encode()).version, Some(2));
+}
+
+#[test]
+fn waste_fills_each_record_up_to_its_size() {
+    // 第一条记录被 30 字节的帧占用 30，剩余 70 去掉帧头后作为 WASTE 负载
+    assert_eq!(
+        waste_lengths(&[100, 50], 30),
+        vec![70 - HEADER_OVERHEAD_SIZE, 50 - HEADER_OVERHEAD_SIZE]
+    );
+    // 剩余空间不足一个帧头时不补
+    assert_eq!(waste_lengths(&[35], 30), Vec::<usize>::new());
+}
+
+#[test]
+fn check_mark_stops_once_payload_is_consumed() {
+    assert_eq!(waste_lengths(&[100, CHECK_MARK, 200], 50), vec![50 - HEADER_OVERHEAD_SIZE]);
+    // 负载尚未写完时越过检查点继续填充
+    assert_eq!(waste_lengths(&[100, CHECK_MARK, 200], 150), vec![150 - HEADER_OVERHEAD_SIZE]);
+}
+
+#[test]
+fn padding_sizes_follow_scheme_ranges() {
+    let padding = PaddingFactory::new(b"stop=2\n0=10-10\n1=20-30,c,40-40").unwrap();
+    assert_eq!(padding.generate_record_payload_sizes(0), vec![10]);
+    for _ in 0..32 {
+        let sizes = padding.generate_record_payload_sizes(1);
+        assert_eq!(sizes.len(), 3);
+        assert!((20..=30).contains(&sizes[0]));
+        assert_eq!(&sizes[1..], &[CHECK_MARK, 40]);
+    }
+    assert!(padding.generate_record_payload_sizes(2).is_empty());
+}
+
+#[test]
+fn extensions_round_trip_and_default_to_none() {
+    let settings = ServerSettings::new();
+    assert!(settings.supports(EXT_PSH_SEQ));
+    let decoded = ServerSettings::decode(&settings.encode());
+    assert!(decoded.supports(EXT_PSH_SEQ));
+
+    // 旧版本的对端不发送 ext
+    let legacy = ClientSettings::decode(b"v=2\nclient=sing-box\npadding-md5=00");
+    assert!(legacy.extensions.is_empty());
+    assert!(!legacy.supports(EXT_PSH_SEQ));
+}
+
+#[test]
+fn max_payload_round_trips_and_is_optional() {
+    let mut client = ClientSettings::new("anytls-rs/test", "0123abcd");
+    client.max_payload = Some(1200);
+    assert_eq!(ClientSettings::decode(&client.encode()).max_payload, Some(1200));
+    let server = ServerSettings {
+        max_payload: Some(4096),
+        ..ServerSettings::new()
+    };
+    assert_eq!(ServerSettings::decode(&server.encode()).max_payload, Some(4096));
+
+    assert_eq!(ServerSettings::decode(&ServerSettings::new().encode()).max_payload, None);
+    assert_eq!(ClientSettings::decode(b"v=2\nmax-payload=abc").max_payload, None);
+}
+
+#[test]
+fn sequenced_push_carries_its_sequence_number() {
+    let frame = Frame::psh_seq(3, 0xdead_beef, b"data");
+    assert_eq!(frame.data.len(), SEQ_PREFIX_SIZE + 4);
+    let (seq, payload) = Frame::split_seq(frame.data).unwrap();
+    assert_eq!(seq, 0xdead_beef);
+    assert_eq!(&payload[..], b"data");
+    assert!(Frame::split_seq(Bytes::from_static(b"abc")).is_err());
+}
+
+#[test]
+fn settings_size_and_entry_count_are_capped() {
+    let normal = ClientSettings::new("anytls-rs/test", "0123abcd").with_platform();
+    assert!(check_settings_size(&normal.encode()).is_ok());
+    assert!(check_settings_size(&vec![b'a'; MAX_SETTINGS_SIZE]).is_ok());
+    assert!(check_settings_size(&vec![b'a'; MAX_SETTINGS_SIZE + 1]).is_err());
+    let entries = vec!["k=v"; MAX_SETTINGS_ENTRIES + 1].join("\n");
+    assert!(entries.len() < MAX_SETTINGS_SIZE);
+    assert!(check_settings_size(entries.as_bytes()).is_err());
+}
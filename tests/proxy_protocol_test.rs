@@ -0,0 +1,57 @@
+use anytls_rs::proxy::proxy_protocol::read_proxy_header;
+use tokio::io::AsyncReadExt;
+
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+#[tokio::test]
+async fn parse_v1_tcp4_header() {
+    let mut input: &[u8] = b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 8443\r\n\x16\x03";
+    let addr = read_proxy_header(&mut input).await.unwrap();
+
+    assert_eq!(addr, Some("203.0.113.7:51234".parse().unwrap()));
+    // 头部之后的 TLS 数据必须保留
+    assert_eq!(input, b"\x16\x03");
+}
+
+#[tokio::test]
+async fn parse_v1_tcp6_and_unknown_headers() {
+    let mut input: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n";
+    let addr = read_proxy_header(&mut input).await.unwrap();
+    assert_eq!(addr, Some("[2001:db8::1]:4000".parse().unwrap()));
+
+    let mut input: &[u8] = b"PROXY UNKNOWN\r\n";
+    assert_eq!(read_proxy_header(&mut input).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn parse_v2_ipv4_header() {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+    header.extend_from_slice(&[198, 51, 100, 9, 10, 0, 0, 1]);
+    header.extend_from_slice(&40000u16.to_be_bytes());
+    header.extend_from_slice(&8443u16.to_be_bytes());
+    header.extend_from_slice(b"rest");
+
+    let mut input = header.as_slice();
+    let addr = read_proxy_header(&mut input).await.unwrap();
+    assert_eq!(addr, Some("198.51.100.9:40000".parse().unwrap()));
+
+    let mut rest = Vec::new();
+    input.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(rest, b"rest");
+}
+
+#[tokio::test]
+async fn parse_v2_local_command_has_no_address() {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+
+    let mut input = header.as_slice();
+    assert_eq!(read_proxy_header(&mut input).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn reject_connection_without_header() {
+    let mut input: &[u8] = b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03\x00";
+    assert!(read_proxy_header(&mut input).await.is_err());
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::protocol::settings::EXT_PSH_SEQ;
+use anytls_rs::proxy::protocol::{ClientSettings, Frame};
+use anytls_rs::proxy::session::{
+    FrameCodec, Session, SessionConfig, Stream, CMD_SETTINGS, CMD_SYN,
+};
+use bytes::BytesMut;
+use common::{session_pair, wait_for};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::sync::mpsc;
+use tokio_util::codec::Encoder;
+
+/// 服务端 Session 的对端由测试直接写原始帧
+async fn raw_client_to_server(
+    settings: ClientSettings,
+) -> (DuplexStream, Arc<Session>, mpsc::UnboundedReceiver<Stream>) {
+    let (mut raw, server_end) = tokio::io::duplex(256 * 1024);
+    let (stream_tx, stream_rx) = mpsc::unbounded_channel();
+    let server = Arc::new(Session::new_server(
+        Box::new(server_end),
+        Some(Arc::new(move |stream| {
+            let _ = stream_tx.send(stream);
+        })),
+        None,
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    server.run().await.unwrap();
+    send(&mut raw, &[Frame::with_data(CMD_SETTINGS, 0, settings.encode())]).await;
+    (raw, server, stream_rx)
+}
+
+async fn send(raw: &mut DuplexStream, frames: &[Frame]) {
+    let mut wire = BytesMut::new();
+    for frame in frames {
+        FrameCodec.encode(frame.clone(), &mut wire).unwrap();
+    }
+    raw.write_all(&wire).await.unwrap();
+}
+
+#[tokio::test]
+async fn out_of_order_frames_are_reassembled() {
+    let settings = ClientSettings::new("raw-test", PaddingFactory::default().md5());
+    let (mut raw, _server, mut incoming) = raw_client_to_server(settings).await;
+    send(&mut raw, &[Frame::new(CMD_SYN, 1)]).await;
+    let mut stream = incoming.recv().await.unwrap();
+
+    let chunks: [&[u8]; 5] = [b"zero,", b"one,", b"two,", b"three,", b"four"];
+    let order = [3, 0, 4, 2, 1, 2, 0];
+    let frames: Vec<_> = order
+        .iter()
+        .map(|&seq| Frame::psh_seq(1, seq, chunks[seq as usize]))
+        .collect();
+    send(&mut raw, &frames).await;
+
+    // 重复的 2 和 0 被丢弃，其余按序号交付
+    let expected = b"zero,one,two,three,four";
+    let mut buf = vec![0u8; expected.len()];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut buf))
+        .await
+        .expect("reassembled data not delivered")
+        .unwrap();
+    assert_eq!(&buf, expected);
+    let mut extra = [0u8; 1];
+    let more = tokio::time::timeout(Duration::from_millis(100), stream.read(&mut extra)).await;
+    assert!(more.is_err(), "duplicate data was delivered");
+}
+
+#[tokio::test]
+async fn sequenced_frames_wait_for_missing_predecessor() {
+    let settings = ClientSettings::new("raw-test", PaddingFactory::default().md5());
+    let (mut raw, _server, mut incoming) = raw_client_to_server(settings).await;
+    send(&mut raw, &[Frame::new(CMD_SYN, 1), Frame::psh_seq(1, 1, b"second")]).await;
+    let mut stream = incoming.recv().await.unwrap();
+
+    let mut buf = [0u8; 16];
+    let early = tokio::time::timeout(Duration::from_millis(100), stream.read(&mut buf)).await;
+    assert!(early.is_err(), "data delivered before its predecessor");
+
+    send(&mut raw, &[Frame::psh_seq(1, 0, b"first,")]).await;
+    let mut buf = [0u8; 12];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"first,second");
+}
+
+#[tokio::test]
+async fn peers_negotiate_sequenced_push() {
+    let (client, server, mut incoming) = session_pair().await;
+    wait_for("client settings", || server.peer_settings().is_some()).await;
+    assert!(server.peer_settings().unwrap().supports(EXT_PSH_SEQ));
+
+    // 首个 Stream 收到服务端数据时，客户端必然已处理了更早到达的 SERVER_SETTINGS
+    let mut first = client.open_stream().await.unwrap();
+    let mut first_remote = incoming.recv().await.unwrap();
+    first_remote.write_all(b"x").await.unwrap();
+    let mut byte = [0u8; 1];
+    first.read_exact(&mut byte).await.unwrap();
+
+    // 之后新建的 Stream 使用带序号的帧，大于一帧的写入被拆成多帧
+    let payload: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let mut stream = client.open_stream().await.unwrap();
+    let mut remote = incoming.recv().await.unwrap();
+    let expected = payload.clone();
+    let writer = tokio::spawn(async move {
+        stream.write_all(&payload).await.unwrap();
+        stream.flush().await.unwrap();
+        stream
+    });
+    let mut received = vec![0u8; expected.len()];
+    remote.read_exact(&mut received).await.unwrap();
+    assert!(received == expected);
+    writer.await.unwrap();
+}
@@ -0,0 +1,10 @@
+use anytls_rs::util::redact::Redacted;
+
+#[test]
+fn password_hash_is_never_formatted() {
+    let hash = Redacted::new([0xabu8; 32]);
+    assert_eq!(format!("{:?}", hash), "***");
+    assert_eq!(format!("{}", hash), "***");
+    assert_eq!(format!("{:#?}", Some(hash)), "Some(\n    ***,\n)");
+    assert_eq!(hash.expose(), &[0xab; 32]);
+}
@@ -0,0 +1,378 @@
+mod common;
+
+use anytls_rs::proxy::relay::{
+    copy_adaptive, copy_bidirectional_pooled, copy_bidirectional_with_idle_timeout,
+    AdaptiveBuffer, AdaptiveSizes,
+};
+use anytls_rs::util::buffer_pool::BufferPool;
+use common::{ClientProcess, ServerProcess};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::test]
+async fn idle_relay_times_out() {
+    let (mut a, _a_peer) = tokio::io::duplex(1024);
+    let (mut b, _b_peer) = tokio::io::duplex(1024);
+    let started = Instant::now();
+    let err = copy_bidirectional_with_idle_timeout(&mut a, &mut b, Duration::from_millis(100))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn activity_resets_idle_timer() {
+    let (mut a, mut a_peer) = tokio::io::duplex(1024);
+    let (mut b, mut b_peer) = tokio::io::duplex(1024);
+    let relay = tokio::spawn(async move {
+        copy_bidirectional_with_idle_timeout(&mut a, &mut b, Duration::from_millis(150)).await
+    });
+
+    // 总时长超过超时，但每次间隔都更短
+    let mut buf = [0u8; 4];
+    for _ in 0..5 {
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        a_peer.write_all(b"ping").await.unwrap();
+        b_peer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+    drop(a_peer);
+    drop(b_peer);
+    let (up, _) = relay.await.unwrap().unwrap();
+    assert_eq!(up, 20);
+}
+
+#[tokio::test]
+async fn server_reaps_stream_to_stalled_target() {
+    // 目标接受连接后既不读也不写
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let stalled = tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut buf = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), conn.read_to_end(&mut buf))
+            .await
+            .expect("target was not closed")
+            .unwrap();
+        buf
+    });
+
+    let server = ServerProcess::spawn(&["--outbound-idle-timeout", "1"]);
+    let client = ClientProcess::spawn(&server.addr, &[]);
+
+    let mut conn = TcpStream::connect(&client.addr).await.unwrap();
+    conn.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut method = [0u8; 2];
+    conn.read_exact(&mut method).await.unwrap();
+    let mut req = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    conn.write_all(&req).await.unwrap();
+    let mut reply = [0u8; 10];
+    conn.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00);
+    conn.write_all(b"hello").await.unwrap();
+
+    // 目标收到数据后被服务端关闭，客户端一侧也读到 EOF
+    assert_eq!(stalled.await.unwrap(), b"hello");
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), conn.read_to_end(&mut rest))
+        .await
+        .expect("client side was not closed")
+        .unwrap();
+    assert!(rest.is_empty());
+}
+
+/// 经过 pooled relay 把 `total` 字节送到回显端再读回
+async fn pooled_round_trip(pool: &Arc<BufferPool>, total: usize) {
+    let (mut a, a_peer) = tokio::io::duplex(64 * 1024);
+    let (mut b, b_peer) = tokio::io::duplex(64 * 1024);
+    let pool = Arc::clone(pool);
+    let relay = tokio::spawn(async move {
+        copy_bidirectional_pooled(&mut a, &mut b, Duration::ZERO, &pool).await
+    });
+    tokio::spawn(async move {
+        let (mut r, mut w) = tokio::io::split(b_peer);
+        tokio::io::copy(&mut r, &mut w).await.unwrap();
+        w.shutdown().await.unwrap();
+    });
+
+    let payload: Vec<u8> = (0..total).map(|i| (i % 253) as u8).collect();
+    let expected = payload.clone();
+    let (mut a_r, mut a_w) = tokio::io::split(a_peer);
+    let writer = tokio::spawn(async move {
+        a_w.write_all(&payload).await.unwrap();
+        a_w.shutdown().await.unwrap();
+    });
+    let mut echoed = Vec::with_capacity(total);
+    a_r.read_to_end(&mut echoed).await.unwrap();
+    writer.await.unwrap();
+
+    let (up, down) = relay.await.unwrap().unwrap();
+    assert_eq!((up, down), (total as u64, total as u64));
+    assert!(echoed == expected);
+}
+
+#[tokio::test]
+async fn pooled_relay_reuses_buffers() {
+    const TOTAL: usize = 16 * 1024 * 1024;
+    let pool = BufferPool::new(4, 16 * 1024);
+
+    pooled_round_trip(&pool, TOTAL).await;
+    assert_eq!(pool.stats().misses, 2);
+    assert_eq!(pool.stats().idle, 2);
+
+    // 第二次转发复用池中的缓冲区
+    pooled_round_trip(&pool, 1024).await;
+    let stats = pool.stats();
+    assert_eq!((stats.hits, stats.misses, stats.idle), (2, 2, 2));
+}
+
+#[test]
+fn pool_keeps_at_most_capacity_buffers() {
+    let pool = BufferPool::new(1, 8);
+    let a = pool.get();
+    let b = pool.get();
+    assert_eq!(a.len(), 8);
+    drop(a);
+    drop(b);
+    assert_eq!(pool.stats().idle, 1);
+    let _c = pool.get();
+    assert_eq!(pool.stats().hits, 1);
+}
+
+#[test]
+fn adaptive_sizes_validate() {
+    assert!(AdaptiveSizes { min: 4096, max: 65536 }.validate().is_ok());
+    assert!(AdaptiveSizes { min: 0, max: 65536 }.validate().is_err());
+    assert!(AdaptiveSizes { min: 8192, max: 4096 }.validate().is_err());
+}
+
+#[tokio::test]
+async fn adaptive_buffer_grows_under_load() {
+    let sizes = AdaptiveSizes { min: 4096, max: 65536 };
+    let (mut r, mut w) = tokio::io::duplex(256 * 1024);
+    let writer = tokio::spawn(async move {
+        w.write_all(&vec![7u8; 4 * 1024 * 1024]).await.unwrap();
+        w.shutdown().await.unwrap();
+    });
+    let mut buf = AdaptiveBuffer::new(sizes);
+    assert_eq!(buf.size(), sizes.min);
+    let copied = copy_adaptive(&mut r, &mut tokio::io::sink(), &mut buf).await.unwrap();
+    writer.await.unwrap();
+    assert_eq!(copied, 4 * 1024 * 1024);
+    assert!(buf.size() > sizes.min, "buffer stayed at {}", buf.size());
+    assert!(buf.size() <= sizes.max);
+}
+
+#[tokio::test]
+async fn adaptive_buffer_shrinks_when_quiet() {
+    let sizes = AdaptiveSizes { min: 4096, max: 65536 };
+    let (mut r, mut w) = tokio::io::duplex(256 * 1024);
+    let writer = tokio::spawn(async move {
+        w.write_all(&vec![7u8; 1024 * 1024]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        w.shutdown().await.unwrap();
+    });
+    let mut buf = AdaptiveBuffer::new(sizes);
+    copy_adaptive(&mut r, &mut tokio::io::sink(), &mut buf).await.unwrap();
+    writer.await.unwrap();
+    assert_eq!(buf.size(), sizes.min);
+}
+
+#[tokio::test]
+async fn client_close_reaches_target_as_eof() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let target = tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut buf = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), conn.read_to_end(&mut buf))
+            .await
+            .expect("target never saw EOF")
+            .unwrap();
+        buf
+    });
+
+    let server = ServerProcess::spawn(&[]);
+    let client = ClientProcess::spawn(&server.addr, &[]);
+
+    let mut conn = TcpStream::connect(&client.addr).await.unwrap();
+    conn.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut method = [0u8; 2];
+    conn.read_exact(&mut method).await.unwrap();
+    let mut req = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    conn.write_all(&req).await.unwrap();
+    let mut reply = [0u8; 10];
+    conn.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00);
+
+    // 只关闭写方向，连接本身保持打开
+    conn.write_all(b"upload done").await.unwrap();
+    conn.shutdown().await.unwrap();
+    assert_eq!(target.await.unwrap(), b"upload done");
+    drop(conn);
+}
+
+#[tokio::test]
+async fn relay_over_plaintext_transport() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (conn, _) = listener.accept().await.unwrap();
+        let (mut r, mut w) = conn.into_split();
+        tokio::io::copy(&mut r, &mut w).await.unwrap();
+    });
+
+    let server = ServerProcess::spawn(&["--no-tls"]);
+    let client = ClientProcess::spawn(&server.addr, &["--no-tls"]);
+
+    let mut conn = TcpStream::connect(&client.addr).await.unwrap();
+    conn.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut method = [0u8; 2];
+    conn.read_exact(&mut method).await.unwrap();
+    let mut req = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    conn.write_all(&req).await.unwrap();
+    let mut reply = [0u8; 10];
+    conn.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00);
+
+    conn.write_all(b"no tls here").await.unwrap();
+    let mut echoed = [0u8; 11];
+    tokio::time::timeout(Duration::from_secs(5), conn.read_exact(&mut echoed))
+        .await
+        .expect("echo did not arrive")
+        .unwrap();
+    assert_eq!(&echoed, b"no tls here");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn client_connect_relays_to_unix_socket() {
+    use anytls_rs::proxy::padding::PaddingFactory;
+    use anytls_rs::proxy::session::Client;
+    use anytls_rs::proxy::transport::{self, AuthMode};
+
+    let dir = std::env::temp_dir().join(format!("anytls-unix-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("echo.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = conn.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+
+    let server = ServerProcess::spawn(&[
+        "--no-tls",
+        "--allow-unix-socket",
+        path.to_str().unwrap(),
+    ]);
+    let padding = Arc::new(PaddingFactory::default());
+    let password = transport::password_sha256(common::PASSWORD);
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        password,
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let client = Client::builder(dial, padding).build();
+
+    let target = format!("unix:{}", path.display());
+    let mut stream = client.connect(&target).await.unwrap();
+    stream.write_all(b"over a unix socket").await.unwrap();
+    let mut echoed = [0u8; 18];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut echoed))
+        .await
+        .expect("echo did not arrive")
+        .unwrap();
+    assert_eq!(&echoed, b"over a unix socket");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_not_on_the_allowlist_is_refused() {
+    use anytls_rs::proxy::padding::PaddingFactory;
+    use anytls_rs::proxy::session::Client;
+    use anytls_rs::proxy::transport::{self, AuthMode};
+
+    let dir = std::env::temp_dir().join(format!("anytls-unix-deny-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (allowed, denied) = (dir.join("allowed.sock"), dir.join("denied.sock"));
+    let _ = std::fs::remove_file(&denied);
+    let listener = tokio::net::UnixListener::bind(&denied).unwrap();
+
+    let allow = ["--no-tls", "--allow-unix-socket", allowed.to_str().unwrap()];
+    let server = ServerProcess::spawn(&allow);
+    let padding = Arc::new(PaddingFactory::default());
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        transport::password_sha256(common::PASSWORD),
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let client = Client::builder(dial, padding).build();
+
+    // 同一目录下的其他路径与 `..` 绕路都不在列表中
+    let dir_name = dir.file_name().unwrap().to_str().unwrap();
+    let detour = format!("unix:{}/../{}/denied.sock", dir.display(), dir_name);
+    for target in [format!("unix:{}", denied.display()), detour] {
+        let mut stream = client.connect(&target).await.unwrap();
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf))
+            .await
+            .expect("refused stream was not closed");
+        assert!(read.is_err() || buf.is_empty(), "{}", target);
+    }
+    let accepted = tokio::time::timeout(Duration::from_millis(200), listener.accept()).await;
+    assert!(accepted.is_err(), "server connected to an unlisted socket");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn client_connect_timeout_fails_fast_on_black_holed_target() {
+    use anytls_rs::proxy::padding::PaddingFactory;
+    use anytls_rs::proxy::session::Client;
+    use anytls_rs::proxy::transport::{self, AuthMode};
+
+    // backlog 为 0 的监听队列被占满后，新的 SYN 会被丢弃，连接一直停在握手阶段
+    let socket = tokio::net::TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let black_hole = socket.listen(0).unwrap();
+    let target = black_hole.local_addr().unwrap().to_string();
+    let _queued = std::net::TcpStream::connect(&target).unwrap();
+
+    let server = ServerProcess::spawn(&["--no-tls", "--connect-timeout-ms", "30000"]);
+    let padding = Arc::new(PaddingFactory::default());
+    let password = transport::password_sha256(common::PASSWORD);
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        password,
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let client = Client::builder(dial, padding).build();
+
+    let started = Instant::now();
+    let stream = client
+        .connect_with_timeout(&target, Some(Duration::from_millis(300)))
+        .await
+        .unwrap();
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .expect("server ignored the client connect timeout");
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert!(started.elapsed() >= Duration::from_millis(250));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use anytls_rs::util::runtime::{self, RuntimeOptions, MAX_WORKER_THREADS};
+use common::{captured_logs, ServerProcess, PASSWORD};
+
+#[test]
+fn worker_threads_are_validated() {
+    let options = |worker_threads, current_thread| RuntimeOptions {
+        worker_threads,
+        current_thread,
+    };
+    assert!(options(None, false).validate().is_ok());
+    assert!(options(None, true).validate().is_ok());
+    assert!(options(Some(2), false).validate().is_ok());
+    assert!(options(Some(0), false).validate().is_err());
+    assert!(options(Some(MAX_WORKER_THREADS + 1), false).validate().is_err());
+    assert!(options(Some(2), true).validate().is_err());
+
+    assert_eq!(options(Some(3), false).effective_worker_threads(), 3);
+    assert_eq!(options(None, true).effective_worker_threads(), 1);
+    assert!(options(None, false).effective_worker_threads() >= 1);
+}
+
+#[test]
+fn built_runtimes_run_tasks() {
+    for options in [
+        RuntimeOptions { worker_threads: Some(2), current_thread: false },
+        RuntimeOptions { worker_threads: None, current_thread: true },
+    ] {
+        let runtime = options.build().unwrap();
+        assert_eq!(runtime.block_on(async { tokio::spawn(async { 7 }).await.unwrap() }), 7);
+    }
+}
+
+#[tokio::test]
+async fn guarded_task_panics_are_logged_and_counted() {
+    let logs = captured_logs();
+    let before = runtime::panicked_tasks();
+    runtime::spawn_guarded("[Test] Connection 192.0.2.1:9", async {}).await.unwrap();
+    assert_eq!(runtime::panicked_tasks(), before);
+
+    let handle = runtime::spawn_guarded("[Test] Connection 192.0.2.1:10", async {
+        tokio::task::yield_now().await;
+        let empty: Vec<u8> = Vec::new();
+        let _ = empty[1];
+    });
+    // panic 被捕获，任务视为正常结束
+    handle.await.unwrap();
+    assert_eq!(runtime::panicked_tasks(), before + 1);
+    let logs = logs.lines();
+    let logged = logs.iter().find(|line| line.contains("192.0.2.1:10")).expect("panic not logged");
+    assert!(logged.contains("index out of bounds"), "{}", logged);
+}
+
+#[test]
+fn server_rejects_zero_worker_threads() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_anytls-server"))
+        .args(["-l", &common::free_addr(), "-p", PASSWORD, "--worker-threads", "0"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--worker-threads must be between"), "{}", stderr);
+}
+
+#[test]
+fn server_runs_on_a_current_thread_runtime() {
+    let server = ServerProcess::spawn(&["--current-thread"]);
+    assert!(std::net::TcpStream::connect(&server.addr).is_ok());
+}
@@ -0,0 +1,450 @@
+mod common;
+
+use anytls_rs::proxy::addr_codec::{
+    build_socks_addr, build_typed_target_header, SocksAddr, StreamType, ATYP_STREAM_TYPE,
+};
+use anytls_rs::proxy::padding::PaddingFactory;
+use anytls_rs::proxy::session::{Client, Session, SessionConfig};
+use anytls_rs::proxy::transport::{self, AuthMode};
+use anytls_rs::util::tls::TlsClientOptions;
+use common::{wait_for, ServerProcess, PASSWORD};
+use rustls::pki_types::ServerName;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+#[tokio::test]
+async fn server_drops_silent_client_after_auth_timeout() {
+    let server = ServerProcess::spawn(&["--auth-timeout-ms", "300"]);
+
+    let tcp = TcpStream::connect(&server.addr).await.unwrap();
+    let tls_config = transport::create_tls_config(&TlsClientOptions::default()).unwrap();
+    let connector = TlsConnector::from(tls_config);
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut tls = connector.connect(server_name, tcp).await.unwrap();
+
+    let start = Instant::now();
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), tls.read(&mut buf))
+        .await
+        .expect("server kept the silent connection open");
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert!(start.elapsed() >= Duration::from_millis(250));
+}
+
+#[tokio::test]
+async fn server_drops_client_that_never_starts_tls_handshake() {
+    let server = ServerProcess::spawn(&["--tls-handshake-timeout-ms", "300"]);
+
+    let mut tcp = TcpStream::connect(&server.addr).await.unwrap();
+    let start = Instant::now();
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), tcp.read(&mut buf))
+        .await
+        .expect("server kept the connection without a ClientHello open");
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert!(start.elapsed() >= Duration::from_millis(250));
+}
+
+#[tokio::test]
+async fn server_closes_stream_without_target_address() {
+    let server = ServerProcess::spawn(&["--no-tls", "--target-timeout-ms", "300"]);
+    let padding = Arc::new(PaddingFactory::default());
+    let password = transport::password_sha256(PASSWORD);
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        password,
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let conn = dial().await.unwrap();
+    let session = Arc::new(Session::new_client(conn, padding, SessionConfig::default()));
+    session.run().await.unwrap();
+
+    // 打开 Stream 后不发送目标地址
+    let stream = session.open_stream().await.unwrap();
+    let start = Instant::now();
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .expect("server kept the stream open");
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert!(start.elapsed() >= Duration::from_millis(250));
+    assert!(!session.is_closed());
+}
+
+#[tokio::test]
+async fn server_appends_access_log_line_when_stream_finishes() {
+    let path = std::env::temp_dir().join(format!("anytls-access-{}.csv", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let server = ServerProcess::spawn(&["--no-tls", "--access-log", path.to_str().unwrap()]);
+
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = target.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut conn, _) = target.accept().await.unwrap();
+        let mut buf = [0u8; 5];
+        conn.read_exact(&mut buf).await.unwrap();
+        conn.write_all(b"pong!!").await.unwrap();
+    });
+
+    let padding = Arc::new(PaddingFactory::default());
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        transport::password_sha256(PASSWORD),
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let conn = dial().await.unwrap();
+    let session = Arc::new(Session::new_client(conn, padding, SessionConfig::default()));
+    session.run().await.unwrap();
+    let mut stream = session.open_stream().await.unwrap();
+    let header = build_socks_addr(&SocksAddr::parse_target(&target_addr).unwrap()).unwrap();
+    stream.write_all(&header).await.unwrap();
+    stream.write_all(b"ping!").await.unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"pong!!");
+    drop(stream);
+
+    let read_log = || std::fs::read_to_string(&path).unwrap_or_default();
+    wait_for("access log line", || read_log().lines().count() == 2).await;
+    let log = read_log();
+    let mut lines = log.lines();
+    assert_eq!(
+        lines.next(),
+        Some("timestamp,target,bytes_up,bytes_down,duration_ms,close_reason")
+    );
+    let fields: Vec<&str> = lines.next().unwrap().split(',').collect();
+    assert_eq!(fields.len(), 6);
+    assert!(fields[0].parse::<f64>().is_ok());
+    assert_eq!(fields[1], target_addr);
+    assert_eq!(fields[2], (header.len() + 5).to_string());
+    assert_eq!(fields[3], "6");
+    assert!(fields[4].parse::<u64>().is_ok());
+    assert_eq!(fields[5], "done");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn stream_type_prefix_selects_tcp_or_udp_relay() {
+    let server = ServerProcess::spawn(&["--no-tls"]);
+    let padding = Arc::new(PaddingFactory::default());
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        transport::password_sha256(PASSWORD),
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let client = Client::builder(dial, padding).build();
+
+    // UDP：数据报原样回显
+    let udp_target = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let udp_addr = udp_target.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1500];
+        loop {
+            let (n, from) = udp_target.recv_from(&mut buf).await.unwrap();
+            udp_target.send_to(&buf[..n], from).await.unwrap();
+        }
+    });
+    let mut udp = client.connect_udp(&udp_addr).await.unwrap();
+    for datagram in [&b"ping"[..], b"second datagram"] {
+        udp.write_u16(datagram.len() as u16).await.unwrap();
+        udp.write_all(datagram).await.unwrap();
+        let len = udp.read_u16().await.unwrap() as usize;
+        let mut reply = vec![0u8; len];
+        udp.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, datagram);
+    }
+
+    // 显式的 TCP 类型与不带前缀时相同，连接 TCP 目标
+    let tcp_target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tcp_addr = SocksAddr::parse_target(&tcp_target.local_addr().unwrap().to_string()).unwrap();
+    let mut tcp = client.create_stream().await.unwrap();
+    let header = build_typed_target_header(StreamType::Tcp, &tcp_addr, None).unwrap();
+    tcp.write_all(&[&[ATYP_STREAM_TYPE, 0x01][..], &header, b"hello"].concat()).await.unwrap();
+    let (mut conn, _) = tcp_target.accept().await.unwrap();
+    let mut buf = [0u8; 5];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn stream_workers_bound_concurrent_streams() {
+    let server = ServerProcess::spawn(&["--no-tls", "--stream-workers", "2"]);
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = target.local_addr().unwrap().to_string();
+
+    let padding = Arc::new(PaddingFactory::default());
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        transport::password_sha256(PASSWORD),
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let conn = dial().await.unwrap();
+    let session = Arc::new(Session::new_client(conn, padding, SessionConfig::default()));
+    session.run().await.unwrap();
+    let header = build_socks_addr(&SocksAddr::parse_target(&target_addr).unwrap()).unwrap();
+    let mut streams = Vec::new();
+    for _ in 0..5 {
+        let mut stream = session.open_stream().await.unwrap();
+        stream.write_all(&header).await.unwrap();
+        streams.push(stream);
+    }
+
+    // 两个名额都被占用，其余 Stream 留在队列中，不会连接目标
+    let accept = || tokio::time::timeout(Duration::from_millis(500), target.accept());
+    let mut held = vec![accept().await.unwrap().unwrap().0, accept().await.unwrap().unwrap().0];
+    assert!(accept().await.is_err(), "a third stream was relayed concurrently");
+
+    // 结束一个转发后下一个 Stream 才开始
+    drop(held.remove(0));
+    held.push(accept().await.unwrap().unwrap().0);
+    assert!(accept().await.is_err(), "a fourth stream was relayed concurrently");
+}
+
+#[tokio::test]
+async fn server_writes_its_pid_to_pidfile() {
+    let path = std::env::temp_dir().join(format!("anytls-server-test-{}.pid", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let server = ServerProcess::spawn(&["--pidfile", path.to_str().unwrap()]);
+
+    wait_for("pidfile", || path.exists()).await;
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(contents.trim(), server.pid().to_string());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn unknown_user_fails_startup() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_anytls-server"))
+        .args(["-l", &common::free_addr(), "-p", PASSWORD])
+        .args(["--user", "anytls-no-such-user"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unknown user anytls-no-such-user"), "{}", stderr);
+}
+
+#[test]
+fn no_tls_refuses_non_loopback_listen() {
+    let port = common::free_addr().rsplit(':').next().unwrap().to_string();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_anytls-server"))
+        .args(["-l", &format!("0.0.0.0:{}", port), "-p", PASSWORD, "--no-tls"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("loopback-only"), "{}", stderr);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn server_accepts_on_inherited_listener_fd() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = ServerProcess::spawn_with_listener(&listener);
+    drop(listener);
+
+    // 连接在服务端启动前就能进入内核队列，握手成功说明服务端在该 fd 上 accept
+    let tcp = TcpStream::connect(&server.addr).await.unwrap();
+    let connector = TlsConnector::from(
+        transport::create_tls_config(&TlsClientOptions::default()).unwrap(),
+    );
+    let handshake = connector.connect(ServerName::try_from("localhost").unwrap(), tcp);
+    tokio::time::timeout(Duration::from_secs(10), handshake)
+        .await
+        .expect("server never accepted on the inherited fd")
+        .unwrap();
+}
+
+/// 向健康检查端点发送 `GET path`，返回状态行；端点尚未监听时重试
+async fn http_status(addr: &str, path: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut conn = loop {
+        match TcpStream::connect(addr).await {
+            Ok(conn) => break conn,
+            Err(e) if Instant::now() > deadline => panic!("health endpoint not reachable: {}", e),
+            Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+        }
+    };
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    conn.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).await.unwrap();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sigusr1_enters_lame_duck() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    let health = common::free_addr();
+    let mut server = ServerProcess::spawn(&["--no-tls", "--health-listen", &health]);
+    assert_eq!(http_status(&health, "/readyz").await, "HTTP/1.1 200 OK");
+
+    let padding = Arc::new(PaddingFactory::default());
+    let password = transport::password_sha256(PASSWORD);
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        password,
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let conn = dial().await.unwrap();
+    let session = Arc::new(Session::new_client(conn, padding, SessionConfig::default()));
+    session.run().await.unwrap();
+    // 往返一次，确保服务端已登记该 Session，否则 lame duck 会立即排空退出
+    session.heartbeat_probe(Duration::from_secs(2)).await.unwrap();
+
+    kill(Pid::from_raw(server.pid() as i32), Signal::SIGUSR1).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while http_status(&health, "/readyz").await != "HTTP/1.1 503 Service Unavailable" {
+        assert!(Instant::now() < deadline, "readiness did not change");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(http_status(&health, "/healthz").await, "HTTP/1.1 200 OK");
+
+    // 新连接被拒绝，已建立的 Session 继续服务
+    while TcpStream::connect(&server.addr).await.is_ok() {
+        assert!(Instant::now() < deadline, "listener still accepting");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    session.heartbeat_probe(Duration::from_secs(2)).await.unwrap();
+    assert!(server.try_wait().is_none());
+
+    // 最后一个 Session 关闭后进程自行退出
+    session.close().await.unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        if let Some(status) = server.try_wait() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "server did not exit after draining");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    assert!(status.success(), "{:?}", status);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sigterm_ends_lame_duck_with_sessions_still_open() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    let mut server = ServerProcess::spawn(&["--no-tls"]);
+    let padding = Arc::new(PaddingFactory::default());
+    let dial = transport::create_plain_dial_out_func(
+        server.addr.clone(),
+        transport::password_sha256(PASSWORD),
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let conn = dial().await.unwrap();
+    let session = Arc::new(Session::new_client(conn, padding, SessionConfig::default()));
+    session.run().await.unwrap();
+    session.heartbeat_probe(Duration::from_secs(2)).await.unwrap();
+
+    let pid = Pid::from_raw(server.pid() as i32);
+    kill(pid, Signal::SIGUSR1).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while TcpStream::connect(&server.addr).await.is_ok() {
+        assert!(Instant::now() < deadline, "listener still accepting");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    kill(pid, Signal::SIGTERM).unwrap();
+    let status = loop {
+        if let Some(status) = server.try_wait() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "server ignored SIGTERM in lame duck");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    assert!(status.success(), "{:?}", status);
+    wait_for("client session to see the server exit", || session.is_closed()).await;
+}
+
+#[tokio::test]
+async fn upstream_relays_streams_through_a_second_server() {
+    let path = std::env::temp_dir().join(format!("anytls-upstream-{}.csv", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let upstream = ServerProcess::spawn(&["--access-log", path.to_str().unwrap()]);
+    let relay = ServerProcess::spawn(&["--no-tls", "--upstream", &upstream.addr]);
+
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = target.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut conn, _) = target.accept().await.unwrap();
+        let mut buf = [0u8; 5];
+        conn.read_exact(&mut buf).await.unwrap();
+        conn.write_all(b"pong!!").await.unwrap();
+    });
+
+    let padding = Arc::new(PaddingFactory::default());
+    let dial = transport::create_plain_dial_out_func(
+        relay.addr.clone(),
+        transport::password_sha256(PASSWORD),
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let client = Client::builder(dial, padding).build();
+    let mut stream = client.connect(&target_addr).await.unwrap();
+    stream.write_all(b"ping!").await.unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"pong!!");
+    drop(stream);
+
+    // 目标连接由上游服务端建立
+    let read_log = || std::fs::read_to_string(&path).unwrap_or_default();
+    wait_for("upstream access log line", || read_log().lines().count() == 2).await;
+    let log = read_log();
+    let fields: Vec<&str> = log.lines().nth(1).unwrap().split(',').collect();
+    assert_eq!(fields[1], target_addr);
+    assert_eq!(fields[5], "done");
+    client.close().await.unwrap();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn upstream_trust_system_roots_refuses_an_untrusted_exit_certificate() {
+    // 子进程继承 SSL_CERT_FILE，“系统”证书库中只有一个无关的证书，出口的自签名证书不受信任
+    let roots = rcgen::generate_simple_self_signed(vec!["unrelated.example".to_string()]).unwrap();
+    let roots_path = std::env::temp_dir().join(format!("anytls-roots-{}.pem", std::process::id()));
+    std::fs::write(&roots_path, roots.cert.pem()).unwrap();
+    std::env::remove_var("SSL_CERT_DIR");
+    std::env::set_var("SSL_CERT_FILE", &roots_path);
+
+    let upstream = ServerProcess::spawn(&[]);
+    let relay_args = ["--no-tls", "--upstream", &upstream.addr, "--upstream-trust-system-roots"];
+    let relay = ServerProcess::spawn(&relay_args);
+
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = target.local_addr().unwrap().to_string();
+    let padding = Arc::new(PaddingFactory::default());
+    let dial = transport::create_plain_dial_out_func(
+        relay.addr.clone(),
+        transport::password_sha256(PASSWORD),
+        Arc::clone(&padding),
+        AuthMode::Legacy,
+    );
+    let client = Client::builder(dial, padding).build();
+    let mut stream = client.connect(&target_addr).await.unwrap();
+    stream.write_all(b"ping!").await.unwrap();
+    let mut reply = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut reply))
+        .await
+        .expect("stream was not closed");
+    assert!(read.is_err() || reply.is_empty());
+    let accepted = tokio::time::timeout(Duration::from_millis(200), target.accept()).await;
+    assert!(accepted.is_err(), "relay reached the target through an untrusted exit");
+    client.close().await.unwrap();
+    let _ = std::fs::remove_file(&roots_path);
+}
@@ -0,0 +1,1499 @@
+mod common;
+
+use anytls_rs::proxy::padding::{decompress_scheme, PaddingFactory, SCHEME_DEFLATE_MARK};
+use anytls_rs::proxy::protocol::ClientSettings;
+use anytls_rs::proxy::session::{
+    Frame, FrameCodec, Priority, Role, Session, SessionConfig, Stream, CMD_ALERT, CMD_FIN,
+    CMD_HEART_REQUEST, CMD_HEART_RESPONSE, CMD_PSH, CMD_SERVER_SETTINGS, CMD_SETTINGS, CMD_SYN,
+    CMD_SYNACK, CMD_UPDATE_PADDING_SCHEME, CMD_WASTE, HEADER_OVERHEAD_SIZE, SEQ_PREFIX_SIZE,
+};
+use bytes::{Bytes, BytesMut};
+use common::{captured_logs, session_pair, session_pair_with, wait_for};
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio_util::codec::Decoder;
+
+#[tokio::test]
+async fn stream_is_closed_right_after_peer_fin() {
+    let (client, _server, mut incoming) = session_pair().await;
+    let mut stream = client.open_stream().await.unwrap();
+    let mut remote = incoming.recv().await.unwrap();
+
+    remote.write_all(b"bye").await.unwrap();
+    remote.shutdown().await.unwrap();
+
+    wait_for("FIN to be processed", || stream.is_closed()).await;
+    assert_eq!(client.stream_count(), 0);
+
+    // 关闭前已到达的数据仍可读出，随后是 EOF
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"bye");
+
+    let err = stream.write_all(b"late").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+}
+
+#[tokio::test]
+async fn session_close_marks_streams_closed() {
+    let (client, server, mut incoming) = session_pair().await;
+    let stream = client.open_stream().await.unwrap();
+    let remote = incoming.recv().await.unwrap();
+
+    client.close().await.unwrap();
+    assert!(stream.is_closed());
+
+    // 对端读到 EOF 后也会关闭自身 Session
+    wait_for("server session to close", || server.is_closed()).await;
+    assert!(remote.is_closed());
+}
+
+#[test]
+fn dropping_a_running_session_without_close_closes_its_streams() {
+    let logs = captured_logs();
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let (stream, id) = runtime.block_on(async {
+        let (client, _server, mut incoming) = session_pair().await;
+        let stream = client.open_stream().await.unwrap();
+        incoming.recv().await.unwrap();
+        (stream, client.id())
+    });
+    assert!(!stream.is_closed());
+
+    // 关闭运行时会中止读写任务，它们持有的最后一个 Session 引用随之释放
+    drop(runtime);
+    assert!(stream.is_closed());
+    let expected = format!("Session {} (Client) dropped without close, open streams: 1", id);
+    let logs = logs.lines();
+    assert!(logs.iter().any(|line| line.contains(&expected)), "missing log in {:?}", logs);
+}
+
+#[tokio::test]
+async fn peer_closing_the_transport_cleanly_logs_no_error() {
+    let logs = captured_logs();
+    let (client_end, mut peer) = tokio::io::duplex(64 * 1024);
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    client.run().await.unwrap();
+    let stream = client.open_stream().await.unwrap();
+
+    // 对端发完一个完整的帧后关闭连接
+    peer.write_all(&Frame::new(CMD_FIN, stream.id()).to_bytes()).await.unwrap();
+    peer.shutdown().await.unwrap();
+    drop(peer);
+    wait_for("session to close", || client.is_closed()).await;
+    assert!(stream.is_closed());
+
+    let prefix = format!("Session {} ", client.id());
+    let logs = logs.lines();
+    assert!(!logs.iter().any(|line| line.contains(&prefix)), "unexpected logs {:?}", logs);
+}
+
+#[tokio::test]
+async fn reset_stream_surfaces_an_error_instead_of_eof() {
+    let (client, server, mut incoming) = session_pair().await;
+    let mut stream = client.open_stream().await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut remote = incoming.recv().await.unwrap();
+    let mut buf = [0u8; 5];
+    remote.read_exact(&mut buf).await.unwrap();
+
+    // 本端还有未读出的数据，重置后不再交付
+    remote.write_all(b"unread").await.unwrap();
+    remote.flush().await.unwrap();
+    wait_for("data to be buffered", || stream.buffered_bytes() == 6).await;
+    stream.reset("bad upstream");
+    let err = stream.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    assert_eq!(stream.buffered_bytes(), 0);
+    assert_eq!(stream.write(b"late").await.unwrap_err().kind(), ErrorKind::ConnectionReset);
+
+    wait_for("peer to see the reset", || remote.is_closed()).await;
+    let err = remote.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    assert_eq!(err.to_string(), "stream reset by peer: bad upstream");
+    wait_for("both ends to drop the stream", || {
+        client.stream_count() == 0 && server.stream_count() == 0
+    })
+    .await;
+
+    // 通过 Session 重置同样对两端生效
+    let mut stream = client.open_stream().await.unwrap();
+    stream.write_all(b"x").await.unwrap();
+    let remote = incoming.recv().await.unwrap();
+    server.reset_stream(remote.id(), "").await.unwrap();
+    assert_eq!(remote.read(&mut buf).await.unwrap_err().to_string(), "stream reset");
+    let err = stream.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    assert!(!client.is_closed() && !server.is_closed());
+}
+
+#[tokio::test]
+async fn syns_beyond_accept_backlog_are_rejected() {
+    let (client_end, server_end) = tokio::io::duplex(256 * 1024);
+    let padding = Arc::new(PaddingFactory::default());
+    let server = Arc::new(
+        Session::new_server(
+            Box::new(server_end),
+            None,
+            None,
+            Arc::clone(&padding),
+            SessionConfig { accept_backlog: Some(2), ..SessionConfig::default() },
+        ),
+    );
+    let mut incoming = server.incoming().unwrap();
+    assert!(server.incoming().is_none());
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        padding,
+        SessionConfig::default(),
+    ));
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+
+    // 服务端不取出任何 Stream，队列只能容纳 2 个
+    let mut streams = Vec::new();
+    for _ in 0..6 {
+        streams.push(client.open_stream().await.unwrap());
+    }
+
+    wait_for("excess streams to be rejected", || client.stream_count() == 2).await;
+    assert_eq!(server.stream_count(), 2);
+    assert_eq!(streams.iter().filter(|s| s.is_closed()).count(), 4);
+    assert!(!client.is_closed());
+
+    let accepted = [incoming.recv().await.unwrap(), incoming.recv().await.unwrap()];
+    assert_eq!([accepted[0].id(), accepted[1].id()], [streams[0].id(), streams[1].id()]);
+}
+
+#[tokio::test]
+async fn syn_flood_beyond_rate_is_rejected() {
+    let config = SessionConfig { max_syn_rate: 5, ..SessionConfig::default() };
+    let (client, server, mut incoming) = session_pair_with(config).await;
+
+    // 首个 Stream 往返一次，之后的 Stream 等待 SYNACK
+    let mut first = client.open_stream().await.unwrap();
+    let mut first_remote = incoming.recv().await.unwrap();
+    first_remote.write_all(b"x").await.unwrap();
+    first.read_exact(&mut [0u8; 1]).await.unwrap();
+
+    let (mut accepted, mut rejected) = (0, 0);
+    for _ in 0..50 {
+        match client.open_stream().await {
+            Ok(mut stream) => {
+                accepted += 1;
+                let _ = stream.shutdown().await;
+            }
+            Err(e) => {
+                assert_eq!(e.to_string(), "remote: stream open rate exceeded");
+                rejected += 1;
+            }
+        }
+    }
+    assert!((1..=6).contains(&accepted), "accepted {} streams", accepted);
+    assert_eq!(accepted + rejected, 50);
+    assert!(!client.is_closed() && !server.is_closed());
+
+    // 令牌按速率补充
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    client.open_stream().await.unwrap();
+}
+
+#[tokio::test]
+async fn stream_ids_match_on_both_ends() {
+    let (client, _server, mut incoming) = session_pair().await;
+    let first = client.open_stream().await.unwrap();
+    let second = client.open_stream().await.unwrap();
+    // 客户端使用奇数 id
+    assert_eq!((first.id(), second.id()), (1, 3));
+    assert_eq!(incoming.recv().await.unwrap().id(), first.id());
+    assert_eq!(incoming.recv().await.unwrap().id(), second.id());
+}
+
+#[tokio::test]
+async fn stream_ids_skip_streams_still_open_after_wraparound() {
+    let (client, server, mut incoming) = session_pair().await;
+    let mut low = client.open_stream().await.unwrap();
+    let mut low_remote = incoming.recv().await.unwrap();
+    assert_eq!(low.id(), 1);
+
+    client.set_next_stream_id(u32::MAX);
+    let last = client.open_stream().await.unwrap();
+    let mut wrapped = client.open_stream().await.unwrap();
+    // 回绕后 1 仍在使用，跳到下一个奇数
+    assert_eq!((last.id(), wrapped.id()), (u32::MAX, 3));
+    assert_eq!(incoming.recv().await.unwrap().id(), u32::MAX);
+    let mut wrapped_remote = incoming.recv().await.unwrap();
+    assert_eq!(wrapped_remote.id(), 3);
+
+    // 两个低位 Stream 的数据互不混淆
+    low.write_all(b"low").await.unwrap();
+    wrapped.write_all(b"wrapped").await.unwrap();
+    let mut buf = [0u8; 7];
+    low_remote.read_exact(&mut buf[..3]).await.unwrap();
+    assert_eq!(&buf[..3], b"low");
+    wrapped_remote.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"wrapped");
+
+    // 服务端打开的 Stream 使用偶数 id
+    assert_eq!(server.open_stream().await.unwrap().id(), 2);
+}
+
+#[tokio::test]
+async fn rejected_syn_error_reaches_the_opener() {
+    let (client_end, server_end) = tokio::io::duplex(256 * 1024);
+    let padding = Arc::new(PaddingFactory::default());
+    let server = Arc::new(
+        Session::new_server(
+            Box::new(server_end),
+            None,
+            None,
+            Arc::clone(&padding),
+            SessionConfig { accept_backlog: Some(1), ..SessionConfig::default() },
+        ),
+    );
+    let mut incoming = server.incoming().unwrap();
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        padding,
+        SessionConfig::default(),
+    ));
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+
+    // 首个 Stream 收到数据时客户端已知道服务端会回复 SYNACK
+    let mut first = client.open_stream().await.unwrap();
+    let mut first_remote = incoming.recv().await.unwrap();
+    first_remote.write_all(b"x").await.unwrap();
+    let mut byte = [0u8; 1];
+    first.read_exact(&mut byte).await.unwrap();
+    first.established().await.unwrap();
+
+    // 第二个占满队列，第三个被拒绝
+    let _queued = client.open_stream().await.unwrap();
+    let err = client.open_stream().await.unwrap_err();
+    assert_eq!(err.to_string(), "remote: accept backlog full");
+    assert!(!client.is_closed());
+}
+
+#[tokio::test]
+async fn closed_future_resolves_on_peer_fin() {
+    let (client, _server, mut incoming) = session_pair().await;
+    let stream = client.open_stream().await.unwrap();
+    let mut remote = incoming.recv().await.unwrap();
+
+    let closed = stream.closed();
+    let (_r, _w) = stream.split();
+    let waiter = tokio::spawn(closed);
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiter.is_finished());
+
+    remote.shutdown().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(2), waiter)
+        .await
+        .expect("closed() did not resolve after FIN")
+        .unwrap();
+}
+
+/// 记录所有写入字节、写入与 flush 次数的传输层，读端永远挂起
+#[derive(Clone, Default)]
+struct RecordingIo {
+    written: Arc<Mutex<Vec<u8>>>,
+    writes: Arc<AtomicUsize>,
+    flushes: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for RecordingIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for RecordingIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        self.writes.fetch_add(1, Ordering::AcqRel);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.flushes.fetch_add(1, Ordering::AcqRel);
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl RecordingIo {
+    fn contains(&self, needle: &[u8]) -> bool {
+        let written = self.written.lock().unwrap();
+        written.windows(needle.len()).any(|w| w == needle)
+    }
+}
+
+#[tokio::test]
+async fn frame_groups_are_never_interleaved() {
+    let io = RecordingIo::default();
+    let session = Arc::new(Session::new_client(
+        Box::new(io.clone()),
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    session.run().await.unwrap();
+
+    let mut tasks = Vec::new();
+    for task in 0..4u8 {
+        let mut stream = session.open_stream().await.unwrap();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..50 {
+                stream.write_all(b"noise").await.unwrap();
+            }
+        }));
+        let session = Arc::clone(&session);
+        tasks.push(tokio::spawn(async move {
+            for group in 0..50u8 {
+                let frames = (0..3u8)
+                    .map(|i| Frame::with_data(CMD_PSH, 100, Bytes::from(vec![task, group, i])))
+                    .collect();
+                session.write_frames(frames).await.unwrap();
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    session.flush().await.unwrap();
+
+    // 去掉 padding 产生的 CMD_WASTE 后，每组的三帧必须相邻
+    let frames: Vec<_> = recorded_frames(&io, 0)
+        .into_iter()
+        .filter(|f| f.cmd != CMD_WASTE)
+        .collect();
+    let mut groups = 0;
+    for (i, frame) in frames.iter().enumerate() {
+        if frame.sid == 100 && frame.data[2] == 0 {
+            for k in 1..3 {
+                let next = &frames[i + k];
+                assert_eq!(next.sid, 100);
+                assert_eq!(next.data[..], [frame.data[0], frame.data[1], k as u8]);
+            }
+            groups += 1;
+        }
+    }
+    assert_eq!(groups, 200);
+}
+
+/// 解码 RecordingIo 从 `offset` 起记录的帧
+fn recorded_frames(io: &RecordingIo, offset: usize) -> Vec<Frame> {
+    decode_frames(&io.written.lock().unwrap()[offset..])
+}
+
+fn decode_frames(bytes: &[u8]) -> Vec<Frame> {
+    let mut wire = BytesMut::from(bytes);
+    let mut frames = Vec::new();
+    while let Some(frame) = FrameCodec.decode(&mut wire).unwrap() {
+        frames.push(frame);
+    }
+    frames
+}
+
+#[tokio::test]
+async fn set_padding_applies_to_a_live_session() {
+    let io = RecordingIo::default();
+    let none = Arc::new(PaddingFactory::new(b"stop=0").unwrap());
+    let session = Arc::new(Session::new_client(
+        Box::new(io.clone()),
+        none,
+        SessionConfig::default(),
+    ));
+    session.run().await.unwrap();
+    let mut stream = session.open_stream().await.unwrap();
+    stream.write_all(b"before").await.unwrap();
+    stream.flush().await.unwrap();
+    assert!(recorded_frames(&io, 0).iter().all(|f| f.cmd != CMD_WASTE));
+
+    let scheme: String = std::iter::once("stop=100".to_string())
+        .chain((0..100).map(|pkt| format!("\n{}=400-400", pkt)))
+        .collect();
+    let padded = Arc::new(PaddingFactory::new(scheme.as_bytes()).unwrap());
+    session.set_padding(Arc::clone(&padded)).await.unwrap();
+    assert_eq!(session.padding().md5(), padded.md5());
+
+    let offset = io.written.lock().unwrap().len();
+    stream.write_all(b"after").await.unwrap();
+    stream.flush().await.unwrap();
+    let frames = recorded_frames(&io, offset);
+    assert_eq!(frames[0].data[..], b"after"[..]);
+    assert!(frames.iter().any(|f| f.cmd == CMD_WASTE));
+}
+
+#[tokio::test]
+async fn padding_stats_count_waste_bytes() {
+    let io = RecordingIo::default();
+    // 固定长度的记录，填充量与随机数无关
+    let padding = Arc::new(PaddingFactory::new(b"stop=2\n0=1000-1000\n1=500-500").unwrap());
+    let session = Arc::new(Session::new_client(
+        Box::new(io.clone()),
+        padding,
+        SessionConfig::default(),
+    ));
+    session.run().await.unwrap();
+    let offset = io.written.lock().unwrap().len();
+
+    for len in [100, 200, 300] {
+        session.write_data_frame(1, &vec![0u8; len]).await.unwrap();
+        session.flush().await.unwrap();
+    }
+
+    // 包 0：107 字节负载补足到 1000；包 1：207 字节补足到 500；包 2 已停止填充
+    let stats = session.padding_stats();
+    assert_eq!(stats.payload_bytes, 107 + 207);
+    assert_eq!(stats.padding_bytes, (1000 - 107) + (500 - 207));
+    assert!((stats.amplification() - 1500.0 / 314.0).abs() < 1e-9);
+
+    let waste: usize = recorded_frames(&io, offset)
+        .iter()
+        .filter(|f| f.cmd == CMD_WASTE)
+        .map(|f| f.data.len() + HEADER_OVERHEAD_SIZE)
+        .sum();
+    assert_eq!(waste as u64, stats.padding_bytes);
+}
+
+#[tokio::test]
+async fn padding_resumes_after_idle_gap() {
+    let io = RecordingIo::default();
+    let scheme = Arc::new(PaddingFactory::new(b"stop=3\n0=400-400\n1=400-400\n2=400-400").unwrap());
+    let session = Arc::new(
+        Session::new_client(Box::new(io.clone()), scheme, SessionConfig::default())
+            .with_padding_idle_reset(Duration::from_millis(100)),
+    );
+    session.run().await.unwrap();
+    let mut stream = session.open_stream().await.unwrap();
+    for chunk in [&b"one"[..], b"two", b"three"] {
+        stream.write_all(chunk).await.unwrap();
+        stream.flush().await.unwrap();
+    }
+    // SETTINGS、SYN 与 "one" 用完 stop=3，之后的帧不再填充
+    let offset = io.written.lock().unwrap().len();
+    stream.write_all(b"four").await.unwrap();
+    stream.flush().await.unwrap();
+    assert!(recorded_frames(&io, offset).iter().all(|f| f.cmd != CMD_WASTE));
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let offset = io.written.lock().unwrap().len();
+    stream.write_all(b"burst").await.unwrap();
+    stream.flush().await.unwrap();
+    let frames = recorded_frames(&io, offset);
+    assert_eq!(frames[0].data[..], b"burst"[..]);
+    assert!(frames.iter().any(|f| f.cmd == CMD_WASTE));
+}
+
+#[tokio::test]
+async fn server_set_padding_pushes_scheme_to_client() {
+    let io = RecordingIo::default();
+    let server = Arc::new(Session::new_server(
+        Box::new(io.clone()),
+        None,
+        None,
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    server.run().await.unwrap();
+    let scheme = Arc::new(PaddingFactory::new(b"stop=2\n0=10-20\n1=30-40").unwrap());
+    server.set_padding(Arc::clone(&scheme)).await.unwrap();
+    server.flush().await.unwrap();
+
+    let frames = recorded_frames(&io, 0);
+    let update = frames
+        .iter()
+        .find(|f| f.cmd == CMD_UPDATE_PADDING_SCHEME)
+        .expect("no padding update sent");
+    assert_eq!(update.data, scheme.raw_scheme);
+}
+
+#[tokio::test]
+async fn client_adopts_pushed_padding_scheme_only_within_its_limit() {
+    let config = SessionConfig { max_padding_bytes: 2000, ..SessionConfig::default() };
+    let (client, server, _incoming) = session_pair_with(config).await;
+
+    // 1=300-1500,c,400-500 最多 2000 字节，恰好不超过上限
+    let within = Arc::new(PaddingFactory::new(b"stop=2\n0=30-30\n1=300-1500,c,400-500").unwrap());
+    assert_eq!(within.max_packet_bytes(), 2000);
+    server.set_padding(Arc::clone(&within)).await.unwrap();
+    wait_for("client to adopt the scheme", || {
+        client.padding().raw_scheme == within.raw_scheme
+    })
+    .await;
+
+    // 超过上限的方案被忽略，Session 保持打开，继续使用之前的方案
+    let huge = Arc::new(PaddingFactory::new(b"stop=1\n0=60000-65000,60000-65000").unwrap());
+    server.set_padding(huge).await.unwrap();
+    server.flush().await.unwrap();
+    client.heartbeat_probe(Duration::from_secs(2)).await.unwrap();
+    assert!(!client.is_closed() && !server.is_closed());
+    assert_eq!(client.padding().raw_scheme, within.raw_scheme);
+}
+
+/// 有 `stop` 个包的长方案，之后每个包最多 1400 字节
+fn large_scheme(stop: u32, first_packet: &str) -> Vec<u8> {
+    let mut raw = format!("stop={}\n0={}", stop, first_packet).into_bytes();
+    for pkt in 1..stop {
+        raw.extend_from_slice(format!("\n{}=100-400,c,500-1000", pkt).as_bytes());
+    }
+    raw
+}
+
+#[tokio::test]
+async fn server_compresses_padding_scheme_for_clients_that_support_it() {
+    let (mut peer, server_end) = tokio::io::duplex(256 * 1024);
+    let scheme = Arc::new(PaddingFactory::new(&large_scheme(300, "30-30")).unwrap());
+    let server = Arc::new(
+        Session::new_server(
+            Box::new(server_end),
+            None,
+            None,
+            Arc::clone(&scheme),
+            SessionConfig::default(),
+        )
+        .with_compress_padding(true),
+    );
+    server.run().await.unwrap();
+
+    // 客户端的方案与服务端不同，服务端随即下发自己的方案
+    let settings = ClientSettings::new("test", "stale-md5").encode();
+    let frames = exchange(&mut peer, &[Frame::with_data(CMD_SETTINGS, 0, settings)]).await;
+    let update = frames
+        .iter()
+        .find(|f| f.cmd == CMD_UPDATE_PADDING_SCHEME)
+        .expect("no padding update sent");
+    assert_eq!(update.data[0], SCHEME_DEFLATE_MARK);
+    assert!(update.data.len() * 4 < scheme.raw_scheme.len());
+    assert_eq!(decompress_scheme(update.data.clone()).unwrap(), scheme.raw_scheme);
+}
+
+#[tokio::test]
+async fn client_decompresses_pushed_padding_scheme() {
+    let config = SessionConfig {
+        compress_padding: true,
+        max_padding_bytes: 2000,
+        ..SessionConfig::default()
+    };
+    let (client, server, _incoming) = session_pair_with(config).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let within = Arc::new(PaddingFactory::new(&large_scheme(300, "30-30")).unwrap());
+    server.set_padding(Arc::clone(&within)).await.unwrap();
+    wait_for("client to adopt the scheme", || {
+        client.padding().raw_scheme == within.raw_scheme
+    })
+    .await;
+
+    // 解压后才能看出第一个包超过上限
+    let huge = Arc::new(PaddingFactory::new(&large_scheme(300, "60000-65000")).unwrap());
+    server.set_padding(huge).await.unwrap();
+    server.flush().await.unwrap();
+    client.heartbeat_probe(Duration::from_secs(2)).await.unwrap();
+    assert!(!client.is_closed() && !server.is_closed());
+    assert_eq!(client.padding().raw_scheme, within.raw_scheme);
+}
+
+#[tokio::test]
+async fn pushed_padding_scheme_resumes_padding_after_stop() {
+    let (mut peer, client_end) = tokio::io::duplex(256 * 1024);
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        Arc::new(PaddingFactory::new(b"stop=1\n0=30-30").unwrap()),
+        SessionConfig::default(),
+    ));
+    client.run().await.unwrap();
+
+    // SETTINGS 用完 stop=1，之后的数据不再填充
+    exchange(&mut peer, &[]).await;
+    client.write_data_frame(1, b"before").await.unwrap();
+    client.flush().await.unwrap();
+    let frames = exchange(&mut peer, &[]).await;
+    assert!(frames.iter().all(|f| f.cmd != CMD_WASTE));
+
+    let scheme = Arc::new(PaddingFactory::new(&large_scheme(300, "30-30")).unwrap());
+    let update = Frame::with_data(CMD_UPDATE_PADDING_SCHEME, 0, scheme.raw_scheme.clone());
+    exchange(&mut peer, &[update]).await;
+    assert_eq!(client.padding().raw_scheme, scheme.raw_scheme);
+
+    client.write_data_frame(1, b"after").await.unwrap();
+    client.flush().await.unwrap();
+    let frames = exchange(&mut peer, &[]).await;
+    assert!(frames.iter().any(|f| f.cmd == CMD_WASTE));
+}
+
+#[tokio::test]
+async fn flush_waits_until_bytes_reach_transport() {
+    let io = RecordingIo::default();
+    let session = Arc::new(Session::new_client(
+        Box::new(io.clone()),
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    session.run().await.unwrap();
+
+    let mut stream = session.open_stream().await.unwrap();
+    stream.write_all(b"flush-me-please").await.unwrap();
+    stream.flush().await.unwrap();
+    assert!(io.contains(b"flush-me-please"));
+    assert!(io.flushes.load(Ordering::Acquire) >= 1);
+
+    let flushes = io.flushes.load(Ordering::Acquire);
+    session.flush().await.unwrap();
+    assert!(io.flushes.load(Ordering::Acquire) > flushes);
+}
+
+#[tokio::test]
+async fn concurrent_streams_share_batched_transport_writes() {
+    const STREAMS: usize = 50;
+    const CHUNKS: usize = 4;
+    let io = RecordingIo::default();
+    let session = Arc::new(Session::new_client(
+        Box::new(io.clone()),
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    session.run().await.unwrap();
+
+    let mut streams = Vec::new();
+    for _ in 0..STREAMS {
+        streams.push(session.open_stream().await.unwrap());
+    }
+    // 先让 padding 阶段的帧全部写出
+    session.flush().await.unwrap();
+    let writes_before = io.writes.load(Ordering::Acquire);
+
+    let tasks: Vec<_> = streams
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut stream)| {
+            tokio::spawn(async move {
+                for chunk in 0..CHUNKS {
+                    let payload = format!("stream-{i}-chunk-{chunk};");
+                    stream.write_all(payload.as_bytes()).await.unwrap();
+                }
+                stream
+            })
+        })
+        .collect();
+    let mut streams = Vec::new();
+    for task in tasks {
+        streams.push(task.await.unwrap());
+    }
+    session.flush().await.unwrap();
+
+    let frames = STREAMS * CHUNKS;
+    let writes = io.writes.load(Ordering::Acquire) - writes_before;
+    assert!(writes * 4 <= frames, "{writes} writes for {frames} frames");
+    for i in 0..STREAMS {
+        assert!(io.contains(format!("stream-{i}-chunk-{};", CHUNKS - 1).as_bytes()));
+    }
+}
+
+#[tokio::test]
+async fn interleaved_data_is_routed_to_the_matching_stream() {
+    const STREAMS: usize = 8;
+    const CHUNKS: usize = 16;
+    let (client, _server, mut incoming) = session_pair().await;
+    let mut streams = Vec::new();
+    for _ in 0..STREAMS {
+        streams.push(client.open_stream().await.unwrap());
+    }
+    // 轮流在各个 Stream 上写，连接上的 PSH 帧交错到达
+    for chunk in 0..CHUNKS {
+        for stream in &mut streams {
+            let payload = format!("{}:{};", stream.id(), chunk);
+            stream.write_all(payload.as_bytes()).await.unwrap();
+        }
+    }
+    for stream in &mut streams {
+        stream.shutdown().await.unwrap();
+    }
+
+    for _ in 0..STREAMS {
+        let mut remote = incoming.recv().await.unwrap();
+        let mut received = String::new();
+        remote.read_to_string(&mut received).await.unwrap();
+        let expected: String =
+            (0..CHUNKS).map(|chunk| format!("{}:{};", remote.id(), chunk)).collect();
+        assert_eq!(received, expected);
+    }
+}
+
+/// 接受前 `budget` 字节后写入永远挂起，读端同样挂起，模拟对端停止读取
+struct StallingIo {
+    budget: usize,
+}
+
+impl AsyncRead for StallingIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for StallingIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.budget == 0 {
+            return Poll::Pending;
+        }
+        let n = buf.len().min(self.budget);
+        self.budget -= n;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if self.budget == 0 {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[tokio::test]
+async fn stalled_transport_fails_session_after_write_timeout() {
+    let session = Arc::new(
+        Session::new_client(
+            Box::new(StallingIo { budget: 4096 }),
+            Arc::new(PaddingFactory::default()),
+            SessionConfig::default(),
+        )
+        .with_write_timeout(Duration::from_millis(200)),
+    );
+    session.run().await.unwrap();
+    let mut stream = session.open_stream().await.unwrap();
+
+    let started = std::time::Instant::now();
+    let payload = vec![0u8; 256 * 1024];
+    let write = tokio::time::timeout(Duration::from_secs(5), async {
+        stream.write_all(&payload).await?;
+        stream.flush().await
+    })
+    .await
+    .expect("stream write hung on a stalled transport");
+    assert!(write.is_err());
+    wait_for("session to close", || session.is_closed()).await;
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(stream.is_closed());
+}
+
+/// 打开前写入一直挂起的传输层，打开后记录写入的字节
+#[derive(Clone, Default)]
+struct GatedIo {
+    open: Arc<AtomicBool>,
+    waker: Arc<Mutex<Option<Waker>>>,
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl GatedIo {
+    fn set_open(&self, open: bool) {
+        self.open.store(open, Ordering::Release);
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+impl AsyncRead for GatedIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for GatedIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if !self.open.load(Ordering::Acquire) {
+            *self.waker.lock().unwrap() = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        self.written.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn dropping_stream_with_full_frame_queue_still_cleans_up() {
+    let io = GatedIo::default();
+    io.set_open(true);
+    let session = Arc::new(Session::new_server(
+        Box::new(io.clone()),
+        None,
+        None,
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    session.run().await.unwrap();
+    let dropped = session.open_stream().await.unwrap();
+    let mut filler = session.open_stream().await.unwrap();
+    session.flush().await.unwrap();
+    assert_eq!(session.stream_count(), 2);
+
+    // 传输层卡住后填满帧队列
+    io.set_open(false);
+    let filling = tokio::spawn(async move {
+        loop {
+            filler.write_all(b"x").await.unwrap();
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let dropped_id = dropped.id();
+    drop(dropped);
+    io.set_open(true);
+    wait_for("dropped stream to leave the session", || session.stream_count() == 1).await;
+    filling.abort();
+    session.flush().await.unwrap();
+
+    let mut fin = vec![CMD_FIN];
+    fin.extend_from_slice(&dropped_id.to_be_bytes());
+    fin.extend_from_slice(&[0, 0]);
+    let written = io.written.lock().unwrap();
+    assert!(written.windows(fin.len()).any(|w| w == fin), "FIN for dropped stream not sent");
+}
+
+#[tokio::test]
+async fn high_priority_frames_overtake_queued_bulk_frames() {
+    let io = GatedIo::default();
+    io.set_open(true);
+    let session = Arc::new(Session::new_server(
+        Box::new(io.clone()),
+        None,
+        None,
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    session.run().await.unwrap();
+    let mut bulk = session.open_stream().await.unwrap();
+    let mut interactive = session.open_stream().await.unwrap();
+    assert_eq!(bulk.priority(), Priority::Normal);
+    bulk.set_priority(Priority::Low).await.unwrap();
+    interactive.set_priority(Priority::High).await.unwrap();
+    session.flush().await.unwrap();
+    let offset = io.written.lock().unwrap().len();
+
+    // 写循环卡在第一帧上时积压一批低优先级数据
+    io.set_open(false);
+    bulk.write_all(b"first").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    for _ in 0..100 {
+        bulk.write_all(b"bulk").await.unwrap();
+    }
+    interactive.write_all(b"key").await.unwrap();
+    io.set_open(true);
+    session.flush().await.unwrap();
+
+    let frames: Vec<Frame> = decode_frames(&io.written.lock().unwrap()[offset..])
+        .into_iter()
+        .filter(|f| f.cmd == CMD_PSH)
+        .collect();
+    assert_eq!(frames.len(), 102);
+    assert_eq!((frames[0].sid, &frames[0].data[..]), (bulk.id(), &b"first"[..]));
+    assert_eq!((frames[1].sid, &frames[1].data[..]), (interactive.id(), &b"key"[..]));
+    assert!(frames[2..].iter().all(|f| f.sid == bulk.id()));
+}
+
+#[tokio::test]
+async fn slow_reader_bounds_buffered_bytes_to_recv_window() {
+    const WINDOW: usize = 64 * 1024;
+    const TOTAL: usize = 2 * 1024 * 1024;
+    let (client_end, server_end) = tokio::io::duplex(256 * 1024);
+    let padding = Arc::new(PaddingFactory::default());
+    let server = Arc::new(
+        Session::new_server(
+            Box::new(server_end),
+            None,
+            None,
+            Arc::clone(&padding),
+            SessionConfig {
+                accept_backlog: Some(4),
+                recv_window: WINDOW,
+                ..SessionConfig::default()
+            },
+        ),
+    );
+    let mut incoming = server.incoming().unwrap();
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        padding,
+        SessionConfig::default(),
+    ));
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+
+    let mut stream = client.open_stream().await.unwrap();
+    let writer = tokio::spawn(async move {
+        let chunk: Vec<u8> = (0..16 * 1024).map(|i| (i % 251) as u8).collect();
+        for _ in 0..TOTAL / chunk.len() {
+            stream.write_all(&chunk).await.unwrap();
+        }
+        stream
+    });
+
+    // 不读取时，已缓存的数据停在窗口上限
+    let remote = incoming.recv().await.unwrap();
+    wait_for("window to fill", || remote.buffered_bytes() > 0).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(remote.buffered_bytes() <= WINDOW);
+
+    let mut received = 0;
+    let mut buf = vec![0u8; 4096];
+    while received < TOTAL {
+        let n = remote.read(&mut buf).await.unwrap();
+        assert!(n > 0);
+        assert!(buf[..n]
+            .iter()
+            .enumerate()
+            .all(|(i, b)| *b == ((received + i) % (16 * 1024) % 251) as u8));
+        received += n;
+        assert!(remote.buffered_bytes() <= WINDOW);
+    }
+    writer.await.unwrap();
+    assert_eq!(remote.buffered_bytes(), 0);
+}
+
+#[tokio::test]
+async fn shared_stream_supports_concurrent_read_and_write() {
+    let (client, _server, mut incoming) = session_pair().await;
+    let stream = Arc::new(client.open_stream().await.unwrap());
+    let remote = incoming.recv().await.unwrap();
+    tokio::spawn(async move {
+        let (mut r, mut w) = remote.split();
+        let _ = tokio::io::copy(&mut r, &mut w).await;
+    });
+
+    const TOTAL: usize = 256 * 1024;
+    let writer = {
+        let stream = Arc::clone(&stream);
+        tokio::spawn(async move {
+            let chunk = [7u8; 4096];
+            let mut sent = 0;
+            while sent < TOTAL {
+                sent += stream.write(&chunk).await.unwrap();
+            }
+            sent
+        })
+    };
+    let reader = {
+        let stream = Arc::clone(&stream);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 8192];
+            let mut received = 0;
+            while received < TOTAL {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0 && buf[..n].iter().all(|b| *b == 7));
+                received += n;
+            }
+            received
+        })
+    };
+    assert_eq!(writer.await.unwrap(), TOTAL);
+    assert_eq!(reader.await.unwrap(), TOTAL);
+}
+
+#[tokio::test]
+async fn split_ref_halves_borrow_the_stream() {
+    let (client, _server, mut incoming) = session_pair().await;
+    let stream = Arc::new(client.open_stream().await.unwrap());
+    let mut remote = incoming.recv().await.unwrap();
+
+    let (mut r, mut w) = stream.split_ref();
+    let send = async {
+        w.write_all(b"ping").await.unwrap();
+        w.flush().await.unwrap();
+    };
+    let echo = async {
+        let mut buf = [0u8; 4];
+        remote.read_exact(&mut buf).await.unwrap();
+        remote.write_all(b"pong").await.unwrap();
+    };
+    tokio::join!(send, echo);
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+
+    w.shutdown().await.unwrap();
+    assert!(stream.is_closed());
+    assert_eq!(Arc::strong_count(&stream), 1);
+}
+
+/// 客户端按 `report_platform` 发送 SETTINGS，返回服务端看到的设置
+async fn settings_seen_by_server(report_platform: bool) -> ClientSettings {
+    let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+    let padding = Arc::new(PaddingFactory::default());
+    let server = Arc::new(Session::new_server(
+        Box::new(server_end),
+        None,
+        None,
+        Arc::clone(&padding),
+        SessionConfig::default(),
+    ));
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        padding,
+        SessionConfig { report_platform, ..SessionConfig::default() },
+    ));
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+    wait_for("client settings", || server.peer_settings().is_some()).await;
+    server.peer_settings().unwrap()
+}
+
+#[tokio::test]
+async fn platform_is_reported_only_when_enabled() {
+    let settings = settings_seen_by_server(true).await;
+    assert_eq!(settings.os.as_deref(), Some(std::env::consts::OS));
+    assert_eq!(settings.arch.as_deref(), Some(std::env::consts::ARCH));
+    assert!(settings.client.is_some());
+
+    let settings = settings_seen_by_server(false).await;
+    assert_eq!((settings.os, settings.arch), (None, None));
+    assert!(settings.client.is_some());
+}
+
+/// 读到 `total` 字节为止，返回单次读取的最大长度；每次 read 最多交付一帧的数据
+async fn largest_read(stream: &mut Stream, total: usize) -> usize {
+    let mut buf = vec![0u8; 64 * 1024];
+    let (mut received, mut largest) = (0, 0);
+    while received < total {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "unexpected EOF");
+        received += n;
+        largest = largest.max(n);
+    }
+    largest
+}
+
+#[tokio::test]
+async fn smaller_max_payload_is_honored_both_ways() {
+    let (client_end, server_end) = tokio::io::duplex(256 * 1024);
+    let (stream_tx, mut incoming) = tokio::sync::mpsc::unbounded_channel();
+    let padding = Arc::new(PaddingFactory::default());
+    let server = Arc::new(
+        Session::new_server(
+            Box::new(server_end),
+            Some(Arc::new(move |stream| {
+                let _ = stream_tx.send(stream);
+            })),
+            None,
+            Arc::clone(&padding),
+            SessionConfig::default(),
+        )
+        .with_max_payload(1000),
+    );
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        padding,
+        SessionConfig { max_payload: 4000, ..SessionConfig::default() },
+    ));
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+    wait_for("client settings", || server.peer_settings().is_some()).await;
+    assert_eq!(server.peer_settings().unwrap().max_payload, Some(4000));
+
+    // 收到服务端数据时，客户端已处理了更早到达的 SERVER_SETTINGS
+    let mut stream = client.open_stream().await.unwrap();
+    let mut remote: Stream = incoming.recv().await.unwrap();
+    remote.write_all(b"x").await.unwrap();
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte).await.unwrap();
+
+    let payload = vec![7u8; 20_000];
+    let limit = 1000 - SEQ_PREFIX_SIZE;
+    stream.write_all(&payload).await.unwrap();
+    assert_eq!(largest_read(&mut remote, payload.len()).await, limit);
+    remote.write_all(&payload).await.unwrap();
+    assert_eq!(largest_read(&mut stream, payload.len()).await, limit);
+}
+
+/// 以原始帧与 Session 交互：发送 `frames` 后用心跳确认它们都已处理，返回期间收到的帧
+async fn exchange(peer: &mut tokio::io::DuplexStream, frames: &[Frame]) -> Vec<Frame> {
+    let mut wire = BytesMut::new();
+    for frame in frames {
+        frame.encode_into(&mut wire);
+    }
+    Frame::new(CMD_HEART_REQUEST, 99).encode_into(&mut wire);
+    peer.write_all(&wire).await.unwrap();
+
+    let (mut received, mut wire) = (Vec::new(), BytesMut::new());
+    loop {
+        while let Some(frame) = FrameCodec.decode(&mut wire).unwrap() {
+            if frame.cmd == CMD_HEART_RESPONSE && frame.sid == 99 {
+                return received;
+            }
+            received.push(frame);
+        }
+        let mut buf = [0u8; 1024];
+        let n = tokio::time::timeout(Duration::from_secs(5), peer.read(&mut buf))
+            .await
+            .expect("no heartbeat response")
+            .unwrap();
+        assert!(n > 0, "connection closed before the heartbeat response");
+        wire.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[tokio::test]
+async fn client_ignores_commands_only_a_client_sends() {
+    let (mut peer, client_end) = tokio::io::duplex(64 * 1024);
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    assert_eq!(client.role(), Role::Client);
+    client.run().await.unwrap();
+
+    let settings = ClientSettings::new("peer", "md5").encode();
+    let received = exchange(
+        &mut peer,
+        &[Frame::new(CMD_SYN, 5), Frame::with_data(CMD_SETTINGS, 0, settings)],
+    )
+    .await;
+    assert!(received.iter().all(|f| f.cmd != CMD_SYNACK && f.cmd != CMD_SERVER_SETTINGS));
+    assert_eq!(client.stream_count(), 0);
+    assert!(!client.is_closed());
+}
+
+#[tokio::test]
+async fn server_ignores_commands_only_a_server_sends() {
+    let (mut peer, server_end) = tokio::io::duplex(64 * 1024);
+    let server = Arc::new(Session::new_server(
+        Box::new(server_end),
+        None,
+        None,
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    assert_eq!(server.role(), Role::Server);
+    server.run().await.unwrap();
+
+    // 客户端收到无效的填充方案会关闭 Session，服务端不处理该命令
+    let received = exchange(
+        &mut peer,
+        &[
+            Frame::with_data(CMD_SYNACK, 1, Bytes::from_static(b"refused")),
+            Frame::with_data(CMD_SERVER_SETTINGS, 0, Bytes::from_static(b"v=2")),
+            Frame::with_data(CMD_UPDATE_PADDING_SCHEME, 0, Bytes::from_static(b"not a scheme")),
+        ],
+    )
+    .await;
+    assert!(received.is_empty());
+    assert!(server.peer_settings().is_none());
+    assert!(!server.is_closed());
+
+    // 客户端的命令照常处理
+    let received = exchange(&mut peer, &[Frame::new(CMD_SYN, 1)]).await;
+    assert_eq!(received.iter().map(|f| (f.cmd, f.sid)).collect::<Vec<_>>(), [(CMD_SYNACK, 1)]);
+}
+
+#[tokio::test]
+async fn oversized_settings_frame_is_rejected_with_alert() {
+    let (mut peer, server_end) = tokio::io::duplex(64 * 1024);
+    let server = Arc::new(Session::new_server(
+        Box::new(server_end),
+        None,
+        None,
+        Arc::new(PaddingFactory::default()),
+        SessionConfig::default(),
+    ));
+    server.run().await.unwrap();
+
+    let blob = "k=v\n".repeat(2000);
+    let settings = Frame::with_data(CMD_SETTINGS, 0, Bytes::from(blob));
+    peer.write_all(&settings.to_bytes()).await.unwrap();
+
+    let mut wire = BytesMut::new();
+    let alert = loop {
+        if let Some(frame) = FrameCodec.decode(&mut wire).unwrap() {
+            if frame.cmd == CMD_ALERT {
+                break frame;
+            }
+            continue;
+        }
+        let mut buf = [0u8; 1024];
+        let n = tokio::time::timeout(Duration::from_secs(5), peer.read(&mut buf))
+            .await
+            .expect("no alert sent")
+            .unwrap();
+        assert!(n > 0, "connection closed without an alert");
+        wire.extend_from_slice(&buf[..n]);
+    };
+    assert!(String::from_utf8_lossy(&alert.data).contains("settings too large"));
+    wait_for("server session to close", || server.is_closed()).await;
+    assert!(server.peer_settings().is_none());
+}
+
+#[tokio::test]
+async fn custom_session_config_is_normalized_and_applied() {
+    let (client_end, server_end) = tokio::io::duplex(256 * 1024);
+    let padding = Arc::new(PaddingFactory::default());
+    let config = SessionConfig {
+        recv_window: 1,
+        max_payload: 1,
+        frame_queue_capacity: 0,
+        accept_backlog: Some(0),
+        ..SessionConfig::default()
+    };
+    let server = Arc::new(Session::new_server(
+        Box::new(server_end),
+        None,
+        None,
+        Arc::clone(&padding),
+        config,
+    ));
+    assert_eq!(server.config().recv_window, u16::MAX as usize);
+    assert_eq!(server.config().max_payload, 256);
+    assert_eq!(server.config().frame_queue_capacity, 1);
+    assert_eq!(server.config().accept_backlog, Some(1));
+    let mut incoming = server.incoming().expect("backlog comes from the config");
+
+    let client_config = SessionConfig { report_platform: true, ..SessionConfig::default() };
+    let client = Arc::new(Session::new_client(Box::new(client_end), padding, client_config));
+    assert_eq!(client.config(), &client_config);
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+
+    let mut stream = client.open_stream().await.unwrap();
+    let mut remote = incoming.recv().await.unwrap();
+    remote.write_all(b"x").await.unwrap();
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte).await.unwrap();
+    assert!(server.peer_settings().unwrap().os.is_some());
+
+    let payload = vec![7u8; 4096];
+    stream.write_all(&payload).await.unwrap();
+    assert_eq!(largest_read(&mut remote, payload.len()).await, 256);
+}
+
+#[tokio::test]
+async fn active_streams_are_enumerated_with_their_counters() {
+    let (client, server, mut incoming) = session_pair().await;
+    let mut streams = Vec::new();
+    for len in [10usize, 20, 30] {
+        let mut stream = client.open_stream().await.unwrap();
+        stream.write_all(&vec![1u8; len]).await.unwrap();
+        streams.push(stream);
+    }
+    let mut remotes = Vec::new();
+    for len in [10usize, 20, 30] {
+        let mut remote = incoming.recv().await.unwrap();
+        let mut buf = vec![0u8; len];
+        remote.read_exact(&mut buf).await.unwrap();
+        remotes.push(remote);
+    }
+    remotes[1].set_target("example.com:443");
+
+    let ids: Vec<u32> = streams.iter().map(|stream| stream.id()).collect();
+    let local = client.active_streams().await;
+    assert_eq!(local.iter().map(|info| info.id).collect::<Vec<_>>(), ids);
+    assert_eq!(local.iter().map(|info| info.bytes_sent).collect::<Vec<_>>(), [10, 20, 30]);
+    assert!(local.iter().all(|info| info.target.is_none()));
+
+    let remote = server.active_streams().await;
+    assert_eq!(remote.iter().map(|info| info.id).collect::<Vec<_>>(), ids);
+    assert_eq!(remote.iter().map(|info| info.bytes_received).collect::<Vec<_>>(), [10, 20, 30]);
+    assert_eq!(remote[1].target.as_deref(), Some("example.com:443"));
+    assert!(remote[0].age <= local[0].age);
+}
+
+#[tokio::test]
+async fn session_buffer_cap_alerts_and_closes() {
+    const CAP: usize = 100 * 1024;
+    let (client_end, server_end) = tokio::io::duplex(256 * 1024);
+    let padding = Arc::new(PaddingFactory::default());
+    let server = Arc::new(Session::new_server(
+        Box::new(server_end),
+        None,
+        None,
+        Arc::clone(&padding),
+        SessionConfig { accept_backlog: Some(8), max_buffered: CAP, ..SessionConfig::default() },
+    ));
+    let mut incoming = server.incoming().unwrap();
+    let client = Arc::new(Session::new_client(
+        Box::new(client_end),
+        padding,
+        SessionConfig::default(),
+    ));
+    server.run().await.unwrap();
+    client.run().await.unwrap();
+
+    // 每个 Stream 都在自己的窗口内，只有总量超过上限
+    let mut first = client.open_stream().await.unwrap();
+    first.write_all(&vec![1u8; 60 * 1024]).await.unwrap();
+    let mut remote = incoming.recv().await.unwrap();
+    let mut buf = vec![0u8; 10 * 1024];
+    remote.read_exact(&mut buf).await.unwrap();
+    wait_for("buffered bytes", || server.buffered_bytes() == 50 * 1024).await;
+
+    let mut second = client.open_stream().await.unwrap();
+    second.write_all(&vec![2u8; 60 * 1024]).await.unwrap();
+    wait_for("server session to close", || server.is_closed()).await;
+    wait_for("client session to close", || client.is_closed()).await;
+    assert!(server.buffered_bytes() > CAP);
+}
+
+#[tokio::test]
+async fn write_bytes_delivers_the_whole_buffer() {
+    let (client, _server, mut incoming) = session_pair().await;
+    let stream = client.open_stream().await.unwrap();
+    let payload: Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+    stream.write_bytes(Bytes::from(payload.clone())).await.unwrap();
+    stream.write_bytes(Bytes::new()).await.unwrap();
+    drop(stream);
+
+    let mut remote = incoming.recv().await.unwrap();
+    let mut received = Vec::new();
+    remote.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, payload);
+}
+
+#[tokio::test]
+async fn shutdown_read_makes_peer_writes_fail() {
+    let (client, server, mut incoming) = session_pair().await;
+    let mut stream = client.open_stream().await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let remote = incoming.recv().await.unwrap();
+    wait_for("data to arrive", || server.buffered_bytes() == 5).await;
+
+    remote.shutdown_read().await.unwrap();
+    assert_eq!(server.buffered_bytes(), 0);
+    let mut buf = [0u8; 8];
+    assert_eq!(remote.read(&mut buf).await.unwrap(), 0);
+
+    let err = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Err(e) = stream.write_all(b"more").await {
+                return e;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("writer never observed the stop signal");
+    assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+
+    // 写方向不受影响
+    remote.write(b"reply").await.unwrap();
+    let mut reply = [0u8; 5];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"reply");
+}
+
+#[tokio::test]
+async fn shutdown_read_wakes_a_pending_reader() {
+    let (client, _server, mut incoming) = session_pair().await;
+    let mut stream = client.open_stream().await.unwrap();
+    stream.write_all(b"x").await.unwrap();
+    let remote = Arc::new(incoming.recv().await.unwrap());
+    let mut first = [0u8; 1];
+    remote.read(&mut first).await.unwrap();
+
+    let reader = Arc::clone(&remote);
+    let pending = tokio::spawn(async move {
+        let mut buf = [0u8; 8];
+        reader.read(&mut buf).await
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    remote.shutdown_read().await.unwrap();
+    let read = tokio::time::timeout(Duration::from_secs(5), pending)
+        .await
+        .expect("pending reader was not woken")
+        .unwrap();
+    assert_eq!(read.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_fin_ack() {
+    let config = SessionConfig {
+        fin_ack_timeout: Duration::from_secs(5),
+        ..SessionConfig::default()
+    };
+    let (client, server, mut incoming) = session_pair_with(config).await;
+    let mut stream = client.open_stream().await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let remote = incoming.recv().await.unwrap();
+    // 收到对端数据时对端的设置也已处理，此时双方都知道对方支持 CMD_FIN_ACK
+    remote.write(b"pong").await.unwrap();
+    let mut pong = [0u8; 4];
+    stream.read_exact(&mut pong).await.unwrap();
+
+    let started = std::time::Instant::now();
+    stream.shutdown().await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(stream.close_acknowledged());
+    assert!(stream.is_closed());
+    wait_for("remote to see the FIN", || remote.is_closed()).await;
+    assert_eq!(server.stream_count(), 0);
+}
+
+#[tokio::test]
+async fn shutdown_does_not_wait_for_peers_without_fin_ack() {
+    // 对端从不发送设置，视为不支持 CMD_FIN_ACK 的旧版本
+    let io = RecordingIo::default();
+    let session = Arc::new(
+        Session::new_client(
+            Box::new(io.clone()),
+            Arc::new(PaddingFactory::default()),
+            SessionConfig::default(),
+        )
+        .with_fin_ack_timeout(Duration::from_secs(30)),
+    );
+    session.run().await.unwrap();
+    let mut stream = session.open_stream().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), stream.shutdown())
+        .await
+        .expect("shutdown waited for an ack the peer never sends")
+        .unwrap();
+    assert!(!stream.close_acknowledged());
+    assert!(stream.is_closed());
+}
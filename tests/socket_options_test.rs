@@ -0,0 +1,115 @@
+use anytls_rs::proxy::outbound::socket::{PortRange, SocketOptions, MAX_SOCKET_BUFFER_SIZE};
+use std::io::ErrorKind;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const REQUESTED: u32 = 64 * 1024;
+
+fn sized(size: u32) -> SocketOptions {
+    SocketOptions {
+        send_buffer_size: Some(size),
+        recv_buffer_size: Some(size),
+        ..SocketOptions::default()
+    }
+}
+
+#[test]
+fn requested_buffer_sizes_are_applied() {
+    let (sndbuf, rcvbuf) = sized(REQUESTED).effective().unwrap();
+    let (default_sndbuf, _) = SocketOptions::default().effective().unwrap();
+    // Linux 会把请求值加倍，其他系统至少按请求值设置（均未超过系统上限）
+    assert!(sndbuf >= REQUESTED, "SO_SNDBUF {}", sndbuf);
+    assert!(rcvbuf >= REQUESTED, "SO_RCVBUF {}", rcvbuf);
+    assert_ne!(sndbuf, default_sndbuf);
+}
+
+#[test]
+fn out_of_range_buffer_sizes_are_rejected() {
+    assert!(SocketOptions::default().validate().is_ok());
+    assert!(sized(REQUESTED).validate().is_ok());
+    for size in [0, 1024, MAX_SOCKET_BUFFER_SIZE + 1] {
+        let err = sized(size).validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
+
+#[tokio::test]
+async fn connect_with_buffer_sizes_reaches_target() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = listener.local_addr().unwrap().to_string();
+    let echo = tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 4];
+        conn.read_exact(&mut buf).await.unwrap();
+        conn.write_all(&buf).await.unwrap();
+    });
+
+    let mut conn = sized(REQUESTED).connect(&target).await.unwrap();
+    conn.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    echo.await.unwrap();
+
+    let err = sized(REQUESTED).connect("127.0.0.1:1").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+}
+
+fn from_ports(range: PortRange) -> SocketOptions {
+    SocketOptions {
+        source_ports: Some(range),
+        ..SocketOptions::default()
+    }
+}
+
+/// 系统当前未占用的端口
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+#[test]
+fn port_range_parses_and_validates() {
+    let range: PortRange = "40000-40010".parse().unwrap();
+    assert_eq!(range, PortRange { low: 40000, high: 40010 });
+    assert_eq!(range.to_string(), "40000-40010");
+    assert!(range.contains(40000) && range.contains(40010) && !range.contains(40011));
+    assert_eq!("5000-5000".parse::<PortRange>().unwrap().low, 5000);
+    for bad in ["0-10", "10-5", "10", "a-b", "1-70000", ""] {
+        assert!(bad.parse::<PortRange>().is_err(), "{}", bad);
+    }
+}
+
+#[tokio::test]
+async fn connections_bind_source_ports_within_range() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = listener.local_addr().unwrap().to_string();
+    let low = free_port().min(u16::MAX - 15);
+    let range = PortRange { low, high: low + 15 };
+
+    let mut conns = Vec::new();
+    let mut ports = Vec::new();
+    for _ in 0..4 {
+        let conn = from_ports(range).connect(&target).await.unwrap();
+        let port = conn.local_addr().unwrap().port();
+        assert!(range.contains(port), "source port {} outside {}", port, range);
+        ports.push(port);
+        conns.push(conn);
+        listener.accept().await.unwrap();
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    assert_eq!(ports.len(), 4);
+}
+
+#[tokio::test]
+async fn exhausted_range_falls_back_to_ephemeral_port() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = listener.local_addr().unwrap().to_string();
+    let port = free_port();
+    let range = PortRange { low: port, high: port };
+
+    let first = from_ports(range).connect(&target).await.unwrap();
+    assert_eq!(first.local_addr().unwrap().port(), port);
+    let second = from_ports(range).connect(&target).await.unwrap();
+    assert_ne!(second.local_addr().unwrap().port(), port);
+}
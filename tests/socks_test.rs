@@ -0,0 +1,215 @@
+use anytls_rs::proxy::addr_codec::{AddressType, SocksAddr};
+use anytls_rs::proxy::socks::{self, Socks5Handshake, SocksError, SocksRequest};
+use std::io::ErrorKind;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+/// 在服务端一侧运行握手，客户端一侧由测试直接读写字节
+async fn run_handshake(
+    handshake: Socks5Handshake,
+    client_bytes: &[u8],
+) -> (std::io::Result<SocksRequest>, DuplexStream) {
+    let (mut client, mut server) = tokio::io::duplex(1024);
+    client.write_all(client_bytes).await.unwrap();
+    let result = handshake.negotiate(&mut server).await;
+    drop(server);
+    (result, client)
+}
+
+async fn read_all(mut client: DuplexStream) -> Vec<u8> {
+    let mut out = Vec::new();
+    client.read_to_end(&mut out).await.unwrap();
+    out
+}
+
+#[tokio::test]
+async fn no_auth_connect_ipv4() {
+    let bytes = [&[5, 1, 0][..], &[5, 1, 0, 1, 10, 0, 0, 1, 0x01, 0xbb]].concat();
+    let (result, client) = run_handshake(Socks5Handshake::new(), &bytes).await;
+    let req = result.unwrap();
+    assert_eq!(req.command, socks::CMD_CONNECT);
+    assert_eq!(
+        req.addr,
+        SocksAddr {
+            atyp: AddressType::Ipv4,
+            host: "10.0.0.1".to_string(),
+            port: 443,
+        }
+    );
+    assert_eq!(read_all(client).await, [5, 0]);
+}
+
+#[tokio::test]
+async fn request_parses_domain_and_ipv6() {
+    let mut domain = vec![5, 3, 0, 3, 11];
+    domain.extend_from_slice(b"example.com");
+    domain.extend_from_slice(&80u16.to_be_bytes());
+    let req = socks::read_request(&mut domain.as_slice()).await.unwrap();
+    assert_eq!(req.command, socks::CMD_UDP_ASSOCIATE);
+    assert_eq!(req.addr.atyp, AddressType::Domain);
+    assert_eq!(req.addr.to_host_port(), "example.com:80");
+
+    let mut ipv6 = vec![5, 1, 0, 4];
+    ipv6.extend_from_slice(&std::net::Ipv6Addr::LOCALHOST.octets());
+    ipv6.extend_from_slice(&8080u16.to_be_bytes());
+    let req = socks::read_request(&mut ipv6.as_slice()).await.unwrap();
+    assert_eq!(req.addr.atyp, AddressType::Ipv6);
+    assert_eq!(req.addr.to_host_port(), "::1:8080");
+}
+
+#[tokio::test]
+async fn unsupported_method_is_refused() {
+    // 只提供 GSSAPI
+    let (result, client) = run_handshake(Socks5Handshake::new(), &[5, 1, 1]).await;
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    assert_eq!(read_all(client).await, [5, 0xff]);
+
+    let (result, _) = run_handshake(Socks5Handshake::new(), &[4, 1, 0]).await;
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn user_pass_auth_accepts_valid_credentials() {
+    let handshake = Socks5Handshake::new().with_credentials("alice", "secret");
+    let mut bytes = vec![5, 2, 0, 2, 1, 5];
+    bytes.extend_from_slice(b"alice");
+    bytes.push(6);
+    bytes.extend_from_slice(b"secret");
+    bytes.extend_from_slice(&[5, 1, 0, 1, 127, 0, 0, 1, 0, 80]);
+    let (result, client) = run_handshake(handshake, &bytes).await;
+    assert_eq!(result.unwrap().addr.to_host_port(), "127.0.0.1:80");
+    assert_eq!(read_all(client).await, [5, 2, 1, 0]);
+}
+
+#[tokio::test]
+async fn user_pass_auth_rejects_wrong_password_and_no_auth_clients() {
+    let handshake = Socks5Handshake::new().with_credentials("alice", "secret");
+    let mut bytes = vec![5, 1, 2, 1, 5];
+    bytes.extend_from_slice(b"alice");
+    bytes.push(5);
+    bytes.extend_from_slice(b"wrong");
+    let (result, client) = run_handshake(handshake.clone(), &bytes).await;
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    assert_eq!(read_all(client).await, [5, 2, 1, 1]);
+
+    // 配置了认证时不接受无认证客户端
+    let (result, client) = run_handshake(handshake, &[5, 1, 0]).await;
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    assert_eq!(read_all(client).await, [5, 0xff]);
+}
+
+/// 只完成方法协商，返回选中的方法与写给客户端的字节
+async fn select_method(handshake: &Socks5Handshake, offered: &[u8]) -> (Option<u8>, Vec<u8>) {
+    let (mut client, mut server) = tokio::io::duplex(1024);
+    let greeting = [&[5, offered.len() as u8][..], offered].concat();
+    client.write_all(&greeting).await.unwrap();
+    let chosen = handshake.negotiate_method(&mut server).await.ok();
+    drop(server);
+    (chosen, read_all(client).await)
+}
+
+#[tokio::test]
+async fn method_selection_prefers_user_pass_and_can_allow_anonymous() {
+    let both = Socks5Handshake::new().with_credentials("alice", "secret").with_anonymous(true);
+    assert_eq!(both.supported_methods(), [socks::METHOD_USER_PASS, socks::METHOD_NO_AUTH]);
+    assert_eq!(select_method(&both, &[2]).await, (Some(2), vec![5, 2]));
+    assert_eq!(select_method(&both, &[0]).await, (Some(0), vec![5, 0]));
+    // 客户端的顺序不影响选择
+    assert_eq!(select_method(&both, &[0, 2]).await, (Some(2), vec![5, 2]));
+
+    let strict = Socks5Handshake::new().with_credentials("alice", "secret");
+    assert_eq!(select_method(&strict, &[2]).await, (Some(2), vec![5, 2]));
+    assert_eq!(select_method(&strict, &[0]).await, (None, vec![5, 0xff]));
+    assert_eq!(select_method(&strict, &[0, 2]).await, (Some(2), vec![5, 2]));
+
+    let open = Socks5Handshake::new();
+    assert_eq!(select_method(&open, &[2]).await, (None, vec![5, 0xff]));
+    assert_eq!(select_method(&open, &[0]).await, (Some(0), vec![5, 0]));
+    assert_eq!(select_method(&open, &[2, 0]).await, (Some(0), vec![5, 0]));
+}
+
+#[tokio::test]
+async fn anonymous_client_skips_auth_when_allowed() {
+    let handshake = Socks5Handshake::new().with_credentials("alice", "secret").with_anonymous(true);
+    let bytes = [&[5, 1, 0][..], &[5, 1, 0, 1, 127, 0, 0, 1, 0, 80]].concat();
+    let (result, client) = run_handshake(handshake, &bytes).await;
+    assert_eq!(result.unwrap().addr.to_host_port(), "127.0.0.1:80");
+    assert_eq!(read_all(client).await, [5, 0]);
+}
+
+#[tokio::test]
+async fn unsupported_address_type_gets_reply() {
+    let bytes = [5, 1, 0, 5, 1, 0, 9];
+    let (result, client) = run_handshake(Socks5Handshake::new(), &bytes).await;
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(read_all(client).await, [5, 0, 5, 8, 0, 1, 0, 0, 0, 0, 0, 0]);
+}
+
+#[tokio::test]
+async fn write_reply_encodes_bound_address() {
+    let mut out = Vec::new();
+    socks::write_reply(&mut out, socks::REP_COMMAND_NOT_SUPPORTED, None)
+        .await
+        .unwrap();
+    assert_eq!(out, [5, 7, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    let bound = SocksAddr {
+        atyp: AddressType::Domain,
+        host: "proxy".to_string(),
+        port: 1080,
+    };
+    let mut out = Vec::new();
+    socks::write_reply(&mut out, socks::REP_SUCCEEDED, Some(&bound))
+        .await
+        .unwrap();
+    assert_eq!(out, [&[5, 0, 0, 3, 5][..], b"proxy", &[0x04, 0x38]].concat());
+}
+
+async fn request_error(bytes: &[u8]) -> SocksError {
+    let err = socks::read_request(&mut &bytes[..]).await.unwrap_err();
+    SocksError::from_io(&err).unwrap_or_else(|| panic!("unexpected error: {}", err))
+}
+
+#[tokio::test]
+async fn request_rejects_malformed_fields() {
+    assert_eq!(
+        request_error(&[4, 1, 0, 1, 1, 2, 3, 4, 0, 80]).await,
+        SocksError::InvalidVersion
+    );
+    assert_eq!(
+        request_error(&[5, 1, 1, 1, 1, 2, 3, 4, 0, 80]).await,
+        SocksError::NonZeroReserved
+    );
+    assert_eq!(request_error(&[5, 1, 0, 9]).await, SocksError::UnsupportedAddressType);
+    assert_eq!(request_error(&[5, 1, 0, 3, 0, 0, 80]).await, SocksError::EmptyDomain);
+    assert_eq!(
+        request_error(&[5, 1, 0, 1, 1, 2, 3, 4, 0, 0]).await,
+        SocksError::ZeroPort
+    );
+}
+
+#[tokio::test]
+async fn udp_associate_allows_zero_address() {
+    let req = socks::read_request(&mut &[5, 3, 0, 1, 0, 0, 0, 0, 0, 0][..])
+        .await
+        .unwrap();
+    assert_eq!(req.command, socks::CMD_UDP_ASSOCIATE);
+    assert_eq!(req.addr.port, 0);
+}
+
+#[tokio::test]
+async fn truncated_domain_is_an_eof_error() {
+    // 长度字段声称 20 字节，实际只有 3 字节
+    let err = socks::read_request(&mut &[5, 1, 0, 3, 20, b'a', b'b', b'c'][..])
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn invalid_request_gets_general_failure_reply() {
+    let bytes = [5, 1, 0, 5, 1, 7, 1, 1, 2, 3, 4, 0, 80];
+    let (result, client) = run_handshake(Socks5Handshake::new(), &bytes).await;
+    let err = result.unwrap_err();
+    assert_eq!(SocksError::from_io(&err), Some(SocksError::NonZeroReserved));
+    assert_eq!(read_all(client).await, [5, 0, 5, 1, 0, 1, 0, 0, 0, 0, 0, 0]);
+}
@@ -0,0 +1,518 @@
+use anytls_rs::proxy::transport;
+use anytls_rs::util::mkcert;
+use anytls_rs::util::tls::{
+    load_system_roots, CipherPreference, TlsClientOptions, TlsServerOptions,
+};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{CipherSuite, ServerConfig, SupportedCipherSuite};
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+fn suites(pref: CipherPreference) -> Vec<CipherSuite> {
+    pref.crypto_provider()
+        .cipher_suites
+        .iter()
+        .map(SupportedCipherSuite::suite)
+        .collect()
+}
+
+#[test]
+fn cipher_preference_parses_cli_values() {
+    assert_eq!("auto".parse(), Ok(CipherPreference::Auto));
+    assert_eq!("AES".parse(), Ok(CipherPreference::Aes));
+    assert_eq!("chacha".parse(), Ok(CipherPreference::Chacha));
+    assert!("des".parse::<CipherPreference>().is_err());
+    assert_eq!(CipherPreference::Chacha.to_string(), "chacha");
+}
+
+#[test]
+fn cipher_preference_orders_suites_within_each_version() {
+    let chacha = suites(CipherPreference::Chacha);
+    assert_eq!(chacha[0], CipherSuite::TLS13_CHACHA20_POLY1305_SHA256);
+    assert!(chacha[..3]
+        .iter()
+        .all(|s| format!("{:?}", s).starts_with("TLS13_")));
+
+    let aes = suites(CipherPreference::Aes);
+    assert!(matches!(
+        aes[0],
+        CipherSuite::TLS13_AES_256_GCM_SHA384 | CipherSuite::TLS13_AES_128_GCM_SHA256
+    ));
+    assert_eq!(aes[2], CipherSuite::TLS13_CHACHA20_POLY1305_SHA256);
+
+    let mut sorted_auto = suites(CipherPreference::Auto);
+    let mut sorted_aes = aes.clone();
+    sorted_auto.sort_by_key(|s| u16::from(*s));
+    sorted_aes.sort_by_key(|s| u16::from(*s));
+    assert_eq!(sorted_auto, sorted_aes);
+}
+
+#[tokio::test]
+async fn server_cipher_preference_wins_handshake() {
+    let server_options = TlsServerOptions {
+        cipher: CipherPreference::Chacha,
+        ..Default::default()
+    };
+    let server_config = mkcert::generate_key_pair("localhost", &server_options).unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let client_options = TlsClientOptions {
+        cipher: CipherPreference::Aes,
+        ..Default::default()
+    };
+    let connector = TlsConnector::from(transport::create_tls_config(&client_options).unwrap());
+
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let server = tokio::spawn(async move { acceptor.accept(server_io).await.unwrap() });
+    let client = connector
+        .connect(ServerName::try_from("localhost").unwrap(), client_io)
+        .await
+        .unwrap();
+    let server = server.await.unwrap();
+
+    let negotiated = client.get_ref().1.negotiated_cipher_suite().unwrap();
+    assert_eq!(negotiated.suite(), CipherSuite::TLS13_CHACHA20_POLY1305_SHA256);
+    assert_eq!(
+        server.get_ref().1.negotiated_cipher_suite().unwrap().suite(),
+        negotiated.suite()
+    );
+}
+
+struct TestCa {
+    issuer: CertifiedIssuer<'static, KeyPair>,
+}
+
+impl TestCa {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let issuer = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+        Self { issuer }
+    }
+
+    /// 签发客户端证书，返回 (证书 PEM, 私钥 PEM)
+    fn issue_client(&self) -> (String, String) {
+        self.issue("client")
+    }
+
+    /// 签发 `name` 的证书，返回 (证书 PEM, 私钥 PEM)
+    fn issue(&self, name: &str) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec![name.to_string()])
+            .unwrap()
+            .signed_by(&key, &self.issuer)
+            .unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+}
+
+fn write_temp(name: &str, contents: &str) -> String {
+    let path: PathBuf = std::env::temp_dir().join(format!(
+        "anytls-tls-test-{}-{}",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+/// 用给定客户端选项与要求 `ca` 签发证书的服务端握手，返回服务端握手结果
+async fn mtls_handshake(ca: &TestCa, client_options: TlsClientOptions, tag: &str) -> bool {
+    let server_options = TlsServerOptions {
+        client_ca: Some(write_temp(&format!("{}-ca.pem", tag), &ca.issuer.pem())),
+        ..Default::default()
+    };
+    let server_config = mkcert::generate_key_pair("localhost", &server_options).unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let connector = TlsConnector::from(transport::create_tls_config(&client_options).unwrap());
+
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let server = tokio::spawn(async move { acceptor.accept(server_io).await.is_ok() });
+    let client = connector
+        .connect(ServerName::try_from("localhost").unwrap(), client_io)
+        .await;
+    let accepted = server.await.unwrap();
+    drop(client);
+    accepted
+}
+
+#[tokio::test]
+async fn mutual_tls_accepts_client_with_valid_certificate() {
+    let ca = TestCa::new();
+    let (cert, key) = ca.issue_client();
+    let options = TlsClientOptions {
+        client_cert: Some(write_temp("valid-cert.pem", &cert)),
+        client_key: Some(write_temp("valid-key.pem", &key)),
+        ..Default::default()
+    };
+    assert!(mtls_handshake(&ca, options, "valid").await);
+}
+
+#[tokio::test]
+async fn mutual_tls_rejects_missing_or_foreign_certificate() {
+    let ca = TestCa::new();
+    assert!(!mtls_handshake(&ca, TlsClientOptions::default(), "missing").await);
+
+    let (cert, key) = TestCa::new().issue_client();
+    let options = TlsClientOptions {
+        client_cert: Some(write_temp("foreign-cert.pem", &cert)),
+        client_key: Some(write_temp("foreign-key.pem", &key)),
+        ..Default::default()
+    };
+    assert!(!mtls_handshake(&ca, options, "foreign").await);
+}
+
+#[test]
+fn client_cert_without_key_is_rejected() {
+    let options = TlsClientOptions {
+        client_cert: Some("cert.pem".to_string()),
+        ..Default::default()
+    };
+    let err = transport::create_tls_config(&options).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+/// 用 `server_config` 与信任系统根证书的客户端握手，返回客户端握手结果
+async fn system_roots_handshake(server_config: ServerConfig) -> bool {
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let options = TlsClientOptions {
+        trust_system_roots: true,
+        ..Default::default()
+    };
+    let connector = TlsConnector::from(transport::create_tls_config(&options).unwrap());
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move { acceptor.accept(server_io).await.is_ok() });
+    connector
+        .connect(ServerName::try_from("localhost").unwrap(), client_io)
+        .await
+        .is_ok()
+}
+
+#[tokio::test]
+async fn trust_system_roots_verifies_server_certificate() {
+    // rustls-native-certs 在设置 SSL_CERT_FILE 时只读取该文件（及 SSL_CERT_DIR），
+    // 以此作为测试用的“系统”证书库
+    let ca = TestCa::new();
+    std::env::remove_var("SSL_CERT_DIR");
+    std::env::set_var("SSL_CERT_FILE", write_temp("system-roots.pem", &ca.issuer.pem()));
+    let roots = load_system_roots().unwrap();
+    assert_eq!(roots.len(), 1);
+
+    let (cert, key) = ca.issue("localhost");
+    let signed = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()],
+            PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap(),
+        )
+        .unwrap();
+    assert!(system_roots_handshake(signed).await);
+
+    let self_signed = mkcert::generate_key_pair("localhost", &TlsServerOptions::default()).unwrap();
+    assert!(!system_roots_handshake(self_signed).await);
+}
+
+#[tokio::test]
+async fn keylog_file_receives_session_secrets() {
+    let client_log = write_temp("client-keylog.txt", "");
+    let server_log = write_temp("server-keylog.txt", "");
+    let server_options = TlsServerOptions {
+        keylog_file: Some(server_log.clone()),
+        ..Default::default()
+    };
+    let acceptor = TlsAcceptor::from(Arc::new(
+        mkcert::generate_key_pair("localhost", &server_options).unwrap(),
+    ));
+    let client_options = TlsClientOptions {
+        keylog_file: Some(client_log.clone()),
+        ..Default::default()
+    };
+    let connector = TlsConnector::from(transport::create_tls_config(&client_options).unwrap());
+
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let server = tokio::spawn(async move { acceptor.accept(server_io).await.unwrap() });
+    let _client = connector
+        .connect(ServerName::try_from("localhost").unwrap(), client_io)
+        .await
+        .unwrap();
+    let _server = server.await.unwrap();
+
+    for path in [client_log, server_log] {
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert!(lines.iter().any(|l| l.starts_with("CLIENT_HANDSHAKE_TRAFFIC_SECRET ")));
+        assert!(lines.iter().any(|l| l.starts_with("CLIENT_TRAFFIC_SECRET_0 ")));
+        // NSS 格式：标签、32 字节 client random、密钥，均为十六进制
+        let fields: Vec<&str> = lines[0].split(' ').collect();
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[1].len(), 64);
+        assert!(fields[2].chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
+
+#[test]
+fn keylog_is_off_by_default() {
+    let config = transport::create_tls_config(&TlsClientOptions::default()).unwrap();
+    assert!(!config.key_log.will_log("CLIENT_TRAFFIC_SECRET_0"));
+}
+
+/// 记录客户端写出的全部字节，用于检查 TLS 记录长度
+struct Tap {
+    inner: DuplexStream,
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl AsyncRead for Tap {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Tap {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            self.written.lock().unwrap().extend_from_slice(&buf[..n]);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[tokio::test]
+async fn fragment_size_limits_record_length() {
+    const FRAGMENT: usize = 512;
+    let options = TlsClientOptions {
+        fragment_size: Some(FRAGMENT),
+        ..Default::default()
+    };
+    let client_config = transport::create_tls_config(&options).unwrap();
+    assert_eq!(client_config.max_fragment_size, Some(FRAGMENT));
+    let server_options = TlsServerOptions {
+        fragment_size: Some(FRAGMENT),
+        ..Default::default()
+    };
+    let server_config = mkcert::generate_key_pair("localhost", &server_options).unwrap();
+    assert_eq!(server_config.max_fragment_size, Some(FRAGMENT));
+
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let client_io = Tap {
+        inner: client_io,
+        written: Arc::clone(&written),
+    };
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let server = tokio::spawn(async move {
+        let mut conn = acceptor.accept(server_io).await.unwrap();
+        let mut buf = vec![0u8; 8000];
+        conn.read_exact(&mut buf).await.unwrap();
+        buf
+    });
+    let mut client = TlsConnector::from(client_config)
+        .connect(ServerName::try_from("localhost").unwrap(), client_io)
+        .await
+        .unwrap();
+    client.write_all(&[0xab; 8000]).await.unwrap();
+    client.flush().await.unwrap();
+    assert_eq!(server.await.unwrap(), vec![0xab; 8000]);
+
+    // 每个记录的明文不超过上限减去记录头，密文另有内容类型与 AEAD 标签的 17 字节
+    let wire = written.lock().unwrap().clone();
+    let (mut offset, mut app_records) = (0, 0);
+    while offset + 5 <= wire.len() {
+        let len = u16::from_be_bytes([wire[offset + 3], wire[offset + 4]]) as usize;
+        if wire[offset] == 23 {
+            assert!(len <= FRAGMENT - 5 + 17, "record of {} bytes", len);
+            app_records += 1;
+        }
+        offset += 5 + len;
+    }
+    assert_eq!(offset, wire.len());
+    assert!(app_records >= 8000 / (FRAGMENT - 5));
+}
+
+#[test]
+fn out_of_range_fragment_size_is_rejected() {
+    for size in [31, 16390] {
+        let options = TlsClientOptions {
+            fragment_size: Some(size),
+            ..Default::default()
+        };
+        let err = transport::create_tls_config(&options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let options = TlsServerOptions {
+            fragment_size: Some(size),
+            ..Default::default()
+        };
+        assert!(mkcert::generate_key_pair("localhost", &options).is_err());
+    }
+}
+
+/// 以 `server_name` 连接要求 SNI 为 example.com 的服务端，返回双方的握手结果
+async fn handshake_with_required_sni(server_name: &str) -> (io::Result<()>, io::Result<()>) {
+    let options = TlsServerOptions {
+        require_sni: Some("example.com".to_string()),
+        ..Default::default()
+    };
+    let server_config = mkcert::RotatingServerConfig::new("example.com", options).unwrap();
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let server = tokio::spawn(async move {
+        let accepted = server_config.accept(server_io).await;
+        accepted.map(|tls| tls.get_ref().1.server_name().map(str::to_string)).map(|sni| {
+            assert_eq!(sni.as_deref(), Some("example.com"));
+        })
+    });
+    let client_config = transport::create_tls_config(&TlsClientOptions::default()).unwrap();
+    let name = ServerName::try_from(server_name.to_string()).unwrap();
+    // 服务端读完客户端的 Finished 之前不能关闭连接
+    let client = TlsConnector::from(client_config).connect(name, client_io).await;
+    let server = server.await.unwrap();
+    (client.map(drop), server)
+}
+
+#[tokio::test]
+async fn require_sni_refuses_mismatched_client_hellos() {
+    let (client, server) = handshake_with_required_sni("EXAMPLE.com").await;
+    client.unwrap();
+    server.unwrap();
+
+    // 其他名称与不带 SNI（以 IP 地址连接）的 ClientHello 都不会收到任何应答
+    for name in ["probe.example.net", "127.0.0.1"] {
+        let (client, server) = handshake_with_required_sni(name).await;
+        assert_eq!(server.unwrap_err().kind(), io::ErrorKind::PermissionDenied, "{}", name);
+        assert!(client.is_err(), "{}", name);
+    }
+}
+
+/// 用 `create_dial_out_func` 连接本地 TLS 服务端，返回服务端看到的 SNI
+async fn sni_seen_by_server(host: &str, sni: Option<&str>) -> Option<String> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = format!("{}:{}", host, listener.local_addr().unwrap().port());
+    let server_config = mkcert::generate_key_pair("localhost", &TlsServerOptions::default());
+    let acceptor = TlsAcceptor::from(Arc::new(server_config.unwrap()));
+    let server = tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.unwrap();
+        let tls = acceptor.accept(tcp).await.unwrap();
+        tls.get_ref().1.server_name().map(str::to_string)
+    });
+
+    let dial = transport::create_dial_out_func(
+        server_addr,
+        transport::create_tls_config(&TlsClientOptions::default()).unwrap(),
+        sni.map(str::to_string),
+        transport::password_sha256("sni"),
+        Arc::new(anytls_rs::proxy::padding::PaddingFactory::default()),
+        transport::AuthMode::Legacy,
+    );
+    let _conn = dial().await.unwrap();
+    server.await.unwrap()
+}
+
+#[tokio::test]
+async fn sni_defaults_to_server_host_and_can_be_omitted() {
+    assert_eq!(sni_seen_by_server("localhost", None).await.as_deref(), Some("localhost"));
+    assert_eq!(
+        sni_seen_by_server("localhost", Some("cdn.example.com")).await.as_deref(),
+        Some("cdn.example.com")
+    );
+    assert_eq!(sni_seen_by_server("localhost", Some("")).await, None);
+    // IP 地址不能作为 SNI
+    assert_eq!(sni_seen_by_server("127.0.0.1", None).await, None);
+}
+
+#[tokio::test]
+async fn sni_list_rotates_across_dials() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = format!("localhost:{}", listener.local_addr().unwrap().port());
+    let server_config = mkcert::generate_key_pair("localhost", &TlsServerOptions::default());
+    let acceptor = TlsAcceptor::from(Arc::new(server_config.unwrap()));
+    let server = tokio::spawn(async move {
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let tls = acceptor.accept(tcp).await.unwrap();
+            seen.push(tls.get_ref().1.server_name().map(str::to_string));
+        }
+        seen
+    });
+
+    let dial = transport::create_dial_out_func(
+        server_addr,
+        transport::create_tls_config(&TlsClientOptions::default()).unwrap(),
+        Some(" a.example.com, ,b.example.com".to_string()),
+        transport::password_sha256("sni"),
+        Arc::new(anytls_rs::proxy::padding::PaddingFactory::default()),
+        transport::AuthMode::Legacy,
+    );
+    let mut conns = Vec::new();
+    for _ in 0..3 {
+        conns.push(dial().await.unwrap());
+    }
+    let seen = server.await.unwrap();
+    let names: Vec<_> = seen.iter().map(|name| name.as_deref().unwrap()).collect();
+    assert_eq!(names, ["a.example.com", "b.example.com", "a.example.com"]);
+}
+
+#[test]
+fn server_name_is_derived_from_server_address() {
+    let name = |addr, sni| transport::tls_server_name(addr, sni).unwrap().to_str().into_owned();
+    assert_eq!(name("proxy.example.com:8443", None), "proxy.example.com");
+    assert_eq!(name("proxy.example.com:8443", Some("front.example.org")), "front.example.org");
+    assert_eq!(name("[2001:db8::1]:443", None), "2001:db8::1");
+    assert_eq!(name("10.0.0.1:443", Some("")), "10.0.0.1");
+    assert!(transport::tls_server_name("bad host:443", None).is_err());
+}
+
+/// 与 `acceptor` 握手，返回服务端出示的证书
+async fn presented_cert(acceptor: TlsAcceptor) -> Vec<u8> {
+    let connector = TlsConnector::from(
+        transport::create_tls_config(&TlsClientOptions::default()).unwrap(),
+    );
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let server = tokio::spawn(async move { acceptor.accept(server_io).await.unwrap() });
+    let client = connector
+        .connect(ServerName::try_from("localhost").unwrap(), client_io)
+        .await
+        .unwrap();
+    let _server = server.await.unwrap();
+    client.get_ref().1.peer_certificates().unwrap()[0].to_vec()
+}
+
+#[tokio::test]
+async fn rotating_config_presents_a_new_cert_after_the_interval() {
+    let rotating = mkcert::RotatingServerConfig::new("localhost", TlsServerOptions::default());
+    let rotating = rotating.unwrap();
+    let first = presented_cert(rotating.acceptor()).await;
+    assert_eq!(presented_cert(rotating.acceptor()).await, first);
+
+    let held = rotating.acceptor();
+    let task = rotating.spawn_rotation(Duration::from_millis(100));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let rotated = presented_cert(rotating.acceptor()).await;
+    assert_ne!(rotated, first);
+    // 轮换前取得的 acceptor 仍使用旧证书
+    assert_eq!(presented_cert(held).await, first);
+    task.abort();
+}